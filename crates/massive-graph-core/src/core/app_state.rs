@@ -43,12 +43,16 @@
 use std::sync::Arc;
 use crate::comms::network::Network;
 use crate::core::config::Config;
+use crate::delta::DeltaStamper;
 use crate::storage::{Store, StorageImpl};
 
 /// Central application state holding all services and components
 pub struct AppState  {
     /// Storage system - configured Store instance
     pub store: Arc<Store>,
+
+    /// Stamps deltas built or accepted by the server before they reach the store
+    pub stamper: Arc<DeltaStamper>,
     
     /// Application configuration
     pub config: Config,
@@ -85,6 +89,7 @@ impl Clone for AppState {
     fn clone(&self) -> Self {
         Self {
             store: self.store.clone(),      // Only clones Arc, not the storage itself
+            stamper: self.stamper.clone(),
             config: self.config.clone(),
             network: self.network.clone(),
             metrics: self.metrics.clone(),
@@ -133,11 +138,13 @@ impl AppState {
     /// This is called by the factory after all services are initialized
     pub fn new(
         store: Arc<Store>,
+        stamper: Arc<DeltaStamper>,
         config: Config,
         network: Network,
     ) -> Self {
         Self {
             store,
+            stamper,
             config,
             network,
             metrics: MetricsServiceStub,
@@ -31,6 +31,10 @@ pub struct Config {
     
     /// QUIC ingress configuration
     pub quic: QuicConfig,
+
+    /// Delta processing configuration
+    #[serde(default)]
+    pub delta: DeltaConfig,
 }
 
 /// Server configuration
@@ -46,7 +50,7 @@ pub struct StorageConfig {
     /// Storage backend type
     pub storage_type: StorageType,
     
-    /// Data directory path (for future disk storage)
+    /// Data directory path; holds the persisted schema (`schema.log`)
     pub data_dir: PathBuf,
 }
 
@@ -88,6 +92,36 @@ pub struct QuicConfig {
     pub key_path: Option<String>,
 }
 
+/// Delta processing configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeltaConfig {
+    /// How long a delta's content hash is remembered for deduplication (milliseconds)
+    pub dedup_window_ms: u64,
+    
+    /// Maximum remembered hashes per document; oldest are evicted first
+    pub dedup_max_entries: usize,
+
+    /// Key signing stored deltas, as 64 hex digits; shared by every node verifying them.
+    /// Without it deltas are signed with a random per-process key.
+    pub mac_key: Option<String>,
+}
+
+impl DeltaConfig {
+    /// Parsed `mac_key`
+    pub fn mac_key(&self) -> Result<[u8; 32], String> {
+        let hex = self.mac_key.as_deref().ok_or("delta.mac_key is not configured")?;
+        if hex.len() != 64 || !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+            return Err("delta.mac_key must be 64 hex digits".to_string());
+        }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        Ok(key)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -95,6 +129,7 @@ impl Default for Config {
             storage: StorageConfig::default(),
             metrics: MetricsConfig::default(),
             quic: QuicConfig::default(),
+            delta: DeltaConfig::default(),
         }
     }
 }
@@ -140,6 +175,16 @@ impl Default for QuicConfig {
     }
 }
 
+impl Default for DeltaConfig {
+    fn default() -> Self {
+        Self {
+            dedup_window_ms: 1_000,
+            dedup_max_entries: 4_096,
+            mac_key: None,
+        }
+    }
+}
+
 /// Load configuration from file
 pub fn load_config(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
     let config_str = std::fs::read_to_string(path)?;
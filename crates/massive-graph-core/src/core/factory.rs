@@ -7,18 +7,30 @@ use std::sync::Arc;
 use crate::core::app_state::AppState;
 use crate::core::config::{Config};
 use crate::comms::network::Network;
+use crate::delta::DeltaStamper;
 use crate::log_info;
+use crate::storage::schema_log::SchemaLog;
 use crate::storage::Store;
 
 
 /// Create AppState based on configuration (for server use)
-pub fn create_app_state(config: Config) -> Arc<AppState> {
+///
+/// Fails if the persisted schema cannot be loaded or the delta configuration is invalid.
+pub fn create_app_state(config: Config) -> Result<Arc<AppState>, String> {
     log_info!("Creating AppState with storage type: {:?}", config.storage.storage_type);
     log_info!("Creating AppState with ZeroCopyStorage");
     
+    // Field indices must be restored before any delta is accepted
+    log_info!("Loading schema from {:?}", config.storage.data_dir);
+    let schema = SchemaLog::load(&config.storage.data_dir)
+        .map_err(|e| format!("Failed to load schema registry: {}", e))?;
+
     log_info!("Initializing ZeroCopyStore");
-    let store = Arc::new(Store::new());
+    let store = Arc::new(Store::with_schema(schema));
     log_info!("ZeroCopyStore initialized successfully");
+
+    let stamper = DeltaStamper::from_config(&config.delta)
+        .map_err(|e| format!("Invalid delta configuration: {}", e))?;
     
     log_info!("Initializing Network");
     let network = Network::new();
@@ -26,10 +38,11 @@ pub fn create_app_state(config: Config) -> Arc<AppState> {
     
     let app_state = AppState::new(
         store,
+        Arc::new(stamper),
         config,
         network,
     );
     
     log_info!("AppState with ZeroCopyStorage created successfully");
-    Arc::new(app_state)
+    Ok(Arc::new(app_state))
 }
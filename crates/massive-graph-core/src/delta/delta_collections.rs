@@ -0,0 +1,534 @@
+//! Collection delta operations
+//!
+//! Collection values use the nested value layout (see `value_nested`): an element count
+//! and offsets table followed by the elements, Map and Object entries sorted by key so
+//! replicas encode identical bytes. Collection is an unordered set: elements are unique
+//! and never addressed by position.
+//!
+//! Payloads carry a value of the field's own type: the elements to add (Append, Insert,
+//! Splice, SliceUpdate) or, for Collection, to remove. Positions and keys come from an
+//! element selector - one parameter group after the field's own groups (ArraySet for
+//! Array, KeySet for Map/Object).
+//!
+//! `InsertWhere`, `ReplaceWhere` and `DeleteWhere` select elements with a predicate
+//! (see `delta_predicate`) instead:
+//! ```text
+//! DeleteWhere:   [predicate]
+//! ReplaceWhere:  [predicate][element value]
+//! InsertWhere:   [predicate][value of the field's type]
+//! ```
+//! ReplaceWhere replaces the value of every matching element (entry values for
+//! Map/Object). InsertWhere inserts an Array's new elements before the first match (at
+//! the end if none match); for Collection, Map and Object it is a conditional insert that
+//! only adds when no existing element matches.
+//!
+//! Out-of-range positions are handled deterministically: insertion points (Insert,
+//! Splice) are clamped to the end, removals ignore positions past the end, and
+//! SliceUpdate fails because it can only replace elements that exist.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::delta::delta_predicate::Predicate;
+use crate::types::field::{ArrayParam, ParamGroup, ParamGroupValues};
+use crate::types::value_nested::{encode_elements, encode_entries};
+use crate::types::{DeltaOp, Value, ValueType};
+
+/// Whether values of this type are handled by collection operations
+pub fn is_collection(value_type: ValueType) -> bool {
+    matches!(value_type, ValueType::Array | ValueType::Map | ValueType::Object | ValueType::Collection)
+}
+
+/// Parameter group a collection operation expects as its element selector, if any
+pub fn selector_group(op: DeltaOp, value_type: ValueType) -> Option<ParamGroup> {
+    match (value_type, op) {
+        (
+            ValueType::Array,
+            DeltaOp::Insert
+            | DeltaOp::Remove
+            | DeltaOp::Splice
+            | DeltaOp::SliceUpdate
+            | DeltaOp::Reshape
+            | DeltaOp::InsertAt
+            | DeltaOp::ReplaceAt
+            | DeltaOp::DeleteAt,
+        ) => Some(ParamGroup::ArraySet),
+        (ValueType::Map | ValueType::Object, DeltaOp::Remove) => Some(ParamGroup::KeySet),
+        _ => None,
+    }
+}
+
+/// Decoded contents of a collection value
+#[derive(Clone, Debug, PartialEq)]
+enum Contents {
+    /// Array and Collection elements, each an encoded value
+    List(Vec<Vec<u8>>),
+    /// Map and Object entries by key
+    Entries(BTreeMap<String, Vec<u8>>),
+}
+
+/// A collection value being edited
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollectionValue {
+    /// Array, Map, Object or Collection
+    value_type: ValueType,
+    /// Elements or entries
+    contents: Contents,
+}
+
+impl CollectionValue {
+    /// Empty collection of the given type
+    pub fn empty(value_type: ValueType) -> Self {
+        let contents = match value_type {
+            ValueType::Map | ValueType::Object => Contents::Entries(BTreeMap::new()),
+            _ => Contents::List(Vec::new()),
+        };
+        Self { value_type, contents }
+    }
+
+    /// Decode a complete encoded value of `value_type`
+    pub fn decode(value_type: ValueType, bytes: &[u8]) -> Result<Self, String> {
+        if bytes.first() != Some(&(value_type as u8)) {
+            return Err(format!("Field expects {:?}, got type tag {:?}", value_type, bytes.first()));
+        }
+        let encoded = Value::from_bytes(bytes).map_err(|e| e.to_string())?;
+        if encoded.total_size() != bytes.len() {
+            return Err(format!("{:?} value is followed by {} stray bytes", value_type, bytes.len() - encoded.total_size()));
+        }
+
+        let mut value = Self::empty(value_type);
+        match &mut value.contents {
+            Contents::List(items) => {
+                for element in encoded.elements().map_err(|e| e.to_string())? {
+                    let item = element.map_err(|e| e.to_string())?.encoded();
+                    if value_type == ValueType::Collection && items.iter().any(|i| i == item) {
+                        return Err("Duplicate element in Collection".to_string());
+                    }
+                    items.push(item.to_vec());
+                }
+            }
+            Contents::Entries(entries) => {
+                let mut previous = None;
+                for entry in encoded.entries().map_err(|e| e.to_string())? {
+                    let (key, item) = entry.map_err(|e| e.to_string())?;
+                    if previous.is_some_and(|previous| previous >= key) {
+                        return Err(format!("Entry key {:?} is out of order or duplicated", key));
+                    }
+                    previous = Some(key);
+                    entries.insert(key.to_string(), item.encoded().to_vec());
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// Encode as a wire value
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut output = Vec::new();
+        match &self.contents {
+            Contents::List(items) => encode_elements(self.value_type, items, &mut output)?,
+            Contents::Entries(entries) => encode_entries(self.value_type, entries, &mut output)?,
+        }
+        Ok(output)
+    }
+
+    /// Number of elements or entries
+    pub fn len(&self) -> usize {
+        match &self.contents {
+            Contents::List(items) => items.len(),
+            Contents::Entries(entries) => entries.len(),
+        }
+    }
+
+    /// Whether the collection is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encoded value of a Map/Object entry
+    pub fn entry(&self, key: &str) -> Option<&[u8]> {
+        match &self.contents {
+            Contents::Entries(entries) => entries.get(key).map(|v| v.as_slice()),
+            Contents::List(_) => None,
+        }
+    }
+
+    /// Apply `InsertWhere`, `ReplaceWhere` or `DeleteWhere`
+    fn apply_where(&mut self, op: DeltaOp, payload: &[u8]) -> Result<(), String> {
+        let (predicate, read) = Predicate::decode(payload)?;
+        let rest = &payload[read..];
+        let value_type = self.value_type;
+        match op {
+            DeltaOp::DeleteWhere => {
+                if !rest.is_empty() {
+                    return Err("DeleteWhere carries only a predicate".to_string());
+                }
+                match &mut self.contents {
+                    Contents::List(items) => items.retain(|item| !predicate.matches(None, item)),
+                    Contents::Entries(entries) => entries.retain(|key, value| !predicate.matches(Some(key), value)),
+                }
+            }
+            DeltaOp::ReplaceWhere => {
+                if Value::encoded_len(rest).ok() != Some(rest.len()) {
+                    return Err("ReplaceWhere expects a single replacement value".to_string());
+                }
+                match &mut self.contents {
+                    Contents::List(items) if value_type == ValueType::Collection => {
+                        let before = items.len();
+                        items.retain(|item| !predicate.matches(None, item));
+                        if items.len() != before && !items.iter().any(|item| item == rest) {
+                            items.push(rest.to_vec());
+                        }
+                    }
+                    Contents::List(items) => items
+                        .iter_mut()
+                        .filter(|item| predicate.matches(None, item))
+                        .for_each(|item| *item = rest.to_vec()),
+                    Contents::Entries(entries) => entries
+                        .iter_mut()
+                        .filter(|(key, value)| predicate.matches(Some(key), value))
+                        .for_each(|(_, value)| *value = rest.to_vec()),
+                }
+            }
+            DeltaOp::InsertWhere => {
+                let at = match &self.contents {
+                    Contents::List(items) => items.iter().position(|item| predicate.matches(None, item)),
+                    Contents::Entries(entries) => entries.iter().position(|(key, value)| predicate.matches(Some(key), value)),
+                };
+                if value_type == ValueType::Array {
+                    let at = at.unwrap_or(self.len()) as u32;
+                    let selector = ParamGroupValues::ArraySet(vec![ArrayParam::Index(at)]);
+                    self.apply(DeltaOp::Insert, Some(&selector), rest)?;
+                } else if at.is_none() {
+                    self.apply(DeltaOp::Insert, None, rest)?;
+                } else {
+                    // Validate the payload even when nothing is inserted
+                    Self::decode(value_type, rest)?;
+                }
+            }
+            _ => return Err(format!("{:?} is not a predicate operation", op)),
+        }
+        Ok(())
+    }
+
+    /// Apply a collection operation
+    ///
+    /// `payload` is the delta's encoded value and `selector` the decoded element
+    /// selector required by `selector_group`.
+    pub fn apply(&mut self, op: DeltaOp, selector: Option<&ParamGroupValues>, payload: &[u8]) -> Result<(), String> {
+        let value_type = self.value_type;
+        let unsupported = || format!("{:?} is not supported on {:?}", op, value_type);
+
+        if op == DeltaOp::Clear {
+            *self = Self::empty(value_type);
+            return Ok(());
+        }
+        if matches!(op, DeltaOp::InsertWhere | DeltaOp::ReplaceWhere | DeltaOp::DeleteWhere) {
+            return self.apply_where(op, payload);
+        }
+        if op == DeltaOp::Remove && value_type != ValueType::Collection {
+            match &mut self.contents {
+                Contents::Entries(entries) => {
+                    for key in selector_keys(selector)? {
+                        entries.remove(key);
+                    }
+                }
+                Contents::List(items) => {
+                    let remove = positions(selector_params(selector)?, items.len())?;
+                    let mut index = 0;
+                    items.retain(|_| {
+                        index += 1;
+                        !remove.contains(&(index - 1))
+                    });
+                }
+            }
+            return Ok(());
+        }
+
+        let incoming = Self::decode(value_type, payload)?;
+        match (&mut self.contents, incoming.contents) {
+            (Contents::Entries(entries), Contents::Entries(new)) => match op {
+                DeltaOp::Append => entries.extend(new),
+                DeltaOp::Insert => new.into_iter().for_each(|(k, v)| {
+                    entries.entry(k).or_insert(v);
+                }),
+                _ => return Err(unsupported()),
+            },
+            (Contents::List(items), Contents::List(new)) if value_type == ValueType::Collection => match op {
+                DeltaOp::Append | DeltaOp::Insert => {
+                    for item in new {
+                        if !items.contains(&item) {
+                            items.push(item);
+                        }
+                    }
+                }
+                DeltaOp::Remove => items.retain(|item| !new.contains(item)),
+                _ => return Err(unsupported()),
+            },
+            (Contents::List(items), Contents::List(new)) => {
+                let len = items.len();
+                match op {
+                    DeltaOp::Append => items.extend(new),
+                    DeltaOp::Insert => {
+                        let at = match selector_params(selector)? {
+                            [ArrayParam::Index(i)] => (*i as usize).min(len),
+                            _ => return Err("Insert expects a single index".to_string()),
+                        };
+                        items.splice(at..at, new);
+                    }
+                    DeltaOp::Splice => {
+                        let (start, end) = match selector_params(selector)? {
+                            [ArrayParam::Index(i)] => (*i as usize, *i as usize),
+                            [ArrayParam::Range(start, end)] if start <= end => (*start as usize, *end as usize),
+                            _ => return Err("Splice expects a single index or range".to_string()),
+                        };
+                        items.splice(start.min(len)..end.min(len), new);
+                    }
+                    DeltaOp::SliceUpdate => {
+                        let targets = ordered_positions(selector_params(selector)?, len)?;
+                        if targets.len() != new.len() {
+                            return Err(format!("SliceUpdate selects {} elements but carries {}", targets.len(), new.len()));
+                        }
+                        for (at, item) in targets.into_iter().zip(new) {
+                            items[at] = item;
+                        }
+                    }
+                    DeltaOp::Reshape => {
+                        let dims = match selector_params(selector)? {
+                            [ArrayParam::Dimensions(dims)] if !dims.is_empty() => dims.clone(),
+                            _ => return Err("Reshape expects dimensions".to_string()),
+                        };
+                        *items = reshape(std::mem::take(items), &dims)?;
+                    }
+                    _ => return Err(unsupported()),
+                }
+            }
+            _ => unreachable!("decoded with the same value type"),
+        }
+        Ok(())
+    }
+}
+
+/// Keys of a KeySet selector
+fn selector_keys(selector: Option<&ParamGroupValues>) -> Result<&[String], String> {
+    match selector {
+        Some(ParamGroupValues::KeySet(keys)) => Ok(keys),
+        _ => Err("Expected a key selector".to_string()),
+    }
+}
+
+/// Parameters of an ArraySet selector
+fn selector_params(selector: Option<&ParamGroupValues>) -> Result<&[ArrayParam], String> {
+    match selector {
+        Some(ParamGroupValues::ArraySet(params)) => Ok(params),
+        _ => Err("Expected an array selector".to_string()),
+    }
+}
+
+/// Selected positions below `len`, skipping positions past the end
+fn positions(params: &[ArrayParam], len: usize) -> Result<BTreeSet<usize>, String> {
+    Ok(select_positions(params, len, false)?.into_iter().collect())
+}
+
+/// Selected positions in selection order, all of which must exist
+fn ordered_positions(params: &[ArrayParam], len: usize) -> Result<Vec<usize>, String> {
+    select_positions(params, len, true)
+}
+
+/// Expand array parameters into positions; past-the-end positions fail if `strict`, else are skipped
+fn select_positions(params: &[ArrayParam], len: usize, strict: bool) -> Result<Vec<usize>, String> {
+    let mut out = Vec::new();
+    let push_range = |start: u32, end: u32, out: &mut Vec<usize>| -> Result<(), String> {
+        let (start, end) = (start as usize, end as usize);
+        if start > end {
+            return Err(format!("Invalid range {}..{}", start, end));
+        }
+        if strict && end > len {
+            return Err(format!("Range {}..{} is past the end ({})", start, end, len));
+        }
+        out.extend(start.min(len)..end.min(len));
+        Ok(())
+    };
+    for param in params {
+        match param {
+            ArrayParam::Index(i) => push_range(*i, i.saturating_add(1), &mut out)?,
+            ArrayParam::Indices(v) => v.iter().try_for_each(|&i| push_range(i, i.saturating_add(1), &mut out))?,
+            ArrayParam::Range(start, end) => push_range(*start, *end, &mut out)?,
+            ArrayParam::Ranges(v) => v.iter().try_for_each(|&(start, end)| push_range(start, end, &mut out))?,
+            ArrayParam::Dimensions(_) => return Err("Dimensions do not select positions".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+/// Nest a flat element list into arrays of the given dimensions (outermost first)
+fn reshape(items: Vec<Vec<u8>>, dims: &[u32]) -> Result<Vec<Vec<u8>>, String> {
+    let total = dims.iter().try_fold(1usize, |acc, &d| acc.checked_mul(d as usize));
+    if total != Some(items.len()) {
+        return Err(format!("Cannot reshape {} elements to {:?}", items.len(), dims));
+    }
+    let mut level = items;
+    // Group innermost dimension first; the outermost dimension is the resulting list itself
+    for &dim in dims[1..].iter().rev() {
+        let dim = dim as usize;
+        let mut grouped = Vec::with_capacity(level.len() / dim.max(1));
+        let mut level_iter = level.into_iter();
+        while level_iter.len() > 0 {
+            let chunk = CollectionValue {
+                value_type: ValueType::Array,
+                contents: Contents::List(level_iter.by_ref().take(dim).collect()),
+            };
+            grouped.push(chunk.encode()?);
+        }
+        level = grouped;
+    }
+    Ok(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(v: i64) -> Vec<u8> {
+        let mut out = vec![ValueType::Int as u8];
+        out.extend_from_slice(&v.to_le_bytes());
+        out
+    }
+
+    fn array(values: &[i64]) -> Vec<u8> {
+        let value = CollectionValue { value_type: ValueType::Array, contents: Contents::List(values.iter().map(|&v| int(v)).collect()) };
+        value.encode().unwrap()
+    }
+
+    fn map(entries: &[(&str, i64)]) -> Vec<u8> {
+        let entries = entries.iter().map(|(k, v)| (k.to_string(), int(*v))).collect();
+        CollectionValue { value_type: ValueType::Map, contents: Contents::Entries(entries) }.encode().unwrap()
+    }
+
+    fn apply(current: &[i64], op: DeltaOp, selector: Vec<ArrayParam>, payload: &[i64]) -> Result<Vec<u8>, String> {
+        let mut value = CollectionValue::decode(ValueType::Array, &array(current))?;
+        let selector = ParamGroupValues::ArraySet(selector);
+        value.apply(op, Some(&selector), &array(payload))?;
+        value.encode()
+    }
+
+    #[test]
+    fn test_round_trip_and_validation() {
+        let bytes = array(&[1, 2, 3]);
+        assert_eq!(CollectionValue::decode(ValueType::Array, &bytes).unwrap().encode().unwrap(), bytes);
+        assert!(CollectionValue::decode(ValueType::Map, &bytes).is_err());
+        assert!(CollectionValue::decode(ValueType::Array, &bytes[..bytes.len() - 1]).is_err());
+
+        let bytes = map(&[("b", 2), ("a", 1)]);
+        assert_eq!(CollectionValue::decode(ValueType::Map, &bytes).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_array_ops() {
+        assert_eq!(apply(&[1, 2], DeltaOp::Append, vec![], &[3]).unwrap(), array(&[1, 2, 3]));
+        assert_eq!(apply(&[1, 3], DeltaOp::Insert, vec![ArrayParam::Index(1)], &[2]).unwrap(), array(&[1, 2, 3]));
+        assert_eq!(
+            apply(&[1, 2, 3, 4], DeltaOp::Remove, vec![ArrayParam::Indices(vec![0, 2])], &[]).unwrap(),
+            array(&[2, 4])
+        );
+        assert_eq!(apply(&[1, 2, 3, 4], DeltaOp::Splice, vec![ArrayParam::Range(1, 3)], &[9]).unwrap(), array(&[1, 9, 4]));
+        assert_eq!(
+            apply(&[1, 2, 3], DeltaOp::SliceUpdate, vec![ArrayParam::Indices(vec![2, 0])], &[7, 8]).unwrap(),
+            array(&[8, 2, 7])
+        );
+    }
+
+    #[test]
+    fn test_out_of_range_positions() {
+        // Insertion points clamp to the end
+        assert_eq!(apply(&[1], DeltaOp::Insert, vec![ArrayParam::Index(10)], &[2]).unwrap(), array(&[1, 2]));
+        assert_eq!(apply(&[1, 2], DeltaOp::Splice, vec![ArrayParam::Range(1, 10)], &[3]).unwrap(), array(&[1, 3]));
+        // Removals skip positions past the end
+        assert_eq!(apply(&[1, 2], DeltaOp::Remove, vec![ArrayParam::Range(1, 10)], &[]).unwrap(), array(&[1]));
+        assert_eq!(apply(&[1, 2], DeltaOp::Remove, vec![ArrayParam::Index(5)], &[]).unwrap(), array(&[1, 2]));
+        // Updates must name existing elements
+        assert!(apply(&[1, 2], DeltaOp::SliceUpdate, vec![ArrayParam::Index(2)], &[3]).is_err());
+        assert!(apply(&[1, 2], DeltaOp::SliceUpdate, vec![ArrayParam::Index(0)], &[3, 4]).is_err());
+    }
+
+    #[test]
+    fn test_reshape() {
+        let reshaped = apply(&[1, 2, 3, 4, 5, 6], DeltaOp::Reshape, vec![ArrayParam::Dimensions(vec![2, 3])], &[]).unwrap();
+        let rows = CollectionValue::decode(ValueType::Array, &reshaped).unwrap();
+        assert_eq!(rows.contents, Contents::List(vec![array(&[1, 2, 3]), array(&[4, 5, 6])]));
+        assert!(apply(&[1, 2, 3], DeltaOp::Reshape, vec![ArrayParam::Dimensions(vec![2, 2])], &[]).is_err());
+    }
+
+    #[test]
+    fn test_map_ops() {
+        let mut value = CollectionValue::decode(ValueType::Map, &map(&[("a", 1)])).unwrap();
+        value.apply(DeltaOp::Insert, None, &map(&[("a", 9), ("b", 2)])).unwrap();
+        assert_eq!(value.encode().unwrap(), map(&[("a", 1), ("b", 2)]));
+        value.apply(DeltaOp::Append, None, &map(&[("a", 9)])).unwrap();
+        assert_eq!(value.encode().unwrap(), map(&[("a", 9), ("b", 2)]));
+
+        let keys = ParamGroupValues::KeySet(vec!["a".to_string(), "missing".to_string()]);
+        value.apply(DeltaOp::Remove, Some(&keys), &[]).unwrap();
+        assert_eq!(value.encode().unwrap(), map(&[("b", 2)]));
+        assert!(value.apply(DeltaOp::Splice, None, &map(&[])).is_err());
+
+        value.apply(DeltaOp::Clear, None, &[]).unwrap();
+        assert!(value.is_empty());
+    }
+
+    #[test]
+    fn test_collection_is_a_set() {
+        let set = |values: &[i64]| {
+            let contents = Contents::List(values.iter().map(|&v| int(v)).collect());
+            CollectionValue { value_type: ValueType::Collection, contents }.encode().unwrap()
+        };
+        let mut value = CollectionValue::empty(ValueType::Collection);
+        value.apply(DeltaOp::Append, None, &set(&[1, 2])).unwrap();
+        value.apply(DeltaOp::Append, None, &set(&[2, 3])).unwrap();
+        value.apply(DeltaOp::Remove, None, &set(&[1])).unwrap();
+        assert_eq!(value.encode().unwrap(), set(&[2, 3]));
+        let mut duplicated = Vec::new();
+        encode_elements(ValueType::Collection, [int(1), int(1)], &mut duplicated).unwrap();
+        assert!(CollectionValue::decode(ValueType::Collection, &duplicated).is_err());
+    }
+
+    #[test]
+    fn test_predicate_ops() {
+        use crate::delta::delta_predicate::{Comparison, Target};
+
+        let encode = |predicate: Predicate, rest: &[u8]| {
+            let mut payload = Vec::new();
+            predicate.encode(&mut payload).unwrap();
+            payload.extend_from_slice(rest);
+            payload
+        };
+        let below = |v| Predicate::compare(Target::Element, Comparison::Lt, int(v));
+
+        let mut value = CollectionValue::decode(ValueType::Array, &array(&[5, 1, 7, 2])).unwrap();
+        value.apply(DeltaOp::DeleteWhere, None, &encode(below(3), &[])).unwrap();
+        assert_eq!(value.encode().unwrap(), array(&[5, 7]));
+        value.apply(DeltaOp::ReplaceWhere, None, &encode(below(6), &int(0))).unwrap();
+        assert_eq!(value.encode().unwrap(), array(&[0, 7]));
+
+        // Sorted insert: before the first element greater than 3, or at the end
+        let above = |v| Predicate::compare(Target::Element, Comparison::Gt, int(v));
+        value.apply(DeltaOp::InsertWhere, None, &encode(above(3), &array(&[3]))).unwrap();
+        value.apply(DeltaOp::InsertWhere, None, &encode(above(100), &array(&[9]))).unwrap();
+        assert_eq!(value.encode().unwrap(), array(&[0, 3, 7, 9]));
+
+        // Map entries match on key; InsertWhere only adds when nothing matches
+        let mut value = CollectionValue::decode(ValueType::Map, &map(&[("a", 1), ("b", 2)])).unwrap();
+        let key_a = || Predicate::compare(Target::Key, Comparison::Eq, {
+            let mut out = Vec::new();
+            Value::encode(ValueType::String, b"a", &mut out).unwrap();
+            out
+        });
+        value.apply(DeltaOp::InsertWhere, None, &encode(key_a(), &map(&[("c", 3)]))).unwrap();
+        assert_eq!(value.len(), 2);
+        value.apply(DeltaOp::DeleteWhere, None, &encode(key_a(), &[])).unwrap();
+        assert_eq!(value.encode().unwrap(), map(&[("b", 2)]));
+
+        // Trailing bytes and malformed predicates are rejected
+        assert!(value.apply(DeltaOp::DeleteWhere, None, &encode(below(1), &[0])).is_err());
+        assert!(value.apply(DeltaOp::DeleteWhere, None, &[0x7f]).is_err());
+    }
+}
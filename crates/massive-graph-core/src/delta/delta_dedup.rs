@@ -0,0 +1,194 @@
+//! Short-window delta deduplication
+//!
+//! Unreliable channels (WebRTC data channels with a bounded retransmit time) can deliver
+//! the same delta more than once. Each document keeps a sliding window of the
+//! `(user, content hash)` pairs it has accepted; a delta whose pair is still in the
+//! window is a retransmission and is dropped.
+//!
+//! Incoming deltas are checked before `DeltaStamper::stamp`, so a dropped retransmission
+//! never consumes a sequence number.
+//!
+//! Identical deltas sent deliberately by the same user inside the window are also
+//! treated as duplicates, so the window should stay close to the channel retransmit time.
+
+use std::collections::{HashSet, VecDeque};
+
+use dashmap::DashMap;
+
+use crate::core::config::DeltaConfig;
+use crate::core::utils::current_timestamp;
+use crate::types::delta::DeltaIncoming;
+use crate::types::ParseError;
+use crate::{DocId, UserId};
+
+/// Accepted hashes of one document, oldest first
+#[derive(Default)]
+struct DocWindow {
+    /// (accepted at, user, content hash) in acceptance order
+    order: VecDeque<(u64, UserId, u64)>,
+    /// Membership index over `order`
+    seen: HashSet<(UserId, u64)>,
+}
+
+impl DocWindow {
+    /// Drop entries older than `cutoff`, then the oldest beyond `max_entries - 1`
+    fn evict(&mut self, cutoff: u64, max_entries: usize) {
+        while let Some(&(at, user_id, hash)) = self.order.front() {
+            if at >= cutoff && self.order.len() < max_entries {
+                break;
+            }
+            self.order.pop_front();
+            self.seen.remove(&(user_id, hash));
+        }
+    }
+}
+
+/// Per-document sliding window of recently accepted content hashes
+pub struct DeltaDeduplicator {
+    /// Window length in nanoseconds (timestamps are `current_timestamp` nanos)
+    window_nanos: u64,
+    /// Maximum remembered hashes per document
+    max_entries: usize,
+    /// Window per document
+    docs: DashMap<DocId, DocWindow>,
+}
+
+impl DeltaDeduplicator {
+    /// Create a deduplicator from configuration
+    pub fn new(config: &DeltaConfig) -> Self {
+        Self::with_window(config.dedup_window_ms, config.dedup_max_entries)
+    }
+
+    /// Create a deduplicator remembering up to `max_entries` hashes per document for `window_ms`
+    pub fn with_window(window_ms: u64, max_entries: usize) -> Self {
+        Self {
+            window_nanos: window_ms.saturating_mul(1_000_000),
+            max_entries: max_entries.max(1),
+            docs: DashMap::new(),
+        }
+    }
+
+    /// Record a delta seen now, returning `true` if it is new and `false` if it is a duplicate
+    pub fn check(&self, doc_id: DocId, user_id: UserId, content_hash: u64) -> bool {
+        self.check_at(doc_id, user_id, content_hash, current_timestamp())
+    }
+
+    /// Record a delta seen at `now` (nanoseconds), returning `true` if it is new
+    pub fn check_at(&self, doc_id: DocId, user_id: UserId, content_hash: u64, now: u64) -> bool {
+        let mut window = self.docs.entry(doc_id).or_default();
+        window.evict(now.saturating_sub(self.window_nanos), usize::MAX);
+        if window.seen.contains(&(user_id, content_hash)) {
+            return false;
+        }
+        window.evict(0, self.max_entries);
+        window.order.push_back((now, user_id, content_hash));
+        window.seen.insert((user_id, content_hash));
+        true
+    }
+
+    /// Check an incoming delta by its client content hash before it is stamped
+    pub fn check_incoming(&self, doc_id: DocId, user_id: UserId, delta: &DeltaIncoming) -> Result<bool, ParseError> {
+        delta.validate()?;
+        let header = delta.client_header().ok_or(ParseError::InvalidFormat)?;
+        Ok(self.check(doc_id, user_id, header.content_hash))
+    }
+
+    /// Drop expired entries everywhere and forget documents with empty windows
+    pub fn purge(&self, now: u64) {
+        let cutoff = now.saturating_sub(self.window_nanos);
+        self.docs.retain(|_, window| {
+            window.evict(cutoff, usize::MAX);
+            !window.order.is_empty()
+        });
+    }
+
+    /// Number of documents with a non-empty window
+    pub fn tracked_documents(&self) -> usize {
+        self.docs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::DeltaStamper;
+    use crate::types::delta::{DeltaBuilder, DELTA_SECURE_HEADER_SIZE};
+    use crate::types::DeltaOp;
+
+    const MS: u64 = 1_000_000;
+
+    #[test]
+    fn test_duplicate_within_window_is_dropped() {
+        let dedup = DeltaDeduplicator::with_window(100, 16);
+        let doc_id = DocId::random();
+        let user_id = UserId::random();
+
+        assert!(dedup.check_at(doc_id, user_id, 42, 0));
+        assert!(!dedup.check_at(doc_id, user_id, 42, 50 * MS));
+        assert!(!dedup.check_at(doc_id, user_id, 42, 100 * MS));
+        assert!(dedup.check_at(doc_id, user_id, 42, 101 * MS));
+    }
+
+    #[test]
+    fn test_key_includes_user_and_document() {
+        let dedup = DeltaDeduplicator::with_window(100, 16);
+        let doc_id = DocId::random();
+        let user_id = UserId::random();
+
+        assert!(dedup.check_at(doc_id, user_id, 42, 0));
+        assert!(dedup.check_at(doc_id, UserId::random(), 42, 0));
+        assert!(dedup.check_at(DocId::random(), user_id, 42, 0));
+        assert!(dedup.check_at(doc_id, user_id, 43, 0));
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let dedup = DeltaDeduplicator::with_window(1_000, 2);
+        let doc_id = DocId::random();
+        let user_id = UserId::random();
+
+        assert!(dedup.check_at(doc_id, user_id, 1, 0));
+        assert!(dedup.check_at(doc_id, user_id, 2, MS));
+        assert!(dedup.check_at(doc_id, user_id, 3, 2 * MS));
+        // 1 was evicted to make room for 3
+        assert!(dedup.check_at(doc_id, user_id, 1, 3 * MS));
+        assert!(!dedup.check_at(doc_id, user_id, 3, 4 * MS));
+    }
+
+    #[test]
+    fn test_purge_forgets_idle_documents() {
+        let dedup = DeltaDeduplicator::with_window(100, 16);
+        dedup.check_at(DocId::random(), UserId::random(), 1, 0);
+        dedup.check_at(DocId::random(), UserId::random(), 1, 90 * MS);
+        dedup.purge(150 * MS);
+        assert_eq!(dedup.tracked_documents(), 1);
+        dedup.purge(200 * MS);
+        assert_eq!(dedup.tracked_documents(), 0);
+    }
+
+    #[test]
+    fn test_retransmission_dropped_before_stamping() {
+        let stamper = DeltaStamper::new([3; 32]);
+        let dedup = DeltaDeduplicator::new(&DeltaConfig::default());
+        let doc_id = DocId::random();
+        let user_id = UserId::random();
+        let framed = |incoming: Vec<u8>| {
+            let mut buf = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+            buf.extend_from_slice(&incoming);
+            buf
+        };
+        let a = framed(DeltaBuilder::new(DeltaOp::Increment).field(0, 1).int(1).build().unwrap());
+        let b = framed(DeltaBuilder::new(DeltaOp::Increment).field(0, 1).int(2).build().unwrap());
+
+        let mut sequences = Vec::new();
+        for mut buf in [a.clone(), a, b] {
+            let incoming = DeltaIncoming::new(&buf[DELTA_SECURE_HEADER_SIZE..]);
+            if dedup.check_incoming(doc_id, user_id, &incoming).unwrap() {
+                sequences.push(stamper.stamp(user_id, doc_id, &mut buf).unwrap().server_header().delta_sequence_number);
+            }
+        }
+        assert_eq!(sequences, vec![1, 2]);
+
+        assert!(dedup.check_incoming(doc_id, user_id, &DeltaIncoming::new(&[0; 4])).is_err());
+    }
+}
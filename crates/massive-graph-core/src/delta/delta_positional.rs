@@ -0,0 +1,227 @@
+//! Positional collaboration operations
+//!
+//! `Prepend`, `InsertAt`, `ReplaceAt` and `DeleteAt` edit an Array by position. A client
+//! computes positions against the version of the array it last saw, so each positional
+//! payload starts with that version - the sequence number of the last delta the client
+//! had applied:
+//! ```text
+//! InsertAt / ReplaceAt:  [base sequence: u64 LE][Array value]
+//! DeleteAt:              [base sequence: u64 LE]
+//! Prepend:               [Array value]
+//! ```
+//! The position itself is the single `Index` of the ArraySet selector.
+//!
+//! Each array slot keeps a log of positional edits. Before applying, the position is
+//! transformed past every logged edit with a sequence after the base, so concurrent
+//! edits keep their intent and every replica - applying deltas in sequence order -
+//! converges. Concurrent inserts at the same position are ordered by sequence (the
+//! earlier delta ends up first). Replacing or deleting an element that a concurrent
+//! delta already deleted is a no-op.
+//!
+//! Other structural edits (Set, Delete, Insert, Remove, Splice, ...) cannot be
+//! transformed through and reset the log: positional deltas based before them fail, as
+//! do deltas based before the oldest retained edit.
+
+use std::collections::VecDeque;
+
+use crate::delta::delta_collections::CollectionValue;
+use crate::types::field::{ArrayParam, ParamGroupValues};
+use crate::types::DeltaOp;
+
+/// Positional edits remembered per array slot
+pub const MAX_POSITIONAL_HISTORY: usize = 1024;
+
+/// Whether the operation is a positional collaboration op
+pub fn is_positional(op: DeltaOp) -> bool {
+    matches!(op, DeltaOp::Prepend | DeltaOp::InsertAt | DeltaOp::ReplaceAt | DeltaOp::DeleteAt)
+}
+
+/// An applied positional edit, in post-transform positions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionalEdit {
+    /// `count` elements inserted before `at`
+    Insert {
+        /// Insertion point
+        at: usize,
+        /// Number of inserted elements
+        count: usize,
+    },
+    /// Element at `at` removed
+    Delete {
+        /// Removed position
+        at: usize,
+    },
+    /// Element at `at` replaced
+    Replace {
+        /// Replaced position
+        at: usize,
+    },
+}
+
+/// Recent positional edits of one array slot
+#[derive(Clone, Debug, Default)]
+pub struct PositionalLog {
+    /// Oldest base sequence that can still be transformed
+    horizon: u64,
+    /// (sequence, edit), oldest first
+    edits: VecDeque<(u64, PositionalEdit)>,
+}
+
+impl PositionalLog {
+    /// Empty log that cannot transform positions based before `horizon`
+    pub fn with_horizon(horizon: u64) -> Self {
+        Self { horizon, edits: VecDeque::new() }
+    }
+
+    /// Record an edit applied at `sequence`
+    pub fn record(&mut self, sequence: u64, edit: PositionalEdit) {
+        if self.edits.len() == MAX_POSITIONAL_HISTORY {
+            if let Some((dropped, _)) = self.edits.pop_front() {
+                self.horizon = dropped;
+            }
+        }
+        self.edits.push_back((sequence, edit));
+    }
+
+    /// Forget all edits: positions based before `sequence` can no longer be transformed
+    pub fn reset(&mut self, sequence: u64) {
+        self.edits.clear();
+        self.horizon = sequence;
+    }
+
+    /// Edits applied after `base`
+    fn since(&self, base: u64) -> Result<impl Iterator<Item = &PositionalEdit>, String> {
+        if base < self.horizon {
+            return Err(format!("Base sequence {} is older than the positional history ({})", base, self.horizon));
+        }
+        Ok(self.edits.iter().filter(move |(sequence, _)| *sequence > base).map(|(_, edit)| edit))
+    }
+
+    /// Transform an insertion point computed at `base`
+    pub fn transform_gap(&self, base: u64, mut position: usize) -> Result<usize, String> {
+        for edit in self.since(base)? {
+            match *edit {
+                PositionalEdit::Insert { at, count } if at <= position => position += count,
+                PositionalEdit::Delete { at } if at < position => position -= 1,
+                _ => {}
+            }
+        }
+        Ok(position)
+    }
+
+    /// Transform an element position computed at `base`; `None` if the element was deleted
+    pub fn transform_element(&self, base: u64, mut position: usize) -> Result<Option<usize>, String> {
+        for edit in self.since(base)? {
+            match *edit {
+                PositionalEdit::Insert { at, count } if at <= position => position += count,
+                PositionalEdit::Delete { at } if at == position => return Ok(None),
+                PositionalEdit::Delete { at } if at < position => position -= 1,
+                _ => {}
+            }
+        }
+        Ok(Some(position))
+    }
+}
+
+/// Apply a positional op to an array slot, returning the edit to log (`None` for a no-op)
+///
+/// `current` is the sequence of the last delta applied to the document, bounding the base.
+pub fn apply_positional(
+    value: &mut CollectionValue,
+    log: &PositionalLog,
+    op: DeltaOp,
+    current: u64,
+    selector: Option<&ParamGroupValues>,
+    payload: &[u8],
+) -> Result<Option<PositionalEdit>, String> {
+    let before = value.len();
+    if op == DeltaOp::Prepend {
+        value.apply(DeltaOp::Insert, Some(&ParamGroupValues::ArraySet(vec![ArrayParam::Index(0)])), payload)?;
+        return Ok(Some(PositionalEdit::Insert { at: 0, count: value.len() - before }));
+    }
+
+    let base_bytes = payload.get(..8).ok_or("Positional payload is missing its base sequence")?;
+    let base = u64::from_le_bytes(base_bytes.try_into().unwrap());
+    if base > current {
+        return Err(format!("Base sequence {} is ahead of the document ({})", base, current));
+    }
+    let position = match selector {
+        Some(ParamGroupValues::ArraySet(params)) => match params.as_slice() {
+            [ArrayParam::Index(i)] => *i as usize,
+            _ => return Err(format!("{:?} expects a single index", op)),
+        },
+        _ => return Err(format!("{:?} expects an array selector", op)),
+    };
+    let single = |at: usize| ParamGroupValues::ArraySet(vec![ArrayParam::Index(at as u32)]);
+
+    match op {
+        DeltaOp::InsertAt => {
+            let at = log.transform_gap(base, position)?.min(before);
+            value.apply(DeltaOp::Insert, Some(&single(at)), &payload[8..])?;
+            Ok(Some(PositionalEdit::Insert { at, count: value.len() - before }))
+        }
+        DeltaOp::ReplaceAt | DeltaOp::DeleteAt => {
+            let Some(at) = log.transform_element(base, position)? else {
+                return Ok(None);
+            };
+            if at >= before {
+                return Err(format!("{:?} position {} is past the end ({})", op, at, before));
+            }
+            if op == DeltaOp::ReplaceAt {
+                value.apply(DeltaOp::SliceUpdate, Some(&single(at)), &payload[8..])?;
+                Ok(Some(PositionalEdit::Replace { at }))
+            } else {
+                if payload.len() != 8 {
+                    return Err("DeleteAt carries no value".to_string());
+                }
+                value.apply(DeltaOp::Remove, Some(&single(at)), &[])?;
+                Ok(Some(PositionalEdit::Delete { at }))
+            }
+        }
+        _ => Err(format!("{:?} is not a positional operation", op)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_transform() {
+        let mut log = PositionalLog::default();
+        log.record(2, PositionalEdit::Insert { at: 1, count: 2 });
+        log.record(3, PositionalEdit::Delete { at: 0 });
+
+        assert_eq!(log.transform_gap(1, 0).unwrap(), 0);
+        // Concurrent insert at the same point goes first
+        assert_eq!(log.transform_gap(1, 1).unwrap(), 2);
+        assert_eq!(log.transform_gap(1, 5).unwrap(), 6);
+        // Edits at or before the base are already reflected
+        assert_eq!(log.transform_gap(3, 5).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_element_transform() {
+        let mut log = PositionalLog::default();
+        log.record(2, PositionalEdit::Delete { at: 1 });
+        log.record(3, PositionalEdit::Replace { at: 0 });
+
+        assert_eq!(log.transform_element(1, 0).unwrap(), Some(0));
+        assert_eq!(log.transform_element(1, 1).unwrap(), None);
+        assert_eq!(log.transform_element(1, 2).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_history_horizon() {
+        let mut log = PositionalLog::default();
+        for sequence in 1..=(MAX_POSITIONAL_HISTORY as u64 + 1) {
+            log.record(sequence, PositionalEdit::Replace { at: 0 });
+        }
+        assert!(log.transform_gap(0, 0).is_err());
+        assert!(log.transform_gap(1, 0).is_ok());
+
+        log.reset(5_000);
+        assert!(log.transform_element(4_999, 0).is_err());
+        assert_eq!(log.transform_element(5_000, 3).unwrap(), Some(3));
+    }
+}
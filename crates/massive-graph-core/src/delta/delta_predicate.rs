@@ -0,0 +1,388 @@
+//! Compact binary predicates for `InsertWhere`, `ReplaceWhere` and `DeleteWhere`
+//!
+//! A predicate is a prefix-encoded tree evaluated against each element of a collection
+//! (or each entry of a Map/Object):
+//! ```text
+//! 0x01 AND     [count: u8][predicate]...
+//! 0x02 OR      [count: u8][predicate]...
+//! 0x03 NOT     [predicate]
+//! 0x10 COMPARE [comparison: u8][target][value]
+//!
+//! target:      0x00 element | 0x01 entry key | 0x02 [depth: u8]([key length][UTF-8 key])...
+//! comparison:  0x00 = | 0x01 != | 0x02 < | 0x03 <= | 0x04 > | 0x05 >=
+//! value:       encoded wire value
+//! ```
+//! The field target walks Map/Object elements by key, so `weight < 0.1` over an array of
+//! edge objects is `COMPARE < [field "weight"] Float(0.1)`. Entry keys compare as Strings.
+//!
+//! Int and Float compare numerically with each other; Strings and Binary compare
+//! bytewise; Timestamps compare chronologically and Bools order `false` before `true`.
+//! Other types only support `=` and `!=`. A comparison whose target is missing or whose
+//! types cannot be ordered is false.
+
+use std::cmp::Ordering;
+
+use crate::types::varint::{decode_varint, encode_varint};
+use crate::types::{Value, ValueType};
+
+/// Deepest nesting of AND/OR/NOT accepted on the wire
+pub const MAX_PREDICATE_DEPTH: usize = 32;
+
+const TAG_AND: u8 = 0x01;
+const TAG_OR: u8 = 0x02;
+const TAG_NOT: u8 = 0x03;
+const TAG_COMPARE: u8 = 0x10;
+
+/// What a comparison looks at
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// The element value itself
+    Element,
+    /// The entry key (Map and Object collections only)
+    Key,
+    /// A value nested inside Map/Object elements, one key per level
+    Field(Vec<String>),
+}
+
+/// Comparison operator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Equal
+    Eq = 0,
+    /// Not equal
+    Ne = 1,
+    /// Less than
+    Lt = 2,
+    /// Less than or equal
+    Le = 3,
+    /// Greater than
+    Gt = 4,
+    /// Greater than or equal
+    Ge = 5,
+}
+
+impl TryFrom<u8> for Comparison {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Comparison::Eq,
+            1 => Comparison::Ne,
+            2 => Comparison::Lt,
+            3 => Comparison::Le,
+            4 => Comparison::Gt,
+            5 => Comparison::Ge,
+            _ => return Err(format!("Invalid comparison {}", value)),
+        })
+    }
+}
+
+/// Decoded predicate tree
+#[derive(Clone, Debug, PartialEq)]
+pub enum Predicate {
+    /// Every child matches
+    And(Vec<Predicate>),
+    /// Any child matches
+    Or(Vec<Predicate>),
+    /// The child does not match
+    Not(Box<Predicate>),
+    /// Compare a target against an encoded value
+    Compare {
+        /// Value compared
+        target: Target,
+        /// Operator
+        comparison: Comparison,
+        /// Encoded wire value on the right-hand side
+        value: Vec<u8>,
+    },
+}
+
+impl Predicate {
+    /// Comparison against an encoded value
+    pub fn compare(target: Target, comparison: Comparison, value: Vec<u8>) -> Self {
+        Predicate::Compare { target, comparison, value }
+    }
+
+    /// Decode a predicate from the start of `bytes`, returning it and the bytes read
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), String> {
+        Self::decode_at(bytes, 0)
+    }
+
+    fn decode_at(bytes: &[u8], depth: usize) -> Result<(Self, usize), String> {
+        if depth > MAX_PREDICATE_DEPTH {
+            return Err("Predicate nested too deeply".to_string());
+        }
+        let truncated = || "Truncated predicate".to_string();
+        let (&tag, _) = bytes.split_first().ok_or_else(truncated)?;
+        match tag {
+            TAG_AND | TAG_OR => {
+                let count = *bytes.get(1).ok_or_else(truncated)?;
+                let mut read = 2;
+                let mut children = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let (child, len) = Self::decode_at(&bytes[read..], depth + 1)?;
+                    children.push(child);
+                    read += len;
+                }
+                Ok((if tag == TAG_AND { Predicate::And(children) } else { Predicate::Or(children) }, read))
+            }
+            TAG_NOT => {
+                let (child, len) = Self::decode_at(&bytes[1..], depth + 1)?;
+                Ok((Predicate::Not(Box::new(child)), 1 + len))
+            }
+            TAG_COMPARE => {
+                let comparison = Comparison::try_from(*bytes.get(1).ok_or_else(truncated)?)?;
+                let mut read = 3;
+                let target = match *bytes.get(2).ok_or_else(truncated)? {
+                    0x00 => Target::Element,
+                    0x01 => Target::Key,
+                    0x02 => {
+                        let depth = *bytes.get(read).ok_or_else(truncated)?;
+                        read += 1;
+                        let mut keys = Vec::with_capacity(depth as usize);
+                        for _ in 0..depth {
+                            let (len, n) = decode_varint(&bytes[read..]).map_err(|e| e.to_string())?;
+                            let key = bytes.get(read + n..read + n + len as usize).ok_or_else(truncated)?;
+                            keys.push(std::str::from_utf8(key).map_err(|_| "Predicate key is not UTF-8")?.to_string());
+                            read += n + len as usize;
+                        }
+                        Target::Field(keys)
+                    }
+                    other => return Err(format!("Invalid predicate target {}", other)),
+                };
+                let len = Value::encoded_len(&bytes[read..]).map_err(|e| e.to_string())?;
+                let value = bytes[read..read + len].to_vec();
+                Ok((Predicate::Compare { target, comparison, value }, read + len))
+            }
+            other => Err(format!("Invalid predicate tag {}", other)),
+        }
+    }
+
+    /// Append the wire encoding
+    pub fn encode(&self, output: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            Predicate::And(children) | Predicate::Or(children) => {
+                output.push(if matches!(self, Predicate::And(_)) { TAG_AND } else { TAG_OR });
+                output.push(u8::try_from(children.len()).map_err(|_| "Too many predicate children".to_string())?);
+                children.iter().try_for_each(|child| child.encode(output))?;
+            }
+            Predicate::Not(child) => {
+                output.push(TAG_NOT);
+                child.encode(output)?;
+            }
+            Predicate::Compare { target, comparison, value } => {
+                output.extend_from_slice(&[TAG_COMPARE, *comparison as u8]);
+                match target {
+                    Target::Element => output.push(0x00),
+                    Target::Key => output.push(0x01),
+                    Target::Field(keys) => {
+                        output.push(0x02);
+                        output.push(u8::try_from(keys.len()).map_err(|_| "Field path too deep".to_string())?);
+                        for key in keys {
+                            encode_varint(key.len() as u32, output)?;
+                            output.extend_from_slice(key.as_bytes());
+                        }
+                    }
+                }
+                if Value::encoded_len(value).ok() != Some(value.len()) {
+                    return Err("Predicate value is not a single encoded value".to_string());
+                }
+                output.extend_from_slice(value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an element (with its entry key, for Map/Object) matches
+    pub fn matches(&self, key: Option<&str>, element: &[u8]) -> bool {
+        match self {
+            Predicate::And(children) => children.iter().all(|c| c.matches(key, element)),
+            Predicate::Or(children) => children.iter().any(|c| c.matches(key, element)),
+            Predicate::Not(child) => !child.matches(key, element),
+            Predicate::Compare { target, comparison, value } => {
+                let actual = match target {
+                    Target::Element => Some(element.to_vec()),
+                    Target::Key => key.and_then(|k| {
+                        let mut encoded = Vec::with_capacity(k.len() + 4);
+                        Value::encode(ValueType::String, k.as_bytes(), &mut encoded).ok()?;
+                        Some(encoded)
+                    }),
+                    Target::Field(path) => lookup(element, path).map(<[u8]>::to_vec),
+                };
+                actual.is_some_and(|actual| comparison.holds(relate(&actual, value)))
+            }
+        }
+    }
+}
+
+/// How two encoded values relate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Relation {
+    /// Comparable values
+    Ordered(Ordering),
+    /// Equal values without an order
+    Equal,
+    /// Different values without an order
+    Unequal,
+    /// Not comparable at all (NaN)
+    Incomparable,
+}
+
+impl Comparison {
+    /// Whether the comparison holds for a relation
+    fn holds(self, relation: Relation) -> bool {
+        match relation {
+            Relation::Ordered(ordering) => match self {
+                Comparison::Eq => ordering == Ordering::Equal,
+                Comparison::Ne => ordering != Ordering::Equal,
+                Comparison::Lt => ordering == Ordering::Less,
+                Comparison::Le => ordering != Ordering::Greater,
+                Comparison::Gt => ordering == Ordering::Greater,
+                Comparison::Ge => ordering != Ordering::Less,
+            },
+            Relation::Equal => self == Comparison::Eq,
+            Relation::Unequal => self == Comparison::Ne,
+            Relation::Incomparable => false,
+        }
+    }
+}
+
+/// Value nested inside Map/Object elements, read in place
+fn lookup<'a>(element: &'a [u8], path: &[String]) -> Option<&'a [u8]> {
+    let mut current = Value::from_bytes(element).ok()?;
+    for key in path {
+        current = current.get_key(key).ok()??;
+    }
+    Some(current.encoded())
+}
+
+/// Data bytes of an encoded value (after type and length)
+fn data(bytes: &[u8]) -> Option<&[u8]> {
+    match ValueType::tag_fixed_size(*bytes.first()?) {
+        Some(_) => bytes.get(1..),
+        None => bytes.get(1 + decode_varint(bytes.get(1..)?).ok()?.1..),
+    }
+}
+
+/// Relate two encoded values
+fn relate(left: &[u8], right: &[u8]) -> Relation {
+    let int = |bytes: &[u8]| (bytes[0] == ValueType::Int as u8).then(|| data(bytes)?.try_into().ok().map(i64::from_le_bytes)).flatten();
+    let float = |bytes: &[u8]| match bytes[0] {
+        t if t == ValueType::Float as u8 => data(bytes)?.try_into().ok().map(f64::from_le_bytes),
+        _ => int(bytes).map(|v| v as f64),
+    };
+    if let (Some(l), Some(r)) = (int(left), int(right)) {
+        return Relation::Ordered(l.cmp(&r));
+    }
+    if let (Some(l), Some(r)) = (float(left), float(right)) {
+        return l.partial_cmp(&r).map_or(Relation::Incomparable, Relation::Ordered);
+    }
+
+    let (Some(ld), Some(rd)) = (data(left), data(right)) else {
+        return Relation::Incomparable;
+    };
+    if left[0] != right[0] {
+        return Relation::Unequal;
+    }
+    match left[0] {
+        t if t == ValueType::Timestamp as u8 => match (<[u8; 8]>::try_from(ld), <[u8; 8]>::try_from(rd)) {
+            (Ok(l), Ok(r)) => Relation::Ordered(u64::from_le_bytes(l).cmp(&u64::from_le_bytes(r))),
+            _ => Relation::Incomparable,
+        },
+        t if t == ValueType::String as u8 || t == ValueType::Binary as u8 || t == ValueType::Bool as u8 => {
+            Relation::Ordered(ld.cmp(rd))
+        }
+        _ if ld == rd => Relation::Equal,
+        _ => Relation::Unequal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValueWriter;
+
+    fn int(v: i64) -> Vec<u8> {
+        let mut out = Vec::new();
+        Value::encode(ValueType::Int, &v.to_le_bytes(), &mut out).unwrap();
+        out
+    }
+
+    fn float(v: f64) -> Vec<u8> {
+        let mut out = Vec::new();
+        Value::encode(ValueType::Float, &v.to_le_bytes(), &mut out).unwrap();
+        out
+    }
+
+    fn string(v: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        Value::encode(ValueType::String, v.as_bytes(), &mut out).unwrap();
+        out
+    }
+
+    /// Object element `{ weight: <float> }`
+    fn edge(weight: f64) -> Vec<u8> {
+        let mut writer = ValueWriter::new();
+        writer.entries(ValueType::Object, [("weight", float(weight))]).unwrap();
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let predicate = Predicate::And(vec![
+            Predicate::compare(Target::Field(vec!["weight".to_string()]), Comparison::Lt, float(0.1)),
+            Predicate::Not(Box::new(Predicate::Or(vec![
+                Predicate::compare(Target::Key, Comparison::Eq, string("keep")),
+                Predicate::compare(Target::Element, Comparison::Ge, int(3)),
+            ]))),
+        ]);
+        let mut bytes = Vec::new();
+        predicate.encode(&mut bytes).unwrap();
+        assert_eq!(Predicate::decode(&bytes).unwrap(), (predicate, bytes.len()));
+        for len in 0..bytes.len() {
+            assert!(Predicate::decode(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_rejects_deep_nesting() {
+        let mut bytes = vec![TAG_NOT; MAX_PREDICATE_DEPTH + 1];
+        Predicate::compare(Target::Element, Comparison::Eq, int(1)).encode(&mut bytes).unwrap();
+        assert!(Predicate::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_field_comparison() {
+        let light = Predicate::compare(Target::Field(vec!["weight".to_string()]), Comparison::Lt, float(0.1));
+        assert!(light.matches(None, &edge(0.05)));
+        assert!(!light.matches(None, &edge(0.5)));
+        // Missing field or non-object element
+        assert!(!light.matches(None, &int(0)));
+    }
+
+    #[test]
+    fn test_value_relations() {
+        let eq = |l: &[u8], r: &[u8], c| Predicate::compare(Target::Element, c, r.to_vec()).matches(None, l);
+        assert!(eq(&int(2), &float(2.5), Comparison::Lt));
+        assert!(eq(&int(i64::MAX), &int(i64::MAX - 1), Comparison::Gt));
+        assert!(eq(&string("abc"), &string("abd"), Comparison::Lt));
+        assert!(eq(&string("1"), &int(1), Comparison::Ne));
+        assert!(!eq(&string("1"), &int(1), Comparison::Lt));
+        assert!(!eq(&float(f64::NAN), &float(f64::NAN), Comparison::Eq));
+
+        let encoded = |f: &dyn Fn(&mut ValueWriter)| {
+            let mut writer = ValueWriter::new();
+            f(&mut writer);
+            writer.into_bytes()
+        };
+        assert!(eq(&encoded(&|w| { w.bool(false); }), &encoded(&|w| { w.bool(true); }), Comparison::Lt));
+        assert!(eq(&encoded(&|w| { w.timestamp(1 << 40); }), &encoded(&|w| { w.timestamp(2); }), Comparison::Gt));
+        // Unordered types only support equality
+        let null = encoded(&|w| { w.null(); });
+        assert!(eq(&null, &null, Comparison::Eq) && !eq(&null, &null, Comparison::Le));
+
+        let key = Predicate::compare(Target::Key, Comparison::Eq, string("a"));
+        assert!(key.matches(Some("a"), &int(0)));
+        assert!(!key.matches(None, &int(0)));
+    }
+}
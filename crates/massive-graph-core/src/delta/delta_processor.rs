@@ -1,52 +1,1060 @@
 //! Delta processor implementation
-//! 
+//!
 //! This module contains the core logic for processing delta operations
 //! against the document storage.
+//!
+//! `DeltaOp::Deltas` groups are applied all-or-nothing: the target journals what the
+//! nested deltas change and undoes it if one of them fails. `SchemaDocument` records the
+//! prior value of each slot the group touches, so a group costs what it changes rather
+//! than a copy of the document. Nested deltas take consecutive sequence numbers starting
+//! at the group's header sequence.
+//!
+//! `SchemaDocument` is the target used by document storage: it resolves each delta's
+//! field through the schema registry, expands the field address into concrete slots and
+//! checks values against the field's declared `ValueType`. Numeric operations treat a
+//! missing value as zero and fail, rather than wrap, on integer overflow. Collection
+//! operations treat a missing value as an empty collection (see `delta_collections`);
+//! positional operations also transform positions past concurrent edits (see
+//! `delta_positional`).
+//!
+//! Lifecycle deltas take sequence numbers like any other change: `CreateDocument` starts
+//! a document (or recreates a deleted one), `DeleteDocument` clears it and rejects
+//! further changes until it is recreated, and `CreateSnapshot` records the encoded
+//! state at its sequence so readers can load it instead of replaying every delta. Only
+//! the latest snapshot is kept. A snapshot starts with the document's schema binding,
+//! `[0]` or `[1][SchemaBinding]`, followed by the encoded `DocumentState`.
+//! `CreateDocument` may carry a one-byte `DocumentType`; stream types make the document
+//! an append-only stream that accepts only `StreamAppend` and `StreamMarkAt` (see
+//! `document_stream`), and `Schema` makes it a schema document that accepts only
+//! `CreateSchema`. The type byte may be followed by a schema binding, which resolves
+//! the document's fields against a schema document (see `schema_directory`). Stream and
+//! schema documents are rebuilt from their deltas, not snapshots.
+//! Hierarchy deltas (`AddChild`, `RemoveChild`, `SetParent`) are maintained by the
+//! store's `DocumentHierarchy`; documents only validate and sequence them.
 
-// Commented out unused import
-// use crate::storage::DocumentStorage;
-
-// TODO: Implement delta processor for POC
-// The following function is commented out as it references types not yet defined
-
-/*
-/// Apply a delta to the storage system
-/// 
-/// This function processes a delta's operations sequentially, applying each
-/// operation to the storage system. Operations are executed in order, and if
-/// any operation fails, the entire delta is considered failed.
-/// 
-/// # Arguments
-/// 
-/// * `storage` - Mutable reference to storage implementing DocumentStorage
-/// * `delta` - The delta containing operations to apply
-/// 
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::{Map, Value as Json};
+
+use crate::delta::delta_collections::{is_collection, selector_group, CollectionValue};
+use crate::delta::delta_positional::{apply_positional, is_positional, PositionalLog};
+use crate::storage::document_state::{DocumentState, FieldSlot};
+use crate::storage::document_stream::DocumentStream;
+use crate::storage::schema_directory::{SchemaBinding, SchemaDefinition, SchemaDirectory};
+use crate::storage::hierarchy::{is_hierarchy_op, payload_doc_id};
+use crate::types::delta::{DeltaPayload, DeltaRef};
+use crate::types::document::DocumentType;
+use crate::types::field::{FieldDescriptor, ParamGroupValues};
+use crate::types::varint::decode_varint;
+use crate::types::{DeltaOp, SchemaRegistry, Value, ValueType};
+use crate::UserId;
+
+/// Document state that deltas are applied to
+pub trait DeltaTarget {
+    /// Apply a single, non-group delta carrying the given sequence number
+    fn apply_one(&mut self, sequence: u64, payload: &DeltaPayload) -> Result<(), String>;
+
+    /// Start recording changes so the group being applied can be undone
+    fn begin_group(&mut self);
+
+    /// Stop recording, undoing every change since `begin_group` unless `commit`
+    fn end_group(&mut self, commit: bool);
+}
+
+/// Apply a stamped delta to a target
+///
 /// # Returns
-/// 
-/// * `Ok(())` if all operations succeeded
-/// * `Err(String)` if any operation failed
-pub fn apply_delta<S: DocumentStorage>(storage: &mut S, delta: &crate::delta::types::Delta) -> Result<(), String> {
-    // TODO: Parse operations from the delta data
-    // This needs to implement the binary protocol parsing that extracts
-    // Operation structs from the delta.data bytes
-    
-    // TODO: Apply each operation sequentially
-    // Each operation type (DocumentCreate, PropertySet, etc.) needs to be
-    // mapped to the appropriate DocumentStorage trait method calls
-    
-    // Placeholder implementation
-    Ok(())
-}
-*/
-
-/// Temporary placeholder function for POC
-/// TODO: Implement actual delta processing
-pub fn apply_delta_placeholder() -> Result<(), String> {
-    Ok(())
-} 
-
-// Commented out until Delta::new() is implemented
-// /// Delta factory to create a delta from scratch
-// pub fn create_delta() -> Result<Delta, String> {
-//     Ok(Delta::new())
-// }
\ No newline at end of file
+///
+/// * `Ok(())` if the delta (every nested delta, for groups) was applied
+/// * `Err(String)` if it failed - the target is left unchanged
+pub fn apply_delta<T: DeltaTarget>(target: &mut T, delta: &DeltaRef) -> Result<(), String> {
+    apply_payload(target, delta.server_header().delta_sequence_number, delta.payload())
+}
+
+/// Apply a payload whose first sequence number is `sequence`
+pub fn apply_payload<T: DeltaTarget>(target: &mut T, sequence: u64, payload: &DeltaPayload) -> Result<(), String> {
+    if payload.delta_op != DeltaOp::Deltas {
+        return target.apply_one(sequence, payload);
+    }
+
+    let group = payload.group().map_err(|e| e.to_string())?;
+    target.begin_group();
+    let result = (sequence..).zip(group.iter()).try_for_each(|(sequence, child)| {
+        let child_payload = child.payload().ok_or("Nested delta was not validated")?;
+        target
+            .apply_one(sequence, child_payload)
+            .map_err(|e| format!("Group rolled back at sequence {}: {}", sequence, e))
+    });
+    target.end_group(result.is_ok());
+    result
+}
+
+/// Changes made by the group being applied to a `SchemaDocument`
+#[derive(Clone)]
+struct GroupJournal {
+    /// Sequence before the group
+    sequence: u64,
+    /// Prior value and positional log of each slot, recorded when the group first changes it
+    slots: HashMap<FieldSlot, (Option<Vec<u8>>, Option<PositionalLog>)>,
+    /// Document-wide fields, saved before the group's first lifecycle, stream or schema delta
+    document: Option<Box<SavedDocument>>,
+}
+
+/// Document-wide fields of a `SchemaDocument`
+#[derive(Clone)]
+struct SavedDocument {
+    schema: Arc<SchemaRegistry>,
+    positional_horizon: u64,
+    deleted: bool,
+    snapshot: Option<(u64, Arc<Vec<u8>>)>,
+    stream: Option<DocumentStream>,
+    definition: Option<SchemaDefinition>,
+    binding: Option<SchemaBinding>,
+    /// Values and positional logs cleared by a DeleteDocument in the group
+    cleared: Option<(DocumentState, HashMap<FieldSlot, PositionalLog>)>,
+}
+
+/// Document values typed by a schema registry
+#[derive(Clone)]
+pub struct SchemaDocument {
+    /// Schema resolving field indices to descriptors
+    schema: Arc<SchemaRegistry>,
+    /// Current values
+    state: DocumentState,
+    /// Positional edit history of array slots
+    positional: HashMap<FieldSlot, PositionalLog>,
+    /// Sequence before which no positional history exists (restore or recreation point)
+    positional_horizon: u64,
+    /// Whether a DeleteDocument delta was the last lifecycle change
+    deleted: bool,
+    /// Latest encoded state recorded by CreateSnapshot, with its sequence
+    snapshot: Option<(u64, Arc<Vec<u8>>)>,
+    /// Entries and marks, for stream documents
+    stream: Option<DocumentStream>,
+    /// Published fields, for schema documents
+    definition: Option<SchemaDefinition>,
+    /// Schema document the fields resolve against, if bound
+    binding: Option<SchemaBinding>,
+    /// Owner of the document, whose schema documents it may bind to
+    directory: Option<(UserId, Arc<SchemaDirectory>)>,
+    /// Undo journal of the group being applied
+    journal: Option<GroupJournal>,
+}
+
+impl SchemaDocument {
+    /// Create an empty document typed by `schema`
+    pub fn new(schema: Arc<SchemaRegistry>) -> Self {
+        Self::from_state(schema, DocumentState::new())
+    }
+
+    /// Resume a document from a decoded snapshot
+    pub fn from_state(schema: Arc<SchemaRegistry>, state: DocumentState) -> Self {
+        Self {
+            schema,
+            positional_horizon: state.sequence(),
+            state,
+            positional: HashMap::new(),
+            deleted: false,
+            snapshot: None,
+            stream: None,
+            definition: None,
+            binding: None,
+            directory: None,
+            journal: None,
+        }
+    }
+
+    /// Resume a document from a snapshot recorded by `CreateSnapshot`, binding it again
+    /// to its schema document through `directory`
+    pub fn from_snapshot(
+        schema: Arc<SchemaRegistry>,
+        owner: UserId,
+        directory: Arc<SchemaDirectory>,
+        snapshot: &[u8],
+    ) -> Result<Self, String> {
+        let (binding, state) = match snapshot.split_first() {
+            Some((0, state)) => (None, state),
+            Some((1, rest)) if rest.len() >= SchemaBinding::ENCODED_SIZE => {
+                let (binding, state) = rest.split_at(SchemaBinding::ENCODED_SIZE);
+                (Some(SchemaBinding::decode(binding)?), state)
+            }
+            _ => return Err("Snapshot is missing its schema binding".to_string()),
+        };
+        let state = DocumentState::decode(state).map_err(|e| e.to_string())?;
+        let mut document = Self::from_state(schema, state);
+        if let Some(binding) = binding {
+            document.schema = directory.resolve(owner, &binding)?;
+            document.binding = Some(binding);
+        }
+        Ok(document.with_directory(owner, directory))
+    }
+
+    /// Resolve schema bindings in `CreateDocument` deltas against the schema documents
+    /// `owner` registered in `directory`
+    pub fn with_directory(mut self, owner: UserId, directory: Arc<SchemaDirectory>) -> Self {
+        self.directory = Some((owner, directory));
+        self
+    }
+
+    /// Schema the document is typed by
+    pub fn schema(&self) -> &Arc<SchemaRegistry> {
+        &self.schema
+    }
+
+    /// Current values
+    pub fn state(&self) -> &DocumentState {
+        &self.state
+    }
+
+    /// Current values as JSON keyed by concrete path (see `FieldSlot::concrete_path`)
+    pub fn properties(&self) -> Result<Map<String, Json>, String> {
+        let schema = self.schema.current_version().snapshot();
+        let mut properties = Map::new();
+        for (slot, value) in self.state.iter() {
+            let descriptor = schema
+                .index_of_canonical(slot.field_index)
+                .and_then(|index| schema.get_field(index))
+                .ok_or_else(|| format!("Field {} is not in the current schema", slot.field_index))?;
+            let value = Value::from_bytes(value).map_err(|e| e.to_string())?;
+            properties.insert(slot.concrete_path(&descriptor.path)?, value.to_json()?);
+        }
+        Ok(properties)
+    }
+
+    /// Whether the document has been deleted
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    /// Latest snapshot recorded by CreateSnapshot as (sequence, binding and encoded state)
+    pub fn latest_snapshot(&self) -> Option<&(u64, Arc<Vec<u8>>)> {
+        self.snapshot.as_ref()
+    }
+
+    /// Type the document was created with
+    pub fn doc_type(&self) -> DocumentType {
+        match (&self.stream, &self.definition) {
+            (Some(stream), _) => stream.doc_type(),
+            (None, Some(_)) => DocumentType::Schema,
+            (None, None) => DocumentType::Tree,
+        }
+    }
+
+    /// Schema document the fields resolve against, if bound
+    pub fn binding(&self) -> Option<SchemaBinding> {
+        self.binding
+    }
+
+    /// Registry of fields published by a schema document
+    pub fn defined_schema(&self) -> Option<&Arc<SchemaRegistry>> {
+        self.definition.as_ref().map(|definition| definition.registry())
+    }
+
+    /// Entries and marks, for stream documents
+    pub fn stream(&self) -> Option<&DocumentStream> {
+        self.stream.as_ref()
+    }
+
+    /// Make stream entries and schema fields staged by `delta`, the stamped delta just
+    /// applied, visible to readers
+    pub fn publish(&mut self, delta: &Arc<Vec<u8>>) -> Result<(), String> {
+        if let Some(definition) = self.definition.as_mut() {
+            definition.publish();
+        }
+        self.stream.as_mut().map_or(Ok(()), |stream| stream.publish(delta))
+    }
+
+    /// Apply a lifecycle delta, returning `false` if `op` is not one
+    fn apply_lifecycle(&mut self, sequence: u64, payload: &DeltaPayload) -> Result<bool, String> {
+        let op = payload.delta_op;
+        if !matches!(op, DeltaOp::CreateDocument | DeltaOp::DeleteDocument | DeltaOp::CreateSnapshot) {
+            return Ok(false);
+        }
+        let value = payload.payload_value;
+        if !value.is_empty() && op != DeltaOp::CreateDocument {
+            return Err(format!("{:?} carries no value", op));
+        }
+        self.save_document();
+        match op {
+            DeltaOp::CreateDocument if !self.deleted && self.state.sequence() > 0 => {
+                return Err("Document already exists".to_string());
+            }
+            DeltaOp::CreateDocument => {
+                let doc_type = match value.first() {
+                    Some(&tag) => DocumentType::try_from(tag).map_err(|e| e.to_string())?,
+                    None => DocumentType::Tree,
+                };
+                if value.len() > 1 {
+                    let binding = SchemaBinding::decode(&value[1..])?;
+                    let (owner, directory) = self.directory.as_ref().ok_or("Schema bindings need a schema directory")?;
+                    self.schema = directory.resolve(*owner, &binding)?;
+                    self.binding = Some(binding);
+                }
+                self.stream = if doc_type.is_stream() { Some(DocumentStream::new(doc_type)?) } else { None };
+                self.definition = (doc_type == DocumentType::Schema).then(SchemaDefinition::new);
+                self.deleted = false;
+            }
+            _ if self.deleted => return Err("Document has been deleted".to_string()),
+            DeltaOp::DeleteDocument => {
+                let state = std::mem::take(&mut self.state);
+                let positional = std::mem::take(&mut self.positional);
+                if let Some(saved) = self.journal.as_mut().and_then(|journal| journal.document.as_mut()) {
+                    saved.cleared.get_or_insert((state, positional));
+                }
+                self.positional_horizon = sequence;
+                self.stream = None;
+                self.definition = None;
+                self.deleted = true;
+            }
+            DeltaOp::CreateSnapshot if self.stream.is_some() || self.definition.is_some() => {
+                return Err("Stream and schema documents are rebuilt from their deltas, not snapshots".to_string());
+            }
+            _ => {}
+        }
+        self.state.set_sequence(sequence);
+        if op == DeltaOp::CreateSnapshot {
+            self.snapshot = Some((sequence, Arc::new(self.encode_snapshot()?)));
+        }
+        Ok(true)
+    }
+
+    /// Schema binding followed by the encoded state
+    fn encode_snapshot(&self) -> Result<Vec<u8>, String> {
+        let mut snapshot = Vec::new();
+        match self.binding {
+            Some(binding) => {
+                snapshot.push(1);
+                binding.encode_into(&mut snapshot);
+            }
+            None => snapshot.push(0),
+        }
+        snapshot.extend(self.state.encode()?);
+        Ok(snapshot)
+    }
+
+    /// Save the document-wide fields into the group journal, if recording and not yet saved
+    fn save_document(&mut self) {
+        let Some(journal) = self.journal.as_mut() else { return };
+        journal.document.get_or_insert_with(|| {
+            Box::new(SavedDocument {
+                schema: self.schema.clone(),
+                positional_horizon: self.positional_horizon,
+                deleted: self.deleted,
+                snapshot: self.snapshot.clone(),
+                stream: self.stream.clone(),
+                definition: self.definition.clone(),
+                binding: self.binding,
+                cleared: None,
+            })
+        });
+    }
+
+    /// Record the prior contents of `slot` in the group journal, if recording
+    fn save_slot(&mut self, slot: &FieldSlot) {
+        let Some(journal) = self.journal.as_mut() else { return };
+        // Slots cleared by a DeleteDocument come back with the cleared state
+        if journal.document.as_ref().is_some_and(|saved| saved.cleared.is_some()) || journal.slots.contains_key(slot) {
+            return;
+        }
+        let prior = (self.state.get(slot).map(<[u8]>::to_vec), self.positional.get(slot).cloned());
+        journal.slots.insert(slot.clone(), prior);
+    }
+
+    /// Descriptor of the addressed field, every slot the address selects and the
+    /// element selector trailing the field's own parameter groups, if the operation takes one
+    fn resolve(&self, payload: &DeltaPayload) -> Result<(FieldDescriptor, Vec<FieldSlot>, Option<ParamGroupValues>), String> {
+        let address = &payload.field_address;
+        let version = address.schema_version as u32;
+        let descriptor = self
+            .schema
+            .get_field_at_version(address.field_index, version)
+            .ok_or_else(|| format!("Unknown field {} at schema version {}", address.field_index, address.schema_version))?;
+        // Slots are keyed by canonical index so reordered schema versions address the same state
+        let field_index = self.schema.canonical_index(address.field_index, version).unwrap_or(address.field_index);
+        self.schema.record_access(address.field_index, version);
+
+        let selector_type = selector_group(payload.delta_op, descriptor.value_type);
+        let has_selector = selector_type.is_some();
+        let mut groups = descriptor.param_groups.clone();
+        groups.extend(selector_type);
+        let mut params = address.decode_params(&groups).map_err(|e| e.to_string())?;
+        let selector = if has_selector { params.groups.pop() } else { None };
+
+        let slots = FieldSlot::expand(field_index, &params)?;
+        Ok((descriptor, slots, selector))
+    }
+
+    /// Apply a field operation
+    fn apply_field(&mut self, sequence: u64, payload: &DeltaPayload) -> Result<(), String> {
+        let (descriptor, slots, selector) = self.resolve(payload)?;
+        let value_type = descriptor.value_type;
+        let mut edits = Vec::new();
+
+        // Compute every new value before touching the state so a failing slot leaves it unchanged
+        let updates: Vec<(FieldSlot, Option<Vec<u8>>)> = match payload.delta_op {
+            DeltaOp::Set => {
+                let value = typed_value(value_type, payload.payload_value)?;
+                if is_collection(value_type) {
+                    CollectionValue::decode(value_type, value)?;
+                }
+                slots.into_iter().map(|slot| (slot, Some(value.to_vec()))).collect()
+            }
+            DeltaOp::Delete => slots.into_iter().map(|slot| (slot, None)).collect(),
+            op @ (DeltaOp::Increment | DeltaOp::Multiply | DeltaOp::Modulus | DeltaOp::Power) => {
+                let operand = Number::read(value_type, payload.payload_value)?;
+                slots
+                    .into_iter()
+                    .map(|slot| {
+                        let current = match self.state.get(&slot) {
+                            Some(bytes) => Number::read(value_type, bytes)?,
+                            None => Number::zero(value_type),
+                        };
+                        Ok((slot, Some(current.apply(op, operand)?.encode())))
+                    })
+                    .collect::<Result<_, String>>()?
+            }
+            op @ (DeltaOp::Append
+            | DeltaOp::Insert
+            | DeltaOp::Remove
+            | DeltaOp::Splice
+            | DeltaOp::Clear
+            | DeltaOp::SliceUpdate
+            | DeltaOp::Reshape
+            | DeltaOp::InsertWhere
+            | DeltaOp::ReplaceWhere
+            | DeltaOp::DeleteWhere) => {
+                if !is_collection(value_type) {
+                    return Err(format!("{:?} on {:?} field", op, value_type));
+                }
+                slots
+                    .into_iter()
+                    .map(|slot| {
+                        let mut value = match self.state.get(&slot) {
+                            Some(bytes) => CollectionValue::decode(value_type, bytes)?,
+                            None => CollectionValue::empty(value_type),
+                        };
+                        value.apply(op, selector.as_ref(), payload.payload_value)?;
+                        Ok((slot, Some(value.encode()?)))
+                    })
+                    .collect::<Result<_, String>>()?
+            }
+            op @ (DeltaOp::Prepend | DeltaOp::InsertAt | DeltaOp::ReplaceAt | DeltaOp::DeleteAt) => {
+                if value_type != ValueType::Array {
+                    return Err(format!("{:?} on {:?} field", op, value_type));
+                }
+                let empty = PositionalLog::with_horizon(self.positional_horizon);
+                slots
+                    .into_iter()
+                    .map(|slot| {
+                        let mut value = match self.state.get(&slot) {
+                            Some(bytes) => CollectionValue::decode(value_type, bytes)?,
+                            None => CollectionValue::empty(value_type),
+                        };
+                        let log = self.positional.get(&slot).unwrap_or(&empty);
+                        let current = self.state.sequence();
+                        if let Some(edit) = apply_positional(&mut value, log, op, current, selector.as_ref(), payload.payload_value)? {
+                            edits.push((slot.clone(), edit));
+                        }
+                        Ok((slot, Some(value.encode()?)))
+                    })
+                    .collect::<Result<_, String>>()?
+            }
+            op => return Err(format!("Unsupported operation {:?}", op)),
+        };
+
+        // Structural edits that positions cannot be transformed through
+        let resets_positions =
+            value_type == ValueType::Array && !is_positional(payload.delta_op) && payload.delta_op != DeltaOp::Append;
+        for (slot, _) in &updates {
+            self.save_slot(slot);
+        }
+        let horizon = self.positional_horizon;
+        for (slot, edit) in edits {
+            self.positional.entry(slot).or_insert_with(|| PositionalLog::with_horizon(horizon)).record(sequence, edit);
+        }
+        for (slot, value) in updates {
+            if resets_positions {
+                self.positional.entry(slot.clone()).or_default().reset(sequence);
+            }
+            match value {
+                Some(value) => self.state.set(slot, value),
+                None => {
+                    self.state.remove(&slot);
+                }
+            }
+        }
+        self.state.set_sequence(sequence);
+        Ok(())
+    }
+}
+
+impl DeltaTarget for SchemaDocument {
+    fn apply_one(&mut self, sequence: u64, payload: &DeltaPayload) -> Result<(), String> {
+        if sequence <= self.state.sequence() {
+            return Err(format!("Sequence {} is not after {}", sequence, self.state.sequence()));
+        }
+        if self.apply_lifecycle(sequence, payload)? {
+            return Ok(());
+        }
+        if self.deleted {
+            return Err("Document has been deleted".to_string());
+        }
+        if is_hierarchy_op(payload.delta_op) {
+            payload_doc_id(payload.delta_op, payload.payload_value)?;
+            self.state.set_sequence(sequence);
+            return Ok(());
+        }
+        if self.stream.is_some() || self.definition.is_some() {
+            self.save_document();
+        }
+        match (payload.delta_op, self.stream.as_mut(), self.definition.as_mut()) {
+            (DeltaOp::StreamAppend, Some(stream), _) => stream.append(payload.payload_value)?,
+            (DeltaOp::StreamMarkAt, Some(stream), _) => stream.mark_at(payload.payload_value)?,
+            (DeltaOp::CreateSchema, _, Some(definition)) => definition.stage(payload.payload_value)?,
+            (op @ (DeltaOp::StreamAppend | DeltaOp::StreamMarkAt), None, _) => {
+                return Err(format!("{:?} on a document that is not a stream", op));
+            }
+            (DeltaOp::CreateSchema, _, None) => return Err("CreateSchema on a document that is not a schema".to_string()),
+            (op, Some(_), _) => return Err(format!("Stream documents do not accept {:?}", op)),
+            (op, _, Some(_)) => return Err(format!("Schema documents do not accept {:?}", op)),
+            (_, None, None) => return self.apply_field(sequence, payload),
+        }
+        self.state.set_sequence(sequence);
+        Ok(())
+    }
+
+    fn begin_group(&mut self) {
+        self.journal = Some(GroupJournal { sequence: self.state.sequence(), slots: HashMap::new(), document: None });
+    }
+
+    fn end_group(&mut self, commit: bool) {
+        let Some(journal) = self.journal.take() else { return };
+        if commit {
+            return;
+        }
+        if let Some(saved) = journal.document {
+            let saved = *saved;
+            self.schema = saved.schema;
+            self.positional_horizon = saved.positional_horizon;
+            self.deleted = saved.deleted;
+            self.snapshot = saved.snapshot;
+            self.stream = saved.stream;
+            self.definition = saved.definition;
+            self.binding = saved.binding;
+            if let Some((state, positional)) = saved.cleared {
+                self.state = state;
+                self.positional = positional;
+            }
+        }
+        for (slot, (value, log)) in journal.slots {
+            match log {
+                Some(log) => self.positional.insert(slot.clone(), log),
+                None => self.positional.remove(&slot),
+            };
+            match value {
+                Some(value) => self.state.set(slot, value),
+                None => {
+                    self.state.remove(&slot);
+                }
+            }
+        }
+        self.state.set_sequence(journal.sequence);
+    }
+}
+
+/// Check that `bytes` is exactly one encoded value of `value_type`
+fn typed_value(value_type: ValueType, bytes: &[u8]) -> Result<&[u8], String> {
+    let (&tag, rest) = bytes.split_first().ok_or("Missing value")?;
+    if tag != value_type as u8 {
+        return Err(format!("Field expects {:?}, got type tag {}", value_type, tag));
+    }
+    let expected = match value_type.fixed_size() {
+        Some(size) => size,
+        None => {
+            let (len, read) = decode_varint(rest).map_err(|e| e.to_string())?;
+            read + len as usize
+        }
+    };
+    if rest.len() != expected {
+        return Err(format!("{:?} value has {} bytes, expected {}", value_type, rest.len(), expected));
+    }
+    Ok(bytes)
+}
+
+/// Operand or stored value of a numeric operation
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    /// Read an encoded value of a numeric field
+    fn read(value_type: ValueType, bytes: &[u8]) -> Result<Self, String> {
+        if !matches!(value_type, ValueType::Int | ValueType::Float) {
+            return Err(format!("Numeric operation on {:?} field", value_type));
+        }
+        let data: [u8; 8] = typed_value(value_type, bytes)?[1..].try_into().unwrap();
+        Ok(match value_type {
+            ValueType::Int => Number::Int(i64::from_le_bytes(data)),
+            _ => Number::Float(f64::from_le_bytes(data)),
+        })
+    }
+
+    /// Value of a field that has never been set
+    fn zero(value_type: ValueType) -> Self {
+        match value_type {
+            ValueType::Float => Number::Float(0.0),
+            _ => Number::Int(0),
+        }
+    }
+
+    /// `self op operand` for a numeric delta operation
+    fn apply(self, op: DeltaOp, operand: Number) -> Result<Self, String> {
+        let overflow = || format!("{:?} overflowed", op);
+        match (self, operand) {
+            (Number::Int(a), Number::Int(b)) => match op {
+                DeltaOp::Increment => a.checked_add(b).map(Number::Int).ok_or_else(overflow),
+                DeltaOp::Multiply => a.checked_mul(b).map(Number::Int).ok_or_else(overflow),
+                DeltaOp::Modulus if b == 0 => Err("Modulus by zero".to_string()),
+                DeltaOp::Modulus => a.checked_rem(b).map(Number::Int).ok_or_else(overflow),
+                DeltaOp::Power => {
+                    let exp = u32::try_from(b).map_err(|_| format!("Invalid integer exponent {}", b))?;
+                    a.checked_pow(exp).map(Number::Int).ok_or_else(overflow)
+                }
+                _ => Err(format!("{:?} is not a numeric operation", op)),
+            },
+            (Number::Float(a), Number::Float(b)) => match op {
+                DeltaOp::Increment => Ok(Number::Float(a + b)),
+                DeltaOp::Multiply => Ok(Number::Float(a * b)),
+                DeltaOp::Modulus if b == 0.0 => Err("Modulus by zero".to_string()),
+                DeltaOp::Modulus => Ok(Number::Float(a % b)),
+                DeltaOp::Power => Ok(Number::Float(a.powf(b))),
+                _ => Err(format!("{:?} is not a numeric operation", op)),
+            },
+            _ => Err("Mixed Int and Float operands".to_string()),
+        }
+    }
+
+    /// Encode as a wire value
+    fn encode(self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(9);
+        match self {
+            Number::Int(v) => {
+                output.push(ValueType::Int as u8);
+                output.extend_from_slice(&v.to_le_bytes());
+            }
+            Number::Float(v) => {
+                output.push(ValueType::Float as u8);
+                output.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::delta::DeltaStamper;
+    use crate::storage::document_state::{DocumentState, SlotKey};
+    use crate::types::delta::{DeltaBuilder, DeltaIncoming, DELTA_SECURE_HEADER_SIZE};
+    use crate::types::field::{ArrayParam, FieldParams};
+    use crate::types::value_json::set_deltas;
+    use crate::types::ValueWriter;
+    use crate::{DocId, UserId};
+
+    /// Records applied deltas; fields listed in `reject` fail
+    #[derive(Default)]
+    struct Recorder {
+        applied: Vec<(u64, u32)>,
+        reject: Vec<u32>,
+        group_start: usize,
+    }
+
+    impl DeltaTarget for Recorder {
+        fn apply_one(&mut self, sequence: u64, payload: &DeltaPayload) -> Result<(), String> {
+            let field = payload.field_address.field_index;
+            if self.reject.contains(&field) {
+                return Err(format!("field {} rejected", field));
+            }
+            self.applied.push((sequence, field));
+            Ok(())
+        }
+
+        fn begin_group(&mut self) {
+            self.group_start = self.applied.len();
+        }
+
+        fn end_group(&mut self, commit: bool) {
+            if !commit {
+                self.applied.truncate(self.group_start);
+            }
+        }
+    }
+
+    /// Stamp a group of Set deltas on the given fields
+    fn stamped_group(stamper: &DeltaStamper, fields: &[u32]) -> Vec<u8> {
+        let children: Vec<Vec<u8>> = fields
+            .iter()
+            .map(|&f| DeltaBuilder::new(DeltaOp::Set).field(0, f).int(f as i64).build().unwrap())
+            .collect();
+        let refs: Vec<&[u8]> = children.iter().map(|c| c.as_slice()).collect();
+        let mut buf = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+        DeltaBuilder::group(&refs).unwrap().build_into(&mut buf).unwrap();
+        stamper.stamp(UserId::random(), DocId::random(), &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_group_applies_every_child_in_sequence() {
+        let stamper = DeltaStamper::new([1; 32]);
+        let buf = stamped_group(&stamper, &[4, 5, 6]);
+        let delta = DeltaRef::from_wire_bytes(&buf).unwrap();
+
+        let mut target = Recorder::default();
+        apply_delta(&mut target, &delta).unwrap();
+        assert_eq!(target.applied, vec![(1, 4), (2, 5), (3, 6)]);
+    }
+
+    #[test]
+    fn test_group_failure_leaves_target_unchanged() {
+        let stamper = DeltaStamper::new([1; 32]);
+        let buf = stamped_group(&stamper, &[4, 5, 6]);
+        let delta = DeltaRef::from_wire_bytes(&buf).unwrap();
+
+        let mut target = Recorder { applied: vec![(0, 1)], reject: vec![6], ..Default::default() };
+        let err = apply_delta(&mut target, &delta).unwrap_err();
+        assert!(err.contains("sequence 3"), "{}", err);
+        assert_eq!(target.applied, vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_single_delta_applies_directly() {
+        let stamper = DeltaStamper::new([1; 32]);
+        let mut buf = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+        DeltaBuilder::new(DeltaOp::Set).field(0, 9).int(1).build_into(&mut buf).unwrap();
+        stamper.stamp(UserId::random(), DocId::random(), &mut buf).unwrap();
+        let delta = DeltaRef::from_wire_bytes(&buf).unwrap();
+
+        let mut target = Recorder::default();
+        apply_delta(&mut target, &delta).unwrap();
+        assert_eq!(target.applied, vec![(1, 9)]);
+    }
+
+    /// Registry with `count: Int`, `ratio: Float`, `name: String`, `users{}.age: Int`,
+    /// `users{}.scores: Array` and `list: Array`
+    fn schema() -> Arc<SchemaRegistry> {
+        let schema = Arc::new(SchemaRegistry::new());
+        for (path, value_type) in [
+            ("count", ValueType::Int),
+            ("ratio", ValueType::Float),
+            ("name", ValueType::String),
+            ("users{}.age", ValueType::Int),
+            ("users{}.scores", ValueType::Array),
+            ("list", ValueType::Array),
+        ] {
+            schema.add_field(FieldDescriptor::new(path.to_string(), value_type).unwrap());
+        }
+        schema
+    }
+
+    /// Apply a built (unstamped) delta as the next sequence number
+    fn apply(doc: &mut SchemaDocument, incoming: Vec<u8>) -> Result<(), String> {
+        let delta = DeltaIncoming::new(&incoming);
+        delta.validate().map_err(|e| e.to_string())?;
+        apply_payload(doc, doc.state().sequence() + 1, delta.payload().unwrap())
+    }
+
+    fn int_at(doc: &SchemaDocument, slot: &FieldSlot) -> Option<i64> {
+        doc.state().get(slot).map(|v| i64::from_le_bytes(v[1..].try_into().unwrap()))
+    }
+
+    #[test]
+    fn test_set_and_delete() {
+        let mut doc = SchemaDocument::new(schema());
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 2).string("ada").build().unwrap()).unwrap();
+        assert_eq!(doc.state().get(&FieldSlot::field(2)), Some(&[16, 3, b'a', b'd', b'a'][..]));
+
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Delete).field(0, 2).build().unwrap()).unwrap();
+        assert!(doc.state().is_empty());
+        assert_eq!(doc.state().sequence(), 2);
+    }
+
+    #[test]
+    fn test_set_enforces_declared_type() {
+        let mut doc = SchemaDocument::new(schema());
+        let err = apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).string("1").build().unwrap()).unwrap_err();
+        assert!(err.contains("expects Int"), "{}", err);
+        // Truncated Int payload
+        assert!(apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).raw_value(&[2, 1, 0]).build().unwrap()).is_err());
+        // Unknown field
+        assert!(apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 9).int(1).build().unwrap()).is_err());
+        assert!(doc.state().is_empty());
+        assert_eq!(doc.state().sequence(), 0);
+    }
+
+    #[test]
+    fn test_properties_round_trip_through_set_deltas() {
+        let schema = schema();
+        let properties = json!({"count": 3, "name": "ada", "users{alice}.age": 36, "users{bob}.scores": [1, 2.5]});
+        let properties = properties.as_object().unwrap();
+        let mut doc = SchemaDocument::new(schema.clone());
+        for delta in set_deltas(&schema, properties).unwrap() {
+            apply(&mut doc, delta).unwrap();
+        }
+        assert_eq!(&doc.properties().unwrap(), properties);
+
+        let unknown = json!({"users.alice.height": 1});
+        assert!(set_deltas(&schema, unknown.as_object().unwrap()).unwrap_err().contains("users.alice.height"));
+    }
+
+    #[test]
+    fn test_numeric_ops() {
+        let mut doc = SchemaDocument::new(schema());
+        let count = FieldSlot::field(0);
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Increment).field(0, 0).int(5).build().unwrap()).unwrap();
+        assert_eq!(int_at(&doc, &count), Some(5));
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Multiply).field(0, 0).int(3).build().unwrap()).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Modulus).field(0, 0).int(4).build().unwrap()).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Power).field(0, 0).int(3).build().unwrap()).unwrap();
+        assert_eq!(int_at(&doc, &count), Some(27));
+
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Increment).field(0, 1).float(1.5).build().unwrap()).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Power).field(0, 1).float(2.0).build().unwrap()).unwrap();
+        let ratio = doc.state().get(&FieldSlot::field(1)).unwrap();
+        assert_eq!(f64::from_le_bytes(ratio[1..].try_into().unwrap()), 2.25);
+    }
+
+    #[test]
+    fn test_numeric_errors_leave_state_unchanged() {
+        let mut doc = SchemaDocument::new(schema());
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(i64::MAX).build().unwrap()).unwrap();
+        let before = doc.state().clone();
+
+        for delta in [
+            DeltaBuilder::new(DeltaOp::Increment).field(0, 0).int(1),
+            DeltaBuilder::new(DeltaOp::Modulus).field(0, 0).int(0),
+            DeltaBuilder::new(DeltaOp::Power).field(0, 0).int(-1),
+            DeltaBuilder::new(DeltaOp::Increment).field(0, 0).float(1.0),
+            DeltaBuilder::new(DeltaOp::Increment).field(0, 2).string("x"),
+        ] {
+            assert!(apply(&mut doc, delta.build().unwrap()).is_err());
+            assert_eq!(doc.state(), &before);
+        }
+    }
+
+    #[test]
+    fn test_keyed_field_expands_to_slots() {
+        let mut doc = SchemaDocument::new(schema());
+        let mut params = FieldParams::new();
+        params.add_keys(vec!["alice".to_string(), "bob".to_string()]);
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Increment).field(0, 3).params(params).int(30).build().unwrap()).unwrap();
+
+        let slot = |k: &str| FieldSlot { field_index: 3, keys: vec![SlotKey::Key(k.to_string())] };
+        assert_eq!(int_at(&doc, &slot("alice")), Some(30));
+        assert_eq!(int_at(&doc, &slot("bob")), Some(30));
+
+        // Missing parameter group
+        assert!(apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 3).int(1).build().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_stale_sequence_rejected() {
+        let mut doc = SchemaDocument::new(schema());
+        let incoming = DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1).build().unwrap();
+        let delta = DeltaIncoming::new(&incoming);
+        delta.validate().unwrap();
+        apply_payload(&mut doc, 5, delta.payload().unwrap()).unwrap();
+        assert!(apply_payload(&mut doc, 5, delta.payload().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_collection_ops_use_trailing_selector() {
+        let mut doc = SchemaDocument::new(schema());
+        let user = |params: &mut FieldParams| params.add_keys(vec!["ada".to_string()]);
+
+        let mut params = FieldParams::new();
+        user(&mut params);
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Append).field(0, 4).params(params).raw_value(&int_array(&[1, 3])).build().unwrap()).unwrap();
+
+        let mut params = FieldParams::new();
+        user(&mut params);
+        params.add_array(vec![ArrayParam::Index(1)]);
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Insert).field(0, 4).params(params).raw_value(&int_array(&[2])).build().unwrap()).unwrap();
+
+        let slot = FieldSlot { field_index: 4, keys: vec![SlotKey::Key("ada".to_string())] };
+        assert_eq!(doc.state().get(&slot), Some(int_array(&[1, 2, 3]).as_slice()));
+
+        // Insert without its selector, and collection ops on scalar fields, are rejected
+        let mut params = FieldParams::new();
+        user(&mut params);
+        assert!(apply(&mut doc, DeltaBuilder::new(DeltaOp::Insert).field(0, 4).params(params).raw_value(&int_array(&[])).build().unwrap()).is_err());
+        assert!(apply(&mut doc, DeltaBuilder::new(DeltaOp::Clear).field(0, 0).build().unwrap()).is_err());
+    }
+
+    /// Encoded Array of ints
+    fn int_array(values: &[i64]) -> Vec<u8> {
+        let elements = values.iter().map(|&v| {
+            let mut writer = ValueWriter::new();
+            writer.int(v);
+            writer.into_bytes()
+        });
+        let mut writer = ValueWriter::new();
+        writer.elements(ValueType::Array, elements).unwrap();
+        writer.into_bytes()
+    }
+
+    /// Positional delta on `list` computed against `base`
+    fn positional(op: DeltaOp, base: u64, position: u32, values: Option<&[i64]>) -> Vec<u8> {
+        let mut params = FieldParams::new();
+        params.add_array(vec![ArrayParam::Index(position)]);
+        let mut payload = base.to_le_bytes().to_vec();
+        if let Some(values) = values {
+            payload.extend(int_array(values));
+        }
+        DeltaBuilder::new(op).field(0, 5).params(params).raw_value(&payload).build().unwrap()
+    }
+
+    #[test]
+    fn test_concurrent_positional_edits_converge() {
+        let mut doc = SchemaDocument::new(schema());
+        let list = FieldSlot::field(5);
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 5).raw_value(&int_array(&[10, 20, 30])).build().unwrap()).unwrap();
+        let base = doc.state().sequence();
+
+        // Every delta below was written against the same [10, 20, 30]
+        let concurrent = [
+            positional(DeltaOp::InsertAt, base, 1, Some(&[1])),
+            positional(DeltaOp::InsertAt, base, 1, Some(&[2])),
+            positional(DeltaOp::DeleteAt, base, 2, None),
+            positional(DeltaOp::ReplaceAt, base, 0, Some(&[11])),
+            positional(DeltaOp::ReplaceAt, base, 2, Some(&[99])),
+        ];
+        let mut replica = doc.clone();
+        for delta in &concurrent {
+            apply(&mut doc, delta.clone()).unwrap();
+            apply(&mut replica, delta.clone()).unwrap();
+        }
+
+        // 30 was deleted, so replacing it is a no-op; inserts keep sequence order
+        assert_eq!(doc.state().get(&list), Some(int_array(&[11, 1, 2, 20]).as_slice()));
+        assert_eq!(doc.state(), replica.state());
+
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Prepend).field(0, 5).raw_value(&int_array(&[0])).build().unwrap()).unwrap();
+        assert_eq!(doc.state().get(&list), Some(int_array(&[0, 11, 1, 2, 20]).as_slice()));
+    }
+
+    #[test]
+    fn test_positional_base_checks() {
+        let mut doc = SchemaDocument::new(schema());
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 5).raw_value(&int_array(&[1, 2])).build().unwrap()).unwrap();
+        // Base from the future
+        assert!(apply(&mut doc, positional(DeltaOp::DeleteAt, 7, 0, None)).is_err());
+        // Based before a structural Set
+        let stale = positional(DeltaOp::DeleteAt, 0, 0, None);
+        assert!(apply(&mut doc, stale).unwrap_err().contains("older"));
+        // Out of range at the base version
+        assert!(apply(&mut doc, positional(DeltaOp::ReplaceAt, 1, 2, Some(&[3]))).is_err());
+        // Positional ops need an Array field
+        assert!(apply(&mut doc, DeltaBuilder::new(DeltaOp::Prepend).field(0, 0).int(1).build().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_lifecycle_deltas() {
+        let mut doc = SchemaDocument::new(schema());
+        let lifecycle = |op| DeltaBuilder::new(op).build().unwrap();
+
+        apply(&mut doc, lifecycle(DeltaOp::CreateDocument)).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(4).build().unwrap()).unwrap();
+        assert!(apply(&mut doc, lifecycle(DeltaOp::CreateDocument)).unwrap_err().contains("exists"));
+
+        apply(&mut doc, lifecycle(DeltaOp::CreateSnapshot)).unwrap();
+        let (sequence, snapshot) = doc.latest_snapshot().cloned().unwrap();
+        assert_eq!(sequence, 3);
+        assert_eq!(snapshot[0], 0);
+        assert_eq!(DocumentState::decode(&snapshot[1..]).unwrap(), *doc.state());
+
+        apply(&mut doc, lifecycle(DeltaOp::DeleteDocument)).unwrap();
+        assert!(doc.is_deleted() && doc.state().is_empty());
+        assert!(apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1).build().unwrap()).is_err());
+        assert!(apply(&mut doc, lifecycle(DeltaOp::CreateSnapshot)).is_err());
+
+        apply(&mut doc, lifecycle(DeltaOp::CreateDocument)).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1).build().unwrap()).unwrap();
+        assert_eq!(doc.state().sequence(), 6);
+        apply(&mut doc, lifecycle(DeltaOp::CreateSnapshot)).unwrap();
+        assert_eq!(doc.latest_snapshot().unwrap().0, 7);
+    }
+
+    #[test]
+    fn test_failed_group_restores_what_it_touched() {
+        let mut doc = SchemaDocument::new(schema());
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1).build().unwrap()).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 5).raw_value(&int_array(&[1, 2])).build().unwrap()).unwrap();
+        let before = doc.state().clone();
+
+        let group = |children: &[Vec<u8>]| {
+            let refs: Vec<&[u8]> = children.iter().map(|c| c.as_slice()).collect();
+            DeltaBuilder::group(&refs).unwrap().build().unwrap()
+        };
+        let failing = group(&[
+            DeltaBuilder::new(DeltaOp::Increment).field(0, 0).int(2).build().unwrap(),
+            DeltaBuilder::new(DeltaOp::Set).field(0, 2).string("ada").build().unwrap(),
+            DeltaBuilder::new(DeltaOp::DeleteDocument).build().unwrap(),
+            DeltaBuilder::new(DeltaOp::CreateDocument).build().unwrap(),
+            DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(9).build().unwrap(),
+            DeltaBuilder::new(DeltaOp::Increment).field(0, 2).int(1).build().unwrap(),
+        ]);
+        assert!(apply(&mut doc, failing).unwrap_err().contains("sequence 8"));
+        assert_eq!(doc.state(), &before);
+        assert!(!doc.is_deleted());
+
+        // Positional history of the untouched list survives, so positions based on it still apply
+        apply(&mut doc, positional(DeltaOp::DeleteAt, 2, 0, None)).unwrap();
+        let succeeding = group(&[
+            DeltaBuilder::new(DeltaOp::Increment).field(0, 0).int(2).build().unwrap(),
+            DeltaBuilder::new(DeltaOp::Set).field(0, 2).string("ada").build().unwrap(),
+        ]);
+        apply(&mut doc, succeeding).unwrap();
+        assert_eq!(int_at(&doc, &FieldSlot::field(0)), Some(3));
+        assert_eq!(doc.state().get(&FieldSlot::field(5)), Some(int_array(&[2]).as_slice()));
+        assert_eq!(doc.state().sequence(), 5);
+    }
+
+    #[test]
+    fn test_restored_document_rejects_older_positions() {
+        let mut doc = SchemaDocument::new(schema());
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 5).raw_value(&int_array(&[1, 2])).build().unwrap()).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Append).field(0, 5).raw_value(&int_array(&[3])).build().unwrap()).unwrap();
+
+        let mut restored = SchemaDocument::from_state(schema(), doc.state().clone());
+        assert!(apply(&mut restored, positional(DeltaOp::DeleteAt, 1, 0, None)).is_err());
+        apply(&mut restored, positional(DeltaOp::DeleteAt, 2, 0, None)).unwrap();
+        assert_eq!(restored.state().get(&FieldSlot::field(5)), Some(int_array(&[2, 3]).as_slice()));
+    }
+
+    #[test]
+    fn test_reordered_schema_versions_address_the_same_slot() {
+        let schema = schema();
+        let mut doc = SchemaDocument::new(schema.clone());
+        for _ in 0..3 {
+            apply(&mut doc, DeltaBuilder::new(DeltaOp::Increment).field(0, 0).int(1).build().unwrap()).unwrap();
+        }
+        for _ in 0..4 {
+            apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(0, 2).string("ada").build().unwrap()).unwrap();
+        }
+
+        // "name" is now hottest and moves to index 0 at version 1
+        let version = schema.create_optimized_version() as u16;
+        assert_eq!(schema.translate_index(2, 0, version as u32), Some(0));
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Set).field(version, 0).string("bob").build().unwrap()).unwrap();
+        apply(&mut doc, DeltaBuilder::new(DeltaOp::Increment).field(version, 1).int(1).build().unwrap()).unwrap();
+        assert_eq!(doc.state().get(&FieldSlot::field(2)), Some(&[16, 3, b'b', b'o', b'b'][..]));
+        assert_eq!(int_at(&doc, &FieldSlot::field(0)), Some(4));
+        assert_eq!(doc.state().len(), 2);
+    }
+}
@@ -0,0 +1,376 @@
+//! Delta stamping and verification
+//!
+//! Stored deltas are laid out as `[DeltaSecureHeader][incoming bytes]`. The stamper
+//! fills the secure header on ingress: a per-document monotonic sequence number, a link
+//! to the previous delta of the document, a timestamp and a keyed BLAKE3 MAC over the
+//! whole header (signature bytes excluded) and payload. The verifier checks the MAC and
+//! the continuity of each chain. Both take their key from `DeltaConfig::mac_key`; without
+//! one the stamper signs with a random per-process key that only its own process can
+//! verify, while the verifier refuses to start.
+//!
+//! A `DeltaOp::Deltas` group occupies one sequence number per nested delta: its header
+//! carries the first of the range and the chain head advances to the last.
+
+use dashmap::DashMap;
+
+use crate::core::config::DeltaConfig;
+use crate::core::utils::current_timestamp;
+use crate::types::delta::{
+    DeltaIncoming, DeltaRef, DeltaSecureHeader, DELTA_SECURE_HEADER_SIZE, DELTA_SIGNATURE_OFFSET,
+};
+use crate::types::ParseError;
+use crate::{log_warn, DeltaId, DocId, UserId};
+
+/// Last delta of a document chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainHead {
+    /// Last sequence number assigned (0 before the first delta)
+    pub sequence: u64,
+    /// Identifier of the last delta
+    pub delta_id: DeltaId,
+}
+
+impl ChainHead {
+    /// Head of an empty chain - the first delta links to an all-zero id
+    pub const GENESIS: ChainHead = ChainHead { sequence: 0, delta_id: DeltaId::new([0; 8]) };
+}
+
+/// Errors raised when verifying a stamped delta
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// Stored bytes are not a well-formed delta
+    Parse(ParseError),
+    /// MAC does not match the header and payload
+    InvalidSignature,
+    /// Sequence number is not the successor of the chain head
+    SequenceGap {
+        /// Sequence number the chain expects next
+        expected: u64,
+        /// Sequence number found in the header
+        actual: u64,
+    },
+    /// Previous delta id does not match the chain head
+    BrokenChain,
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::Parse(e) => write!(f, "Malformed delta: {}", e),
+            VerifyError::InvalidSignature => write!(f, "Delta signature mismatch"),
+            VerifyError::SequenceGap { expected, actual } => {
+                write!(f, "Sequence gap: expected {}, got {}", expected, actual)
+            }
+            VerifyError::BrokenChain => write!(f, "Previous delta id does not match chain head"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl From<ParseError> for VerifyError {
+    fn from(e: ParseError) -> Self {
+        VerifyError::Parse(e)
+    }
+}
+
+/// Keyed BLAKE3 MAC over every secure header byte but the signature, then the incoming bytes
+fn compute_mac(key: &[u8; 32], bytes: &[u8]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new_keyed(key);
+    hasher.update(&bytes[..DELTA_SIGNATURE_OFFSET]);
+    hasher.update(&bytes[DELTA_SIGNATURE_OFFSET + 32..]);
+    hasher.finalize()
+}
+
+/// Assigns sequence numbers and signs deltas as they are stored
+pub struct DeltaStamper {
+    /// MAC key shared with verifiers
+    key: [u8; 32],
+    /// Chain head per document
+    chains: DashMap<DocId, ChainHead>,
+}
+
+impl DeltaStamper {
+    /// Create a stamper signing with the given key
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key, chains: DashMap::new() }
+    }
+
+    /// Create a stamper signing with the configured key, or a random per-process key
+    /// when none is configured
+    pub fn from_config(config: &DeltaConfig) -> Result<Self, String> {
+        if config.mac_key.is_none() {
+            log_warn!("delta.mac_key is not configured; signing deltas with a per-process key no other node can verify");
+            return Ok(Self::new(rand::random()));
+        }
+        Ok(Self::new(config.mac_key()?))
+    }
+
+    /// Current chain head of a document, if it has any stamped deltas
+    pub fn head(&self, doc_id: &DocId) -> Option<ChainHead> {
+        self.chains.get(doc_id).map(|head| *head)
+    }
+
+    /// Stamp a stored delta in place
+    ///
+    /// `buf` holds a reserved secure header region followed by the incoming bytes. The
+    /// incoming bytes are validated before any sequence number is consumed.
+    pub fn stamp<'a>(&self, user_id: UserId, doc_id: DocId, buf: &'a mut [u8]) -> Result<DeltaRef<'a>, ParseError> {
+        if buf.len() < DELTA_SECURE_HEADER_SIZE {
+            return Err(ParseError::InsufficientData { expected: DELTA_SECURE_HEADER_SIZE, actual: buf.len() });
+        }
+        let span = DeltaIncoming::parse(&buf[DELTA_SECURE_HEADER_SIZE..])?
+            .payload()
+            .map_or(1, |payload| payload.sequence_span());
+
+        {
+            // Entry guard serialises stamping per document so sequences stay gap-free
+            let mut head = self.chains.entry(doc_id).or_insert(ChainHead::GENESIS);
+            let mut header = DeltaSecureHeader {
+                user_id,
+                doc_id,
+                delta_id: DeltaId::random(),
+                previous_delta_id: head.delta_id,
+                delta_sequence_number: head.sequence + 1,
+                timestamp: current_timestamp(),
+                signature: [0; 32],
+                padding: [0; 16],
+            };
+            header.write_to(buf)?;
+            header.signature = *compute_mac(&self.key, buf).as_bytes();
+            buf[DELTA_SIGNATURE_OFFSET..DELTA_SIGNATURE_OFFSET + 32].copy_from_slice(&header.signature);
+            *head = ChainHead { sequence: head.sequence + span, delta_id: header.delta_id };
+        }
+
+        let delta = DeltaRef::from_wire_bytes(buf)?;
+        delta.mark_as_signed();
+        Ok(delta)
+    }
+}
+
+/// Checks signatures and chain continuity of stamped deltas
+pub struct DeltaVerifier {
+    /// MAC key shared with the stamper
+    key: [u8; 32],
+    /// Last verified delta per document
+    chains: DashMap<DocId, ChainHead>,
+}
+
+impl DeltaVerifier {
+    /// Create a verifier for deltas signed with the given key
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key, chains: DashMap::new() }
+    }
+
+    /// Create a verifier for deltas signed with the configured key
+    pub fn from_config(config: &DeltaConfig) -> Result<Self, String> {
+        Ok(Self::new(config.mac_key()?))
+    }
+
+    /// Start verifying a document's chain after an already trusted head
+    pub fn resume_from(&self, doc_id: DocId, head: ChainHead) {
+        self.chains.insert(doc_id, head);
+    }
+
+    /// Check the MAC only, without touching chain state
+    pub fn verify_signature(&self, delta: &DeltaRef) -> Result<(), VerifyError> {
+        // blake3::Hash equality is constant time
+        if compute_mac(&self.key, delta.bytes()) != blake3::Hash::from(delta.server_header().signature) {
+            return Err(VerifyError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    /// Verify the MAC and that the delta extends its document's chain, advancing the chain on success
+    pub fn verify(&self, delta: &DeltaRef) -> Result<(), VerifyError> {
+        self.verify_signature(delta)?;
+
+        let header = delta.server_header();
+        let mut head = self.chains.entry(header.doc_id).or_insert(ChainHead::GENESIS);
+        let expected = head.sequence + 1;
+        if header.delta_sequence_number != expected {
+            return Err(VerifyError::SequenceGap { expected, actual: header.delta_sequence_number });
+        }
+        if header.previous_delta_id != head.delta_id {
+            return Err(VerifyError::BrokenChain);
+        }
+        *head = ChainHead { sequence: *delta.sequence_range().end(), delta_id: header.delta_id };
+        drop(head);
+
+        delta.mark_as_signed();
+        Ok(())
+    }
+
+    /// Verify stored bytes, returning the parsed delta
+    pub fn verify_bytes<'a>(&self, bytes: &'a [u8]) -> Result<DeltaRef<'a>, VerifyError> {
+        let delta = DeltaRef::from_wire_bytes(bytes)?;
+        self.verify(&delta)?;
+        Ok(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::delta::{DeltaBuilder, DeltaFlags, DeltaOp};
+
+    const KEY: [u8; 32] = [7; 32];
+
+    /// Reserve a secure header region ahead of built incoming bytes
+    fn stored(value: i64) -> Vec<u8> {
+        let mut buf = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+        DeltaBuilder::new(DeltaOp::Set).field(0, 1).int(value).build_into(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_stamp_assigns_linked_sequence() {
+        let stamper = DeltaStamper::new(KEY);
+        let user_id = UserId::random();
+        let doc_id = DocId::random();
+
+        let mut first = stored(1);
+        let mut second = stored(2);
+        let (first_id, first_ts) = {
+            let delta = stamper.stamp(user_id, doc_id, &mut first).unwrap();
+            let header = delta.server_header();
+            assert_eq!(header.delta_sequence_number, 1);
+            assert_eq!(header.previous_delta_id, ChainHead::GENESIS.delta_id);
+            assert_eq!(header.user_id, user_id);
+            assert!(delta.flags().contains(DeltaFlags::SIGNED));
+            (header.delta_id, header.timestamp)
+        };
+        let delta = stamper.stamp(user_id, doc_id, &mut second).unwrap();
+        assert_eq!(delta.server_header().delta_sequence_number, 2);
+        assert_eq!(delta.server_header().previous_delta_id, first_id);
+        assert!(delta.server_header().timestamp >= first_ts);
+
+        // Other documents have independent chains
+        let mut other = stored(3);
+        let delta = stamper.stamp(user_id, DocId::random(), &mut other).unwrap();
+        assert_eq!(delta.server_header().delta_sequence_number, 1);
+        assert_eq!(stamper.head(&doc_id).unwrap().sequence, 2);
+    }
+
+    #[test]
+    fn test_stamp_rejects_malformed_incoming_without_consuming_sequence() {
+        let stamper = DeltaStamper::new(KEY);
+        let doc_id = DocId::random();
+        let mut bad = stored(1);
+        bad.truncate(bad.len() - 1);
+        assert!(stamper.stamp(UserId::random(), doc_id, &mut bad).is_err());
+        assert!(stamper.head(&doc_id).is_none());
+    }
+
+    #[test]
+    fn test_verify_accepts_chain_in_order() {
+        let stamper = DeltaStamper::new(KEY);
+        let verifier = DeltaVerifier::new(KEY);
+        let doc_id = DocId::random();
+
+        let mut deltas: Vec<Vec<u8>> = (0..3).map(stored).collect();
+        for buf in deltas.iter_mut() {
+            stamper.stamp(UserId::random(), doc_id, buf).unwrap();
+        }
+        for buf in &deltas {
+            let delta = verifier.verify_bytes(buf).unwrap();
+            assert!(delta.flags().contains(DeltaFlags::SIGNED));
+        }
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let stamper = DeltaStamper::new(KEY);
+        let mut buf = stored(5);
+        stamper.stamp(UserId::random(), DocId::random(), &mut buf).unwrap();
+
+        // Payload byte
+        let mut tampered = buf.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert_eq!(DeltaVerifier::new(KEY).verify_bytes(&tampered).err(), Some(VerifyError::InvalidSignature));
+
+        // Header sequence number and padding
+        for offset in [64, DELTA_SECURE_HEADER_SIZE - 1] {
+            let mut tampered = buf.clone();
+            tampered[offset] ^= 1;
+            assert_eq!(DeltaVerifier::new(KEY).verify_bytes(&tampered).err(), Some(VerifyError::InvalidSignature));
+        }
+
+        // Wrong key
+        assert_eq!(DeltaVerifier::new([8; 32]).verify_bytes(&buf).err(), Some(VerifyError::InvalidSignature));
+        assert!(DeltaVerifier::new(KEY).verify_bytes(&buf).is_ok());
+    }
+
+    #[test]
+    fn test_verify_detects_gaps_and_broken_links() {
+        let stamper = DeltaStamper::new(KEY);
+        let doc_id = DocId::random();
+        let mut deltas: Vec<Vec<u8>> = (0..3).map(stored).collect();
+        for buf in deltas.iter_mut() {
+            stamper.stamp(UserId::random(), doc_id, buf).unwrap();
+        }
+
+        // Skipping the second delta leaves a gap
+        let verifier = DeltaVerifier::new(KEY);
+        verifier.verify_bytes(&deltas[0]).unwrap();
+        assert_eq!(
+            verifier.verify_bytes(&deltas[2]).err(),
+            Some(VerifyError::SequenceGap { expected: 2, actual: 3 })
+        );
+        verifier.verify_bytes(&deltas[1]).unwrap();
+        verifier.verify_bytes(&deltas[2]).unwrap();
+
+        // Resuming at the right sequence but a different head breaks the link
+        let verifier = DeltaVerifier::new(KEY);
+        verifier.resume_from(doc_id, ChainHead { sequence: 1, delta_id: DeltaId::new(*b"unknown!") });
+        assert_eq!(verifier.verify_bytes(&deltas[1]).err(), Some(VerifyError::BrokenChain));
+    }
+
+    #[test]
+    fn test_group_occupies_sequence_range() {
+        let stamper = DeltaStamper::new(KEY);
+        let verifier = DeltaVerifier::new(KEY);
+        let doc_id = DocId::random();
+
+        let children: Vec<Vec<u8>> = (0..3)
+            .map(|i| DeltaBuilder::new(DeltaOp::Set).field(0, i).int(i as i64).build().unwrap())
+            .collect();
+        let refs: Vec<&[u8]> = children.iter().map(|c| c.as_slice()).collect();
+        let mut group = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+        DeltaBuilder::group(&refs).unwrap().build_into(&mut group).unwrap();
+
+        let mut before = stored(0);
+        let mut after = stored(1);
+        stamper.stamp(UserId::random(), doc_id, &mut before).unwrap();
+        let range = stamper.stamp(UserId::random(), doc_id, &mut group).unwrap().sequence_range();
+        assert_eq!(range, 2..=4);
+        let delta = stamper.stamp(UserId::random(), doc_id, &mut after).unwrap();
+        assert_eq!(delta.server_header().delta_sequence_number, 5);
+
+        for buf in [&before, &group, &after] {
+            verifier.verify_bytes(buf).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_key_comes_from_config() {
+        let mut config = DeltaConfig::default();
+        // Stamping falls back to a per-process key; verifying needs the shared one
+        let mut buf = stored(1);
+        DeltaStamper::from_config(&config).unwrap().stamp(UserId::random(), DocId::random(), &mut buf).unwrap();
+        assert!(DeltaVerifier::new(KEY).verify_bytes(&buf).is_err());
+        assert!(DeltaVerifier::from_config(&config).is_err());
+
+        config.mac_key = Some("07".repeat(32));
+        let mut buf = stored(1);
+        DeltaStamper::from_config(&config).unwrap().stamp(UserId::random(), DocId::random(), &mut buf).unwrap();
+        assert!(DeltaVerifier::new(KEY).verify_bytes(&buf).is_ok());
+
+        for invalid in ["07".repeat(31), "zz".repeat(32)] {
+            config.mac_key = Some(invalid);
+            assert!(DeltaStamper::from_config(&config).is_err());
+            assert!(DeltaVerifier::from_config(&config).is_err());
+        }
+    }
+}
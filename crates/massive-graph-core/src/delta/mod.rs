@@ -3,6 +3,23 @@
 /// Delta processor implementation
 pub mod delta_processor;
 
+/// Collection value operations
+pub mod delta_collections;
+
+/// Predicate encoding for conditional collection operations
+pub mod delta_predicate;
+
+/// Positional collaboration operations
+pub mod delta_positional;
+
+/// Secure header stamping and verification
+pub mod delta_stamper;
+
+/// Short-window deduplication of retransmitted deltas
+pub mod delta_dedup;
+
 // Re-export delta processor
 pub use delta_processor::*;
+pub use delta_stamper::{ChainHead, DeltaStamper, DeltaVerifier, VerifyError};
+pub use delta_dedup::DeltaDeduplicator;
 
@@ -0,0 +1,352 @@
+//! Materialised document state
+//!
+//! A document is a set of slots: a schema field index plus the concrete key or index
+//! chosen for each of the field's parameter groups (`users{}.age` with key `alice` is
+//! one slot). Each slot holds an encoded wire `Value`.
+//!
+//! Snapshot layout (integers little-endian, counts/lengths as varints):
+//! ```text
+//! [sequence: u64][slot count]
+//! per slot: [field index][key count: u8][keys][value length][value bytes]
+//! key: 0x01 [length][UTF-8] | 0x02 [index] | 0x03 [count][indices]
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::types::field::{ArrayParam, FieldParams, ParamGroupValues};
+use crate::types::varint::{decode_varint, encode_varint};
+use crate::types::ParseError;
+
+/// Most slots a single field address may expand to
+pub const MAX_SLOTS_PER_ADDRESS: usize = 65_536;
+
+/// Concrete selection for one parameter group
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SlotKey {
+    /// Map key
+    Key(String),
+    /// Array index
+    Index(u32),
+    /// Tensor/matrix coordinate
+    Dims(Vec<u32>),
+}
+
+/// Concrete location of a value within a document
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FieldSlot {
+    /// Schema field index
+    pub field_index: u32,
+    /// One key per parameter group of the field, in descriptor order
+    pub keys: Vec<SlotKey>,
+}
+
+impl FieldSlot {
+    /// Slot of a field without parameter groups
+    pub fn field(field_index: u32) -> Self {
+        Self { field_index, keys: Vec::new() }
+    }
+
+    /// Concrete path of this slot under its field's descriptor path, filling each `[]`
+    /// and `{}` with the slot's key for that group (`users{}.tags[]` becomes
+    /// `users{alice}.tags[3]`; see `path_index`)
+    pub fn concrete_path(&self, descriptor_path: &str) -> Result<String, String> {
+        let mismatch = || format!("Slot keys {:?} do not fit path '{}'", self.keys, descriptor_path);
+        let mut path = String::with_capacity(descriptor_path.len());
+        let mut keys = self.keys.iter();
+        let mut chars = descriptor_path.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if !matches!((ch, chars.peek()), ('[', Some(']')) | ('{', Some('}'))) {
+                path.push(ch);
+                continue;
+            }
+            chars.next();
+            match (ch, keys.next()) {
+                ('[', Some(SlotKey::Index(index))) => path.push_str(&format!("[{}]", index)),
+                ('[', Some(SlotKey::Dims(dims))) => {
+                    let dims: Vec<String> = dims.iter().map(u32::to_string).collect();
+                    path.push_str(&format!("[{}]", dims.join("x")));
+                }
+                ('{', Some(SlotKey::Key(key))) => path.push_str(&format!("{{{}}}", key)),
+                _ => return Err(mismatch()),
+            }
+        }
+        match keys.next() {
+            Some(_) => Err(mismatch()),
+            None => Ok(path),
+        }
+    }
+
+    /// Expand a field address into every slot it selects
+    ///
+    /// Each group contributes its selections (keys, indices, half-open ranges) and the
+    /// result is their cartesian product, in selection order.
+    pub fn expand(field_index: u32, params: &FieldParams) -> Result<Vec<FieldSlot>, String> {
+        let mut slots = vec![FieldSlot::field(field_index)];
+        for group in &params.groups {
+            let choices = Self::group_choices(group)?;
+            if slots.len().saturating_mul(choices.len()) > MAX_SLOTS_PER_ADDRESS {
+                return Err(format!("Field address selects more than {} slots", MAX_SLOTS_PER_ADDRESS));
+            }
+            slots = slots
+                .iter()
+                .flat_map(|slot| {
+                    choices.iter().map(move |choice| {
+                        let mut next = slot.clone();
+                        next.keys.push(choice.clone());
+                        next
+                    })
+                })
+                .collect();
+        }
+        Ok(slots)
+    }
+
+    /// Selections made by one parameter group
+    fn group_choices(group: &ParamGroupValues) -> Result<Vec<SlotKey>, String> {
+        let mut choices = Vec::new();
+        match group {
+            ParamGroupValues::KeySet(keys) => choices.extend(keys.iter().cloned().map(SlotKey::Key)),
+            ParamGroupValues::ArraySet(params) => {
+                for param in params {
+                    match param {
+                        ArrayParam::Index(i) => choices.push(SlotKey::Index(*i)),
+                        ArrayParam::Indices(v) => choices.extend(v.iter().copied().map(SlotKey::Index)),
+                        ArrayParam::Range(start, end) => choices.extend(Self::range(*start, *end)?),
+                        ArrayParam::Ranges(v) => {
+                            for &(start, end) in v {
+                                choices.extend(Self::range(start, end)?);
+                            }
+                        }
+                        ArrayParam::Dimensions(v) => choices.push(SlotKey::Dims(v.clone())),
+                    }
+                    if choices.len() > MAX_SLOTS_PER_ADDRESS {
+                        return Err(format!("Field address selects more than {} slots", MAX_SLOTS_PER_ADDRESS));
+                    }
+                }
+            }
+        }
+        Ok(choices)
+    }
+
+    /// Indices of a half-open range
+    fn range(start: u32, end: u32) -> Result<impl Iterator<Item = SlotKey>, String> {
+        if start > end {
+            return Err(format!("Invalid range {}..{}", start, end));
+        }
+        if (end - start) as usize > MAX_SLOTS_PER_ADDRESS {
+            return Err(format!("Field address selects more than {} slots", MAX_SLOTS_PER_ADDRESS));
+        }
+        Ok((start..end).map(SlotKey::Index))
+    }
+}
+
+/// Current values of a document and the last delta sequence applied to it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DocumentState {
+    /// Encoded wire values by slot
+    slots: BTreeMap<FieldSlot, Vec<u8>>,
+    /// Sequence number of the last applied delta
+    sequence: u64,
+}
+
+impl DocumentState {
+    /// Create an empty state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sequence number of the last applied delta
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Record the sequence number of an applied delta
+    pub fn set_sequence(&mut self, sequence: u64) {
+        self.sequence = sequence;
+    }
+
+    /// Encoded value at a slot
+    pub fn get(&self, slot: &FieldSlot) -> Option<&[u8]> {
+        self.slots.get(slot).map(|v| v.as_slice())
+    }
+
+    /// Store an encoded value at a slot
+    pub fn set(&mut self, slot: FieldSlot, value: Vec<u8>) {
+        self.slots.insert(slot, value);
+    }
+
+    /// Remove a slot, returning its value
+    pub fn remove(&mut self, slot: &FieldSlot) -> Option<Vec<u8>> {
+        self.slots.remove(slot)
+    }
+
+    /// Iterate slots in order
+    pub fn iter(&self) -> impl Iterator<Item = (&FieldSlot, &[u8])> {
+        self.slots.iter().map(|(slot, value)| (slot, value.as_slice()))
+    }
+
+    /// Number of occupied slots
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Whether the document has no values
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Encode a snapshot of the state
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut output = Vec::with_capacity(16 + self.slots.len() * 16);
+        output.extend_from_slice(&self.sequence.to_le_bytes());
+        encode_varint(self.slots.len() as u32, &mut output)?;
+        for (slot, value) in &self.slots {
+            encode_varint(slot.field_index, &mut output)?;
+            output.push(u8::try_from(slot.keys.len()).map_err(|_| "Too many slot keys".to_string())?);
+            for key in &slot.keys {
+                match key {
+                    SlotKey::Key(k) => {
+                        output.push(0x01);
+                        encode_varint(k.len() as u32, &mut output)?;
+                        output.extend_from_slice(k.as_bytes());
+                    }
+                    SlotKey::Index(i) => {
+                        output.push(0x02);
+                        encode_varint(*i, &mut output)?;
+                    }
+                    SlotKey::Dims(dims) => {
+                        output.push(0x03);
+                        encode_varint(dims.len() as u32, &mut output)?;
+                        dims.iter().try_for_each(|&d| encode_varint(d, &mut output))?;
+                    }
+                }
+            }
+            encode_varint(value.len() as u32, &mut output)?;
+            output.extend_from_slice(value);
+        }
+        Ok(output)
+    }
+
+    /// Decode a snapshot produced by `encode`
+    pub fn decode(bytes: &[u8]) -> Result<Self, ParseError> {
+        let mut reader = SnapshotReader { rest: bytes };
+        let sequence = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let count = reader.varint()?;
+        let mut slots = BTreeMap::new();
+        for _ in 0..count {
+            let field_index = reader.varint()?;
+            let key_count = reader.take(1)?[0];
+            let mut keys = Vec::with_capacity(key_count as usize);
+            for _ in 0..key_count {
+                let key = match reader.take(1)?[0] {
+                    0x01 => {
+                        let len = reader.varint()? as usize;
+                        let key = std::str::from_utf8(reader.take(len)?).map_err(|_| ParseError::InvalidUtf8)?;
+                        SlotKey::Key(key.to_string())
+                    }
+                    0x02 => SlotKey::Index(reader.varint()?),
+                    0x03 => {
+                        let n = reader.varint()?;
+                        SlotKey::Dims((0..n).map(|_| reader.varint()).collect::<Result<_, _>>()?)
+                    }
+                    _ => return Err(ParseError::InvalidFormat),
+                };
+                keys.push(key);
+            }
+            let len = reader.varint()? as usize;
+            slots.insert(FieldSlot { field_index, keys }, reader.take(len)?.to_vec());
+        }
+        if !reader.rest.is_empty() {
+            return Err(ParseError::LengthMismatch { declared: bytes.len() - reader.rest.len(), actual: bytes.len() });
+        }
+        Ok(Self { slots, sequence })
+    }
+}
+
+/// Bounds-checked cursor over snapshot bytes
+struct SnapshotReader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        if self.rest.len() < len {
+            return Err(ParseError::InsufficientData { expected: len, actual: self.rest.len() });
+        }
+        let (head, tail) = self.rest.split_at(len);
+        self.rest = tail;
+        Ok(head)
+    }
+
+    fn varint(&mut self) -> Result<u32, ParseError> {
+        let (value, read) = decode_varint(self.rest)?;
+        self.rest = &self.rest[read..];
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::path_index::PathIndex;
+
+    #[test]
+    fn test_expand_cartesian_product() {
+        let mut params = FieldParams::new();
+        params.add_keys(vec!["a".to_string(), "b".to_string()]);
+        params.add_array(vec![ArrayParam::Range(0, 2), ArrayParam::Index(7)]);
+        let slots = FieldSlot::expand(3, &params).unwrap();
+
+        let keys: Vec<Vec<SlotKey>> = slots.into_iter().map(|s| s.keys).collect();
+        let key = |k: &str, i| vec![SlotKey::Key(k.to_string()), SlotKey::Index(i)];
+        assert_eq!(keys, vec![key("a", 0), key("a", 1), key("a", 7), key("b", 0), key("b", 1), key("b", 7)]);
+    }
+
+    #[test]
+    fn test_concrete_path_resolves_back_to_slot() {
+        let mut index = PathIndex::new();
+        index.insert("users{}.tags[]", 4);
+        index.insert("grid[]", 5);
+        let slot = FieldSlot { field_index: 4, keys: vec![SlotKey::Key("alice".to_string()), SlotKey::Index(3)] };
+        let path = slot.concrete_path("users{}.tags[]").unwrap();
+        assert_eq!(path, "users{alice}.tags[3]");
+        let (field_index, params) = index.resolve(&path).unwrap();
+        assert_eq!(FieldSlot::expand(field_index, &params).unwrap(), vec![slot.clone()]);
+
+        let cell = FieldSlot { field_index: 5, keys: vec![SlotKey::Dims(vec![2, 3])] };
+        assert_eq!(cell.concrete_path("grid[]").unwrap(), "grid[2x3]");
+        let (field_index, params) = index.resolve("grid[2x3]").unwrap();
+        assert_eq!(FieldSlot::expand(field_index, &params).unwrap(), vec![cell]);
+
+        // Keys must match the descriptor's groups in number and kind
+        assert!(FieldSlot::field(4).concrete_path("users{}.tags[]").is_err());
+        assert!(slot.concrete_path("users[].tags{}").is_err());
+        assert!(slot.concrete_path("title").is_err());
+    }
+
+    #[test]
+    fn test_expand_rejects_bad_ranges() {
+        let mut params = FieldParams::new();
+        params.add_array(vec![ArrayParam::Range(5, 2)]);
+        assert!(FieldSlot::expand(0, &params).is_err());
+
+        let mut params = FieldParams::new();
+        params.add_array(vec![ArrayParam::Range(0, 4_000_000)]);
+        assert!(FieldSlot::expand(0, &params).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut state = DocumentState::new();
+        state.set(FieldSlot::field(1), vec![2, 1, 0, 0, 0, 0, 0, 0, 0]);
+        state.set(FieldSlot { field_index: 300, keys: vec![SlotKey::Key("alice".to_string()), SlotKey::Index(4)] }, vec![16, 1, b'x']);
+        state.set(FieldSlot { field_index: 2, keys: vec![SlotKey::Dims(vec![1, 2, 3])] }, vec![0]);
+        state.set_sequence(17);
+
+        let bytes = state.encode().unwrap();
+        assert_eq!(DocumentState::decode(&bytes).unwrap(), state);
+        for len in 0..bytes.len() {
+            assert!(DocumentState::decode(&bytes[..len]).is_err());
+        }
+    }
+}
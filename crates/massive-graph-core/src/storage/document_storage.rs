@@ -3,20 +3,58 @@
 //! This module provides a shell implementation of the advanced storage architecture
 //! described in the architecture documents. Currently contains minimal functionality
 //! to maintain compilation without errors.
+//!
+//! Applied deltas are kept until the next `CreateSnapshot`; a reader loads the latest
+//! snapshot and replays only the deltas after it (`ZeroCopyDocumentStorage::load`).
+//! Stream documents publish appended entries once their delta is applied and logged;
+//! readers follow them with a `Cursor` (`ZeroCopyDocumentStorage::stream_cursor`).
+//! Entries share the bytes of the logged deltas instead of copying their values.
 
-use crate::types::document::DocumentHeader;
-use crate::{DocumentStorage};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde_json::{Map, Value as Json};
+
+use crate::delta::delta_processor::{apply_delta, apply_payload, SchemaDocument};
+use crate::log_warn;
+use crate::storage::document_stream::StreamEntry;
+use crate::storage::schema_directory::{SchemaBinding, SchemaDirectory};
+use crate::structures::segmented_stream::Cursor;
+use crate::types::delta::DeltaRef;
+use crate::types::document::{DocumentHeader, DocumentType};
+use crate::types::{DeltaOp, SchemaRegistry, Value};
+use crate::{DeltaId, DocId, DocumentStorage, UserId};
+
+/// Stamped delta kept since the latest snapshot
+#[derive(Clone)]
+struct LoggedDelta {
+    /// Last sequence number the delta occupies
+    last_sequence: u64,
+    /// Stamped wire bytes
+    bytes: Arc<Vec<u8>>,
+}
+
+/// Document values and the deltas applied since its latest snapshot
+struct DocumentInner {
+    /// Materialised values, updated by applied deltas
+    document: SchemaDocument,
+    /// Applied deltas after the latest snapshot, in sequence order
+    deltas: Vec<LoggedDelta>,
+}
 
 /// Advanced zero-copy storage implementation - Shell
 /// 
 /// This is a placeholder implementation that will be built out according to
-/// the Memory Storage Architecture documentation.
-#[derive(Debug)] 
+/// the Memory Storage Architecture documentation. Clones share the same document.
 pub struct ZeroCopyDocumentStorage {
 
     /// User documents container
     doc_header: DocumentHeader<'static>,
 
+    /// Document values and delta log, shared by clones
+    inner: Arc<RwLock<DocumentInner>>,
+
     // /// Space for deltas
     // space_for_deltas: DeltaStreamStorage,
 
@@ -27,12 +65,169 @@ pub struct ZeroCopyDocumentStorage {
 
 
 impl ZeroCopyDocumentStorage {
-    /// Create a new advanced storage instance
+    /// Create a new advanced storage instance with its own empty schema
     pub fn new() -> Self {
+        Self::with_schema(Arc::new(SchemaRegistry::new()))
+    }
+
+    /// Create a storage instance whose deltas are typed by `schema`
+    pub fn with_schema(schema: Arc<SchemaRegistry>) -> Self {
+        Self::from_document(SchemaDocument::new(schema), Vec::new())
+    }
+
+    /// Create a storage instance whose `CreateDocument` delta may bind it to a schema
+    /// document `owner` registered in `directory`; `schema` types it otherwise
+    pub fn with_directory(schema: Arc<SchemaRegistry>, owner: UserId, directory: Arc<SchemaDirectory>) -> Self {
+        Self::from_document(SchemaDocument::new(schema).with_directory(owner, directory), Vec::new())
+    }
+
+    fn from_document(document: SchemaDocument, deltas: Vec<LoggedDelta>) -> Self {
         Self {
             doc_header: DocumentHeader::default(),
+            inner: Arc::new(RwLock::new(DocumentInner { document, deltas })),
+        }
+    }
+
+    /// Load a document of `owner` from its latest snapshot, if any, and the stamped deltas
+    /// that followed it
+    ///
+    /// Deltas (or nested group deltas) already covered by the snapshot are skipped. A
+    /// document bound to a schema document is loaded with that document's registry, found
+    /// in `directory`.
+    pub fn load(
+        schema: Arc<SchemaRegistry>,
+        owner: UserId,
+        directory: Arc<SchemaDirectory>,
+        snapshot: Option<&[u8]>,
+        deltas: &[&[u8]],
+    ) -> Result<Self, String> {
+        let mut document = match snapshot {
+            Some(snapshot) => SchemaDocument::from_snapshot(schema, owner, directory, snapshot)?,
+            None => SchemaDocument::new(schema).with_directory(owner, directory),
+        };
+        let mut log = Vec::with_capacity(deltas.len());
+        for bytes in deltas {
+            let bytes = Arc::new(bytes.to_vec());
+            let delta = DeltaRef::from_wire_bytes(&bytes).map_err(|e| e.to_string())?;
+            let (first, last) = (*delta.sequence_range().start(), *delta.sequence_range().end());
+            let current = document.state().sequence();
+            if last <= current {
+                continue;
+            }
+            if first > current {
+                apply_delta(&mut document, &delta)?;
+            } else {
+                // A group straddling the snapshot: replay only its later children
+                let group = delta.payload().group().map_err(|e| e.to_string())?;
+                for (sequence, child) in (first..=last).zip(group.iter()) {
+                    if sequence > current {
+                        apply_payload(&mut document, sequence, child.payload().ok_or("Nested delta was not validated")?)?;
+                    }
+                }
+            }
+            document.publish(&bytes)?;
+            log.push(LoggedDelta { last_sequence: last, bytes });
+        }
+        Ok(Self::from_document(document, log))
+    }
+
+    /// Latest snapshot as (sequence, encoded snapshot; see `SchemaDocument::from_snapshot`)
+    pub fn latest_snapshot(&self) -> Option<(u64, Arc<Vec<u8>>)> {
+        self.inner.read().unwrap().document.latest_snapshot().cloned()
+    }
+
+    /// Stamped deltas applied after `sequence` and after the latest snapshot
+    pub fn deltas_since(&self, sequence: u64) -> Vec<Arc<Vec<u8>>> {
+        let inner = self.inner.read().unwrap();
+        inner.deltas.iter().filter(|d| d.last_sequence > sequence).map(|d| d.bytes.clone()).collect()
+    }
+
+    /// Stamped delta with `delta_id`, while it is kept (deltas before the latest
+    /// snapshot are compacted away)
+    pub fn find_delta(&self, delta_id: DeltaId) -> Option<Arc<Vec<u8>>> {
+        let inner = self.inner.read().unwrap();
+        inner.deltas.iter().rev()
+            .find(|d| DeltaRef::from_wire_bytes(&d.bytes).is_ok_and(|delta| delta.server_header().delta_id == delta_id))
+            .map(|d| d.bytes.clone())
+    }
+
+    /// Documents the field values point at through `DocumentRef` and `DeltaRef` values
+    ///
+    /// Values that fail to parse hold nothing that could be followed and are skipped.
+    pub fn referenced_documents(&self) -> BTreeSet<DocId> {
+        let inner = self.inner.read().unwrap();
+        inner.document.state().iter()
+            .filter_map(|(_, value)| Value::from_bytes(value).and_then(|value| value.references()).ok())
+            .flatten()
+            .filter_map(|reference| reference.doc_id())
+            .collect()
+    }
+
+    /// Sequence number of the last applied delta
+    pub fn sequence(&self) -> u64 {
+        self.inner.read().unwrap().document.state().sequence()
+    }
+
+    /// Current values as JSON keyed by concrete path (see `SchemaDocument::properties`)
+    pub fn properties(&self) -> Result<Map<String, Json>, String> {
+        self.inner.read().unwrap().document.properties()
+    }
+
+    /// Type the document was created with
+    pub fn doc_type(&self) -> DocumentType {
+        self.inner.read().unwrap().document.doc_type()
+    }
+
+    /// Whether the last lifecycle delta deleted the document
+    pub fn is_deleted(&self) -> bool {
+        self.inner.read().unwrap().document.is_deleted()
+    }
+
+    /// Schema document the fields resolve against, if bound
+    pub fn schema_binding(&self) -> Option<SchemaBinding> {
+        self.inner.read().unwrap().document.binding()
+    }
+
+    /// Registry of fields published by a schema document
+    pub fn defined_schema(&self) -> Option<Arc<SchemaRegistry>> {
+        self.inner.read().unwrap().document.defined_schema().cloned()
+    }
+
+    /// Cursor over a stream document's entries (encoded wire values), starting at a
+    /// named mark or, without one, at the head
+    pub fn stream_cursor(&self, mark: Option<&str>) -> Result<Cursor<StreamEntry>, String> {
+        let inner = self.inner.read().unwrap();
+        let stream = inner.document.stream().ok_or("Document is not a stream")?;
+        match mark {
+            Some(name) => stream.cursor_from_mark(name),
+            None => stream.cursor_at(0),
         }
     }
+
+    /// Whether a stamped delta creates its document: a `CreateDocument`, or a group
+    /// starting with one (e.g. creating a document under a parent)
+    pub fn creates_document(delta: &[u8]) -> Result<bool, String> {
+        let delta = DeltaRef::from_wire_bytes(delta).map_err(|e| e.to_string())?;
+        let payload = delta.payload();
+        if payload.delta_op != DeltaOp::Deltas {
+            return Ok(payload.delta_op == DeltaOp::CreateDocument);
+        }
+        let group = payload.group().map_err(|e| e.to_string())?;
+        let first = group.iter().next();
+        Ok(first.and_then(|child| child.payload().map(|p| p.delta_op)) == Some(DeltaOp::CreateDocument))
+    }
+}
+
+impl fmt::Debug for ZeroCopyDocumentStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.read().unwrap();
+        f.debug_struct("ZeroCopyDocumentStorage")
+            .field("doc_header", &self.doc_header)
+            .field("sequence", &inner.document.state().sequence())
+            .field("slots", &inner.document.state().len())
+            .field("logged_deltas", &inner.deltas.len())
+            .finish()
+    }
 }
 
 impl Default for ZeroCopyDocumentStorage {
@@ -43,21 +238,38 @@ impl Default for ZeroCopyDocumentStorage {
 
 impl Clone for ZeroCopyDocumentStorage {
     fn clone(&self) -> Self {
-        // Create a new empty storage - we don't clone the actual data
-        Self::new()
+        Self {
+            doc_header: DocumentHeader::default(),
+            inner: self.inner.clone(),
+        }
     }
 }
 
 impl DocumentStorage for ZeroCopyDocumentStorage {
-    /// Get document by document ID - Shell implementation
+    /// Get a snapshot of the document values (see `DocumentState::encode`)
     fn get_document(&self) -> Option<Vec<u8>> {
-        // TODO: Implement according to Memory Storage Architecture
-        None
+        self.inner.read().unwrap().document.state().encode().ok()
     }
     
-    /// Apply delta to document - Shell implementation
-    fn apply_delta(&self, _delta: Vec<u8>) -> Result<(), String> {
-        // TODO: Implement delta application system
+    /// Apply a stamped delta (secure header followed by the incoming delta)
+    fn apply_delta(&self, delta: Vec<u8>) -> Result<(), String> {
+        let delta = Arc::new(delta);
+        let parsed = DeltaRef::from_wire_bytes(&delta).map_err(|e| e.to_string())?;
+        let last_sequence = *parsed.sequence_range().end();
+        let mut inner = self.inner.write().unwrap();
+        let snapshot = inner.document.latest_snapshot().map(|(sequence, _)| *sequence);
+        apply_delta(&mut inner.document, &parsed)?;
+        // Applied means committed: log before publishing so the log never trails the state
+        inner.deltas.push(LoggedDelta { last_sequence, bytes: delta.clone() });
+        if let Err(e) = inner.document.publish(&delta) {
+            log_warn!("Stream entries of delta {} stay staged: {}", last_sequence, e);
+        }
+
+        // Compact the log behind a new snapshot
+        let latest = inner.document.latest_snapshot().map(|(sequence, _)| *sequence);
+        if let Some(snapshot_sequence) = latest.filter(|_| latest != snapshot) {
+            inner.deltas.retain(|d| d.last_sequence > snapshot_sequence);
+        }
         Ok(())
     }
     
@@ -71,4 +283,142 @@ impl DocumentStorage for ZeroCopyDocumentStorage {
         // TODO: Implement document deletion with zero-copy architecture
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::document_state::{DocumentState, FieldSlot};
+    use crate::storage::test_support::TestStamper;
+    use crate::types::delta::DeltaBuilder;
+    use crate::types::value::Value;
+    use crate::types::{DeltaOp, FieldDescriptor, ValueType};
+    use crate::{DocId, UserId};
+
+    #[test]
+    fn test_applied_deltas_are_visible_through_clones() {
+        let schema = Arc::new(SchemaRegistry::new());
+        schema.add_field(FieldDescriptor::new("count".to_string(), ValueType::Int).unwrap());
+        let storage = ZeroCopyDocumentStorage::with_schema(schema);
+        let view = storage.clone();
+
+        let doc_id = DocId::random();
+        let stamper = TestStamper::new(UserId::random());
+        for amount in [2, 3] {
+            storage.apply_delta(stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::Increment).field(0, 0).int(amount))).unwrap();
+        }
+
+        let state = DocumentState::decode(&view.get_document().unwrap()).unwrap();
+        assert_eq!(state.sequence(), 2);
+        assert_eq!(state.get(&FieldSlot::field(0)), Some(&[2, 5, 0, 0, 0, 0, 0, 0, 0][..]));
+        assert!(storage.apply_delta(vec![0; 8]).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_load_skips_replay() {
+        let schema = Arc::new(SchemaRegistry::new());
+        schema.add_field(FieldDescriptor::new("count".to_string(), ValueType::Int).unwrap());
+        let storage = ZeroCopyDocumentStorage::with_schema(schema.clone());
+        let doc_id = DocId::random();
+        let stamper = TestStamper::new(UserId::random());
+        let increment = || DeltaBuilder::new(DeltaOp::Increment).field(0, 0).int(1);
+
+        storage.apply_delta(stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        storage.apply_delta(stamper.stamp(doc_id, increment())).unwrap();
+        storage.apply_delta(stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateSnapshot))).unwrap();
+        storage.apply_delta(stamper.stamp(doc_id, increment())).unwrap();
+        assert_eq!(storage.deltas_since(0).len(), 1);
+
+        let (sequence, snapshot) = storage.latest_snapshot().unwrap();
+        assert_eq!(sequence, 3);
+        let tail = storage.deltas_since(sequence);
+        let tail: Vec<&[u8]> = tail.iter().map(|d| d.as_slice()).collect();
+        let reader = ZeroCopyDocumentStorage::load(schema, UserId::random(), Arc::new(SchemaDirectory::new()), Some(&snapshot), &tail).unwrap();
+        assert_eq!(reader.get_document(), storage.get_document());
+        assert_eq!(reader.sequence(), 4);
+
+        storage.apply_delta(stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::DeleteDocument))).unwrap();
+        assert!(storage.is_deleted());
+        assert!(storage.apply_delta(stamper.stamp(doc_id, increment())).is_err());
+    }
+
+    #[test]
+    fn test_bound_document_loads_with_its_schema() {
+        let (owner, schema_id, doc_id) = (UserId::random(), DocId::random(), DocId::random());
+        // Field 0 is an Int in the shared schema but a String in the bound one
+        let shared = Arc::new(SchemaRegistry::new());
+        shared.add_field(FieldDescriptor::new("count".to_string(), ValueType::Int).unwrap());
+        let bound = Arc::new(SchemaRegistry::new());
+        bound.add_field(FieldDescriptor::new("title".to_string(), ValueType::String).unwrap());
+        let directory = Arc::new(SchemaDirectory::new());
+        directory.register(owner, schema_id, bound);
+
+        let binding = SchemaBinding { schema_id, version: 0 };
+        let mut create = vec![DocumentType::Tree as u8];
+        binding.encode_into(&mut create);
+        let stamper = TestStamper::new(owner);
+        let log = [
+            stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&create)),
+            stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::Set).field(0, 0).string("draft")),
+            stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateSnapshot)),
+            stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::Set).field(0, 0).string("final")),
+        ];
+        let storage = ZeroCopyDocumentStorage::with_directory(shared.clone(), owner, directory.clone());
+        for delta in &log {
+            storage.apply_delta(delta.clone()).unwrap();
+        }
+
+        // Replaying every delta and loading the snapshot both bind the document again
+        let (_, snapshot) = storage.latest_snapshot().unwrap();
+        let all: Vec<&[u8]> = log.iter().map(|d| d.as_slice()).collect();
+        let load = |snapshot, deltas: &[&[u8]]| ZeroCopyDocumentStorage::load(shared.clone(), owner, directory.clone(), snapshot, deltas);
+        let next = stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1));
+        for reader in [load(None, &all).unwrap(), load(Some(&snapshot), &all[3..]).unwrap()] {
+            assert_eq!(reader.schema_binding(), Some(binding));
+            assert_eq!(reader.get_document(), storage.get_document());
+            assert!(reader.apply_delta(next.clone()).unwrap_err().contains("String"));
+        }
+        assert!(ZeroCopyDocumentStorage::load(shared, UserId::random(), directory, Some(&snapshot), &[]).is_err());
+    }
+
+    #[test]
+    fn test_stream_document_append_mark_and_read() {
+        let storage = ZeroCopyDocumentStorage::new();
+        let doc_id = DocId::random();
+        let stamper = TestStamper::new(UserId::random());
+        let append = |text: &str| DeltaBuilder::new(DeltaOp::StreamAppend).string(text);
+        let mut log = vec![
+            stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&[DocumentType::TextStream as u8])),
+            stamper.stamp(doc_id, append("first")),
+        ];
+        let mut mark = 1u64.to_le_bytes().to_vec();
+        Value::encode(ValueType::String, b"live", &mut mark).unwrap();
+        log.push(stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::StreamMarkAt).raw_value(&mark)));
+        log.push(stamper.stamp(doc_id, append("second")));
+        for delta in &log {
+            storage.apply_delta(delta.clone()).unwrap();
+        }
+
+        // A failed group publishes nothing
+        let group = [append("lost").build().unwrap(), DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1).build().unwrap()];
+        let group: Vec<&[u8]> = group.iter().map(|d| d.as_slice()).collect();
+        assert!(storage.apply_delta(stamper.stamp(doc_id, DeltaBuilder::group(&group).unwrap())).is_err());
+        assert!(storage.apply_delta(stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateSnapshot))).is_err());
+
+        let text = |s: &str| {
+            let mut bytes = Vec::new();
+            Value::encode(ValueType::String, s.as_bytes(), &mut bytes).unwrap();
+            bytes
+        };
+        let mut cursor = storage.stream_cursor(Some("live")).unwrap();
+        assert_eq!(cursor.next().map(StreamEntry::value), Some(text("second").as_slice()));
+        assert!(cursor.next().is_none());
+        assert_eq!(storage.stream_cursor(None).unwrap().next().map(StreamEntry::value), Some(text("first").as_slice()));
+        assert!(storage.stream_cursor(Some("missing")).is_err());
+
+        // Replaying the deltas rebuilds the stream
+        let log: Vec<&[u8]> = log.iter().map(|d| d.as_slice()).collect();
+        let reader = ZeroCopyDocumentStorage::load(Arc::new(SchemaRegistry::new()), UserId::random(), Arc::new(SchemaDirectory::new()), None, &log).unwrap();
+        assert_eq!(reader.stream_cursor(Some("live")).unwrap().next().map(StreamEntry::value), Some(text("second").as_slice()));
+    }
+}
@@ -0,0 +1,279 @@
+//! Stream document contents
+//!
+//! Stream documents (`TextStream`, `BinaryStream`, `DeltaStream`, `DocumentStream`,
+//! `EventStream`) hold an append-only sequence of wire `Value`s in a `SegmentedStream`,
+//! plus named marks - bookmarks at a position that readers can start a `Cursor` from.
+//!
+//! Payloads:
+//! ```text
+//! StreamAppend:  [Value]    String for TextStream, Binary for BinaryStream/DeltaStream,
+//!                           DocumentRef for DocumentStream, any type for EventStream
+//! StreamMarkAt:  [position: u64 LE][String value: mark name]
+//! ```
+//! A mark may point at any position up to the current length; re-marking a name moves it.
+//!
+//! Appends made while applying a delta are staged and only reach the shared segmented
+//! stream when the document storage publishes the delta, so a failing group never
+//! exposes entries to readers. Published entries point into the stamped delta that
+//! appended them rather than copying the value, so the delta log holds the only copy.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::structures::segmented_stream::{Cursor, SegmentedStream};
+use crate::types::delta::DeltaRef;
+use crate::types::document::DocumentType;
+use crate::types::value::Value;
+use crate::types::varint::decode_varint;
+use crate::types::{DeltaOp, ValueType};
+
+/// Entries per segmented stream page
+pub const STREAM_PAGE_SIZE: usize = 1024;
+
+/// Published entry: an encoded wire value inside the stamped delta that appended it
+#[derive(Clone, Debug)]
+pub struct StreamEntry {
+    /// Stamped wire bytes of the appending delta
+    delta: Arc<Vec<u8>>,
+    /// Position of the value within `delta`
+    range: Range<usize>,
+}
+
+impl StreamEntry {
+    /// Encoded wire value
+    pub fn value(&self) -> &[u8] {
+        &self.delta[self.range.clone()]
+    }
+}
+
+/// Entries and marks of a stream document
+///
+/// Clones share the published entries; only the live document should publish.
+#[derive(Clone)]
+pub struct DocumentStream {
+    /// Type of the stream document
+    doc_type: DocumentType,
+    /// Published entries
+    entries: Arc<SegmentedStream<StreamEntry>>,
+    /// Number of published entries
+    published: u64,
+    /// Number of entries appended by the delta being applied
+    pending: usize,
+    /// Entries of applied deltas not yet in `entries`, oldest first
+    unpublished: Vec<StreamEntry>,
+    /// Positions by mark name
+    marks: BTreeMap<String, u64>,
+}
+
+impl DocumentStream {
+    /// Create an empty stream for a stream document type
+    pub fn new(doc_type: DocumentType) -> Result<Self, String> {
+        if !doc_type.is_stream() {
+            return Err(format!("{:?} is not a stream document type", doc_type));
+        }
+        Ok(Self {
+            doc_type,
+            entries: Arc::new(SegmentedStream::with_page_size(STREAM_PAGE_SIZE)),
+            published: 0,
+            pending: 0,
+            unpublished: Vec::new(),
+            marks: BTreeMap::new(),
+        })
+    }
+
+    /// Type of the stream document
+    pub fn doc_type(&self) -> DocumentType {
+        self.doc_type
+    }
+
+    /// Number of entries, published or pending
+    pub fn len(&self) -> u64 {
+        self.published + self.unpublished.len() as u64 + self.pending as u64
+    }
+
+    /// Whether nothing has been appended
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Position of a mark
+    pub fn mark(&self, name: &str) -> Option<u64> {
+        self.marks.get(name).copied()
+    }
+
+    /// Stage a `StreamAppend` payload
+    pub fn append(&mut self, payload: &[u8]) -> Result<(), String> {
+        let len = Value::encoded_len(payload).map_err(|e| e.to_string())?;
+        if len != payload.len() {
+            return Err("StreamAppend carries a single value".to_string());
+        }
+        let expected = match self.doc_type {
+            DocumentType::TextStream => Some(ValueType::String),
+            DocumentType::BinaryStream | DocumentType::DeltaStream => Some(ValueType::Binary),
+            DocumentType::DocumentStream => Some(ValueType::DocumentRef),
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            if payload[0] != expected as u8 {
+                return Err(format!("{:?} expects {:?} entries, got type tag {}", self.doc_type, expected, payload[0]));
+            }
+        }
+        if expected == Some(ValueType::String) {
+            std::str::from_utf8(value_data(payload)?).map_err(|_| "Invalid UTF-8 in text stream entry".to_string())?;
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    /// Apply a `StreamMarkAt` payload
+    pub fn mark_at(&mut self, payload: &[u8]) -> Result<(), String> {
+        let (position, name) = payload.split_at_checked(8).ok_or("StreamMarkAt payload is missing its position")?;
+        let position = u64::from_le_bytes(position.try_into().unwrap());
+        if position > self.len() {
+            return Err(format!("Mark position {} is past the end ({})", position, self.len()));
+        }
+        if name.first() != Some(&(ValueType::String as u8)) || Value::encoded_len(name).map_err(|e| e.to_string())? != name.len() {
+            return Err("StreamMarkAt expects a String mark name".to_string());
+        }
+        let name = std::str::from_utf8(value_data(name)?).map_err(|_| "Invalid UTF-8 in mark name".to_string())?;
+        self.marks.insert(name.to_string(), position);
+        Ok(())
+    }
+
+    /// Make the entries staged by `delta`, the stamped delta just applied, visible to readers
+    ///
+    /// Entries that fail to publish stay staged and are published with the next delta.
+    pub fn publish(&mut self, delta: &Arc<Vec<u8>>) -> Result<(), String> {
+        if self.pending > 0 {
+            self.locate(delta)?;
+        }
+        let mut published = 0;
+        let result = self.unpublished.iter().try_for_each(|entry| {
+            self.entries.append(entry.clone()).map_err(|e| format!("Stream append failed: {:?}", e))?;
+            published += 1;
+            Ok(())
+        });
+        self.unpublished.drain(..published);
+        self.published += published as u64;
+        result
+    }
+
+    /// Move the entries staged by `delta` to `unpublished`
+    fn locate(&mut self, delta: &Arc<Vec<u8>>) -> Result<(), String> {
+        let parsed = DeltaRef::from_wire_bytes(delta).map_err(|e| e.to_string())?;
+        let payload = parsed.payload();
+        let mut values = Vec::new();
+        if payload.delta_op == DeltaOp::Deltas {
+            for child in payload.group().map_err(|e| e.to_string())?.iter() {
+                let child = child.payload().ok_or("Nested delta was not validated")?;
+                if child.delta_op == DeltaOp::StreamAppend {
+                    values.push(child.payload_value);
+                }
+            }
+        } else if payload.delta_op == DeltaOp::StreamAppend {
+            values.push(payload.payload_value);
+        }
+        // Staged entries are the delta's last appends (earlier ones of a group may
+        // precede a snapshot it straddles)
+        let staged = values.len().checked_sub(self.pending).ok_or("Delta did not stage the pending entries")?;
+        for value in &values[staged..] {
+            // Values are sub-slices of `delta`, so their offset locates them in it
+            let start = value.as_ptr() as usize - delta.as_ptr() as usize;
+            self.unpublished.push(StreamEntry { delta: delta.clone(), range: start..start + value.len() });
+        }
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Cursor whose next entry is the published entry at `position`
+    pub fn cursor_at(&self, position: u64) -> Result<Cursor<StreamEntry>, String> {
+        if position > self.published {
+            return Err(format!("Position {} is past the published end ({})", position, self.published));
+        }
+        let index = self.entries.index_at(position as usize).ok_or("Position not found in stream")?;
+        Cursor::new_at(&self.entries, &index).ok_or_else(|| "Position not found in stream".to_string())
+    }
+
+    /// Cursor starting at a named mark
+    pub fn cursor_from_mark(&self, name: &str) -> Result<Cursor<StreamEntry>, String> {
+        let position = self.mark(name).ok_or_else(|| format!("Unknown mark {}", name))?;
+        self.cursor_at(position)
+    }
+}
+
+/// Data bytes of a variable-length encoded value
+fn value_data(value: &[u8]) -> Result<&[u8], String> {
+    let (_, read) = decode_varint(&value[1..]).map_err(|e| e.to_string())?;
+    Ok(&value[1 + read..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::TestStamper;
+    use crate::types::DeltaBuilder;
+    use crate::{DocId, UserId};
+
+    fn text(s: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        Value::encode(ValueType::String, s.as_bytes(), &mut bytes).unwrap();
+        bytes
+    }
+
+    fn mark(position: u64, name: &str) -> Vec<u8> {
+        let mut payload = position.to_le_bytes().to_vec();
+        payload.extend(text(name));
+        payload
+    }
+
+    /// Stamp a group appending `words` and stage it on `stream`, as applying it would
+    fn append_all(stream: &mut DocumentStream, stamper: &TestStamper, words: &[&str]) -> Arc<Vec<u8>> {
+        let children: Vec<Vec<u8>> =
+            words.iter().map(|w| DeltaBuilder::new(DeltaOp::StreamAppend).string(w).build().unwrap()).collect();
+        let children: Vec<&[u8]> = children.iter().map(|c| c.as_slice()).collect();
+        let delta = Arc::new(stamper.stamp(DocId::random(), DeltaBuilder::group(&children).unwrap()));
+        for child in DeltaRef::from_wire_bytes(&delta).unwrap().payload().group().unwrap().iter() {
+            stream.append(child.payload().unwrap().payload_value).unwrap();
+        }
+        delta
+    }
+
+    #[test]
+    fn test_append_publish_and_read_from_mark() {
+        let stamper = TestStamper::new(UserId::random());
+        let mut stream = DocumentStream::new(DocumentType::TextStream).unwrap();
+        let delta = append_all(&mut stream, &stamper, &["a", "b", "c"]);
+        stream.mark_at(&mark(1, "second")).unwrap();
+        // Staged entries are not readable yet
+        assert!(stream.cursor_from_mark("second").is_err());
+
+        stream.publish(&delta).unwrap();
+        let mut cursor = stream.cursor_from_mark("second").unwrap();
+        let entry = cursor.next().unwrap();
+        assert_eq!(entry.value(), text("b"));
+        // Entries point into the delta instead of copying it
+        assert!(Arc::ptr_eq(&entry.delta, &delta));
+        assert_eq!(cursor.next().map(StreamEntry::value), Some(text("c").as_slice()));
+        assert!(cursor.next().is_none());
+
+        // A cursor at the tail sees later entries
+        let delta = append_all(&mut stream, &stamper, &["d"]);
+        stream.publish(&delta).unwrap();
+        assert_eq!(cursor.next().map(StreamEntry::value), Some(text("d").as_slice()));
+        assert_eq!(stream.len(), 4);
+    }
+
+    #[test]
+    fn test_entry_types_and_marks_validated() {
+        let mut stream = DocumentStream::new(DocumentType::TextStream).unwrap();
+        assert!(stream.append(&[ValueType::Binary as u8, 1, 0xff]).is_err());
+        assert!(stream.append(&[ValueType::String as u8, 1, 0xff]).is_err());
+        assert!(stream.mark_at(&mark(1, "past")).is_err());
+        assert!(stream.mark_at(&[0; 8]).is_err());
+
+        let mut events = DocumentStream::new(DocumentType::EventStream).unwrap();
+        events.append(&[ValueType::Bool as u8, 1]).unwrap();
+        assert!(DocumentStream::new(DocumentType::Tree).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Document hierarchy index
+//!
+//! Parent/child relationships between documents, maintained by `AddChild`, `RemoveChild`
+//! and `SetParent` deltas. Each document has at most one parent; children are indexed
+//! per parent so listing them never scans other documents.
+//!
+//! Payloads carry a document id (16 bytes):
+//! ```text
+//! AddChild     on parent:  [child id]
+//! RemoveChild  on parent:  [child id]
+//! SetParent    on child:   [parent id], or empty to make the document a root
+//! ```
+//! A change that would make a document its own ancestor is rejected. A new document is
+//! placed under a parent with a group of `CreateDocument` and `SetParent`. Both ends of a
+//! link belong to one user space, so links are kept per owner.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::RwLock;
+
+use crate::constants::ID16_LENGTH;
+use crate::types::delta::DeltaRef;
+use crate::types::DeltaOp;
+use crate::{DocId, UserId};
+
+/// Whether the operation edits the hierarchy
+pub fn is_hierarchy_op(op: DeltaOp) -> bool {
+    matches!(op, DeltaOp::AddChild | DeltaOp::RemoveChild | DeltaOp::SetParent)
+}
+
+/// Document id carried by a hierarchy payload (`None` for an empty SetParent)
+pub fn payload_doc_id(op: DeltaOp, payload: &[u8]) -> Result<Option<DocId>, String> {
+    match payload.len() {
+        0 if op == DeltaOp::SetParent => Ok(None),
+        ID16_LENGTH => Ok(Some(DocId::from_bytes(payload.try_into().unwrap()))),
+        len => Err(format!("{:?} expects a {}-byte document id, got {} bytes", op, ID16_LENGTH, len)),
+    }
+}
+
+/// Hierarchy ops (and `DeleteDocument`, which detaches the document) carried by a
+/// stamped delta, nested group deltas included, in apply order
+pub fn hierarchy_changes(delta: &DeltaRef) -> Result<Vec<(DeltaOp, Vec<u8>)>, String> {
+    let payload = delta.payload();
+    let relevant = |op| is_hierarchy_op(op) || op == DeltaOp::DeleteDocument;
+    if payload.delta_op != DeltaOp::Deltas {
+        return Ok(if relevant(payload.delta_op) { vec![(payload.delta_op, payload.payload_value.to_vec())] } else { Vec::new() });
+    }
+    let group = payload.group().map_err(|e| e.to_string())?;
+    let mut changes = Vec::new();
+    for child in group.iter() {
+        let child = child.payload().ok_or("Nested delta was not validated")?;
+        if relevant(child.delta_op) {
+            changes.push((child.delta_op, child.payload_value.to_vec()));
+        }
+    }
+    Ok(changes)
+}
+
+/// Parent before a change, used to undo it
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    /// Owner of the documents
+    owner: UserId,
+    /// Document whose parent changed
+    child: DocId,
+    /// Its parent before the change
+    previous: Option<DocId>,
+}
+
+/// Parent and children maps of each user's documents
+#[derive(Debug, Default)]
+struct Links {
+    /// Parent of each non-root document
+    parents: HashMap<(UserId, DocId), DocId>,
+    /// Children of each parent
+    children: HashMap<(UserId, DocId), BTreeSet<DocId>>,
+}
+
+impl Links {
+    /// Set or clear a parent, returning the previous one
+    fn set_parent(&mut self, owner: UserId, child: DocId, parent: Option<DocId>) -> Option<DocId> {
+        let previous = match parent {
+            Some(parent) => self.parents.insert((owner, child), parent),
+            None => self.parents.remove(&(owner, child)),
+        };
+        if let Some(previous) = previous {
+            if let Some(siblings) = self.children.get_mut(&(owner, previous)) {
+                siblings.remove(&child);
+                if siblings.is_empty() {
+                    self.children.remove(&(owner, previous));
+                }
+            }
+        }
+        if let Some(parent) = parent {
+            self.children.entry((owner, parent)).or_default().insert(child);
+        }
+        previous
+    }
+
+    /// Whether `ancestor` is `doc` or one of its ancestors
+    fn is_ancestor_or_self(&self, owner: UserId, ancestor: DocId, mut doc: DocId) -> bool {
+        loop {
+            if doc == ancestor {
+                return true;
+            }
+            match self.parents.get(&(owner, doc)) {
+                Some(&parent) => doc = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Concurrent document hierarchy
+#[derive(Debug, Default)]
+pub struct DocumentHierarchy {
+    links: RwLock<Links>,
+}
+
+impl DocumentHierarchy {
+    /// Create an empty hierarchy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a hierarchy op carried by a delta on `doc_id` of `owner`, returning how to undo it
+    pub fn apply(&self, owner: UserId, doc_id: DocId, op: DeltaOp, payload: &[u8]) -> Result<Undo, String> {
+        let target = payload_doc_id(op, payload)?;
+        let mut links = self.links.write().unwrap();
+        let (child, parent) = match (op, target) {
+            (DeltaOp::AddChild, Some(child)) => (child, Some(doc_id)),
+            (DeltaOp::RemoveChild, Some(child)) => {
+                if links.parents.get(&(owner, child)) != Some(&doc_id) {
+                    return Err(format!("{} is not a child of {}", child, doc_id));
+                }
+                (child, None)
+            }
+            (DeltaOp::SetParent, parent) => (doc_id, parent),
+            _ => return Err(format!("{:?} is not a hierarchy operation", op)),
+        };
+        if let Some(parent) = parent {
+            if links.is_ancestor_or_self(owner, child, parent) {
+                return Err(format!("Making {} the parent of {} would create a cycle", parent, child));
+            }
+        }
+        let previous = links.set_parent(owner, child, parent);
+        Ok(Undo { owner, child, previous })
+    }
+
+    /// Revert changes returned by `apply`, most recent first
+    pub fn undo(&self, undo: &[Undo]) {
+        let mut links = self.links.write().unwrap();
+        for change in undo.iter().rev() {
+            links.set_parent(change.owner, change.child, change.previous);
+        }
+    }
+
+    /// Detach a deleted document of `owner` from its parent and make its children roots
+    pub fn remove_document(&self, owner: UserId, doc_id: DocId) -> Vec<Undo> {
+        let mut links = self.links.write().unwrap();
+        let mut undo = Vec::new();
+        let children: Vec<DocId> =
+            links.children.get(&(owner, doc_id)).map(|c| c.iter().copied().collect()).unwrap_or_default();
+        for child in children {
+            undo.push(Undo { owner, child, previous: links.set_parent(owner, child, None) });
+        }
+        undo.push(Undo { owner, child: doc_id, previous: links.set_parent(owner, doc_id, None) });
+        undo
+    }
+
+    /// Parent of a document of `owner`
+    pub fn parent(&self, owner: UserId, doc_id: DocId) -> Option<DocId> {
+        self.links.read().unwrap().parents.get(&(owner, doc_id)).copied()
+    }
+
+    /// Children of a document of `owner`, in id order
+    pub fn children(&self, owner: UserId, doc_id: DocId) -> Vec<DocId> {
+        let links = self.links.read().unwrap();
+        links.children.get(&(owner, doc_id)).map(|c| c.iter().copied().collect()).unwrap_or_default()
+    }
+
+    /// Ancestors of a document of `owner`, nearest first
+    pub fn ancestors(&self, owner: UserId, doc_id: DocId) -> Vec<DocId> {
+        let links = self.links.read().unwrap();
+        let mut ancestors = Vec::new();
+        let mut current = doc_id;
+        while let Some(&parent) = links.parents.get(&(owner, current)) {
+            ancestors.push(parent);
+            current = parent;
+        }
+        ancestors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(doc: DocId) -> Vec<u8> {
+        doc.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_add_move_and_remove_children() {
+        let hierarchy = DocumentHierarchy::new();
+        let user = UserId::random();
+        let (root, a, b) = (DocId::random(), DocId::random(), DocId::random());
+
+        hierarchy.apply(user, root, DeltaOp::AddChild, &id(a)).unwrap();
+        hierarchy.apply(user, b, DeltaOp::SetParent, &id(a)).unwrap();
+        assert_eq!(hierarchy.children(user, root), vec![a]);
+        assert_eq!(hierarchy.ancestors(user, b), vec![a, root]);
+
+        // Moving b to root removes it from a's children
+        hierarchy.apply(user, root, DeltaOp::AddChild, &id(b)).unwrap();
+        assert!(hierarchy.children(user, a).is_empty());
+        assert_eq!(hierarchy.parent(user, b), Some(root));
+
+        assert!(hierarchy.apply(user, a, DeltaOp::RemoveChild, &id(b)).is_err());
+        hierarchy.apply(user, root, DeltaOp::RemoveChild, &id(b)).unwrap();
+        hierarchy.apply(user, a, DeltaOp::SetParent, &[]).unwrap();
+        assert!(hierarchy.children(user, root).is_empty());
+    }
+
+    #[test]
+    fn test_cycles_rejected() {
+        let hierarchy = DocumentHierarchy::new();
+        let user = UserId::random();
+        let (a, b, c) = (DocId::random(), DocId::random(), DocId::random());
+        hierarchy.apply(user, a, DeltaOp::AddChild, &id(b)).unwrap();
+        hierarchy.apply(user, b, DeltaOp::AddChild, &id(c)).unwrap();
+
+        assert!(hierarchy.apply(user, c, DeltaOp::AddChild, &id(a)).is_err());
+        assert!(hierarchy.apply(user, a, DeltaOp::SetParent, &id(c)).is_err());
+        assert!(hierarchy.apply(user, a, DeltaOp::SetParent, &id(a)).is_err());
+        assert_eq!(hierarchy.ancestors(user, c), vec![b, a]);
+    }
+
+    #[test]
+    fn test_undo_and_remove_document() {
+        let hierarchy = DocumentHierarchy::new();
+        let user = UserId::random();
+        let (a, b, c) = (DocId::random(), DocId::random(), DocId::random());
+        hierarchy.apply(user, a, DeltaOp::AddChild, &id(b)).unwrap();
+        let undo = vec![
+            hierarchy.apply(user, c, DeltaOp::AddChild, &id(b)).unwrap(),
+            hierarchy.apply(user, a, DeltaOp::AddChild, &id(c)).unwrap(),
+        ];
+        hierarchy.undo(&undo);
+        assert_eq!(hierarchy.parent(user, b), Some(a));
+        assert_eq!(hierarchy.parent(user, c), None);
+
+        hierarchy.apply(user, b, DeltaOp::AddChild, &id(c)).unwrap();
+        hierarchy.remove_document(user, b);
+        assert!(hierarchy.children(user, a).is_empty());
+        assert_eq!(hierarchy.parent(user, c), None);
+        assert!(hierarchy.apply(user, b, DeltaOp::AddChild, &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_links_kept_per_user() {
+        let hierarchy = DocumentHierarchy::new();
+        let (owner, other) = (UserId::random(), UserId::random());
+        let (parent, child) = (DocId::random(), DocId::random());
+        hierarchy.apply(owner, parent, DeltaOp::AddChild, &id(child)).unwrap();
+        hierarchy.apply(other, child, DeltaOp::AddChild, &id(parent)).unwrap();
+        assert_eq!(hierarchy.children(owner, parent), vec![child]);
+        assert_eq!(hierarchy.parent(other, parent), Some(child));
+
+        // Another user's document with the same id keeps the owner's children
+        hierarchy.remove_document(other, parent);
+        assert_eq!(hierarchy.children(owner, parent), vec![child]);
+        assert!(hierarchy.apply(other, parent, DeltaOp::RemoveChild, &id(child)).is_err());
+    }
+}
@@ -28,6 +28,24 @@ pub trait DocumentStorage: Send + Sync {
 /// Zero-Copy Store (high-performance implementation)
 pub mod document_storage;
 
+/// Materialised document values
+pub mod document_state;
+
+/// Stream document entries and marks
+pub mod document_stream;
+
+/// Schema documents and the bindings documents resolve fields through
+pub mod schema_directory;
+
+/// Parent/child relationships between documents
+pub mod hierarchy;
+
+/// Reference resolution and the reverse-reference index
+pub mod references;
+
+/// Store schema persisted to the data directory
+pub mod schema_log;
+
 /// Simple Store (JSON-based implementation)
 pub mod document_simple;
 
@@ -37,6 +55,10 @@ pub mod user_space;
 /// Flat Storage (multi-user storage management)
 pub mod store;
 
+/// Helpers shared by storage tests
+#[cfg(test)]
+pub(crate) mod test_support;
+
 /// Re-export main storage types
 pub use document_storage::{ZeroCopyDocumentStorage};
 pub use document_simple::SimpleDocumentStorage;
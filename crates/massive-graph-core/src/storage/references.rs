@@ -0,0 +1,139 @@
+//! Reference resolution and the reverse-reference index
+//!
+//! Field values may point at other documents through `DocumentRef` and `DeltaRef` values
+//! (see `value_reference`). The store follows them with `Store::resolve` and indexes them
+//! per target, so the documents pointing at a document are listed without scanning, e.g.
+//! before deleting it. References resolve within their owner's user space, so documents
+//! are indexed by `(UserId, DocId)`. The index is rebuilt for a document from its values
+//! each time a delta applies; a deleted document drops its own references but stays
+//! indexed as a target while others still point at it.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+use crate::storage::ZeroCopyDocumentStorage;
+use crate::{DocId, UserId};
+
+/// Target of a reference value, resolved by `Store::resolve`
+#[derive(Clone, Debug)]
+pub enum Resolved {
+    /// Referenced document
+    Document(Arc<ZeroCopyDocumentStorage>),
+    /// Stamped wire bytes of the referenced delta
+    Delta(Arc<Vec<u8>>),
+    /// Referenced user, which has a user space
+    User(UserId),
+}
+
+/// Outgoing and incoming references between the documents of each user
+#[derive(Debug, Default)]
+struct Links {
+    /// Documents each document references
+    outgoing: HashMap<(UserId, DocId), BTreeSet<DocId>>,
+    /// Documents referencing each document
+    incoming: HashMap<(UserId, DocId), BTreeSet<DocId>>,
+}
+
+impl Links {
+    /// Replace the documents `doc_id` of `owner` references
+    fn set(&mut self, owner: UserId, doc_id: DocId, targets: BTreeSet<DocId>) {
+        let previous = if targets.is_empty() {
+            self.outgoing.remove(&(owner, doc_id))
+        } else {
+            self.outgoing.insert((owner, doc_id), targets.clone())
+        };
+        let previous = previous.unwrap_or_default();
+        for removed in previous.difference(&targets) {
+            if let Some(referrers) = self.incoming.get_mut(&(owner, *removed)) {
+                referrers.remove(&doc_id);
+                if referrers.is_empty() {
+                    self.incoming.remove(&(owner, *removed));
+                }
+            }
+        }
+        for added in targets.difference(&previous) {
+            self.incoming.entry((owner, *added)).or_default().insert(doc_id);
+        }
+    }
+}
+
+/// Concurrent reverse-reference index
+#[derive(Debug, Default)]
+pub struct ReferenceIndex {
+    links: RwLock<Links>,
+}
+
+impl ReferenceIndex {
+    /// Create an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Re-index the references of `doc_id` of `owner` from its current values, or drop
+    /// them when the document no longer exists
+    ///
+    /// Values are read before taking the index lock. A document that has moved on by the
+    /// time the lock is held is skipped, as the refresh after its latest delta indexes it,
+    /// so concurrent refreshes of one document always leave the index matching its latest
+    /// values.
+    pub fn refresh(&self, owner: UserId, doc_id: DocId, document: Option<&ZeroCopyDocumentStorage>) {
+        let (sequence, mut targets) =
+            document.map(|document| (document.sequence(), document.referenced_documents())).unwrap_or_default();
+        let mut links = self.links.write().unwrap();
+        if document.is_some_and(|document| document.sequence() != sequence) {
+            return;
+        }
+        // A document pointing at itself never blocks its own deletion
+        targets.remove(&doc_id);
+        links.set(owner, doc_id, targets);
+    }
+
+    /// Documents of `owner` referencing its document `doc_id`, in id order
+    pub fn referrers(&self, owner: UserId, doc_id: DocId) -> Vec<DocId> {
+        let links = self.links.read().unwrap();
+        links.incoming.get(&(owner, doc_id)).map(|r| r.iter().copied().collect()).unwrap_or_default()
+    }
+
+    /// Documents of `owner` its document `doc_id` references, in id order
+    pub fn references(&self, owner: UserId, doc_id: DocId) -> Vec<DocId> {
+        let links = self.links.read().unwrap();
+        links.outgoing.get(&(owner, doc_id)).map(|r| r.iter().copied().collect()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_links_track_changed_targets() {
+        let mut links = Links::default();
+        let user = UserId::random();
+        let (a, b, c, d) = (DocId::random(), DocId::random(), DocId::random(), DocId::random());
+        links.set(user, a, BTreeSet::from([b, c]));
+        links.set(user, d, BTreeSet::from([c]));
+        assert_eq!(links.incoming[&(user, c)], BTreeSet::from([a, d]));
+
+        // Dropping a target removes only that edge
+        links.set(user, a, BTreeSet::from([c]));
+        assert!(!links.incoming.contains_key(&(user, b)));
+        assert_eq!(links.outgoing[&(user, a)], BTreeSet::from([c]));
+
+        links.set(user, a, BTreeSet::new());
+        links.set(user, d, BTreeSet::new());
+        assert!(links.incoming.is_empty() && links.outgoing.is_empty());
+    }
+
+    #[test]
+    fn test_links_kept_per_user() {
+        let mut links = Links::default();
+        let (owner, other) = (UserId::random(), UserId::random());
+        let (a, target) = (DocId::random(), DocId::random());
+        links.set(owner, a, BTreeSet::from([target]));
+        links.set(other, a, BTreeSet::from([a]));
+        links.set(other, a, BTreeSet::new());
+        assert_eq!(links.outgoing[&(owner, a)], BTreeSet::from([target]));
+        assert_eq!(links.incoming[&(owner, target)], BTreeSet::from([a]));
+        assert!(!links.incoming.contains_key(&(other, target)));
+    }
+}
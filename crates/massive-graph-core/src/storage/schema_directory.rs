@@ -0,0 +1,212 @@
+//! Schema documents
+//!
+//! A document created with `DocumentType::Schema` owns a `SchemaRegistry` that its
+//! `CreateSchema` deltas publish field descriptors to. Other documents of the same user
+//! bind to a schema document by id and version when they are created, and resolve their
+//! fields against its registry instead of the process-wide one, so tenants get separate
+//! field index spaces. The directory is shared by every user space but keyed by owner,
+//! so one user's documents can neither bind to nor replace another user's schemas.
+//!
+//! Payloads:
+//! ```text
+//! CreateSchema:    [field count varint][FieldDescriptor::encode_into]...
+//! CreateDocument:  [] | [doc type: u8] | [doc type: u8][schema doc id: 16][schema version: u32 LE]
+//! ```
+//! Published fields are appended, so field indices of a schema never change meaning.
+
+use std::sync::Arc;
+
+use dashmap::DashMap;
+
+use crate::constants::ID16_LENGTH;
+use crate::types::varint::{decode_varint, encode_varint};
+use crate::types::{FieldDescriptor, SchemaRegistry};
+use crate::{DocId, UserId};
+
+/// Schema a document is bound to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SchemaBinding {
+    /// Schema document id
+    pub schema_id: DocId,
+    /// Schema version the document was created against
+    pub version: u32,
+}
+
+impl SchemaBinding {
+    /// Encoded size following the document type byte
+    pub const ENCODED_SIZE: usize = ID16_LENGTH + 4;
+
+    /// Decode a binding from a `CreateDocument` payload tail
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != Self::ENCODED_SIZE {
+            return Err(format!("Schema binding expects {} bytes, got {}", Self::ENCODED_SIZE, bytes.len()));
+        }
+        let (id, version) = bytes.split_at(ID16_LENGTH);
+        Ok(Self {
+            schema_id: DocId::from_bytes(id.try_into().unwrap()),
+            version: u32::from_le_bytes(version.try_into().unwrap()),
+        })
+    }
+
+    /// Append the binding to a `CreateDocument` payload
+    pub fn encode_into(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(self.schema_id.as_bytes());
+        output.extend_from_slice(&self.version.to_le_bytes());
+    }
+}
+
+/// Encode a `CreateSchema` payload
+pub fn encode_fields(fields: &[FieldDescriptor]) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    encode_varint(fields.len() as u32, &mut output)?;
+    for field in fields {
+        field.encode_into(&mut output)?;
+    }
+    Ok(output)
+}
+
+/// Decode a `CreateSchema` payload
+pub fn decode_fields(bytes: &[u8]) -> Result<Vec<FieldDescriptor>, String> {
+    let (count, mut offset) = decode_varint(bytes).map_err(|e| e.to_string())?;
+    let mut fields = Vec::new();
+    for _ in 0..count {
+        let (field, read) = FieldDescriptor::decode(&bytes[offset..]).map_err(|e| e.to_string())?;
+        fields.push(field);
+        offset += read;
+    }
+    if offset != bytes.len() {
+        return Err("Trailing bytes after schema fields".to_string());
+    }
+    Ok(fields)
+}
+
+/// Fields published by a schema document
+///
+/// Fields staged by `CreateSchema` reach the registry only when published, so a
+/// failing group never publishes them. Clones share the registry.
+#[derive(Clone)]
+pub struct SchemaDefinition {
+    /// Published fields
+    registry: Arc<SchemaRegistry>,
+    /// Fields staged but not yet published
+    pending: Vec<FieldDescriptor>,
+}
+
+impl SchemaDefinition {
+    /// Create a definition with no fields
+    pub fn new() -> Self {
+        Self { registry: Arc::new(SchemaRegistry::new()), pending: Vec::new() }
+    }
+
+    /// Registry holding the published fields
+    pub fn registry(&self) -> &Arc<SchemaRegistry> {
+        &self.registry
+    }
+
+    /// Stage the fields of a `CreateSchema` payload
+    pub fn stage(&mut self, payload: &[u8]) -> Result<(), String> {
+        let fields = decode_fields(payload)?;
+        for field in &fields {
+            let staged = self.pending.iter().any(|p| p.path == field.path);
+            if staged || self.registry.get_field_index(&field.path).is_some() {
+                return Err(format!("Field {} is already defined", field.path));
+            }
+        }
+        self.pending.extend(fields);
+        Ok(())
+    }
+
+    /// Add staged fields to the registry
+    pub fn publish(&mut self) {
+        for field in self.pending.drain(..) {
+            self.registry.add_field(field);
+        }
+    }
+}
+
+impl Default for SchemaDefinition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Schema registries of schema documents, by owner and document id
+#[derive(Debug, Default)]
+pub struct SchemaDirectory {
+    schemas: DashMap<(UserId, DocId), Arc<SchemaRegistry>>,
+}
+
+impl SchemaDirectory {
+    /// Create an empty directory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register (or replace) the registry of a schema document of `owner`
+    pub fn register(&self, owner: UserId, schema_id: DocId, registry: Arc<SchemaRegistry>) {
+        self.schemas.insert((owner, schema_id), registry);
+    }
+
+    /// Registry of a schema document of `owner`
+    pub fn get(&self, owner: UserId, schema_id: DocId) -> Option<Arc<SchemaRegistry>> {
+        self.schemas.get(&(owner, schema_id)).map(|r| r.value().clone())
+    }
+
+    /// Forget a deleted schema document; documents already bound keep their registry
+    pub fn remove(&self, owner: UserId, schema_id: DocId) {
+        self.schemas.remove(&(owner, schema_id));
+    }
+
+    /// Registry for a binding of a document of `owner`, checking the bound version exists
+    pub fn resolve(&self, owner: UserId, binding: &SchemaBinding) -> Result<Arc<SchemaRegistry>, String> {
+        let registry = self.get(owner, binding.schema_id).ok_or_else(|| format!("Unknown schema {}", binding.schema_id))?;
+        if registry.get_historical_schema(binding.version).is_none() {
+            return Err(format!("Schema {} has no version {}", binding.schema_id, binding.version));
+        }
+        Ok(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValueType;
+
+    fn field(path: &str, value_type: ValueType) -> FieldDescriptor {
+        FieldDescriptor::new(path.to_string(), value_type).unwrap()
+    }
+
+    #[test]
+    fn test_fields_round_trip() {
+        let fields = vec![field("name", ValueType::String), field("*.users{}.age", ValueType::Int)];
+        let bytes = encode_fields(&fields).unwrap();
+        let decoded = decode_fields(&bytes).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].path, "users{}.age");
+        assert!(decoded[1].requires_parent);
+        assert_eq!(decoded[1].value_type, ValueType::Int);
+        for len in 0..bytes.len() {
+            assert!(decode_fields(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_staged_fields_publish_once() {
+        let mut definition = SchemaDefinition::new();
+        definition.stage(&encode_fields(&[field("a", ValueType::Int)]).unwrap()).unwrap();
+        assert!(definition.stage(&encode_fields(&[field("a", ValueType::Float)]).unwrap()).is_err());
+        assert!(definition.registry().get_field_index("a").is_none());
+
+        definition.publish();
+        assert_eq!(definition.registry().get_field_index("a"), Some(0));
+        assert!(definition.stage(&encode_fields(&[field("a", ValueType::Int)]).unwrap()).is_err());
+
+        let directory = SchemaDirectory::new();
+        let (owner, schema_id) = (UserId::random(), DocId::random());
+        directory.register(owner, schema_id, definition.registry().clone());
+        assert!(directory.resolve(owner, &SchemaBinding { schema_id, version: 0 }).is_ok());
+        assert!(directory.resolve(owner, &SchemaBinding { schema_id, version: 7 }).is_err());
+        assert!(directory.resolve(owner, &SchemaBinding { schema_id: DocId::random(), version: 0 }).is_err());
+        assert!(directory.resolve(UserId::random(), &SchemaBinding { schema_id, version: 0 }).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Persisted schema registry
+//!
+//! Every field index of the store schema is written to an append-only log in the data
+//! directory before it becomes visible, and the log is replayed at startup, so stored
+//! deltas keep resolving to the same fields across restarts.
+//!
+//! File layout (`schema.log`):
+//! ```text
+//! [magic "MGSL"][format: u8]
+//! record:  [payload length: u32 LE][checksum: first 4 bytes of blake3(length, payload)][payload]
+//! payload: [1][version varint][field index varint][FieldDescriptor::encode_into]
+//!          [2][version varint][slot count varint] per slot: [canonical varint][0 | 1 + descriptor]
+//! ```
+//! Tag 1 records a field added to the current version, tag 2 a new current version (see
+//! `SchemaRegistry::create_optimized_version`). Each record is synced before the change
+//! is applied. A record torn by a crash can only be the last one: a damaged record is
+//! truncated on load when no intact record follows it, and reported as corruption
+//! otherwise. A log whose header was torn while it was created starts afresh.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::types::schema::SchemaJournal;
+use crate::types::varint::{decode_varint, encode_varint};
+use crate::types::{FieldDescriptor, ImmutableSchema, SchemaRegistry};
+
+/// Log file name inside the data directory
+pub const SCHEMA_LOG_FILE: &str = "schema.log";
+
+/// File magic
+const MAGIC: &[u8; 4] = b"MGSL";
+
+/// Log format version
+const FORMAT: u8 = 2;
+
+/// Record tag: field added
+const FIELD_ADDED: u8 = 1;
+
+/// Record tag: version created
+const VERSION_CREATED: u8 = 2;
+
+/// Record framing: length and checksum
+const RECORD_HEADER: usize = 8;
+
+/// Append-only schema log, attached to a registry as its journal
+#[derive(Debug)]
+pub struct SchemaLog {
+    /// Log file path
+    path: PathBuf,
+    /// Open log, appended under the lock
+    file: Mutex<File>,
+}
+
+impl SchemaLog {
+    /// Load the schema registry persisted in `data_dir`, creating an empty log if there
+    /// is none; every later change to the returned registry is persisted
+    pub fn load(data_dir: &Path) -> Result<Arc<SchemaRegistry>, String> {
+        fs::create_dir_all(data_dir).map_err(|e| format!("Cannot create {}: {}", data_dir.display(), e))?;
+        let path = data_dir.join(SCHEMA_LOG_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+
+        let registry = Arc::new(SchemaRegistry::new());
+        let mut header = MAGIC.to_vec();
+        header.push(FORMAT);
+        if bytes.len() < header.len() && header.starts_with(&bytes) {
+            // New log, or one whose creation was cut short
+            file.set_len(0)
+                .and_then(|_| file.write_all(&header))
+                .and_then(|_| file.sync_data())
+                .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+            sync_dir(data_dir)?;
+        } else {
+            let valid = replay(&registry, &bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+            if valid < bytes.len() {
+                // Torn final record: drop it so new records follow the last complete one
+                file.set_len(valid as u64).and_then(|_| file.sync_data()).map_err(|e| format!("Cannot truncate {}: {}", path.display(), e))?;
+            }
+        }
+
+        registry.attach_journal(Arc::new(Self { path, file: Mutex::new(file) }))?;
+        Ok(registry)
+    }
+
+    /// Append and sync one record
+    fn append(&self, payload: &[u8]) -> Result<(), String> {
+        let len = u32::try_from(payload.len()).map_err(|_| "Schema log record too large".to_string())?.to_le_bytes();
+        let mut record = Vec::with_capacity(RECORD_HEADER + payload.len());
+        record.extend_from_slice(&len);
+        record.extend_from_slice(&checksum(&len, payload));
+        record.extend_from_slice(payload);
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&record)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Cannot append to {}: {}", self.path.display(), e))
+    }
+}
+
+impl SchemaJournal for SchemaLog {
+    fn field_added(&self, version: u32, index: u32, descriptor: &FieldDescriptor) -> Result<(), String> {
+        let mut payload = vec![FIELD_ADDED];
+        encode_varint(version, &mut payload)?;
+        encode_varint(index, &mut payload)?;
+        descriptor.encode_into(&mut payload)?;
+        self.append(&payload)
+    }
+
+    fn version_created(&self, schema: &ImmutableSchema) -> Result<(), String> {
+        let mut payload = vec![VERSION_CREATED];
+        encode_varint(schema.version, &mut payload)?;
+        encode_varint(schema.len() as u32, &mut payload)?;
+        for (index, field) in schema.fields.iter().enumerate() {
+            encode_varint(schema.canonical_index(index as u32), &mut payload)?;
+            match field {
+                Some(field) => {
+                    payload.push(1);
+                    field.encode_into(&mut payload)?;
+                }
+                None => payload.push(0),
+            }
+        }
+        self.append(&payload)
+    }
+}
+
+/// Replay a log into a fresh registry, returning the length of its intact prefix
+fn replay(registry: &SchemaRegistry, bytes: &[u8]) -> Result<usize, String> {
+    if bytes.len() < MAGIC.len() + 1 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err("Not a schema log".to_string());
+    }
+    if bytes[MAGIC.len()] != FORMAT {
+        return Err(format!("Unsupported schema log format {}", bytes[MAGIC.len()]));
+    }
+    let mut offset = MAGIC.len() + 1;
+    while offset < bytes.len() {
+        let Some((payload, end)) = record_at(bytes, offset) else {
+            // A torn write leaves nothing intact after it
+            if (offset + 1..bytes.len()).any(|start| record_at(bytes, start).is_some()) {
+                return Err(format!("Corrupt record at byte {}", offset));
+            }
+            return Ok(offset);
+        };
+        apply_record(registry, payload).map_err(|e| format!("Record at byte {}: {}", offset, e))?;
+        offset = end;
+    }
+    Ok(offset)
+}
+
+/// Payload and end of the intact record starting at `offset`, if any
+fn record_at(bytes: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let header = bytes.get(offset..offset + RECORD_HEADER)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let end = (offset + RECORD_HEADER).checked_add(len)?;
+    let payload = bytes.get(offset + RECORD_HEADER..end)?;
+    (checksum(&header[..4], payload) == header[4..]).then_some((payload, end))
+}
+
+/// Record checksum, covering the length so a damaged length is detected
+fn checksum(len: &[u8], payload: &[u8]) -> [u8; 4] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(len);
+    hasher.update(payload);
+    hasher.finalize().as_bytes()[..4].try_into().unwrap()
+}
+
+/// Sync a directory so a file created in it survives a crash
+fn sync_dir(dir: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    File::open(dir).and_then(|dir| dir.sync_all()).map_err(|e| format!("Cannot sync {}: {}", dir.display(), e))?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Apply one record payload
+fn apply_record(registry: &SchemaRegistry, payload: &[u8]) -> Result<(), String> {
+    let (&tag, mut rest) = payload.split_first().ok_or("Empty record")?;
+    let varint = |rest: &mut &[u8]| -> Result<u32, String> {
+        let (value, read) = decode_varint(rest).map_err(|e| e.to_string())?;
+        *rest = &rest[read..];
+        Ok(value)
+    };
+    let descriptor = |rest: &mut &[u8]| -> Result<FieldDescriptor, String> {
+        let (field, read) = FieldDescriptor::decode(rest).map_err(|e| e.to_string())?;
+        *rest = &rest[read..];
+        Ok(field)
+    };
+    match tag {
+        FIELD_ADDED => {
+            let version = varint(&mut rest)?;
+            let index = varint(&mut rest)?;
+            let field = descriptor(&mut rest)?;
+            if !rest.is_empty() {
+                return Err("Trailing bytes after field".to_string());
+            }
+            registry.restore_field(version, index, field)
+        }
+        VERSION_CREATED => {
+            let version = varint(&mut rest)?;
+            let slots = varint(&mut rest)?;
+            let mut fields = Vec::new();
+            let mut canonical = Vec::new();
+            for _ in 0..slots {
+                canonical.push(varint(&mut rest)?);
+                let (&present, tail) = rest.split_first().ok_or("Truncated version record")?;
+                rest = tail;
+                fields.push(if present == 1 { Some(descriptor(&mut rest)?) } else { None });
+            }
+            if !rest.is_empty() {
+                return Err("Trailing bytes after version".to_string());
+            }
+            registry.restore_version(ImmutableSchema::with_canonical(version, fields, canonical.into()))
+        }
+        _ => Err(format!("Unknown record tag {}", tag)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValueType;
+
+    fn data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("massive-graph-schema-log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn field(path: &str) -> FieldDescriptor {
+        FieldDescriptor::new(path.to_string(), ValueType::Int).unwrap()
+    }
+
+    #[test]
+    fn test_registry_survives_restart() {
+        let dir = data_dir("restart");
+        let registry = SchemaLog::load(&dir).unwrap();
+        registry.add_field(field("a"));
+        registry.add_field(field("*users{}.age"));
+        registry.record_access(1, 0);
+        registry.create_optimized_version();
+        registry.add_field(field("c"));
+        drop(registry);
+
+        let reloaded = SchemaLog::load(&dir).unwrap();
+        assert_eq!(reloaded.current_version().version(), 1);
+        assert_eq!(reloaded.get_field_index("users{}.age"), Some(0));
+        assert!(reloaded.get_field(0).unwrap().requires_parent);
+        assert_eq!(reloaded.get_field_index("c"), Some(2));
+        assert_eq!(reloaded.get_field_index_at_version("a", 0), Some(0));
+        assert_eq!(reloaded.translate_index(1, 0, 1), Some(0));
+
+        // Changes after a reload are persisted too
+        assert_eq!(reloaded.add_field(field("d")), 3);
+        drop(reloaded);
+        assert_eq!(SchemaLog::load(&dir).unwrap().get_field_index("d"), Some(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_truncated_and_corruption_reported() {
+        let dir = data_dir("torn");
+        let registry = SchemaLog::load(&dir).unwrap();
+        registry.add_field(field("a"));
+        registry.add_field(field("b"));
+        drop(registry);
+
+        let path = dir.join(SCHEMA_LOG_FILE);
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+        let registry = SchemaLog::load(&dir).unwrap();
+        assert_eq!(registry.get_field_index("a"), Some(0));
+        assert_eq!(registry.get_field_index("b"), None);
+        assert_eq!(registry.add_field(field("e")), 1);
+        drop(registry);
+        assert_eq!(SchemaLog::load(&dir).unwrap().get_field_index("e"), Some(1));
+
+        // A damaged record followed by an intact one is not a torn write
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[MAGIC.len() + 1 + RECORD_HEADER] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        assert!(SchemaLog::load(&dir).unwrap_err().contains("Corrupt record"));
+
+        // So is a damaged length, even when it points past the end
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[MAGIC.len() + 1 + RECORD_HEADER] ^= 0xff;
+        bytes[MAGIC.len() + 1..MAGIC.len() + 5].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(SchemaLog::load(&dir).unwrap_err().contains("Corrupt record"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_header_starts_a_new_log() {
+        let dir = data_dir("header");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(SCHEMA_LOG_FILE);
+        fs::write(&path, &MAGIC[..2]).unwrap();
+        let registry = SchemaLog::load(&dir).unwrap();
+        assert_eq!(registry.add_field(field("a")), 0);
+        drop(registry);
+        assert_eq!(SchemaLog::load(&dir).unwrap().get_field_index("a"), Some(0));
+
+        fs::write(&path, b"MGXX").unwrap();
+        assert!(SchemaLog::load(&dir).unwrap_err().contains("Not a schema log"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,9 +2,15 @@
 
 use crate::storage::{ZeroCopyDocumentStorage};
 use crate::structures::mph_delta_index::{OptimisedIndexGen, mph_indexer::MphIndexer};
-use crate::types::{UserId, DocId};
+use crate::types::{SchemaRegistry, UserId, DocId};
 use crate::storage::user_space::UserSpace;
-use std::sync::Arc;
+use crate::storage::hierarchy::{hierarchy_changes, is_hierarchy_op, payload_doc_id, DocumentHierarchy};
+use crate::storage::schema_directory::SchemaDirectory;
+use crate::storage::references::{ReferenceIndex, Resolved};
+use crate::types::delta::DeltaRef;
+use crate::types::value_reference::Reference;
+use crate::types::{DeltaOp, Value};
+use std::sync::{Arc, Mutex};
 
 /// Dummy MPH indexer for placeholder wiring (always returns slot 0).
 #[derive(Clone)]
@@ -26,20 +32,74 @@ pub struct Store {
     user_spaces: OptimisedIndexGen<UserId, Arc<UserSpace>, DummyMph>, 
     // user_spaces: DashMap<UserId, Arc<UserSpace<S>>>,
 
+    /// Schema shared by every user space
+    schema: Arc<SchemaRegistry>,
+
+    /// Schema documents of every user space by owner, for their documents to bind to
+    schemas: Arc<SchemaDirectory>,
+
+    /// Parent/child relationships between the documents of each user
+    hierarchy: DocumentHierarchy,
+
+    /// Serialises deltas that change the hierarchy, so a failed delta can undo its changes
+    hierarchy_writes: Mutex<()>,
+
+    /// Serialises user space creation, so concurrent first deltas of a user share one space
+    creating: Mutex<()>,
+
+    /// Documents each document references, and the documents referencing it
+    references: ReferenceIndex,
+
 }
 
 impl Store {
     /// Create a new flat storage with a factory function for storage instances
     pub fn new() -> Self
     {
-        Self { user_spaces: OptimisedIndexGen::new_with_indexer_and_capacity(DummyMph, 4096, 8192) }
+        Self::with_schema(Arc::new(SchemaRegistry::new()))
+    }
+
+    /// Create a store typing documents with an existing schema, e.g. one loaded by `SchemaLog`
+    pub fn with_schema(schema: Arc<SchemaRegistry>) -> Self {
+        Self {
+            user_spaces: OptimisedIndexGen::new_with_indexer_and_capacity(DummyMph, 4096, 8192),
+            schema,
+            schemas: Arc::new(SchemaDirectory::new()),
+            hierarchy: DocumentHierarchy::new(),
+            hierarchy_writes: Mutex::new(()),
+            creating: Mutex::new(()),
+            references: ReferenceIndex::new(),
+        }
+    }
+
+    /// Schema typing every document in the store that is not bound to a schema document
+    pub fn schema(&self) -> &Arc<SchemaRegistry> {
+        &self.schema
+    }
+
+    /// Schema documents available for binding
+    pub fn schemas(&self) -> &Arc<SchemaDirectory> {
+        &self.schemas
     }
     
     /// Get or create an isolate for a specific user
     fn get_or_create_user_space(&self, user_id: UserId) -> Arc<UserSpace> {
-        self.user_spaces
-            .get_owned(&user_id)
-            .unwrap_or_else(|| Arc::new(UserSpace::new(user_id)))
+        self.with_user_space(user_id, |space| Ok(space.clone())).unwrap()
+    }
+
+    /// Run `apply` on the user's space, creating the space only if `apply` succeeds on it
+    fn with_user_space<R>(&self, user_id: UserId, apply: impl FnOnce(&Arc<UserSpace>) -> Result<R, String>) -> Result<R, String> {
+        if let Some(space) = self.user_spaces.get_owned(&user_id) {
+            return apply(&space);
+        }
+        let _creating = self.creating.lock().unwrap();
+        if let Some(space) = self.user_spaces.get_owned(&user_id) {
+            return apply(&space);
+        }
+        let space = Arc::new(UserSpace::with_directory(user_id, self.schema.clone(), self.schemas.clone()));
+        let result = apply(&space)?;
+        self.user_spaces.upsert(user_id, space);
+        Ok(result)
     }
 
     /// Get or create an isolate for a specific user
@@ -73,9 +133,9 @@ impl Store {
         self.get_or_create_user_space(user_id)
     }
     
-    /// Create a document for a specific user
+    /// Create a document for a specific user from a stamped `CreateDocument` delta
     pub fn create_document(&self, user_id: UserId, doc_id: DocId, doc_data: Vec<u8>) -> Result<(), String> {
-        self.get_user_space(user_id).create_document(doc_id, doc_data)
+        self.with_user_space(user_id, |space| space.create_document(doc_id, doc_data))
     }
     
     /// Get a document for a specific user
@@ -83,9 +143,15 @@ impl Store {
         self.get_user_space(user_id).get_document(doc_id)
     }
     
-    /// Remove a document for a specific user
+    /// Remove a document for a specific user, detaching it from the hierarchy
+    ///
+    /// Removing a document the user does not have leaves everything as it was.
     pub fn remove_document(&self, user_id: UserId, doc_id: DocId) -> Result<(), String> {
-        self.get_user_space(user_id).remove_document(doc_id);
+        let _writes = self.hierarchy_writes.lock().unwrap();
+        if self.get_user_space(user_id).remove_document(doc_id) {
+            self.hierarchy.remove_document(user_id, doc_id);
+            self.references.refresh(user_id, doc_id, None);
+        }
         Ok(())
     }
     
@@ -94,9 +160,109 @@ impl Store {
         self.get_user_space(user_id).document_exists(doc_id)
     }
     
-    /// Apply a delta to a document for a specific user
+    /// Apply a stamped delta to a document for a specific user
+    ///
+    /// Lifecycle deltas route through here too: `CreateDocument` creates the document
+    /// (and the user space, for a user's first document). Both ends of a hierarchy
+    /// change must be documents of `user_id`.
     pub fn apply_delta(&self, user_id: UserId, doc_id: DocId, delta: Vec<u8>) -> Result<(), String> {
-        self.get_user_space(user_id).apply_delta(doc_id, delta)
+        let changes = hierarchy_changes(&DeltaRef::from_wire_bytes(&delta).map_err(|e| e.to_string())?)?;
+        self.with_user_space(user_id, |space| {
+            if changes.is_empty() {
+                space.apply_delta(doc_id, delta)
+            } else {
+                self.apply_hierarchy_delta(user_id, space, doc_id, delta, &changes)
+            }
+        })?;
+        self.refresh_references(user_id, doc_id);
+        Ok(())
+    }
+
+    /// Apply a delta carrying hierarchy `changes`, undoing them if the document rejects it
+    fn apply_hierarchy_delta(
+        &self,
+        user_id: UserId,
+        space: &UserSpace,
+        doc_id: DocId,
+        delta: Vec<u8>,
+        changes: &[(DeltaOp, Vec<u8>)],
+    ) -> Result<(), String> {
+        let _writes = self.hierarchy_writes.lock().unwrap();
+        let creates = ZeroCopyDocumentStorage::creates_document(&delta)?;
+        for (op, payload) in changes.iter().filter(|(op, _)| is_hierarchy_op(*op)) {
+            let ends = [(!creates).then_some(doc_id), payload_doc_id(*op, payload)?];
+            if let Some(missing) = ends.into_iter().flatten().find(|id| !space.document_exists(*id)) {
+                return Err(format!("Document {} not found", missing));
+            }
+        }
+        let mut undo = Vec::new();
+        for (op, payload) in changes {
+            match op {
+                DeltaOp::DeleteDocument => undo.extend(self.hierarchy.remove_document(user_id, doc_id)),
+                _ => match self.hierarchy.apply(user_id, doc_id, *op, payload) {
+                    Ok(change) => undo.push(change),
+                    Err(e) => {
+                        self.hierarchy.undo(&undo);
+                        return Err(e);
+                    }
+                },
+            }
+        }
+        let result = space.apply_delta(doc_id, delta);
+        if result.is_err() {
+            self.hierarchy.undo(&undo);
+        }
+        result
+    }
+
+    /// Re-index the references held by a document after a delta applied to it
+    fn refresh_references(&self, user_id: UserId, doc_id: DocId) {
+        let document = self.get_user_space(user_id).get_document(doc_id);
+        self.references.refresh(user_id, doc_id, document.as_deref());
+    }
+
+    /// Follow a reference value on behalf of `user_id`
+    ///
+    /// Documents and deltas resolve only within the caller's user space; a `UserRef`
+    /// resolves when that user has a space. `None` means the target does not exist (or
+    /// the delta was compacted behind a snapshot); values of other types are an error.
+    pub fn resolve(&self, user_id: UserId, value: &Value) -> Result<Option<Resolved>, String> {
+        let reference = value.reference().map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("{:?} is not a reference", value.value_type()))?;
+        let space = self.user_spaces.get_owned(&user_id);
+        Ok(match reference {
+            Reference::Document(doc_id) => space.and_then(|space| space.get_document(doc_id)).map(Resolved::Document),
+            Reference::Delta(doc_id, delta_id) => space
+                .and_then(|space| space.get_document(doc_id))
+                .and_then(|document| document.find_delta(delta_id))
+                .map(Resolved::Delta),
+            Reference::User(target) => self.user_spaces.get_owned(&target).map(|_| Resolved::User(target)),
+        })
+    }
+
+    /// Documents of `user_id` whose values reference `doc_id`, without scanning other documents
+    pub fn referrers(&self, user_id: UserId, doc_id: DocId) -> Vec<DocId> {
+        self.references.referrers(user_id, doc_id)
+    }
+
+    /// Documents referenced by the values of `doc_id` of `user_id`
+    pub fn references(&self, user_id: UserId, doc_id: DocId) -> Vec<DocId> {
+        self.references.references(user_id, doc_id)
+    }
+
+    /// Parent of a document of `user_id`
+    pub fn parent(&self, user_id: UserId, doc_id: DocId) -> Option<DocId> {
+        self.hierarchy.parent(user_id, doc_id)
+    }
+
+    /// Children of a document of `user_id`, without scanning other documents
+    pub fn children(&self, user_id: UserId, doc_id: DocId) -> Vec<DocId> {
+        self.hierarchy.children(user_id, doc_id)
+    }
+
+    /// Ancestors of a document of `user_id`, nearest first
+    pub fn ancestors(&self, user_id: UserId, doc_id: DocId) -> Vec<DocId> {
+        self.hierarchy.ancestors(user_id, doc_id)
     }
 
 }
@@ -106,3 +272,252 @@ impl Store {
 // pub type SimpleStore = Store<crate::storage::document_simple::SimpleDocumentStorage>;
 // /// Type alias for Store with ZeroCopyStorage  
 // pub type ZeroCopyStore = Store<crate::storage::document_storage::ZeroCopyDocumentStorage>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_support::TestStamper;
+    use crate::storage::schema_directory::{encode_fields, SchemaBinding};
+    use crate::types::DeltaBuilder;
+    use crate::types::document::DocumentType;
+    use crate::types::{DeltaOp, FieldDescriptor, ValueType, ValueWriter};
+    use crate::DocumentStorage;
+
+    #[test]
+    fn test_lifecycle_deltas_route_through_store() {
+        let store = Store::new();
+        store.schema().add_field(FieldDescriptor::new("count".to_string(), ValueType::Int).unwrap());
+        let (user_id, doc_id) = (UserId::random(), DocId::random());
+        let stamper = TestStamper::new(user_id);
+
+        // Only CreateDocument may touch an unknown document
+        assert!(store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1))).is_err());
+        store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        assert!(store.document_exists(user_id, doc_id));
+
+        store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1))).unwrap();
+        let document = store.get_document(user_id, doc_id).unwrap();
+        assert_eq!(document.sequence(), 3);
+        assert!(document.get_document().is_some());
+
+        store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::DeleteDocument))).unwrap();
+        assert!(!store.document_exists(user_id, doc_id));
+        assert!(store.get_document(user_id, doc_id).is_none());
+    }
+
+    #[test]
+    fn test_failed_first_delta_leaves_no_user_space() {
+        let store = Store::new();
+        let user_id = UserId::random();
+        let stamper = TestStamper::new(user_id);
+        let doc_id = DocId::random();
+        assert!(store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::DeleteDocument))).is_err());
+        assert!(store.create_document(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::Set))).is_err());
+        assert_eq!(store.user_count(), 0);
+
+        store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        assert_eq!(store.user_count(), 1);
+        assert!(store.document_exists(user_id, doc_id));
+    }
+
+    #[test]
+    fn test_hierarchy_deltas_update_store_queries() {
+        let store = Store::new();
+        let user_id = UserId::random();
+        let stamper = TestStamper::new(user_id);
+        let (root, child) = (DocId::random(), DocId::random());
+        let parent_of = |parent: DocId| DeltaBuilder::new(DeltaOp::SetParent).raw_value(parent.as_bytes());
+
+        store.apply_delta(user_id, root, stamper.stamp(root, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        // Create the child under root in one atomic group
+        let group = [
+            DeltaBuilder::new(DeltaOp::CreateDocument).build().unwrap(),
+            parent_of(root).build().unwrap(),
+        ];
+        let group: Vec<&[u8]> = group.iter().map(|d| d.as_slice()).collect();
+        store.apply_delta(user_id, child, stamper.stamp(child, DeltaBuilder::group(&group).unwrap())).unwrap();
+        assert_eq!(store.children(user_id, root), vec![child]);
+        assert_eq!(store.ancestors(user_id, child), vec![root]);
+
+        // Cycle rejected; a failing document delta leaves the hierarchy untouched
+        assert!(store.apply_delta(user_id, root, stamper.stamp(root, parent_of(child))).is_err());
+        assert!(store.apply_delta(user_id, DocId::random(), stamper.stamp(root, parent_of(root))).is_err());
+        assert_eq!(store.parent(user_id, child), Some(root));
+
+        // Both ends must be documents of the caller
+        let (other_user, foreign) = (UserId::random(), DocId::random());
+        store.apply_delta(other_user, foreign, TestStamper::new(other_user).stamp(foreign, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        let add_child = |child: DocId| DeltaBuilder::new(DeltaOp::AddChild).raw_value(child.as_bytes());
+        assert!(store.apply_delta(user_id, root, stamper.stamp(root, add_child(foreign))).unwrap_err().contains("not found"));
+        assert!(store.apply_delta(user_id, child, stamper.stamp(child, parent_of(foreign))).is_err());
+        assert!(store.apply_delta(user_id, foreign, stamper.stamp(foreign, add_child(child))).is_err());
+        assert_eq!(store.parent(user_id, child), Some(root));
+        assert_eq!(store.parent(other_user, foreign), None);
+
+        store.apply_delta(user_id, root, stamper.stamp(root, DeltaBuilder::new(DeltaOp::DeleteDocument))).unwrap();
+        assert_eq!(store.parent(user_id, child), None);
+        assert!(store.children(user_id, root).is_empty());
+
+        // Removing a document detaches it too
+        store.apply_delta(user_id, root, stamper.stamp(root, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        store.apply_delta(user_id, child, stamper.stamp(child, parent_of(root))).unwrap();
+        store.remove_document(user_id, root).unwrap();
+        assert_eq!(store.parent(user_id, child), None);
+    }
+
+    #[test]
+    fn test_hierarchy_stays_with_its_owner() {
+        let store = Store::new();
+        let (owner, other) = (UserId::random(), UserId::random());
+        let (owner_stamper, other_stamper) = (TestStamper::new(owner), TestStamper::new(other));
+        let (root, child) = (DocId::random(), DocId::random());
+        for doc_id in [root, child] {
+            store.apply_delta(owner, doc_id, owner_stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        }
+        store.apply_delta(owner, root, owner_stamper.stamp(root, DeltaBuilder::new(DeltaOp::AddChild).raw_value(child.as_bytes()))).unwrap();
+
+        // Another user's document with the same id has no children of its own
+        store.apply_delta(other, root, other_stamper.stamp(root, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        assert!(store.children(other, root).is_empty());
+        assert_eq!(store.parent(other, child), None);
+
+        // Deleting or removing it leaves the owner's links
+        store.apply_delta(other, root, other_stamper.stamp(root, DeltaBuilder::new(DeltaOp::DeleteDocument))).unwrap();
+        store.remove_document(other, root).unwrap();
+        assert_eq!(store.children(owner, root), vec![child]);
+        assert_eq!(store.ancestors(owner, child), vec![root]);
+    }
+
+    #[test]
+    fn test_documents_resolve_fields_through_bound_schema() {
+        let store = Store::new();
+        let user_id = UserId::random();
+        let stamper = TestStamper::new(user_id);
+        // Two tenants whose field 0 has different types
+        let define = |value_type| {
+            let schema_id = DocId::random();
+            let create = DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&[DocumentType::Schema as u8]);
+            store.apply_delta(user_id, schema_id, stamper.stamp(schema_id, create)).unwrap();
+            let fields = encode_fields(&[FieldDescriptor::new("title".to_string(), value_type).unwrap()]).unwrap();
+            store.apply_delta(user_id, schema_id, stamper.stamp(schema_id, DeltaBuilder::new(DeltaOp::CreateSchema).raw_value(&fields))).unwrap();
+            schema_id
+        };
+        let bound = |schema_id| {
+            let doc_id = DocId::random();
+            let mut payload = vec![DocumentType::Tree as u8];
+            SchemaBinding { schema_id, version: 0 }.encode_into(&mut payload);
+            store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&payload))).unwrap();
+            doc_id
+        };
+        let (text_doc, int_doc) = (bound(define(ValueType::String)), bound(define(ValueType::Int)));
+
+        store.apply_delta(user_id, text_doc, stamper.stamp(text_doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).string("hi"))).unwrap();
+        assert!(store.apply_delta(user_id, text_doc, stamper.stamp(text_doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1))).is_err());
+        store.apply_delta(user_id, int_doc, stamper.stamp(int_doc, DeltaBuilder::new(DeltaOp::Set).field(0, 0).int(1))).unwrap();
+        assert!(store.get_document(user_id, int_doc).unwrap().schema_binding().is_some());
+
+        // Unknown schemas and versions are rejected
+        let orphan = DocId::random();
+        let mut payload = vec![DocumentType::Tree as u8];
+        SchemaBinding { schema_id: DocId::random(), version: 0 }.encode_into(&mut payload);
+        assert!(store.apply_delta(user_id, orphan, stamper.stamp(orphan, DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&payload))).is_err());
+    }
+
+    #[test]
+    fn test_schema_documents_stay_with_their_owner() {
+        let store = Store::new();
+        let (owner, other) = (UserId::random(), UserId::random());
+        let (owner_stamper, other_stamper) = (TestStamper::new(owner), TestStamper::new(other));
+        let schema_id = DocId::random();
+        let create_schema = DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&[DocumentType::Schema as u8]);
+        store.apply_delta(owner, schema_id, owner_stamper.stamp(schema_id, create_schema)).unwrap();
+        let fields = encode_fields(&[FieldDescriptor::new("title".to_string(), ValueType::String).unwrap()]).unwrap();
+        store.apply_delta(owner, schema_id, owner_stamper.stamp(schema_id, DeltaBuilder::new(DeltaOp::CreateSchema).raw_value(&fields))).unwrap();
+        let bind = |user_id: UserId, stamper: &TestStamper| {
+            let doc_id = DocId::random();
+            let mut payload = vec![DocumentType::Tree as u8];
+            SchemaBinding { schema_id, version: 0 }.encode_into(&mut payload);
+            store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&payload)))
+        };
+
+        // Another user's document with the same id neither replaces nor removes the schema
+        store.apply_delta(other, schema_id, other_stamper.stamp(schema_id, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        store.apply_delta(other, schema_id, other_stamper.stamp(schema_id, DeltaBuilder::new(DeltaOp::DeleteDocument))).unwrap();
+        bind(owner, &owner_stamper).unwrap();
+        assert!(bind(other, &other_stamper).unwrap_err().contains("Unknown schema"));
+
+        // The owner deleting it does
+        store.apply_delta(owner, schema_id, owner_stamper.stamp(schema_id, DeltaBuilder::new(DeltaOp::DeleteDocument))).unwrap();
+        assert!(bind(owner, &owner_stamper).is_err());
+    }
+
+    #[test]
+    fn test_references_resolve_and_index_referrers() {
+        let store = Store::new();
+        store.schema().add_field(FieldDescriptor::new("link".to_string(), ValueType::DocumentRef).unwrap());
+        let user_id = UserId::random();
+        let stamper = TestStamper::new(user_id);
+        let encode = |write: &dyn Fn(&mut ValueWriter)| {
+            let mut writer = ValueWriter::new();
+            write(&mut writer);
+            writer.into_bytes()
+        };
+        let link = |doc_id: DocId| encode(&|w| { w.document_ref(doc_id); });
+        let (a, b, target) = (DocId::random(), DocId::random(), DocId::random());
+        for doc_id in [a, b, target] {
+            store.apply_delta(user_id, doc_id, stamper.stamp(doc_id, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+        }
+        let set_link = stamper.stamp(a, DeltaBuilder::new(DeltaOp::Set).field(0, 0).raw_value(&link(target)));
+        let delta_id = DeltaRef::from_wire_bytes(&set_link).unwrap().server_header().delta_id;
+        store.apply_delta(user_id, a, set_link).unwrap();
+        store.apply_delta(user_id, b, stamper.stamp(b, DeltaBuilder::new(DeltaOp::Set).field(0, 0).raw_value(&link(target)))).unwrap();
+        let mut referrers = vec![a, b];
+        referrers.sort();
+        assert_eq!(store.referrers(user_id, target), referrers);
+        assert_eq!(store.references(user_id, a), vec![target]);
+
+        // Documents and deltas resolve only in the caller's user space
+        let resolve = |user_id, bytes: Vec<u8>| store.resolve(user_id, &Value::from_bytes(&bytes).unwrap());
+        assert!(matches!(resolve(user_id, link(target)), Ok(Some(Resolved::Document(_)))));
+        assert!(matches!(resolve(UserId::random(), link(target)), Ok(None)));
+        assert!(matches!(resolve(user_id, encode(&|w| { w.delta_ref(a, delta_id); })), Ok(Some(Resolved::Delta(_)))));
+        assert!(matches!(resolve(user_id, encode(&|w| { w.user_ref(user_id); })), Ok(Some(Resolved::User(_)))));
+        assert!(matches!(resolve(user_id, encode(&|w| { w.user_ref(UserId::random()); })), Ok(None)));
+        assert!(resolve(user_id, encode(&|w| { w.int(1); })).is_err());
+
+        // Re-pointing and deleting referrers updates the index; dangling references remain
+        store.apply_delta(user_id, a, stamper.stamp(a, DeltaBuilder::new(DeltaOp::Set).field(0, 0).raw_value(&link(b)))).unwrap();
+        store.apply_delta(user_id, b, stamper.stamp(b, DeltaBuilder::new(DeltaOp::DeleteDocument))).unwrap();
+        assert!(store.referrers(user_id, target).is_empty());
+        assert_eq!(store.referrers(user_id, b), vec![a]);
+        assert!(matches!(resolve(user_id, link(b)), Ok(None)));
+    }
+
+    #[test]
+    fn test_references_stay_with_their_owner() {
+        let store = Store::new();
+        store.schema().add_field(FieldDescriptor::new("link".to_string(), ValueType::DocumentRef).unwrap());
+        let (owner, other) = (UserId::random(), UserId::random());
+        let (owner_stamper, other_stamper) = (TestStamper::new(owner), TestStamper::new(other));
+        let (a, target) = (DocId::random(), DocId::random());
+        let link = |user_id, stamper: &TestStamper, to: DocId| {
+            let mut writer = ValueWriter::new();
+            writer.document_ref(to);
+            store.apply_delta(user_id, a, stamper.stamp(a, DeltaBuilder::new(DeltaOp::CreateDocument))).unwrap();
+            store.apply_delta(user_id, a, stamper.stamp(a, DeltaBuilder::new(DeltaOp::Set).field(0, 0).raw_value(&writer.into_bytes()))).unwrap();
+        };
+        link(owner, &owner_stamper, target);
+
+        // Another user's document with the same id indexes apart from the owner's
+        link(other, &other_stamper, DocId::random());
+        assert_eq!(store.referrers(owner, target), vec![a]);
+        assert!(store.referrers(other, target).is_empty());
+
+        // Removing its own copy, or a document it does not have, leaves the owner's index
+        store.remove_document(other, target).unwrap();
+        store.remove_document(other, a).unwrap();
+        assert_eq!(store.references(owner, a), vec![target]);
+        store.remove_document(owner, a).unwrap();
+        assert!(store.referrers(owner, target).is_empty());
+    }
+}
@@ -0,0 +1,26 @@
+//! Helpers shared by storage tests
+
+use crate::delta::DeltaStamper;
+use crate::types::delta::{DeltaBuilder, DELTA_SECURE_HEADER_SIZE};
+use crate::{DocId, UserId};
+
+/// Stamps deltas built in tests as one user
+pub struct TestStamper {
+    stamper: DeltaStamper,
+    user_id: UserId,
+}
+
+impl TestStamper {
+    /// Stamper for `user_id`
+    pub fn new(user_id: UserId) -> Self {
+        Self { stamper: DeltaStamper::new([7; 32]), user_id }
+    }
+
+    /// Build `builder` and stamp it as the next delta of `doc_id`
+    pub fn stamp(&self, doc_id: DocId, builder: DeltaBuilder) -> Vec<u8> {
+        let mut buf = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+        builder.build_into(&mut buf).unwrap();
+        self.stamper.stamp(self.user_id, doc_id, &mut buf).unwrap();
+        buf
+    }
+}
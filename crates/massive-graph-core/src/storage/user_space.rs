@@ -1,10 +1,11 @@
 //! UserSpace - Single user storage, indexes, and streams
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use crate::core::utils::current_timestamp;
 use crate::structures::mph_delta_index::mph_indexer::MphIndexer;
 use crate::storage::ZeroCopyDocumentStorage;
-use crate::types::{UserId, DocId};
+use crate::storage::schema_directory::SchemaDirectory;
+use crate::types::{SchemaRegistry, UserId, DocId};
 use crate::DocumentStorage;
 use crate::{log_info};
 use crate::structures::mph_delta_index::OptimisedIndexGen;
@@ -28,6 +29,15 @@ pub struct UserSpace {
     /// Document index
     doc_index: OptimisedIndexGen<DocId, ZeroCopyDocumentStorage, DummyMph>, 
 
+    /// Schema typing this user's documents
+    schema: Arc<SchemaRegistry>,
+
+    /// Schema documents that documents may bind to
+    directory: Arc<SchemaDirectory>,
+
+    /// Serialises document creation, so concurrent creates of one document keep one copy
+    creating: Mutex<()>,
+
     /// User subscriptions
     subsriptions: Vec<DocId>,
     
@@ -64,8 +74,19 @@ impl UserSpaceStats {
 
 
 impl<'a> UserSpace {
-    /// Create a new user space for a specific user
+    /// Create a new user space for a specific user with its own schema
     pub fn new(user_id: UserId) -> Self {
+        Self::with_schema(user_id, Arc::new(SchemaRegistry::new()))
+    }
+
+    /// Create a new user space whose documents are typed by `schema`
+    pub fn with_schema(user_id: UserId, schema: Arc<SchemaRegistry>) -> Self {
+        Self::with_directory(user_id, schema, Arc::new(SchemaDirectory::new()))
+    }
+
+    /// Create a new user space whose documents are typed by `schema` unless bound to one
+    /// of its own schema documents, which it registers in `directory`
+    pub fn with_directory(user_id: UserId, schema: Arc<SchemaRegistry>, directory: Arc<SchemaDirectory>) -> Self {
         // Create a sentinel head node for the user's document stream.
         // let sentinel: *mut UserDocNode<'static> = UserDocNode::boxed(UserDocumentRef { bytes: &[], doc_id: DocId::default() });
         // let user_docs_stream = UserDocStream::new(sentinel);
@@ -75,6 +96,9 @@ impl<'a> UserSpace {
             // user_docs_stream,
             // user_view,
             doc_index: OptimisedIndexGen::new_with_indexer_and_capacity(DummyMph, 4096, 8192),
+            schema,
+            directory,
+            creating: Mutex::new(()),
             connections: Vec::new(),
             queues: Vec::new(),
             subsriptions: Vec::new(),
@@ -92,30 +116,73 @@ impl<'a> UserSpace {
         self.doc_index.len()
     }
     
-    /// Create a document for this user
+    /// Create a document for this user from a stamped `CreateDocument` delta
     pub fn create_document(&self, doc_id: DocId, doc_data: Vec<u8>) -> Result<(), String> {
         log_info!("🔒 UserDocumentSpace::create_document - user: {}, doc: {}, data_size: {}", self.user_id, doc_id, doc_data.len());
-        Ok(())
+        if !ZeroCopyDocumentStorage::creates_document(&doc_data)? {
+            return Err("Documents are created by a CreateDocument delta".to_string());
+        }
+        self.apply_delta(doc_id, doc_data)
     }
     
     /// Get a document for this user as bytes (compat shim over DocumentView)
+    ///
+    /// Deleted documents are kept as tombstones (so their sequence continues if they are
+    /// recreated) but are not returned.
     pub fn get_document(&self, doc_id: DocId) -> Option<Arc<ZeroCopyDocumentStorage>> {
-        self.doc_index.get_owned(&doc_id).map(|v| Arc::new(v.clone()))
+        self.doc_index.get_owned(&doc_id).filter(|v| !v.is_deleted()).map(Arc::new)
     }
     
-    /// Remove a document for this user
-    pub fn remove_document(&self, doc_id: DocId) -> () {
-        self.doc_index.remove(&doc_id)
+    /// Remove a document for this user, returning whether it was there
+    pub fn remove_document(&self, doc_id: DocId) -> bool {
+        let existed = self.doc_index.contains_key(&doc_id);
+        self.doc_index.remove(&doc_id);
+        existed
     }
     
     /// Check if a document exists for this user
     pub fn document_exists(&self, doc_id: DocId) -> bool {
-        self.doc_index.contains_key(&doc_id)
+        self.get_document(doc_id).is_some()
     }
     
-    /// Apply a delta to a document for this user
+    /// Apply a stamped delta to a document for this user
+    ///
+    /// A `CreateDocument` delta (or a group starting with one) for an unknown document
+    /// creates it.
     pub fn apply_delta(&self, doc_id: DocId, delta: Vec<u8>) -> Result<(), String> {
-        self.doc_index.get_owned(&doc_id).unwrap().apply_delta(delta)
+        let document = match self.doc_index.get_owned(&doc_id) {
+            Some(document) => {
+                document.apply_delta(delta)?;
+                document
+            }
+            None => self.create_document_from(doc_id, delta)?,
+        };
+        // Keep the directory in step with this user's schema documents (and their deletion)
+        let registered = self.directory.get(self.user_id, doc_id);
+        match document.defined_schema() {
+            Some(registry) if !registered.as_ref().is_some_and(|r| Arc::ptr_eq(r, &registry)) => {
+                self.directory.register(self.user_id, doc_id, registry)
+            }
+            None if registered.is_some() => self.directory.remove(self.user_id, doc_id),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Create `doc_id` from a delta, or apply it to the document a concurrent delta created
+    fn create_document_from(&self, doc_id: DocId, delta: Vec<u8>) -> Result<ZeroCopyDocumentStorage, String> {
+        let _creating = self.creating.lock().unwrap();
+        if let Some(document) = self.doc_index.get_owned(&doc_id) {
+            document.apply_delta(delta)?;
+            return Ok(document);
+        }
+        if !ZeroCopyDocumentStorage::creates_document(&delta)? {
+            return Err(format!("Document {} not found", doc_id));
+        }
+        let document = ZeroCopyDocumentStorage::with_directory(self.schema.clone(), self.user_id, self.directory.clone());
+        document.apply_delta(delta)?;
+        self.doc_index.upsert(doc_id, document.clone());
+        Ok(document)
     }
 
     /// get stats and runtime info
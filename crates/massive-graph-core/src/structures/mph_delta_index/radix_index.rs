@@ -194,11 +194,13 @@ where
         if cur.is_null() { return None; }
         let buf = unsafe { &*cur };
         
-        // Check for records added since snapshot (hot path optimization)
+        // Check for records added since snapshot (hot path optimization). Before the
+        // first snapshot the tail is 0 and every record is new; there is no TinyMap to
+        // fall back to, so skipping the scan would hide them until a snapshot is taken.
         let snapshot_tail = b.snapshot_tail.load(Ordering::Acquire);
         let current_tail = buf.tail.load(Ordering::Acquire);
         
-        if snapshot_tail > 0 && snapshot_tail < current_tail {
+        if snapshot_tail < current_tail {
             // Scan only NEW records (from snapshot_tail to current tail) in reverse
             for i in (snapshot_tail..current_tail).rev() {
                 let rec = unsafe { &*buf.recs.add(i) };
@@ -1067,3 +1069,25 @@ impl<K, V> Drop for RadixIndex<K, V> {
 
 // Unit tests moved to /tests/radix_index_tests.rs
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ID16;
+
+    #[test]
+    fn test_lookup_before_first_snapshot() {
+        // Records appended before any snapshot resolve, latest first
+        let idx: RadixIndex<ID16, u64> = RadixIndex::with_capacity(16, 64);
+        let guard = epoch::pin();
+        let (key, other) = (ID16::from_bytes(*b"0000000000000007"), ID16::from_bytes(*b"0000000000000008"));
+
+        assert!(idx.get(&key, &guard).is_none());
+        idx.upsert(&key, &1, &guard);
+        assert_eq!(idx.get(&key, &guard), Some(&1));
+        idx.upsert(&key, &2, &guard);
+        assert_eq!(idx.get(&key, &guard), Some(&2));
+        assert!(idx.get(&other, &guard).is_none());
+        idx.delete(&key, &guard);
+        assert!(idx.get(&key, &guard).is_none());
+    }
+}
@@ -412,6 +412,21 @@ impl<T> SegmentedStream<T> {
         unsafe { &*ptr_t }
     }
 
+    /// Index of the entry at `position`, counting from the head of the stream.
+    ///
+    /// Positions only map to entries when appends are serialised (a single writer) and no
+    /// pool pages are recycled. `position` may be one past the last committed entry, for a
+    /// cursor waiting at the tail.
+    pub fn index_at(&self, position: usize) -> Option<StreamIndex<T>> {
+        let pages = self.pages.lock().unwrap();
+        let page = pages.get(position / self.page_size)?;
+        let idx = (position % self.page_size) as u32;
+        if idx > page.committed.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(StreamIndex { page: Arc::as_ptr(page), idx })
+    }
+
     /// Allocate a fresh page or pop one from the pool if available.
     #[inline]
     fn alloc_page_arc(&self) -> Arc<Page<T>> {
@@ -441,6 +456,18 @@ impl<T> Cursor<T> {
         }
     }
 
+    /// Create a cursor whose next item is the entry at `index`.
+    ///
+    /// Returns `None` if the index does not point into this stream.
+    pub fn new_at(stream: &SegmentedStream<T>, index: &StreamIndex<T>) -> Option<Self> {
+        let pages = stream.pages.lock().unwrap();
+        let page = pages.iter().find(|page| ptr::eq(Arc::as_ptr(page), index.page))?;
+        Some(Cursor {
+            page: Arc::clone(page),
+            index: index.idx,
+        })
+    }
+
     /// Read the next item if available; hops pages automatically.
     pub fn next<'a>(&'a mut self) -> Option<&'a T> {
         let mut idx = self.index;
@@ -730,3 +757,30 @@ impl<T> Ring<T> {
     #[inline]
     fn capacity(&self) -> usize { self.cap }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_from_position_across_pages() {
+        // Goal: index_at maps head-relative positions to entries, including the tail
+        let s: SegmentedStream<u64> = SegmentedStream::with_page_size(64);
+        for i in 0..100 {
+            s.append(i).unwrap();
+        }
+        for position in [0usize, 63, 64, 99] {
+            let idx = s.index_at(position).expect("index");
+            let mut c = Cursor::new_at(&s, &idx).expect("cursor");
+            assert_eq!(c.next().copied(), Some(position as u64));
+        }
+
+        // A cursor at the tail picks up later appends
+        let mut tail = Cursor::new_at(&s, &s.index_at(100).unwrap()).unwrap();
+        assert!(tail.next().is_none());
+        s.append(100).unwrap();
+        assert_eq!(tail.next().copied(), Some(100));
+        assert!(s.index_at(102).is_none());
+        assert!(s.index_at(1_000).is_none());
+    }
+}
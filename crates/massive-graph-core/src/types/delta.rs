@@ -1,11 +1,12 @@
 use std::{cell::OnceCell, sync::atomic::{AtomicU16, Ordering}};
 
-use crate::{types::{field::FieldAddress, storage::WireFormat, ParseError}, DeltaId, DocId, UserId};
+use crate::{types::{field::{validate_params_section, FieldAddress, FieldParams}, storage::WireFormat, value::Value, varint::{decode_varint, encode_varint}, ParseError, ValueType}, DeltaId, DocId, UserId};
 
 /// Delta type definitions for the Massive Graph system - Empty Shell
 
 /// Delta operation type - single byte on wire
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeltaOp {
 
     // Atomic Property-level operations
@@ -85,20 +86,128 @@ pub enum DeltaOp {
     Deltas = 64,
 }
 
+impl TryFrom<u8> for DeltaOp {
+    type Error = ParseError;
+
+    /// Checked conversion from the wire op byte - unknown values are rejected
+    fn try_from(op: u8) -> Result<Self, Self::Error> {
+        Ok(match op {
+            0 => DeltaOp::Set,
+            1 => DeltaOp::Delete,
+            2 => DeltaOp::Increment,
+            3 => DeltaOp::Multiply,
+            5 => DeltaOp::Modulus,
+            6 => DeltaOp::Power,
+            8 => DeltaOp::Append,
+            9 => DeltaOp::Splice,
+            10 => DeltaOp::Insert,
+            11 => DeltaOp::Remove,
+            12 => DeltaOp::Clear,
+            13 => DeltaOp::SliceUpdate,
+            14 => DeltaOp::Reshape,
+            16 => DeltaOp::CreateSchema,
+            17 => DeltaOp::CreateDocument,
+            18 => DeltaOp::CreateSnapshot,
+            19 => DeltaOp::DeleteDocument,
+            24 => DeltaOp::AddChild,
+            25 => DeltaOp::RemoveChild,
+            26 => DeltaOp::SetParent,
+            32 => DeltaOp::Prepend,
+            33 => DeltaOp::InsertAt,
+            34 => DeltaOp::InsertWhere,
+            35 => DeltaOp::ReplaceAt,
+            36 => DeltaOp::ReplaceWhere,
+            37 => DeltaOp::DeleteAt,
+            38 => DeltaOp::DeleteWhere,
+            48 => DeltaOp::StreamAppend,
+            49 => DeltaOp::StreamMarkAt,
+            64 => DeltaOp::Deltas,
+            _ => return Err(ParseError::InvalidOperation(op)),
+        })
+    }
+}
+
+/// Size of the client header on the wire
+pub const DELTA_CLIENT_HEADER_SIZE: usize = std::mem::size_of::<DeltaClientHeader>();
+
+/// Size of the server secure header on the wire
+pub const DELTA_SECURE_HEADER_SIZE: usize = std::mem::size_of::<DeltaSecureHeader>();
+
+/// Offset of the MAC signature within the server secure header
+pub const DELTA_SIGNATURE_OFFSET: usize = 80;
+
+/// Offset of the delta op byte within incoming bytes (client header + payload length)
+const DELTA_OP_OFFSET: usize = DELTA_CLIENT_HEADER_SIZE + 4;
+
 
 /// Incoming delta bytes in wire format
+///
+/// Layout (little-endian):
+/// ```text
+/// [content_hash: u64][payload_len: u32][op: u8][schema_version: varint][field_index: varint]
+/// [group_count: u8][groups: (tag: u8, len: varint, body)*][value_len: varint][value]
+/// ```
+/// `payload_len` counts every byte after itself.
 #[repr(C)]
 pub struct DeltaIncoming<'a> {
     /// The raw bytes of the unvalidated delta
     pub bytes: &'a [u8],
     /// The client-provided header
-    pub client_header: OnceCell<&'a DeltaClientHeader>,
+    pub client_header: OnceCell<DeltaClientHeader>,
     /// The delta operation
-    pub delta_payload: OnceCell<&'a DeltaPayload<'a>>,
+    pub delta_payload: OnceCell<DeltaPayload<'a>>,
+}
+
+impl<'a> DeltaIncoming<'a> {
+    /// Wrap raw bytes without validating them
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, client_header: OnceCell::new(), delta_payload: OnceCell::new() }
+    }
+
+    /// Parse and validate incoming bytes, populating the header and payload views
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let incoming = Self::new(bytes);
+        incoming.validate()?;
+        Ok(incoming)
+    }
+
+    /// Total framed length of the delta at the start of `bytes`, read from its payload length
+    pub fn frame_len(bytes: &[u8]) -> Result<usize, ParseError> {
+        if bytes.len() < DELTA_OP_OFFSET {
+            return Err(ParseError::InsufficientData { expected: DELTA_OP_OFFSET, actual: bytes.len() });
+        }
+        let payload_len = u32::from_le_bytes(bytes[DELTA_CLIENT_HEADER_SIZE..DELTA_OP_OFFSET].try_into().unwrap());
+        DELTA_OP_OFFSET.checked_add(payload_len as usize).ok_or(ParseError::InvalidFormat)
+    }
+
+    /// Validate the bytes once; subsequent calls are free
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.delta_payload.get().is_some() {
+            return Ok(());
+        }
+        let (header, payload) = parse_incoming(self.bytes)?;
+        let _ = self.client_header.set(header);
+        let _ = self.delta_payload.set(payload);
+        Ok(())
+    }
+
+    /// Client header, available once validated
+    pub fn client_header(&self) -> Option<&DeltaClientHeader> { self.client_header.get() }
+
+    /// Payload view, available once validated
+    pub fn payload(&self) -> Option<&DeltaPayload<'a>> { self.delta_payload.get() }
+
+    /// Check the client content hash against the payload bytes
+    pub fn content_hash_matches(&self) -> bool {
+        self.bytes.get(DELTA_OP_OFFSET..).is_some_and(|payload| {
+            self.client_header().is_some_and(|header| header.content_hash == content_hash(payload))
+        })
+    }
 }
 
 /// The client-provided header
 #[repr(C, align(8))]  // Cache-line aligned for performance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DeltaClientHeader {
     /// The content hash for delta short-window identification, deduplication and validation
     pub content_hash: u64,          // 8 bytes
@@ -108,6 +217,19 @@ pub struct DeltaClientHeader {
 
 }
 
+impl DeltaClientHeader {
+    /// Build the header for payload bytes (everything after the payload length)
+    pub fn for_payload(payload: &[u8]) -> Self {
+        Self { content_hash: content_hash(payload) }
+    }
+}
+
+/// Content hash of payload bytes - first 8 bytes of their BLAKE3 digest, little-endian
+pub fn content_hash(payload: &[u8]) -> u64 {
+    let digest = blake3::hash(payload);
+    u64::from_le_bytes(digest.as_bytes()[..8].try_into().unwrap())
+}
+
 /// The delta operation
 #[repr(C)]
 pub struct DeltaPayload<'a> {
@@ -121,8 +243,221 @@ pub struct DeltaPayload<'a> {
     pub payload_value: &'a [u8], // variable length
 }
 
+impl<'a> DeltaPayload<'a> {
+    /// Nested deltas of a `DeltaOp::Deltas` payload
+    pub fn group(&self) -> Result<DeltaGroup<'a>, ParseError> {
+        if self.delta_op != DeltaOp::Deltas {
+            return Err(ParseError::InvalidOperation(self.delta_op as u8));
+        }
+        DeltaGroup::parse(self.payload_value)
+    }
+
+    /// Number of sequence numbers this delta occupies - one per nested delta for groups
+    pub fn sequence_span(&self) -> u64 {
+        match self.group() {
+            Ok(group) => group.len() as u64,
+            Err(_) => 1,
+        }
+    }
+}
+
+/// Atomic group of deltas carried in a `DeltaOp::Deltas` payload
+///
+/// Layout: `[count: varint]([len: varint][incoming delta bytes])*`. Groups are never empty
+/// and never nested.
+#[derive(Clone, Copy, Debug)]
+pub struct DeltaGroup<'a> {
+    count: u32,
+    bytes: &'a [u8],
+}
+
+impl<'a> DeltaGroup<'a> {
+    /// Validate a group payload and every nested delta in it
+    pub fn parse(value: &'a [u8]) -> Result<Self, ParseError> {
+        let (count, read) = decode_varint(value)?;
+        if count == 0 {
+            return Err(ParseError::InvalidFormat);
+        }
+        let bytes = &value[read..];
+        let mut rest = bytes;
+        for _ in 0..count {
+            let (child, tail) = split_child(rest)?;
+            let incoming = DeltaIncoming::parse(child)?;
+            if incoming.payload().is_some_and(|p| p.delta_op == DeltaOp::Deltas) {
+                return Err(ParseError::InvalidFormat);
+            }
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(ParseError::LengthMismatch { declared: bytes.len() - rest.len(), actual: bytes.len() });
+        }
+        Ok(Self { count, bytes })
+    }
+
+    /// Number of nested deltas
+    pub fn len(&self) -> usize { self.count as usize }
+
+    /// Groups are never empty once parsed
+    pub fn is_empty(&self) -> bool { self.count == 0 }
+
+    /// Iterate the nested deltas in order
+    pub fn iter(&self) -> DeltaGroupIter<'a> {
+        DeltaGroupIter { remaining: self.count, rest: self.bytes }
+    }
+
+    /// Encode a group payload from incoming delta bytes
+    pub fn encode(children: &[&[u8]], output: &mut Vec<u8>) -> Result<(), String> {
+        encode_varint(children.len() as u32, output)?;
+        for child in children {
+            encode_varint(child.len() as u32, output)?;
+            output.extend_from_slice(child);
+        }
+        Ok(())
+    }
+}
+
+/// Split one length-prefixed nested delta off the front of a group body
+fn split_child(bytes: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let (len, read) = decode_varint(bytes)?;
+    let end = read.checked_add(len as usize).ok_or(ParseError::InvalidFormat)?;
+    let child = bytes
+        .get(read..end)
+        .ok_or(ParseError::InsufficientData { expected: end, actual: bytes.len() })?;
+    Ok((child, &bytes[end..]))
+}
+
+/// Iterator over the nested deltas of a validated group
+pub struct DeltaGroupIter<'a> {
+    remaining: u32,
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for DeltaGroupIter<'a> {
+    type Item = DeltaIncoming<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let (child, tail) = split_child(self.rest).ok()?;
+        self.rest = tail;
+        DeltaIncoming::parse(child).ok()
+    }
+}
+
+/// Builder producing incoming delta bytes in the layout `DeltaIncoming::parse` consumes
+#[derive(Clone, Debug)]
+pub struct DeltaBuilder {
+    op: DeltaOp,
+    schema_version: u16,
+    field_index: u32,
+    params: FieldParams,
+    value_type: Option<ValueType>,
+    value: Vec<u8>,
+}
+
+impl DeltaBuilder {
+    /// Start a delta for the given operation, addressing field 0 of schema version 0
+    pub fn new(op: DeltaOp) -> Self {
+        Self {
+            op,
+            schema_version: 0,
+            field_index: 0,
+            params: FieldParams::new(),
+            value_type: None,
+            value: Vec::new(),
+        }
+    }
+
+    /// Set the schema version and field index
+    pub fn field(mut self, schema_version: u16, field_index: u32) -> Self {
+        self.schema_version = schema_version;
+        self.field_index = field_index;
+        self
+    }
+
+    /// Set the parameter groups of the field address
+    pub fn params(mut self, params: FieldParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Set a typed value, encoded as [type][length if variable][data]
+    pub fn value(mut self, value_type: ValueType, data: &[u8]) -> Self {
+        self.value_type = Some(value_type);
+        self.value = data.to_vec();
+        self
+    }
+
+    /// Set already-encoded value bytes, written as-is
+    pub fn raw_value(mut self, bytes: &[u8]) -> Self {
+        self.value_type = None;
+        self.value = bytes.to_vec();
+        self
+    }
+
+    /// Set a null value
+    pub fn null(self) -> Self { self.value(ValueType::Null, &[]) }
+    /// Set a boolean value
+    pub fn bool(self, value: bool) -> Self { self.value(ValueType::Bool, &[value as u8]) }
+    /// Set an integer value
+    pub fn int(self, value: i64) -> Self { self.value(ValueType::Int, &value.to_le_bytes()) }
+    /// Set a float value
+    pub fn float(self, value: f64) -> Self { self.value(ValueType::Float, &value.to_le_bytes()) }
+    /// Set a timestamp value
+    pub fn timestamp(self, value: u64) -> Self { self.value(ValueType::Timestamp, &value.to_le_bytes()) }
+    /// Set a string value
+    pub fn string(self, value: &str) -> Self { self.value(ValueType::String, value.as_bytes()) }
+    /// Set a binary value
+    pub fn binary(self, value: &[u8]) -> Self { self.value(ValueType::Binary, value) }
+
+    /// Start an atomic group of already-built deltas
+    pub fn group(children: &[&[u8]]) -> Result<Self, String> {
+        let mut value = Vec::with_capacity(children.iter().map(|c| c.len() + 3).sum::<usize>() + 3);
+        DeltaGroup::encode(children, &mut value)?;
+        Ok(Self::new(DeltaOp::Deltas).raw_value(&value))
+    }
+
+    /// Encode to a new buffer
+    pub fn build(&self) -> Result<Vec<u8>, String> {
+        let mut output = Vec::with_capacity(DELTA_OP_OFFSET + 16 + self.params.encoded_size() + self.value.len());
+        self.build_into(&mut output)?;
+        Ok(output)
+    }
+
+    /// Append the encoded delta to `output`
+    pub fn build_into(&self, output: &mut Vec<u8>) -> Result<(), String> {
+        let start = output.len();
+        // Header and payload length are patched once the payload is written
+        output.extend_from_slice(&[0u8; DELTA_OP_OFFSET]);
+        let payload_start = output.len();
+
+        output.push(self.op as u8);
+        encode_varint(self.schema_version as u32, output)?;
+        encode_varint(self.field_index, output)?;
+        self.params.encode_into(output)?;
+
+        let mut value = Vec::with_capacity(self.value.len() + 4);
+        match self.value_type {
+            Some(value_type) => Value::encode(value_type, &self.value, &mut value)?,
+            None => value.extend_from_slice(&self.value),
+        }
+        encode_varint(value.len() as u32, output)?;
+        output.extend_from_slice(&value);
+
+        let payload_len = u32::try_from(output.len() - payload_start)
+            .map_err(|_| format!("Delta payload too large: {} bytes", output.len() - payload_start))?;
+        let header = DeltaClientHeader::for_payload(&output[payload_start..]);
+        output[start..start + DELTA_CLIENT_HEADER_SIZE].copy_from_slice(&header.content_hash.to_le_bytes());
+        output[start + DELTA_CLIENT_HEADER_SIZE..payload_start].copy_from_slice(&payload_len.to_le_bytes());
+        Ok(())
+    }
+}
+
 /// Server generated delta for ordering and validation guarantees
 #[repr(C, align(128))]  // Cache-line aligned for performance
+#[derive(Debug, Clone, Copy)]
 pub struct DeltaSecureHeader {
     /// The user id
     pub user_id: UserId,                // 32 bytes
@@ -142,6 +477,41 @@ pub struct DeltaSecureHeader {
     pub padding: [u8; 16],              // 16 bytes
 }
 
+impl DeltaSecureHeader {
+    /// Read a header from its wire layout (field order as declared, integers little-endian)
+    pub fn read_from(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() < DELTA_SECURE_HEADER_SIZE {
+            return Err(ParseError::InsufficientData { expected: DELTA_SECURE_HEADER_SIZE, actual: bytes.len() });
+        }
+        Ok(Self {
+            user_id: UserId::from_bytes(bytes[0..32].try_into().unwrap()),
+            doc_id: DocId::from_bytes(bytes[32..48].try_into().unwrap()),
+            delta_id: DeltaId::new(bytes[48..56].try_into().unwrap()),
+            previous_delta_id: DeltaId::new(bytes[56..64].try_into().unwrap()),
+            delta_sequence_number: u64::from_le_bytes(bytes[64..72].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(bytes[72..80].try_into().unwrap()),
+            signature: bytes[80..112].try_into().unwrap(),
+            padding: bytes[112..128].try_into().unwrap(),
+        })
+    }
+
+    /// Write the header in the layout `read_from` consumes
+    pub fn write_to(&self, bytes: &mut [u8]) -> Result<(), ParseError> {
+        if bytes.len() < DELTA_SECURE_HEADER_SIZE {
+            return Err(ParseError::InsufficientData { expected: DELTA_SECURE_HEADER_SIZE, actual: bytes.len() });
+        }
+        bytes[0..32].copy_from_slice(self.user_id.as_bytes());
+        bytes[32..48].copy_from_slice(self.doc_id.as_bytes());
+        bytes[48..56].copy_from_slice(self.delta_id.as_bytes());
+        bytes[56..64].copy_from_slice(self.previous_delta_id.as_bytes());
+        bytes[64..72].copy_from_slice(&self.delta_sequence_number.to_le_bytes());
+        bytes[72..80].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[80..112].copy_from_slice(&self.signature);
+        bytes[112..128].copy_from_slice(&self.padding);
+        Ok(())
+    }
+}
+
 #[repr(transparent)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 /// Bitflags for delta validation/state tracking.
@@ -211,6 +581,7 @@ impl<'a> WireFormat<'a> for Delta<'a> {
 }
 
 /// Delta operation containing document changes
+/// Wire layout is the server secure header followed by the incoming client bytes
 pub struct DeltaRef<'a> {
 
     /// raw bytes
@@ -220,28 +591,42 @@ pub struct DeltaRef<'a> {
     state: DeltaTracking,
 
     /// The secured header
-    server_header: &'a DeltaSecureHeader,
+    server_header: DeltaSecureHeader,
 
     /// The delta Client header
-    client_header: &'a DeltaClientHeader,
+    client_header: DeltaClientHeader,
 
     /// The field address
     /// The delta payload
-    payload: &'a DeltaPayload<'a>,
+    payload: DeltaPayload<'a>,
 
 }
 
 impl<'a> DeltaRef<'a> {
+    /// Parse a stored delta ([secure header][incoming bytes]) with full bounds checking
+    pub fn from_wire_bytes(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let server_header = DeltaSecureHeader::read_from(bytes)?;
+        let (client_header, payload) = parse_incoming(&bytes[DELTA_SECURE_HEADER_SIZE..])?;
+        Ok(Self { bytes, state: DeltaTracking::new(), server_header, client_header, payload })
+    }
+
     // Read-only accessors for borrowed data
     /// Get raw bytes.
     pub fn bytes(&self) -> &'a [u8] { self.bytes }
+    /// Get the client bytes following the secure header.
+    pub fn incoming_bytes(&self) -> &'a [u8] { &self.bytes[DELTA_SECURE_HEADER_SIZE..] }
 
     /// Get secured header.
-    pub fn server_header(&self) -> &'a DeltaSecureHeader { self.server_header }
+    pub fn server_header(&self) -> &DeltaSecureHeader { &self.server_header }
     /// Get client header.
-    pub fn client(&self) -> &'a DeltaClientHeader { self.client_header }
+    pub fn client(&self) -> &DeltaClientHeader { &self.client_header }
     /// Get payload view.
-    pub fn payload(&self) -> &'a DeltaPayload<'a> { self.payload }
+    pub fn payload(&self) -> &DeltaPayload<'a> { &self.payload }
+    /// Sequence numbers assigned to this delta - one per nested delta for groups.
+    pub fn sequence_range(&self) -> std::ops::RangeInclusive<u64> {
+        let first = self.server_header.delta_sequence_number;
+        first..=first + self.payload.sequence_span() - 1
+    }
 
     // Mutators operate only on state via &self
     /// Current flags.
@@ -250,121 +635,385 @@ impl<'a> DeltaRef<'a> {
     pub fn set_flags(&self, f: DeltaFlags) { self.state.set(f) }
     /// Mark the delta as validated.
     pub fn mark_as_valid(&self) { self.state.set_bits(DeltaFlags::VALID) }
+    /// Mark the delta's MAC as present and verified.
+    pub fn mark_as_signed(&self) { self.state.set_bits(DeltaFlags::SIGNED) }
+    /// Mark the delta as a duplicate dropped by deduplication.
+    pub fn mark_as_deduped(&self) { self.state.set_bits(DeltaFlags::DEDUPED) }
 }
 
-/// Parse from wire bytes using same varint/TLV encoding as SchemaRegistry
-/// SAFETY: Caller ensures bytes remain valid for Delta's lifetime
-// impl Delta<'a> {
-    // /// Parse delta from wire format bytes
-    // pub fn from_wire_bytes(bytes: &'a [u8]) -> Result<Self, ParseError> {
-    //     // Single upfront check for minimum required bytes
-
-    //     if bytes.len() < 38 {
-    //         return Err(ParseError::InsufficientData { 
-    //             expected: 38, 
-    //             actual: bytes.len() 
-    //         });
-    //     }
-        
-    //     // Now we can use unsafe for speed, knowing we have enough bytes
-    //     unsafe {
-    //         let ptr = bytes.as_ptr();
-            
-    //         // Direct memory casts - no bounds checks needed
-    //         let doc_id = *(ptr as *const DocId);
-    //         let delta_id = *(ptr.add(16) as *const DeltaId);
-    //         let timestamp = *(ptr.add(24) as *const u64);
-    //         let delta_op = match *ptr.add(32) {
-    //             0..=64 => std::mem::transmute(*ptr.add(32)),
-    //             op => return Err(ParseError::InvalidOperation(op)),
-    //         };
-            
-    //         // Parse varints (these still need bounds checking)
-    //         let mut offset = 33;
-            
-    //         // Use unchecked slicing for varints since we validate length
-    //         let (schema_version, consumed) = decode_varint_unchecked(ptr.add(offset), bytes.len() - offset)?;
-    //         offset += consumed;
-            
-    //         let (field_index, consumed) = decode_varint_unchecked(ptr.add(offset), bytes.len() - offset)?;
-    //         offset += consumed;
-            
-    //         // Params count
-    //         if offset >= bytes.len() {
-    //             return Err(ParseError::InsufficientData { expected: offset + 1, actual: bytes.len() });
-    //         }
-    //         let params_count = *ptr.add(offset);
-    //         offset += 1;
-            
-    //         // Mark params section
-    //         let params_start = offset;
-    //         let params_ptr = ptr.add(offset);
-            
-    //         // Fast skip over TLV params
-    //         for _ in 0..params_count {
-    //             if offset >= bytes.len() {
-    //                 return Err(ParseError::InsufficientData { expected: offset + 1, actual: bytes.len() });
-    //             }
-    //             offset += 1; // Skip type byte
-                
-    //             let (length, consumed) = decode_varint_unchecked(ptr.add(offset), bytes.len() - offset)?;
-    //             offset += consumed + length as usize;
-                
-    //             if offset > bytes.len() {
-    //                 return Err(ParseError::InsufficientData { expected: offset, actual: bytes.len() });
-    //             }
-    //         }
-            
-    //         let params_len = offset - params_start;
-            
-    //         // Payload length
-    //         let (payload_len, consumed) = decode_varint_unchecked(ptr.add(offset), bytes.len() - offset)?;
-    //         offset += consumed;
-            
-    //         // Final check for payload
-    //         if bytes.len() < offset + payload_len as usize {
-    //             return Err(ParseError::InsufficientData { 
-    //                 expected: offset + payload_len as usize, 
-    //                 actual: bytes.len() 
-    //             });
-    //         }
-            
-    //         let payload_ptr = ptr.add(offset);
-            
-    //         Ok(Delta {
-    //             doc_id,
-    //             delta_id,
-    //             delta_op,
-    //             timestamp,
-    //             field_address: FieldAddress {
-    //                 schema_version: schema_version as u16,
-    //                 field_index,
-    //                 params_raw: (params_ptr, params_len),
-    //             },
-    //             payload_len,
-    //             payload: (payload_ptr, payload_len as usize),
-    //         })
-    //     }
-    // }
-
-// }
-
-/// Fast varint decoder with minimal checking
-#[inline(always)]
-#[allow(dead_code)]
-unsafe fn decode_varint_unchecked(ptr: *const u8, max_len: usize) -> Result<(u32, usize), ParseError> {
-    if max_len == 0 {
-        return Err(ParseError::InsufficientData { expected: 1, actual: 0 });
+/// Parse and validate incoming client bytes into header and payload views
+fn parse_incoming(bytes: &[u8]) -> Result<(DeltaClientHeader, DeltaPayload<'_>), ParseError> {
+    // Fixed prefix: client header, payload length and op byte
+    if bytes.len() <= DELTA_OP_OFFSET {
+        return Err(ParseError::InsufficientData { expected: DELTA_OP_OFFSET + 1, actual: bytes.len() });
     }
-    
-    let first = *ptr;
-    if first < 128 {
-        Ok((first as u32, 1))
-    } else if first < 192 && max_len >= 2 {
-        Ok((((first & 0x3F) as u32) << 8 | *ptr.add(1) as u32, 2))
-    } else if max_len >= 3 {
-        Ok((((first & 0x3F) as u32) << 16 | (*ptr.add(1) as u32) << 8 | *ptr.add(2) as u32, 3))
-    } else {
-        Err(ParseError::InsufficientData { expected: 3, actual: max_len })
-    }
-}
\ No newline at end of file
+    let client_header = DeltaClientHeader {
+        content_hash: u64::from_le_bytes(bytes[..DELTA_CLIENT_HEADER_SIZE].try_into().unwrap()),
+    };
+    let payload_len = u32::from_le_bytes(bytes[DELTA_CLIENT_HEADER_SIZE..DELTA_OP_OFFSET].try_into().unwrap());
+    // Checked: usize is 32 bits on wasm32
+    let declared = DELTA_OP_OFFSET.checked_add(payload_len as usize).ok_or(ParseError::InvalidFormat)?;
+    if bytes.len() != declared {
+        return Err(ParseError::LengthMismatch { declared, actual: bytes.len() });
+    }
+    let delta_op = DeltaOp::try_from(bytes[DELTA_OP_OFFSET])?;
+    let mut offset = DELTA_OP_OFFSET + 1;
+
+    // Field address: schema version, field index
+    let (schema_version, read) = decode_varint(&bytes[offset..])?;
+    let schema_version = u16::try_from(schema_version).map_err(|_| ParseError::InvalidFormat)?;
+    offset += read;
+    let (field_index, read) = decode_varint(&bytes[offset..])?;
+    offset += read;
+
+    // Param groups: [count] then TLV per group
+    let params_start = offset + 1;
+    offset = offset.checked_add(validate_params_section(&bytes[offset..])?).ok_or(ParseError::InvalidFormat)?;
+    let params = &bytes[params_start..offset];
+
+    // Payload value
+    let (value_len, read) = decode_varint(&bytes[offset..])?;
+    offset += read;
+    let end = offset.checked_add(value_len as usize).ok_or(ParseError::InvalidFormat)?;
+    if end != bytes.len() {
+        return Err(ParseError::LengthMismatch { declared: end, actual: bytes.len() });
+    }
+    if delta_op == DeltaOp::Deltas {
+        DeltaGroup::parse(&bytes[offset..end])?;
+    }
+
+    Ok((client_header, DeltaPayload {
+        payload_len,
+        delta_op,
+        field_address: FieldAddress {
+            schema_version,
+            field_index,
+            params_raw: (params.as_ptr(), params.len()),
+        },
+        payload_value: &bytes[offset..end],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{field::{ArrayParam, FieldDescriptor, ParamGroup, ParamGroupValues}, varint::{encode_varint, VARINT_MAX}};
+    use proptest::prelude::*;
+
+    /// Hand-roll incoming bytes: op, address, raw TLV groups and value
+    fn incoming(op: u8, schema_version: u32, field_index: u32, groups: &[(u8, Vec<u8>)], value: &[u8]) -> Vec<u8> {
+        let mut body = vec![op];
+        encode_varint(schema_version, &mut body).unwrap();
+        encode_varint(field_index, &mut body).unwrap();
+        body.push(groups.len() as u8);
+        for (tag, group) in groups {
+            body.push(*tag);
+            encode_varint(group.len() as u32, &mut body).unwrap();
+            body.extend_from_slice(group);
+        }
+        encode_varint(value.len() as u32, &mut body).unwrap();
+        body.extend_from_slice(value);
+
+        let mut bytes = 42u64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_simple_set() {
+        let bytes = incoming(0, 1, 300, &[], &[2, 7, 0, 0, 0, 0, 0, 0, 0]);
+        let delta = DeltaIncoming::parse(&bytes).unwrap();
+        assert_eq!(delta.client_header().unwrap().content_hash, 42);
+
+        let payload = delta.payload().unwrap();
+        assert_eq!(payload.delta_op, DeltaOp::Set);
+        assert_eq!(payload.field_address.schema_version, 1);
+        assert_eq!(payload.field_address.field_index, 300);
+        assert_eq!(payload.field_address.params_raw.1, 0);
+        assert_eq!(payload.payload_value, &[2, 7, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(DeltaIncoming::frame_len(&bytes).unwrap(), bytes.len());
+    }
+
+    #[test]
+    fn test_parse_param_groups_readable_by_iterator() {
+        // KeySet with "alice", ArraySet with Index(5) and Range(1, 200)
+        let keys = vec![1, 5, b'a', b'l', b'i', b'c', b'e'];
+        let mut array = vec![2, 0x01, 5, 0x03, 1];
+        encode_varint(200, &mut array).unwrap();
+        let bytes = incoming(0, 0, 3, &[(0x01, keys), (0x02, array)], &[]);
+
+        let delta = DeltaIncoming::parse(&bytes).unwrap();
+        let groups = [ParamGroup::KeySet, ParamGroup::ArraySet];
+        let mut iter = delta.payload().unwrap().field_address.params_iter(&groups);
+
+        let first = iter.next_group().unwrap();
+        assert_eq!(first.group_type, ParamGroup::KeySet);
+        assert_eq!(first.count, 1);
+        assert_eq!(first.data.1, 6);
+
+        let second = iter.next_group().unwrap();
+        assert_eq!(second.group_type, ParamGroup::ArraySet);
+        assert_eq!(second.count, 2);
+        assert!(iter.next_group().is_none());
+    }
+
+    #[test]
+    fn test_parse_rejects_every_truncation() {
+        let bytes = incoming(10, 2, 20_000, &[(0x01, vec![1, 2, b'i', b'd'])], &[16, 3, b'a', b'b', b'c']);
+        assert!(DeltaIncoming::parse(&bytes).is_ok());
+        for len in 0..bytes.len() {
+            assert!(DeltaIncoming::parse(&bytes[..len]).is_err(), "truncation at {} accepted", len);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_op() {
+        let bytes = incoming(4, 0, 0, &[], &[]);
+        assert_eq!(DeltaIncoming::parse(&bytes).err(), Some(ParseError::InvalidOperation(4)));
+        let bytes = incoming(255, 0, 0, &[], &[]);
+        assert_eq!(DeltaIncoming::parse(&bytes).err(), Some(ParseError::InvalidOperation(255)));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_params() {
+        // Unknown group tag
+        let bytes = incoming(0, 0, 0, &[(0x09, vec![0])], &[]);
+        assert_eq!(DeltaIncoming::parse(&bytes).err(), Some(ParseError::InvalidParamGroup(0x09)));
+
+        // Unknown array param type
+        let bytes = incoming(0, 0, 0, &[(0x02, vec![1, 0x07, 1])], &[]);
+        assert_eq!(DeltaIncoming::parse(&bytes).err(), Some(ParseError::InvalidArrayParam(0x07)));
+
+        // Invalid UTF-8 key
+        let bytes = incoming(0, 0, 0, &[(0x01, vec![1, 2, 0xFF, 0xFE])], &[]);
+        assert_eq!(DeltaIncoming::parse(&bytes).err(), Some(ParseError::InvalidUtf8));
+
+        // Group body longer than its entries
+        let bytes = incoming(0, 0, 0, &[(0x02, vec![1, 0x01, 3, 9])], &[]);
+        assert!(matches!(DeltaIncoming::parse(&bytes), Err(ParseError::LengthMismatch { .. })));
+    }
+
+    #[test]
+    fn test_parse_rejects_length_mismatches() {
+        // Trailing byte beyond declared payload length
+        let mut bytes = incoming(0, 0, 0, &[], &[1]);
+        bytes.push(0);
+        assert!(matches!(DeltaIncoming::parse(&bytes), Err(ParseError::LengthMismatch { .. })));
+
+        // Value length disagrees with payload length
+        let mut bytes = incoming(0, 0, 0, &[], &[1, 2]);
+        let value_len_at = bytes.len() - 3;
+        bytes[value_len_at] = 1;
+        assert!(matches!(DeltaIncoming::parse(&bytes), Err(ParseError::LengthMismatch { .. })));
+
+        // Schema version beyond u16
+        let bytes = incoming(0, 70_000, 0, &[], &[]);
+        assert_eq!(DeltaIncoming::parse(&bytes).err(), Some(ParseError::InvalidFormat));
+
+        // Largest payload length never wraps the declared frame length
+        let mut bytes = incoming(0, 0, 0, &[], &[]);
+        bytes[DELTA_CLIENT_HEADER_SIZE..DELTA_OP_OFFSET].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(DeltaIncoming::parse(&bytes).is_err());
+        assert!(!matches!(DeltaIncoming::frame_len(&bytes), Ok(len) if len <= bytes.len()));
+    }
+
+    #[test]
+    fn test_delta_ref_from_wire_bytes() {
+        let user_id = UserId::random();
+        let doc_id = DocId::random();
+        let mut stored = Vec::with_capacity(DELTA_SECURE_HEADER_SIZE);
+        stored.extend_from_slice(user_id.as_bytes());
+        stored.extend_from_slice(doc_id.as_bytes());
+        stored.extend_from_slice(b"delta001");
+        stored.extend_from_slice(b"delta000");
+        stored.extend_from_slice(&9u64.to_le_bytes());
+        stored.extend_from_slice(&1234u64.to_le_bytes());
+        stored.extend_from_slice(&[0u8; 48]);
+        assert_eq!(stored.len(), DELTA_SECURE_HEADER_SIZE);
+        stored.extend_from_slice(&incoming(1, 0, 7, &[], &[]));
+
+        let delta = DeltaRef::from_wire_bytes(&stored).unwrap();
+        assert_eq!(delta.server_header().user_id, user_id);
+        assert_eq!(delta.server_header().doc_id, doc_id);
+        assert_eq!(delta.server_header().delta_id.as_str(), "delta001");
+        assert_eq!(delta.server_header().delta_sequence_number, 9);
+        assert_eq!(delta.payload().delta_op, DeltaOp::Delete);
+        assert_eq!(delta.payload().field_address.field_index, 7);
+        assert_eq!(delta.incoming_bytes().len(), stored.len() - DELTA_SECURE_HEADER_SIZE);
+
+        assert!(DeltaRef::from_wire_bytes(&stored[..DELTA_SECURE_HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_builder_matches_hand_rolled_layout() {
+        let mut params = FieldParams::new();
+        params.add_keys(vec!["id".to_string()]);
+        let built = DeltaBuilder::new(DeltaOp::Insert)
+            .field(2, 20_000)
+            .params(params)
+            .string("abc")
+            .build()
+            .unwrap();
+
+        let mut expected = incoming(10, 2, 20_000, &[(0x01, vec![1, 2, b'i', b'd'])], &[16, 3, b'a', b'b', b'c']);
+        let hash = content_hash(&expected[DELTA_OP_OFFSET..]);
+        expected[..DELTA_CLIENT_HEADER_SIZE].copy_from_slice(&hash.to_le_bytes());
+        assert_eq!(built, expected);
+
+        let delta = DeltaIncoming::parse(&built).unwrap();
+        assert!(delta.content_hash_matches());
+    }
+
+    #[test]
+    fn test_builder_typed_values() {
+        let cases: Vec<(DeltaBuilder, Vec<u8>)> = vec![
+            (DeltaBuilder::new(DeltaOp::Set).null(), vec![0]),
+            (DeltaBuilder::new(DeltaOp::Set).bool(true), vec![1, 1]),
+            (DeltaBuilder::new(DeltaOp::Set).int(-1), [vec![2], (-1i64).to_le_bytes().to_vec()].concat()),
+            (DeltaBuilder::new(DeltaOp::Set).float(1.5), [vec![3], 1.5f64.to_le_bytes().to_vec()].concat()),
+            (DeltaBuilder::new(DeltaOp::Set).binary(&[9; 200]), [vec![17, 0x80, 200], vec![9; 200]].concat()),
+            (DeltaBuilder::new(DeltaOp::Delete), vec![]),
+        ];
+        for (builder, value) in cases {
+            let bytes = builder.build().unwrap();
+            let delta = DeltaIncoming::parse(&bytes).unwrap();
+            assert_eq!(delta.payload().unwrap().payload_value, value.as_slice());
+        }
+
+        assert!(DeltaBuilder::new(DeltaOp::Set).value(ValueType::Int, &[1, 2]).build().is_err());
+    }
+
+    #[test]
+    fn test_content_hash_detects_modified_payload() {
+        let mut bytes = DeltaBuilder::new(DeltaOp::Set).field(0, 1).int(7).build().unwrap();
+        assert!(DeltaIncoming::parse(&bytes).unwrap().content_hash_matches());
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert!(!DeltaIncoming::parse(&bytes).unwrap().content_hash_matches());
+    }
+
+    #[test]
+    fn test_builder_rejects_mismatched_params_for_descriptor() {
+        let descriptor = FieldDescriptor::new("users{}.tags[]".to_string(), ValueType::String).unwrap();
+        let mut params = FieldParams::new();
+        params.add_array(vec![ArrayParam::Index(1)]);
+        params.add_keys(vec!["alice".to_string()]);
+        assert!(params.encode(&descriptor).is_err());
+
+        let mut params = FieldParams::new();
+        params.add_keys(vec!["alice".to_string()]);
+        params.add_array(vec![ArrayParam::Index(1)]);
+        let encoded = params.encode(&descriptor).unwrap();
+
+        let bytes = DeltaBuilder::new(DeltaOp::Set).params(params.clone()).string("x").build().unwrap();
+        let delta = DeltaIncoming::parse(&bytes).unwrap();
+        let address = &delta.payload().unwrap().field_address;
+        assert_eq!(address.params_raw.1, encoded.len() - 1);
+        assert_eq!(address.decode_params(&descriptor.param_groups).unwrap(), params);
+    }
+
+    #[test]
+    fn test_group_round_trip() {
+        let children: Vec<Vec<u8>> = vec![
+            DeltaBuilder::new(DeltaOp::Set).field(0, 1).int(5).build().unwrap(),
+            DeltaBuilder::new(DeltaOp::Delete).field(0, 2).build().unwrap(),
+        ];
+        let refs: Vec<&[u8]> = children.iter().map(|c| c.as_slice()).collect();
+        let bytes = DeltaBuilder::group(&refs).unwrap().build().unwrap();
+
+        let delta = DeltaIncoming::parse(&bytes).unwrap();
+        let payload = delta.payload().unwrap();
+        assert_eq!(payload.delta_op, DeltaOp::Deltas);
+        assert_eq!(payload.sequence_span(), 2);
+
+        let group = payload.group().unwrap();
+        assert_eq!(group.len(), 2);
+        let nested: Vec<_> = group.iter().map(|d| d.bytes).collect();
+        assert_eq!(nested, refs);
+    }
+
+    #[test]
+    fn test_group_rejects_malformed_children() {
+        let child = DeltaBuilder::new(DeltaOp::Set).field(0, 1).int(5).build().unwrap();
+
+        // Empty group
+        assert!(DeltaIncoming::parse(&DeltaBuilder::group(&[]).unwrap().build().unwrap()).is_err());
+
+        // Nested group
+        let inner = DeltaBuilder::group(&[&child]).unwrap().build().unwrap();
+        let outer = DeltaBuilder::group(&[&inner]).unwrap().build().unwrap();
+        assert_eq!(DeltaIncoming::parse(&outer).err(), Some(ParseError::InvalidFormat));
+
+        // Truncated child
+        let bytes = DeltaBuilder::group(&[&child[..child.len() - 1]]).unwrap().build().unwrap();
+        assert!(DeltaIncoming::parse(&bytes).is_err());
+
+        // Count larger than children present
+        let mut value = Vec::new();
+        DeltaGroup::encode(&[&child], &mut value).unwrap();
+        value[0] = 2;
+        let bytes = DeltaBuilder::new(DeltaOp::Deltas).raw_value(&value).build().unwrap();
+        assert!(DeltaIncoming::parse(&bytes).is_err());
+
+        // Non-group payloads have no group view
+        let delta = DeltaIncoming::parse(&child).unwrap();
+        assert_eq!(delta.payload().unwrap().group().err(), Some(ParseError::InvalidOperation(0)));
+        assert_eq!(delta.payload().unwrap().sequence_span(), 1);
+    }
+
+    fn array_param() -> impl Strategy<Value = ArrayParam> {
+        let value = 0..=VARINT_MAX;
+        prop_oneof![
+            value.clone().prop_map(ArrayParam::Index),
+            prop::collection::vec(value.clone(), 0..8).prop_map(ArrayParam::Indices),
+            (value.clone(), value.clone()).prop_map(|(start, end)| ArrayParam::Range(start, end)),
+            prop::collection::vec((value.clone(), value.clone()), 0..8).prop_map(ArrayParam::Ranges),
+            prop::collection::vec(value, 0..8).prop_map(ArrayParam::Dimensions),
+        ]
+    }
+
+    fn param_group() -> impl Strategy<Value = ParamGroupValues> {
+        prop_oneof![
+            prop::collection::vec("\\PC{0,24}", 0..6).prop_map(ParamGroupValues::KeySet),
+            prop::collection::vec(array_param(), 0..6).prop_map(ParamGroupValues::ArraySet),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_builder_parser_round_trip(
+            op in any::<u8>().prop_filter_map("known non-group op", |op| {
+                DeltaOp::try_from(op).ok().filter(|op| *op != DeltaOp::Deltas)
+            }),
+            schema_version in any::<u16>(),
+            field_index in 0..=VARINT_MAX,
+            groups in prop::collection::vec(param_group(), 0..5),
+            value in prop::collection::vec(any::<u8>(), 0..512),
+        ) {
+            let params = FieldParams { groups };
+            let bytes = DeltaBuilder::new(op)
+                .field(schema_version, field_index)
+                .params(params.clone())
+                .binary(&value)
+                .build()
+                .unwrap();
+            prop_assert_eq!(DeltaIncoming::frame_len(&bytes).unwrap(), bytes.len());
+
+            let delta = DeltaIncoming::parse(&bytes).unwrap();
+            prop_assert!(delta.content_hash_matches());
+            let payload = delta.payload().unwrap();
+            prop_assert_eq!(payload.delta_op, op);
+            prop_assert_eq!(payload.field_address.schema_version, schema_version);
+            prop_assert_eq!(payload.field_address.field_index, field_index);
+
+            let group_types: Vec<ParamGroup> = params.groups.iter().map(|g| g.group_type()).collect();
+            prop_assert_eq!(payload.field_address.decode_params(&group_types).unwrap(), params);
+
+            let mut expected = Vec::new();
+            Value::encode(ValueType::Binary, &value, &mut expected).unwrap();
+            prop_assert_eq!(payload.payload_value, expected.as_slice());
+        }
+    }
+}
@@ -7,13 +7,13 @@
 
 // use std::sync::atomic::{AtomicPtr};
 
-use crate::{types::{storage::{ WireFormat }}, UserId};
+use crate::{types::{storage::{ WireFormat }, ParseError}, UserId};
 
 use super::{DocId};
 
 /// Document type identifiers
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DocumentType {
     // Root document type
     /// The default document type, used in every document
@@ -72,6 +72,53 @@ pub enum DocumentType {
     EventStream = 84,
 }
 
+impl DocumentType {
+    /// Whether documents of this type are append-only streams
+    pub fn is_stream(self) -> bool {
+        matches!(
+            self,
+            DocumentType::TextStream
+                | DocumentType::BinaryStream
+                | DocumentType::DeltaStream
+                | DocumentType::DocumentStream
+                | DocumentType::EventStream
+        )
+    }
+}
+
+impl TryFrom<u8> for DocumentType {
+    type Error = ParseError;
+
+    /// Checked conversion from the wire type byte - unknown values are rejected
+    fn try_from(doc_type: u8) -> Result<Self, Self::Error> {
+        Ok(match doc_type {
+            0 => DocumentType::Tree,
+            1 => DocumentType::Graph,
+            2 => DocumentType::StateGraph,
+            3 => DocumentType::Schema,
+            16 => DocumentType::Binary,
+            17 => DocumentType::Image,
+            18 => DocumentType::Video,
+            19 => DocumentType::Audio,
+            33 => DocumentType::Tensor,
+            34 => DocumentType::Matrix,
+            35 => DocumentType::Table,
+            48 => DocumentType::JSON,
+            49 => DocumentType::XML,
+            50 => DocumentType::YAML,
+            64 => DocumentType::Markdown,
+            65 => DocumentType::PlainText,
+            66 => DocumentType::Code,
+            80 => DocumentType::TextStream,
+            81 => DocumentType::BinaryStream,
+            82 => DocumentType::DeltaStream,
+            83 => DocumentType::DocumentStream,
+            84 => DocumentType::EventStream,
+            _ => return Err(ParseError::InvalidFormat),
+        })
+    }
+}
+
 // Document metadata structure
 /// Persistent document header (immutable, stored in chunks)
 /// Keeps reference to raw bytes but parses all values upfront
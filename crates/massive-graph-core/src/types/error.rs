@@ -4,7 +4,7 @@
 //! optimized for zero-cost error propagation and clear diagnostics.
 //! 
 //! 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// Errors that can occur during parsing operations
 pub enum ParseError {
    /// Not enough bytes for the expected data
@@ -23,6 +23,31 @@ pub enum ParseError {
    
    /// Corrupted or invalid wire format
    InvalidFormat,
+
+   /// Unknown parameter group tag
+   InvalidParamGroup(u8),
+
+   /// Unknown array parameter type byte
+   InvalidArrayParam(u8),
+
+   /// Declared length does not match the bytes present
+   LengthMismatch {
+       /// Length declared on the wire
+       declared: usize,
+       /// Length actually consumed or available
+       actual: usize
+   },
+
+   /// Unknown value type tag
+   InvalidValueType(u8),
+
+   /// Value read as a different type than it holds
+   TypeMismatch {
+       /// Type tag the reader asked for
+       expected: u8,
+       /// Type tag of the value
+       actual: u8
+   },
 }
 
 impl std::fmt::Display for ParseError {
@@ -40,6 +65,21 @@ impl std::fmt::Display for ParseError {
            ParseError::InvalidFormat => {
                write!(f, "Invalid wire format")
            }
+           ParseError::InvalidParamGroup(tag) => {
+               write!(f, "Invalid parameter group tag: {:#x}", tag)
+           }
+           ParseError::InvalidArrayParam(param_type) => {
+               write!(f, "Invalid array parameter type: {:#x}", param_type)
+           }
+           ParseError::LengthMismatch { declared, actual } => {
+               write!(f, "Length mismatch: declared {} bytes, found {}", declared, actual)
+           }
+           ParseError::InvalidValueType(tag) => {
+               write!(f, "Invalid value type tag: {:#x}", tag)
+           }
+           ParseError::TypeMismatch { expected, actual } => {
+               write!(f, "Type mismatch: expected value type {:#x}, got {:#x}", expected, actual)
+           }
        }
    }
 }
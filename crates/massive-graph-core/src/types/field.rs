@@ -1,4 +1,4 @@
-use crate::{core::utils::current_timestamp, types::ValueType};
+use crate::{core::utils::current_timestamp, types::{varint::{decode_varint, encode_varint, varint_len}, ParseError, ValueType}};
 
 /// Field address with parameters for V2 wire format
 /// Parent requirement is predefined in descriptor, not sent on wire
@@ -12,13 +12,36 @@ pub struct FieldAddress {
 }
 
 impl FieldAddress {
+    /// Encode a field address: `[schema version varint][field index varint][params section]`
+    pub fn encode(schema_version: u16, field_index: u32, descriptor: &FieldDescriptor, params: &FieldParams) -> Result<Vec<u8>, String> {
+        let mut output = Vec::with_capacity(5 + params.encoded_size() + params.groups.len() * 2);
+        encode_varint(schema_version as u32, &mut output)?;
+        encode_varint(field_index, &mut output)?;
+        output.extend_from_slice(&params.encode(descriptor)?);
+        Ok(output)
+    }
+
     /// Create parameter iterator based on field descriptor's param groups
     pub fn params_iter<'a>(&self, param_groups: &'a [ParamGroup]) -> ParamIterator<'a> {
         ParamIterator::new(self.params_raw.0, self.params_raw.1, param_groups)
     }
+
+    /// Decode all parameter groups into typed values
+    pub fn decode_params(&self, param_groups: &[ParamGroup]) -> Result<FieldParams, ParseError> {
+        let mut iter = self.params_iter(param_groups);
+        let mut params = FieldParams::new();
+        while let Some(group) = iter.next_group() {
+            params.groups.push(group.decode()?);
+        }
+        if params.groups.len() != param_groups.len() || iter.remaining != 0 {
+            return Err(ParseError::InvalidFormat);
+        }
+        Ok(params)
+    }
 }
 
 /// Iterator over parameter groups without allocation
+/// Each group is framed on the wire as [tag][body length varint][count][entries]
 pub struct ParamIterator<'a> {
     ptr: *const u8,
     remaining: usize,
@@ -45,81 +68,27 @@ impl<'a> ParamIterator<'a> {
         let group_type = &self.groups[self.current_group];
         self.current_group += 1;
         
-        // Read count byte
-        if self.remaining < 1 {
+        // SAFETY: ptr/remaining describe the params region of a live, parser-validated buffer
+        let bytes = unsafe { std::slice::from_raw_parts(self.ptr, self.remaining) };
+        
+        // Tag must match the group the field descriptor expects at this position
+        let (&tag, rest) = bytes.split_first()?;
+        if ParamGroup::try_from(tag).ok()? != *group_type {
             return None;
         }
-        let count = unsafe { *self.ptr };
-        self.ptr = unsafe { self.ptr.add(1) };
-        self.remaining -= 1;
         
-        let start_ptr = self.ptr;
-        let start_remaining = self.remaining;
+        let (body_len, read) = decode_varint(rest).ok()?;
+        let body = rest.get(read..read.checked_add(body_len as usize)?)?;
+        let (&count, entries) = body.split_first()?;
         
-        // Calculate how much data this group consumes
-        match group_type {
-            ParamGroup::KeySet => {
-                // Skip over count keys (each has length prefix + data)
-                for _ in 0..count {
-                    if self.remaining < 1 {
-                        return None;
-                    }
-                    let key_len = unsafe { *self.ptr } as usize;
-                    let total = 1 + key_len;
-                    if self.remaining < total {
-                        return None;
-                    }
-                    self.ptr = unsafe { self.ptr.add(total) };
-                    self.remaining -= total;
-                }
-            }
-            ParamGroup::ArraySet => {
-                // Skip over array parameters (type byte + data)
-                for _ in 0..count {
-                    if self.remaining < 1 {
-                        return None;
-                    }
-                    let param_type = unsafe { *self.ptr };
-                    self.ptr = unsafe { self.ptr.add(1) };
-                    self.remaining -= 1;
-                    
-                    // Skip data based on type
-                    let data_size = match param_type {
-                        0x01 => 1, // Single index (varint)
-                        0x02 => { // Multiple indices
-                            if self.remaining < 1 { return None; }
-                            let n = unsafe { *self.ptr } as usize;
-                            1 + n // count + n varints
-                        }
-                        0x03 => 2, // Range (2 varints)
-                        0x04 => { // Multiple ranges
-                            if self.remaining < 1 { return None; }
-                            let n = unsafe { *self.ptr } as usize;
-                            1 + n * 2 // count + n range pairs
-                        }
-                        0x05 => { // Dimensions
-                            if self.remaining < 1 { return None; }
-                            let n = unsafe { *self.ptr } as usize;
-                            1 + n // count + n dimension values
-                        }
-                        _ => return None,
-                    };
-                    
-                    if self.remaining < data_size {
-                        return None;
-                    }
-                    self.ptr = unsafe { self.ptr.add(data_size) };
-                    self.remaining -= data_size;
-                }
-            }
-        }
-        
-        let consumed = start_remaining - self.remaining;
+        let consumed = 1 + read + body.len();
+        self.ptr = unsafe { self.ptr.add(consumed) };
+        self.remaining -= consumed;
         
         Some(ParamGroupData {
             group_type: group_type.clone(),
             count,
-            data: (start_ptr, consumed),
+            data: (entries.as_ptr(), entries.len()),
         })
     }
 }
@@ -130,17 +99,87 @@ pub struct ParamGroupData {
     pub group_type: ParamGroup,
     /// Count
     pub count: u8,
-    /// Pointer to raw data including the count byte
+    /// Pointer to the group's entries (after the count byte)
     pub data: (*const u8, usize),
 }
 
+impl ParamGroupData {
+    /// Decode the group's entries into typed keys or array params
+    pub fn decode(&self) -> Result<ParamGroupValues, ParseError> {
+        // SAFETY: data points into the params region the iterator was created over
+        let mut rest = unsafe { std::slice::from_raw_parts(self.data.0, self.data.1) };
+        let next = |rest: &mut &[u8]| -> Result<u32, ParseError> {
+            let (value, read) = decode_varint(rest)?;
+            *rest = &rest[read..];
+            Ok(value)
+        };
+        
+        match self.group_type {
+            ParamGroup::KeySet => {
+                let mut keys = Vec::with_capacity(self.count as usize);
+                for _ in 0..self.count {
+                    let len = next(&mut rest)? as usize;
+                    let key = rest
+                        .get(..len)
+                        .ok_or(ParseError::InsufficientData { expected: len, actual: rest.len() })?;
+                    keys.push(std::str::from_utf8(key).map_err(|_| ParseError::InvalidUtf8)?.to_string());
+                    rest = &rest[len..];
+                }
+                Ok(ParamGroupValues::KeySet(keys))
+            }
+            ParamGroup::ArraySet => {
+                let mut params = Vec::with_capacity(self.count as usize);
+                for _ in 0..self.count {
+                    let (&type_byte, tail) = rest
+                        .split_first()
+                        .ok_or(ParseError::InsufficientData { expected: 1, actual: 0 })?;
+                    rest = tail;
+                    let param = match ArrayParamType::try_from(type_byte)? {
+                        ArrayParamType::Index => ArrayParam::Index(next(&mut rest)?),
+                        ArrayParamType::Indices => {
+                            let n = next(&mut rest)?;
+                            ArrayParam::Indices((0..n).map(|_| next(&mut rest)).collect::<Result<_, _>>()?)
+                        }
+                        ArrayParamType::Range => ArrayParam::Range(next(&mut rest)?, next(&mut rest)?),
+                        ArrayParamType::Ranges => {
+                            let n = next(&mut rest)?;
+                            ArrayParam::Ranges((0..n)
+                                .map(|_| Ok((next(&mut rest)?, next(&mut rest)?)))
+                                .collect::<Result<_, ParseError>>()?)
+                        }
+                        ArrayParamType::Dimensions => {
+                            let n = next(&mut rest)?;
+                            ArrayParam::Dimensions((0..n).map(|_| next(&mut rest)).collect::<Result<_, _>>()?)
+                        }
+                    };
+                    params.push(param);
+                }
+                Ok(ParamGroupValues::ArraySet(params))
+            }
+        }
+    }
+}
+
 /// Parameter group types determined by field descriptor
+#[repr(u8)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum ParamGroup {
     /// Map key selection - "users.{}"
-    KeySet,
+    KeySet = 0x01,
     /// Array element selection - "items[]"
-    ArraySet,
+    ArraySet = 0x02,
+}
+
+impl TryFrom<u8> for ParamGroup {
+    type Error = ParseError;
+
+    fn try_from(tag: u8) -> Result<Self, Self::Error> {
+        match tag {
+            0x01 => Ok(ParamGroup::KeySet),
+            0x02 => Ok(ParamGroup::ArraySet),
+            _ => Err(ParseError::InvalidParamGroup(tag)),
+        }
+    }
 }
 
 /// Array parameter types (require type byte in wire format)
@@ -159,6 +198,98 @@ pub enum ArrayParamType {
     Dimensions = 0x05,   // Tensor/matrix dimensions
 }
 
+impl TryFrom<u8> for ArrayParamType {
+    type Error = ParseError;
+
+    fn try_from(type_byte: u8) -> Result<Self, Self::Error> {
+        match type_byte {
+            0x01 => Ok(ArrayParamType::Index),
+            0x02 => Ok(ArrayParamType::Indices),
+            0x03 => Ok(ArrayParamType::Range),
+            0x04 => Ok(ArrayParamType::Ranges),
+            0x05 => Ok(ArrayParamType::Dimensions),
+            _ => Err(ParseError::InvalidArrayParam(type_byte)),
+        }
+    }
+}
+
+/// Validate a params section ([group count] then [tag][body length][body] per group) at
+/// the start of `bytes`, returning its length including the group count
+pub(crate) fn validate_params_section(bytes: &[u8]) -> Result<usize, ParseError> {
+    let (&group_count, _) = bytes
+        .split_first()
+        .ok_or(ParseError::InsufficientData { expected: 1, actual: 0 })?;
+    let mut offset = 1;
+    for _ in 0..group_count {
+        let tag = *bytes
+            .get(offset)
+            .ok_or(ParseError::InsufficientData { expected: offset + 1, actual: bytes.len() })?;
+        let group = ParamGroup::try_from(tag)?;
+        offset += 1;
+        let (body_len, read) = decode_varint(&bytes[offset..])?;
+        offset += read;
+        let end = offset.checked_add(body_len as usize).ok_or(ParseError::InvalidFormat)?;
+        let body = bytes
+            .get(offset..end)
+            .ok_or(ParseError::InsufficientData { expected: end, actual: bytes.len() })?;
+        validate_group_body(&group, body)?;
+        offset = end;
+    }
+    Ok(offset)
+}
+
+/// Validate a parameter group body ([count][entries]) for the given group type
+pub(crate) fn validate_group_body(group: &ParamGroup, body: &[u8]) -> Result<(), ParseError> {
+    let (&count, mut rest) = body
+        .split_first()
+        .ok_or(ParseError::InsufficientData { expected: 1, actual: 0 })?;
+    
+    for _ in 0..count {
+        match group {
+            ParamGroup::KeySet => {
+                // Key: [length varint][UTF-8 bytes]
+                let (key_len, read) = decode_varint(rest)?;
+                let end = read.checked_add(key_len as usize).ok_or(ParseError::InvalidFormat)?;
+                let key = rest
+                    .get(read..end)
+                    .ok_or(ParseError::InsufficientData { expected: end, actual: rest.len() })?;
+                std::str::from_utf8(key).map_err(|_| ParseError::InvalidUtf8)?;
+                rest = &rest[end..];
+            }
+            ParamGroup::ArraySet => {
+                // Param: [type byte][varints determined by type]
+                let (&type_byte, tail) = rest
+                    .split_first()
+                    .ok_or(ParseError::InsufficientData { expected: 1, actual: 0 })?;
+                rest = tail;
+                let values = match ArrayParamType::try_from(type_byte)? {
+                    ArrayParamType::Index => 1,
+                    ArrayParamType::Range => 2,
+                    ArrayParamType::Indices | ArrayParamType::Dimensions => {
+                        let (n, read) = decode_varint(rest)?;
+                        rest = &rest[read..];
+                        n as usize
+                    }
+                    ArrayParamType::Ranges => {
+                        let (n, read) = decode_varint(rest)?;
+                        rest = &rest[read..];
+                        n as usize * 2
+                    }
+                };
+                for _ in 0..values {
+                    let (_, read) = decode_varint(rest)?;
+                    rest = &rest[read..];
+                }
+            }
+        }
+    }
+    
+    if !rest.is_empty() {
+        return Err(ParseError::LengthMismatch { declared: body.len(), actual: body.len() - rest.len() });
+    }
+    Ok(())
+}
+
 /// Field descriptor stored in schema
 #[derive(Clone, Debug)]
 pub struct FieldDescriptor {
@@ -206,6 +337,31 @@ impl FieldDescriptor {
         })
     }
     
+    /// Encode as `[value type: u8][path length varint][path]`, the path prefixed with `*`
+    /// when the field requires a parent
+    pub fn encode_into(&self, output: &mut Vec<u8>) -> Result<(), String> {
+        let prefix = if self.requires_parent { "*" } else { "" };
+        output.push(self.value_type as u8);
+        encode_varint((prefix.len() + self.path.len()) as u32, output)?;
+        output.extend_from_slice(prefix.as_bytes());
+        output.extend_from_slice(self.path.as_bytes());
+        Ok(())
+    }
+
+    /// Decode a descriptor written by `encode_into`, returning it and the bytes read
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), ParseError> {
+        let (&tag, rest) = bytes.split_first().ok_or(ParseError::InsufficientData { expected: 1, actual: 0 })?;
+        let value_type = ValueType::try_from(tag)?;
+        let (len, read) = decode_varint(rest)?;
+        let end = read.checked_add(len as usize).ok_or(ParseError::InvalidFormat)?;
+        let path = rest
+            .get(read..end)
+            .ok_or(ParseError::InsufficientData { expected: 1 + end, actual: bytes.len() })?;
+        let path = std::str::from_utf8(path).map_err(|_| ParseError::InvalidUtf8)?;
+        let descriptor = Self::new(path.to_string(), value_type).map_err(|_| ParseError::InvalidFormat)?;
+        Ok((descriptor, 1 + end))
+    }
+
     /// Parse and validate path, returning parameter groups
     fn parse_and_validate_path(path: &str) -> Result<Vec<ParamGroup>, String> {
         if path.is_empty() {
@@ -272,14 +428,14 @@ impl FieldDescriptor {
 }
 
 /// Parameters for encoding (high-level API)
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct FieldParams {
     /// List of parameter groups
     pub groups: Vec<ParamGroupValues>,
 }
 
 /// Values for a parameter group
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ParamGroupValues {
     /// Map key selection - "users.{}"
     KeySet(Vec<String>),
@@ -287,8 +443,26 @@ pub enum ParamGroupValues {
     ArraySet(Vec<ArrayParam>),
 }
 
+impl ParamGroupValues {
+    /// Group type of these values
+    pub fn group_type(&self) -> ParamGroup {
+        match self {
+            ParamGroupValues::KeySet(_) => ParamGroup::KeySet,
+            ParamGroupValues::ArraySet(_) => ParamGroup::ArraySet,
+        }
+    }
+    
+    /// Encoded size of the group body (count byte + entries)
+    pub fn body_size(&self) -> usize {
+        1 + match self {
+            ParamGroupValues::KeySet(keys) => keys.iter().map(|k| varint_len(k.len() as u32) + k.len()).sum::<usize>(),
+            ParamGroupValues::ArraySet(params) => params.iter().map(|p| 1 + p.encoded_size()).sum::<usize>(),
+        }
+    }
+}
+
 /// Array parameter values
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ArrayParam {
     /// Single index
     Index(u32),
@@ -316,12 +490,36 @@ impl ArrayParam {
     
     /// Calculate encoded size (excluding type byte)
     pub fn encoded_size(&self) -> usize {
+        let list = |v: &[u32]| varint_len(v.len() as u32) + v.iter().map(|&x| varint_len(x)).sum::<usize>();
         match self {
-            ArrayParam::Index(_) => 1, // 1 varint
-            ArrayParam::Indices(v) => 1 + v.len(), // count + values
-            ArrayParam::Range(_, _) => 2, // 2 varints
-            ArrayParam::Ranges(v) => 1 + v.len() * 2, // count + pairs
-            ArrayParam::Dimensions(v) => 1 + v.len(), // count + values
+            ArrayParam::Index(idx) => varint_len(*idx),
+            ArrayParam::Indices(v) | ArrayParam::Dimensions(v) => list(v),
+            ArrayParam::Range(start, end) => varint_len(*start) + varint_len(*end),
+            ArrayParam::Ranges(v) => {
+                varint_len(v.len() as u32) + v.iter().map(|&(s, e)| varint_len(s) + varint_len(e)).sum::<usize>()
+            }
+        }
+    }
+    
+    /// Encode param data (excluding type byte) as varints
+    fn encode_data(&self, output: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            ArrayParam::Index(idx) => encode_varint(*idx, output),
+            ArrayParam::Indices(values) | ArrayParam::Dimensions(values) => {
+                encode_varint(values.len() as u32, output)?;
+                values.iter().try_for_each(|&v| encode_varint(v, output))
+            }
+            ArrayParam::Range(start, end) => {
+                encode_varint(*start, output)?;
+                encode_varint(*end, output)
+            }
+            ArrayParam::Ranges(ranges) => {
+                encode_varint(ranges.len() as u32, output)?;
+                ranges.iter().try_for_each(|&(start, end)| {
+                    encode_varint(start, output)?;
+                    encode_varint(end, output)
+                })
+            }
         }
     }
 }
@@ -342,28 +540,9 @@ impl FieldParams {
         self.groups.push(ParamGroupValues::ArraySet(params));
     }
     
-    /// Calculate total encoded size
+    /// Calculate encoded size of the group bodies (excluding group count and TLV framing)
     pub fn encoded_size(&self) -> usize {
-        let mut size = 0;
-        
-        for group in &self.groups {
-            size += 1; // Count byte
-            
-            match group {
-                ParamGroupValues::KeySet(keys) => {
-                    for key in keys {
-                        size += 1 + key.len(); // Length byte + string
-                    }
-                }
-                ParamGroupValues::ArraySet(params) => {
-                    for param in params {
-                        size += 1 + param.encoded_size(); // Type byte + data
-                    }
-                }
-            }
-        }
-        
-        size
+        self.groups.iter().map(|group| group.body_size()).sum()
     }
     
     /// Encode to wire format following field descriptor's expected groups
@@ -375,58 +554,50 @@ impl FieldParams {
                 field_desc.param_groups.len()
             ));
         }
+        if self.groups.iter().zip(&field_desc.param_groups).any(|(group, expected)| group.group_type() != *expected) {
+            return Err("Parameter type mismatch".to_string());
+        }
         
-        let mut output = Vec::with_capacity(self.encoded_size());
+        let mut output = Vec::with_capacity(1 + self.encoded_size() + self.groups.len() * 2);
+        self.encode_into(&mut output)?;
+        Ok(output)
+    }
+    
+    /// Encode the params section: [group count] then [tag][body length][count][entries] per group
+    pub fn encode_into(&self, output: &mut Vec<u8>) -> Result<(), String> {
+        if self.groups.len() > u8::MAX as usize {
+            return Err(format!("Too many parameter groups: {}", self.groups.len()));
+        }
+        output.push(self.groups.len() as u8);
         
-        for (i, group) in self.groups.iter().enumerate() {
-            let expected = &field_desc.param_groups[i];
+        for group in &self.groups {
+            output.push(group.group_type() as u8);
+            encode_varint(group.body_size() as u32, output)?;
             
-            match (group, expected) {
-                (ParamGroupValues::KeySet(keys), ParamGroup::KeySet) => {
-                    output.push(keys.len() as u8);
+            match group {
+                ParamGroupValues::KeySet(keys) => {
+                    output.push(Self::count_byte(keys.len())?);
                     for key in keys {
-                        output.push(key.len() as u8);
+                        encode_varint(key.len() as u32, output)?;
                         output.extend_from_slice(key.as_bytes());
                     }
                 }
-                (ParamGroupValues::ArraySet(params), ParamGroup::ArraySet) => {
-                    output.push(params.len() as u8);
+                ParamGroupValues::ArraySet(params) => {
+                    output.push(Self::count_byte(params.len())?);
                     for param in params {
                         output.push(param.type_byte());
-                        // Encode param data (simplified - would use actual varint encoding)
-                        match param {
-                            ArrayParam::Index(idx) => output.push(*idx as u8),
-                            ArrayParam::Indices(indices) => {
-                                output.push(indices.len() as u8);
-                                for idx in indices {
-                                    output.push(*idx as u8);
-                                }
-                            }
-                            ArrayParam::Range(start, end) => {
-                                output.push(*start as u8);
-                                output.push(*end as u8);
-                            }
-                            ArrayParam::Ranges(ranges) => {
-                                output.push(ranges.len() as u8);
-                                for (start, end) in ranges {
-                                    output.push(*start as u8);
-                                    output.push(*end as u8);
-                                }
-                            }
-                            ArrayParam::Dimensions(dims) => {
-                                output.push(dims.len() as u8);
-                                for dim in dims {
-                                    output.push(*dim as u8);
-                                }
-                            }
-                        }
+                        param.encode_data(output)?;
                     }
                 }
-                _ => return Err("Parameter type mismatch".to_string()),
             }
         }
         
-        Ok(output)
+        Ok(())
+    }
+    
+    /// Entry count must fit the single count byte
+    fn count_byte(count: usize) -> Result<u8, String> {
+        u8::try_from(count).map_err(|_| format!("Too many entries in parameter group: {}", count))
     }
 }
 
@@ -97,7 +97,7 @@ impl Default for ID32 {
 
 impl ID8 {
     /// Create a new ID from a 8-byte array
-    pub fn new(bytes: [u8; 8]) -> Self {
+    pub const fn new(bytes: [u8; 8]) -> Self {
         ID8(bytes)
     }
     
@@ -8,6 +8,18 @@ pub mod ids;
 pub mod document;
 /// Value-related types  
 pub mod value;
+/// Nested value layout and traversal
+pub mod value_nested;
+/// Reference values and their targets
+pub mod value_reference;
+/// JSON conversion for wire values
+pub mod value_json;
+/// MessagePack codec for wire values
+pub mod value_msgpack;
+/// CBOR codec for wire values
+pub mod value_cbor;
+/// Document body formats and content negotiation
+pub mod value_format;
 /// System-wide error types
 pub mod error;
 /// Parsing error types
@@ -16,10 +28,18 @@ pub mod parse;
 pub mod delta;
 /// Schema types
 pub mod schema;
+/// Schema version compatibility
+pub mod schema_diff;
+/// Path lookup for schema versions
+pub mod path_index;
+/// Schema export and import
+pub mod schema_export;
 /// User types
 pub mod user;
 /// Field types
 pub mod field;
+/// Variable-length integer encoding
+pub mod varint;
 /// Storage types (native only - contains platform-specific code)
 pub mod storage;
 
@@ -47,8 +67,8 @@ pub type ChunkId = u64;
 // Re-export commonly used types for convenience
 pub use ids::{ID8, ID16, ID32};
 // pub use document::{Document, DocumentType, DocumentIndexes, DocumentState};
-pub use value::{Value, ValueType};
-pub use delta::{Delta, DeltaOp};
+pub use value::{Value, ValueType, ValueWriter};
+pub use delta::{Delta, DeltaBuilder, DeltaGroup, DeltaOp};
 pub use schema::{ImmutableSchema, CachedSchemaVersion, SchemaRegistry};
 pub use field::{FieldDescriptor, FieldAddress, ParamGroup, FieldParams, ArrayParam, ArrayParamType};
 pub use error::ParseError;
\ No newline at end of file
@@ -0,0 +1,377 @@
+//! Path lookup for schema versions
+//!
+//! Maps descriptor paths to field indices, both exactly and by matching a concrete path
+//! against the wildcards of descriptor paths. A concrete path fills in what the
+//! descriptor leaves open, either with selectors or with plain dot separated segments:
+//! - `[...]` fills a `[]`: `3` (index), `1,4` (indices), `2..5` (end exclusive range),
+//!   `0..2,6..8` (ranges), `2x3` (dimensions); mixed lists keep one param per item
+//! - `{...}` fills a `{}`: one or more comma separated keys
+//! - a plain segment fills a `[]` when numeric, or a `{}` as a single key
+//!
+//! ```text
+//! descriptor  users.{}.profile.name    concrete  users.alice.profile.name   params  {alice}
+//! descriptor  users[].tags{}           concrete  users[3].tags{blue,red}    params  [3] {blue,red}
+//!                                      concrete  users.3.tags.blue          params  [3] {blue}
+//! ```
+//! When several descriptors match a plain segment, a literal is preferred over an index,
+//! and an index over a key. Keys cannot contain `.`, `,` or brackets.
+
+use std::collections::HashMap;
+
+use crate::types::field::{ArrayParam, FieldAddress, FieldParams, ParamGroupValues};
+use crate::types::FieldDescriptor;
+
+/// Token of a descriptor path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathToken<'a> {
+    /// Segment that must match exactly
+    Literal(&'a str),
+    /// `{}`: any segment, captured as a map key
+    Key,
+    /// `[]`: a numeric segment, captured as an array index
+    Index,
+}
+
+/// Split a descriptor path into tokens
+pub fn path_tokens(path: &str) -> Vec<PathToken<'_>> {
+    let mut tokens = Vec::new();
+    for segment in path.split('.') {
+        let mut literal_start = 0;
+        let mut chars = segment.char_indices().peekable();
+        while let Some((i, ch)) = chars.next() {
+            let token = match (ch, chars.peek()) {
+                ('[', Some(&(_, ']'))) => PathToken::Index,
+                ('{', Some(&(_, '}'))) => PathToken::Key,
+                _ => continue,
+            };
+            chars.next();
+            if literal_start < i {
+                tokens.push(PathToken::Literal(&segment[literal_start..i]));
+            }
+            tokens.push(token);
+            literal_start = i + 2;
+        }
+        if literal_start < segment.len() {
+            tokens.push(PathToken::Literal(&segment[literal_start..]));
+        }
+    }
+    tokens
+}
+
+/// Token of a concrete path
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConcreteToken<'a> {
+    /// Plain segment: a literal, an array index or a map key
+    Segment(&'a str),
+    /// `[...]` selector
+    Array(Vec<ArrayParam>),
+    /// `{...}` selector
+    Keys(Vec<String>),
+}
+
+/// Split a concrete path into tokens, each with the text it was parsed from
+pub fn concrete_tokens(path: &str) -> Result<Vec<(ConcreteToken<'_>, &str)>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = path;
+    // Selectors may follow anything; a dot must follow a token and a segment must not
+    let mut after_token = false;
+    while let Some(&first) = rest.as_bytes().first() {
+        let (token, len) = match first {
+            b'.' if after_token => {
+                rest = &rest[1..];
+                if rest.is_empty() {
+                    return Err(format!("Path {} ends with '.'", path));
+                }
+                after_token = false;
+                continue;
+            }
+            b'.' => return Err(format!("Empty segment in path {}", path)),
+            b'[' | b'{' => {
+                let close = if first == b'[' { ']' } else { '}' };
+                let end = rest.find(close).ok_or_else(|| format!("Unclosed '{}' in path {}", first as char, path))?;
+                let inner = &rest[1..end];
+                let token = if first == b'[' {
+                    ConcreteToken::Array(parse_array_selector(inner)?)
+                } else {
+                    ConcreteToken::Keys(parse_keys(inner)?)
+                };
+                (token, end + 1)
+            }
+            b']' | b'}' => return Err(format!("Unexpected '{}' in path {}", first as char, path)),
+            _ if after_token => return Err(format!("Missing '.' before {} in path {}", rest, path)),
+            _ => {
+                let len = rest.find(['.', '[', '{', ']', '}']).unwrap_or(rest.len());
+                (ConcreteToken::Segment(&rest[..len]), len)
+            }
+        };
+        tokens.push((token, &rest[..len]));
+        after_token = true;
+        rest = &rest[len..];
+    }
+    if tokens.is_empty() {
+        return Err("Empty path".to_string());
+    }
+    Ok(tokens)
+}
+
+/// Parse the inside of a `[...]` selector
+fn parse_array_selector(inner: &str) -> Result<Vec<ArrayParam>, String> {
+    let number = |s: &str| s.trim().parse::<u32>().map_err(|_| format!("Invalid array position '{}'", s.trim()));
+    let mut params = Vec::new();
+    for item in inner.split(',') {
+        let param = if let Some((start, end)) = item.split_once("..") {
+            let (start, end) = (number(start)?, number(end)?);
+            if start > end {
+                return Err(format!("Range {}..{} ends before it starts", start, end));
+            }
+            ArrayParam::Range(start, end)
+        } else if item.contains('x') {
+            ArrayParam::Dimensions(item.split('x').map(number).collect::<Result<_, _>>()?)
+        } else {
+            ArrayParam::Index(number(item)?)
+        };
+        params.push(param);
+    }
+    let dimensions = params.iter().any(|p| matches!(p, ArrayParam::Dimensions(_)));
+    if dimensions && params.len() > 1 {
+        return Err("Dimensions cannot be combined with other array params".to_string());
+    }
+    if params.len() > 1 && params.iter().all(|p| matches!(p, ArrayParam::Index(_))) {
+        return Ok(vec![ArrayParam::Indices(params.iter().map(|p| if let ArrayParam::Index(i) = p { *i } else { 0 }).collect())]);
+    }
+    if params.len() > 1 && params.iter().all(|p| matches!(p, ArrayParam::Range(..))) {
+        return Ok(vec![ArrayParam::Ranges(params.iter().map(|p| if let ArrayParam::Range(s, e) = p { (*s, *e) } else { (0, 0) }).collect())]);
+    }
+    Ok(params)
+}
+
+/// Parse the inside of a `{...}` selector
+fn parse_keys(inner: &str) -> Result<Vec<String>, String> {
+    inner
+        .split(',')
+        .map(|key| match key.trim() {
+            "" => Err("Empty map key".to_string()),
+            key => Ok(key.to_string()),
+        })
+        .collect()
+}
+
+/// Trie node over path tokens
+#[derive(Clone, Debug, Default)]
+struct PathNode {
+    /// Children by literal segment
+    literals: HashMap<String, PathNode>,
+    /// Child for `[]`
+    index: Option<Box<PathNode>>,
+    /// Child for `{}`
+    key: Option<Box<PathNode>>,
+    /// Field whose path ends here
+    field: Option<u32>,
+}
+
+/// Immutable path lookup of one schema version
+#[derive(Clone, Debug, Default)]
+pub struct PathIndex {
+    /// Field index by descriptor path
+    exact: HashMap<String, u32>,
+    /// Descriptor paths by token, for concrete path matching
+    root: PathNode,
+}
+
+impl PathIndex {
+    /// Create an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a descriptor path
+    pub fn insert(&mut self, path: &str, index: u32) {
+        self.exact.insert(path.to_string(), index);
+        let mut node = &mut self.root;
+        for token in path_tokens(path) {
+            node = match token {
+                PathToken::Literal(segment) => node.literals.entry(segment.to_string()).or_default(),
+                PathToken::Index => node.index.get_or_insert_with(Default::default),
+                PathToken::Key => node.key.get_or_insert_with(Default::default),
+            };
+        }
+        node.field = Some(index);
+    }
+
+    /// Field index of a descriptor path
+    pub fn get(&self, path: &str) -> Option<u32> {
+        self.exact.get(path).copied()
+    }
+
+    /// Number of paths
+    pub fn len(&self) -> usize {
+        self.exact.len()
+    }
+
+    /// Whether no path was added
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+    }
+
+    /// Resolve a concrete path to the field index and the parameters it supplies for
+    /// the descriptor's parameter groups, in order
+    pub fn resolve(&self, concrete: &str) -> Result<(u32, FieldParams), String> {
+        let tokens = concrete_tokens(concrete)?;
+        let mut groups = Vec::new();
+        let mut deepest = 0;
+        if let Some(index) = walk(&self.root, &tokens, 0, &mut groups, &mut deepest) {
+            return Ok((index, FieldParams { groups }));
+        }
+        let Some((_, text)) = tokens.get(deepest) else {
+            return Err(format!("No field matches {}: it stops short of a field path", concrete));
+        };
+        let offset = text.as_ptr() as usize - concrete.as_ptr() as usize;
+        match concrete[..offset].trim_end_matches('.') {
+            "" => Err(format!("No field matches {}: no field path starts with {}", concrete, text)),
+            prefix => Err(format!("No field matches {}: no field path continues {} with {}", concrete, prefix, text)),
+        }
+    }
+
+    /// Match a concrete path (see `resolve`)
+    pub fn match_path(&self, concrete: &str) -> Option<(u32, FieldParams)> {
+        self.resolve(concrete).ok()
+    }
+}
+
+/// Depth-first match of `tokens[depth..]` below `node`; plain segments try literals,
+/// then indices, then keys. `deepest` records how many tokens some branch consumed.
+fn walk(
+    node: &PathNode,
+    tokens: &[(ConcreteToken<'_>, &str)],
+    depth: usize,
+    groups: &mut Vec<ParamGroupValues>,
+    deepest: &mut usize,
+) -> Option<u32> {
+    *deepest = (*deepest).max(depth);
+    let Some((token, _)) = tokens.get(depth) else {
+        return node.field;
+    };
+    let mut descend = |child: &PathNode, group: Option<ParamGroupValues>, groups: &mut Vec<ParamGroupValues>| {
+        let captured = group.is_some();
+        groups.extend(group);
+        let found = walk(child, tokens, depth + 1, groups, deepest);
+        if found.is_none() && captured {
+            groups.pop();
+        }
+        found
+    };
+    match token {
+        ConcreteToken::Segment(segment) => {
+            if let Some(found) = node.literals.get(*segment).and_then(|child| descend(child, None, groups)) {
+                return Some(found);
+            }
+            if let (Some(child), Ok(position)) = (&node.index, segment.parse::<u32>()) {
+                if let Some(found) = descend(child, Some(ParamGroupValues::ArraySet(vec![ArrayParam::Index(position)])), groups) {
+                    return Some(found);
+                }
+            }
+            let child = node.key.as_ref()?;
+            descend(child, Some(ParamGroupValues::KeySet(vec![segment.to_string()])), groups)
+        }
+        ConcreteToken::Array(params) => descend(node.index.as_ref()?, Some(ParamGroupValues::ArraySet(params.clone())), groups),
+        ConcreteToken::Keys(keys) => descend(node.key.as_ref()?, Some(ParamGroupValues::KeySet(keys.clone())), groups),
+    }
+}
+
+/// Field a concrete path resolves to
+#[derive(Clone, Debug)]
+pub struct ResolvedPath {
+    /// Schema version the path was resolved against
+    pub schema_version: u16,
+    /// Field index at that version
+    pub field_index: u32,
+    /// Field descriptor
+    pub descriptor: FieldDescriptor,
+    /// Parameters supplied by the path, one group per descriptor group
+    pub params: FieldParams,
+}
+
+impl ResolvedPath {
+    /// Encode as the field address of a delta
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        FieldAddress::encode(self.schema_version, self.field_index, &self.descriptor, &self.params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        use PathToken::*;
+        assert_eq!(path_tokens("users[].profile.{}.names[]"), vec![
+            Literal("users"), Index, Literal("profile"), Key, Literal("names"), Index,
+        ]);
+        assert_eq!(path_tokens("grid[][]"), vec![Literal("grid"), Index, Index]);
+    }
+
+    #[test]
+    fn test_concrete_paths_match_wildcards() {
+        let mut index = PathIndex::new();
+        index.insert("users.{}.profile.name", 0);
+        index.insert("users.admin.profile.name", 1);
+        index.insert("items[].tags{}", 2);
+
+        let (field, params) = index.match_path("users.alice.profile.name").unwrap();
+        assert_eq!(field, 0);
+        assert_eq!(params.groups, vec![ParamGroupValues::KeySet(vec!["alice".to_string()])]);
+        assert_eq!(index.match_path("users.admin.profile.name").unwrap(), (1, FieldParams::new()));
+
+        let (field, params) = index.match_path("items.3.tags.blue").unwrap();
+        assert_eq!(field, 2);
+        assert_eq!(params.groups, vec![
+            ParamGroupValues::ArraySet(vec![ArrayParam::Index(3)]),
+            ParamGroupValues::KeySet(vec!["blue".to_string()]),
+        ]);
+
+        assert!(index.match_path("items.x.tags.blue").is_none());
+        assert!(index.match_path("users.alice.profile").is_none());
+        assert!(index.match_path("users..profile.name").is_none());
+        assert_eq!(index.get("items[].tags{}"), Some(2));
+    }
+
+    #[test]
+    fn test_selectors() {
+        let mut index = PathIndex::new();
+        index.insert("users[].tags{}", 0);
+        index.insert("grid[][]", 1);
+
+        let (field, params) = index.resolve("users[3].tags{blue, red}").unwrap();
+        assert_eq!(field, 0);
+        assert_eq!(params.groups, vec![
+            ParamGroupValues::ArraySet(vec![ArrayParam::Index(3)]),
+            ParamGroupValues::KeySet(vec!["blue".to_string(), "red".to_string()]),
+        ]);
+        let (_, params) = index.resolve("users[0..2,5..7].tags.blue").unwrap();
+        assert_eq!(params.groups[0], ParamGroupValues::ArraySet(vec![ArrayParam::Ranges(vec![(0, 2), (5, 7)])]));
+        let (_, params) = index.resolve("users[1,4].tags{x}").unwrap();
+        assert_eq!(params.groups[0], ParamGroupValues::ArraySet(vec![ArrayParam::Indices(vec![1, 4])]));
+        let (field, params) = index.resolve("grid[2x3][1,3..4]").unwrap();
+        assert_eq!(field, 1);
+        assert_eq!(params.groups, vec![
+            ParamGroupValues::ArraySet(vec![ArrayParam::Dimensions(vec![2, 3])]),
+            ParamGroupValues::ArraySet(vec![ArrayParam::Index(1), ArrayParam::Range(3, 4)]),
+        ]);
+    }
+
+    #[test]
+    fn test_mismatch_errors() {
+        let mut index = PathIndex::new();
+        index.insert("users[].tags{}", 0);
+
+        let error = index.resolve("users[3].tagz{blue}").unwrap_err();
+        assert!(error.contains("continues users[3] with tagz"), "{}", error);
+        assert!(index.resolve("people[3]").unwrap_err().contains("starts with people"));
+        assert!(index.resolve("users{a}.tags{b}").is_err());
+        assert!(index.resolve("users[3].tags").unwrap_err().contains("stops short"));
+        for bad in ["users[3", "users[a]", "users[5..2]", "users[3]tags", "users.", "users{}", "users[2x3,1]", ""] {
+            assert!(concrete_tokens(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -1,10 +1,14 @@
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
 
 use arc_swap::ArcSwap;
 use dashmap::DashMap;
 
-use crate::types::{ FieldDescriptor };
+use crate::types::field::{validate_params_section, FieldAddress, FieldParams};
+use crate::types::path_index::{PathIndex, ResolvedPath};
+use crate::types::schema_diff::SchemaDiff;
+use crate::types::varint::decode_varint;
+use crate::types::FieldDescriptor;
 
 
 
@@ -17,14 +21,48 @@ pub struct ImmutableSchema {
     /// Immutable field descriptors - never modified after creation
     /// Using Arc<[T]> instead of Vec for true immutability and zero-copy sharing
     pub fields: Arc<[Option<FieldDescriptor>]>,
+
+    /// Canonical index of each field index in this version (see `SchemaRegistry::canonical_index`)
+    /// Indices past the end map to themselves, so an empty table is the identity
+    pub canonical: Arc<[u32]>,
+
+    /// Path lookup, built once when the snapshot is created
+    pub paths: PathIndex,
 }
 
 impl ImmutableSchema {
     /// Create a new immutable schema from a vector of fields
     pub fn new(version: u32, fields: Vec<Option<FieldDescriptor>>) -> Self {
+        Self::with_canonical(version, fields, Arc::from([]))
+    }
+
+    /// Create an immutable schema whose field indices map to canonical indices through `canonical`
+    pub fn with_canonical(version: u32, fields: Vec<Option<FieldDescriptor>>, canonical: Arc<[u32]>) -> Self {
+        let mut paths = PathIndex::new();
+        for (index, field) in fields.iter().enumerate() {
+            if let Some(field) = field {
+                paths.insert(&field.path, index as u32);
+            }
+        }
         Self {
             version,
             fields: fields.into_boxed_slice().into(),
+            canonical,
+            paths,
+        }
+    }
+
+    /// Canonical index of a field index in this version
+    pub fn canonical_index(&self, index: u32) -> u32 {
+        self.canonical.get(index as usize).copied().unwrap_or(index)
+    }
+
+    /// Field index in this version of a canonical index, `None` if no index maps to it
+    pub fn index_of_canonical(&self, canonical: u32) -> Option<u32> {
+        match self.canonical.iter().position(|&c| c == canonical) {
+            Some(index) => Some(index as u32),
+            // Indices past the table are their own canonical index
+            None => (canonical as usize >= self.canonical.len()).then_some(canonical),
         }
     }
     
@@ -39,6 +77,16 @@ impl ImmutableSchema {
     pub fn len(&self) -> usize {
         self.fields.len()
     }
+
+    /// Lookup field index by path
+    pub fn get_field_index(&self, path: &str) -> Option<u32> {
+        self.paths.get(path)
+    }
+
+    /// Match a concrete path (see `PathIndex::match_path`)
+    pub fn match_path(&self, concrete: &str) -> Option<(u32, FieldParams)> {
+        self.paths.match_path(concrete)
+    }
 }
 
 /// Cached schema version with pending changes and lookup acceleration
@@ -72,6 +120,22 @@ impl CachedSchemaVersion {
         }
     }
     
+    /// Create a cached version over a base that already holds fields, indexing their
+    /// paths and continuing field indices after every canonical index in use
+    pub fn indexed(base: Arc<ImmutableSchema>) -> Self {
+        let cached = Self::new(base);
+        for (index, descriptor) in cached.base.fields.iter().enumerate() {
+            if let Some(descriptor) = descriptor {
+                cached.path_lookup.insert(descriptor.path.clone(), index as u32);
+            }
+        }
+        // New fields keep canonical == index, so they must not reuse a canonical index
+        let len = cached.base.len() as u32;
+        let after_canonical = (0..len).map(|i| cached.base.canonical_index(i) + 1).max().unwrap_or(0);
+        cached.next_index.store(after_canonical.max(len), Ordering::Release);
+        cached
+    }
+
     /// Create from existing with new base schema
     pub fn with_new_base(base: Arc<ImmutableSchema>, existing: &CachedSchemaVersion) -> Self {
         Self {
@@ -97,11 +161,57 @@ impl CachedSchemaVersion {
     pub fn get_field_index(&self, path: &str) -> Option<u32> {
         self.path_lookup.get(path).map(|v| *v.value())
     }
+
+    /// Resolve a concrete path against base fields, then pending ones
+    pub fn resolve_path(&self, concrete: &str) -> Result<(u32, FieldParams), String> {
+        let error = match self.base.paths.resolve(concrete) {
+            Ok(found) => return Ok(found),
+            Err(error) => error,
+        };
+        if self.pending.is_empty() {
+            return Err(error);
+        }
+        let mut pending = PathIndex::new();
+        for entry in self.pending.iter() {
+            pending.insert(&entry.value().path, *entry.key());
+        }
+        pending.resolve(concrete).map_err(|_| error)
+    }
+
+    /// Match a concrete path against base fields, then pending ones
+    pub fn match_path(&self, concrete: &str) -> Option<(u32, FieldParams)> {
+        self.resolve_path(concrete).ok()
+    }
     
     /// Get the underlying schema version
     pub fn version(&self) -> u32 {
         self.base.version
     }
+
+    /// Immutable schema holding every field of this version, base and pending
+    pub fn snapshot(&self) -> ImmutableSchema {
+        let mut fields: Vec<Option<FieldDescriptor>> = self.base.fields.to_vec();
+        let mut pending: Vec<(u32, FieldDescriptor)> =
+            self.pending.iter().map(|e| (*e.key(), e.value().clone())).collect();
+        pending.sort_by_key(|&(index, _)| index);
+        for (index, descriptor) in pending {
+            if fields.len() <= index as usize {
+                fields.resize(index as usize + 1, None);
+            }
+            fields[index as usize] = Some(descriptor);
+        }
+        let canonical: Vec<u32> = (0..fields.len() as u32).map(|i| self.base.canonical_index(i)).collect();
+        ImmutableSchema::with_canonical(self.version(), fields, canonical.into())
+    }
+}
+
+/// Receives every change to a registry before it becomes visible, e.g. to persist it
+pub trait SchemaJournal: Send + Sync {
+    /// A field was added to the current version
+    fn field_added(&self, version: u32, index: u32, descriptor: &FieldDescriptor) -> Result<(), String>;
+
+    /// A new current version was created
+    fn version_created(&self, schema: &ImmutableSchema) -> Result<(), String>;
 }
 
 /// Thread-safe schema registry with versioning support
@@ -119,9 +229,29 @@ pub struct SchemaRegistry {
     
     /// Flag to prevent concurrent consolidations
     consolidating: Arc<AtomicBool>,  // Wrap in Arc so it can be shared
+
+    /// Held shared while a field is added and exclusively while the current version is
+    /// replaced, so no field lands in a version after it has been copied
+    replacing: RwLock<()>,
     
     /// Threshold for triggering consolidation
     consolidation_threshold: usize,
+
+    /// Recorded accesses per canonical field index, driving `create_optimized_version`
+    access_counts: DashMap<u32, AtomicU64>,
+
+    /// Journal recording field and version changes, once attached
+    journal: OnceLock<Arc<dyn SchemaJournal>>,
+}
+
+impl std::fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let current = self.current.load();
+        f.debug_struct("SchemaRegistry")
+            .field("version", &current.version())
+            .field("next_index", &current.next_index.load(Ordering::Acquire))
+            .finish()
+    }
 }
 
 impl SchemaRegistry {
@@ -138,21 +268,74 @@ impl SchemaRegistry {
             history: Arc::new(history),
             next_version: AtomicU32::new(1),
             consolidating: Arc::new(AtomicBool::new(false)),
+            replacing: RwLock::new(()),
             consolidation_threshold: 100, // Consolidate after 100 pending fields
+            access_counts: DashMap::new(),
+            journal: OnceLock::new(),
         }
     }
     
+    /// Create a registry from schema versions, oldest first; the last becomes current
+    pub fn from_versions(versions: Vec<ImmutableSchema>) -> Result<Self, String> {
+        if versions.windows(2).any(|pair| pair[0].version >= pair[1].version) {
+            return Err("Schema versions must be strictly increasing".to_string());
+        }
+        let mut versions: Vec<Arc<ImmutableSchema>> = versions.into_iter().map(Arc::new).collect();
+        let current = versions.pop().ok_or("A schema needs at least one version")?;
+        let registry = Self::new();
+        registry.history.clear();
+        for version in versions {
+            registry.history.insert(version.version, version);
+        }
+        registry.next_version.store(current.version + 1, Ordering::Release);
+        registry.current.store(Arc::new(CachedSchemaVersion::indexed(current)));
+        Ok(registry)
+    }
+
+    /// Every version, oldest first, the current one including its pending fields
+    pub fn versions(&self) -> Vec<Arc<ImmutableSchema>> {
+        let current = self.current.load();
+        let mut versions: Vec<Arc<ImmutableSchema>> = self
+            .history
+            .iter()
+            .filter(|entry| *entry.key() != current.version())
+            .map(|entry| entry.value().clone())
+            .collect();
+        versions.push(Arc::new(current.snapshot()));
+        versions.sort_by_key(|schema| schema.version);
+        versions
+    }
+
     /// Get current cached schema version for reading
     pub fn current_version(&self) -> Arc<CachedSchemaVersion> {
         self.current.load().clone()
     }
     
-    /// Add a new field to the schema - always non-blocking
+    /// Attach the journal that records every later change
+    pub fn attach_journal(&self, journal: Arc<dyn SchemaJournal>) -> Result<(), String> {
+        self.journal.set(journal).map_err(|_| "Schema registry already has a journal".to_string())
+    }
+
+    /// Add a new field to the schema - only waits while the current version is replaced
+    ///
+    /// Panics if the attached journal cannot record the field; use `try_add_field` to
+    /// handle that.
     pub fn add_field(self: &Arc<Self>, descriptor: FieldDescriptor) -> u32 {
+        self.try_add_field(descriptor).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Add a new field, failing if the attached journal cannot record it
+    pub fn try_add_field(self: &Arc<Self>, descriptor: FieldDescriptor) -> Result<u32, String> {
+        let _adding = self.replacing.read().unwrap();
         let current = self.current.load();
         
         // Atomically claim next index
         let index = current.next_index.fetch_add(1, Ordering::AcqRel);
+
+        // Journal before the field becomes visible; a failed write only leaves a gap
+        if let Some(journal) = self.journal.get() {
+            journal.field_added(current.version(), index, &descriptor)?;
+        }
         
         // Always add to pending map (no unsafe code!)
         current.pending.insert(index, descriptor.clone());
@@ -163,7 +346,39 @@ impl SchemaRegistry {
             self.try_consolidate();
         }
         
-        index
+        Ok(index)
+    }
+
+    /// Replay a field recorded by a journal into the current version
+    ///
+    /// A field recorded against a version that is no longer current was added while
+    /// that version was being replaced and is dropped, as it was before the restart.
+    pub fn restore_field(&self, version: u32, index: u32, descriptor: FieldDescriptor) -> Result<(), String> {
+        let _adding = self.replacing.read().unwrap();
+        let current = self.current.load();
+        if version != current.version() {
+            return Ok(());
+        }
+        if current.get_field(index).is_some() {
+            return Err(format!("Field index {} of version {} is already taken", index, version));
+        }
+        current.path_lookup.insert(descriptor.path.clone(), index);
+        current.pending.insert(index, descriptor);
+        current.next_index.fetch_max(index + 1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Replay a version recorded by a journal, making it current
+    pub fn restore_version(&self, schema: ImmutableSchema) -> Result<(), String> {
+        let _replacing = self.replacing.write().unwrap();
+        let current = self.current.load_full();
+        if schema.version <= current.version() {
+            return Err(format!("Schema version {} does not follow {}", schema.version, current.version()));
+        }
+        self.history.insert(current.version(), Arc::new(current.snapshot()));
+        self.next_version.store(schema.version + 1, Ordering::Release);
+        self.current.store(Arc::new(CachedSchemaVersion::indexed(Arc::new(schema))));
+        Ok(())
     }
     
     /// Get field by index from current version
@@ -191,25 +406,46 @@ impl SchemaRegistry {
     }
     
     /// Lookup field index by path in specific version
-    /// Note: This requires the path_lookup to be rebuilt for historical versions
     pub fn get_field_index_at_version(&self, path: &str, version: u32) -> Option<u32> {
         let current = self.current.load();
         if version == current.version() {
             return current.get_field_index(path);
         }
-        
-        // For historical versions, need to search the immutable schema
-        self.history.get(&version).and_then(|schema| {
-            // Linear search through fields (could be optimized with cached lookups)
-            for (idx, field_opt) in schema.fields.iter().enumerate() {
-                if let Some(field) = field_opt {
-                    if field.path == path {
-                        return Some(idx as u32);
-                    }
-                }
-            }
-            None
-        })
+        self.history.get(&version).and_then(|schema| schema.get_field_index(path))
+    }
+
+    /// Resolve a concrete path such as `users[3].tags{blue}` or `users.alice.profile.name`
+    /// in the current version (see `PathIndex` for the syntax)
+    pub fn resolve_path(&self, concrete: &str) -> Result<ResolvedPath, String> {
+        self.resolve_path_at_version(concrete, self.current.load().version())
+    }
+
+    /// Resolve a concrete path in a specific version
+    pub fn resolve_path_at_version(&self, concrete: &str, version: u32) -> Result<ResolvedPath, String> {
+        let schema_version = u16::try_from(version).map_err(|_| format!("Schema version {} does not fit a field address", version))?;
+        let current = self.current.load();
+        let (field_index, params) = if version == current.version() {
+            current.resolve_path(concrete)?
+        } else {
+            let schema = self.history.get(&version).ok_or_else(|| format!("Unknown schema version {}", version))?;
+            schema.paths.resolve(concrete)?
+        };
+        let descriptor = self
+            .get_field_at_version(field_index, version)
+            .ok_or_else(|| format!("Unknown field {} at schema version {}", field_index, version))?;
+        Ok(ResolvedPath { schema_version, field_index, descriptor, params })
+    }
+
+    /// Resolve a concrete path in the current version to its field index, descriptor
+    /// and the parameters it supplies
+    pub fn match_path(&self, concrete: &str) -> Option<(u32, FieldDescriptor, FieldParams)> {
+        self.match_path_at_version(concrete, self.current.load().version())
+    }
+
+    /// Resolve a concrete path in a specific version, see `match_path`
+    pub fn match_path_at_version(&self, concrete: &str, version: u32) -> Option<(u32, FieldDescriptor, FieldParams)> {
+        let resolved = self.resolve_path_at_version(concrete, version).ok()?;
+        Some((resolved.field_index, resolved.descriptor, resolved.params))
     }
     
     /// Try to consolidate pending fields into immutable schema
@@ -224,18 +460,19 @@ impl SchemaRegistry {
             return;
         }
         
-        let current = self.current.load();
-        let current_arc = Arc::clone(&*current);  
         let registry = Arc::clone(self);  // Clone the Arc<SchemaRegistry>
         // Consolidate in background thread
         std::thread::spawn(move || {
-            registry.consolidate_background(current_arc);
+            registry.consolidate_background();
             registry.consolidating.store(false, Ordering::Release);
         });
     }
     
     /// Background consolidation - creates new immutable schema with merged fields
-    fn consolidate_background(&self, current: Arc<CachedSchemaVersion>) {
+    fn consolidate_background(&self) {
+        // Fields added while copying would be dropped by the swap
+        let _replacing = self.replacing.write().unwrap();
+        let current = self.current.load_full();
         let pending_count = current.pending.len();
         if pending_count == 0 {
             return; // Nothing to consolidate
@@ -269,9 +506,10 @@ impl SchemaRegistry {
         }
         
         // Create new immutable schema (same version - just storage optimization)
-        let new_immutable = Arc::new(ImmutableSchema::new(
+        let new_immutable = Arc::new(ImmutableSchema::with_canonical(
             current.base.version,
-            new_fields
+            new_fields,
+            current.base.canonical.clone(),
         ));
         
         // Create new cached version with empty pending
@@ -284,27 +522,124 @@ impl SchemaRegistry {
         self.current.store(Arc::new(new_cached));
     }
     
+    /// Record an access to a field addressed at `version`, for `create_optimized_version`
+    pub fn record_access(&self, index: u32, version: u32) {
+        let Some(canonical) = self.canonical_index(index, version) else {
+            return;
+        };
+        if let Some(count) = self.access_counts.get(&canonical) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.access_counts.entry(canonical).or_default().fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Recorded accesses of a field, by canonical index
+    pub fn access_count(&self, canonical: u32) -> u64 {
+        self.access_counts.get(&canonical).map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    /// Version-independent identity of a field addressed at `version`
+    ///
+    /// A field's canonical index is the index it was added at. Reordered versions give it
+    /// a different index on the wire; state keyed by canonical index is unaffected.
+    /// Returns `None` for an unknown version (the field itself is not checked).
+    pub fn canonical_index(&self, index: u32, version: u32) -> Option<u32> {
+        let current = self.current.load();
+        if version == current.version() {
+            return Some(current.base.canonical_index(index));
+        }
+        self.history.get(&version).map(|schema| schema.canonical_index(index))
+    }
+
+    /// Translate a field index encoded against `from` into its index at version `to`
+    pub fn translate_index(&self, index: u32, from: u32, to: u32) -> Option<u32> {
+        let canonical = self.canonical_index(index, from)?;
+        let target = self.get_historical_schema(to)?;
+        let translated = target.index_of_canonical(canonical)?;
+        self.get_field_at_version(translated, to)?;
+        Some(translated)
+    }
+
     /// Create a new schema version with field reordering
-    /// This is the only operation that actually changes the version number
+    ///
+    /// Fields are ordered by recorded accesses, most accessed first (ties keep their
+    /// current order), so hot fields get the lowest indices and the fewest varint bytes
+    /// on the wire. The outgoing version, pending fields included, is kept in history so
+    /// deltas encoded against it still decode; `translate_index` maps between versions.
+    /// Fields added meanwhile wait for the new version and are added to it.
+    ///
+    /// Panics if the attached journal cannot record the version; use
+    /// `try_create_optimized_version` to handle that.
     pub fn create_optimized_version(&self) -> u32 {
+        self.try_create_optimized_version().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Create a new reordered schema version, failing if the attached journal cannot
+    /// record it (the current version is then kept)
+    pub fn try_create_optimized_version(&self) -> Result<u32, String> {
+        // Keep background consolidation from swapping the current version underneath
+        while self.consolidating.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            std::thread::yield_now();
+        }
+        let _replacing = self.replacing.write().unwrap();
+        let current = self.current.load_full();
+
+        // Every field of the outgoing version, base and pending
+        let outgoing = Arc::new(current.snapshot());
+        let fields = &outgoing.fields;
+        let canonical = &outgoing.canonical;
+
+        // Hottest first; the sort is stable so equally hot fields keep their order
+        let mut order: Vec<usize> = (0..fields.len()).filter(|&i| fields[i].is_some()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.access_count(canonical[i])));
+
+        let version = self.next_version.fetch_add(1, Ordering::AcqRel);
+        let reordered: Vec<Option<FieldDescriptor>> = order.iter().map(|&i| fields[i].clone()).collect();
+        let reordered_canonical: Vec<u32> = order.iter().map(|&i| canonical[i]).collect();
+        let optimized = CachedSchemaVersion::indexed(Arc::new(ImmutableSchema::with_canonical(
+            version,
+            reordered,
+            reordered_canonical.into(),
+        )));
+        if let Some(journal) = self.journal.get() {
+            if let Err(e) = journal.version_created(&optimized.base) {
+                self.consolidating.store(false, Ordering::Release);
+                return Err(e);
+            }
+        }
+
+        self.history.insert(outgoing.version, outgoing);
+        self.current.store(Arc::new(optimized));
+        self.consolidating.store(false, Ordering::Release);
+        Ok(version)
+    }
+
+    /// Every field of a version, including fields still pending in the current version
+    pub fn schema_at(&self, version: u32) -> Option<Arc<ImmutableSchema>> {
         let current = self.current.load();
-        
-        // TODO: Implement field reordering based on access patterns
-        // This would:
-        // 1. Analyze field access patterns
-        // 2. Reorder fields so hot fields get lower indices (fewer bytes on wire)
-        // 3. Create truly new version with new field ordering
-        // 4. Store old version in history for backward compatibility
-        
-        // let new_version_num = self.next_version.fetch_add(1, Ordering::AcqRel);
-        
-        // Store current immutable schema in history
-        self.history.insert(current.base.version, current.base.clone());
-        
-        // For now, just return current version
-        current.base.version
+        if version == current.version() {
+            return Some(Arc::new(current.snapshot()));
+        }
+        self.history.get(&version).map(|v| v.clone())
     }
-    
+
+    /// Diff two versions of this registry
+    pub fn diff_versions(&self, from: u32, to: u32) -> Option<SchemaDiff> {
+        Some(SchemaDiff::between(&*self.schema_at(from)?, &*self.schema_at(to)?))
+    }
+
+    /// Check that replacing the current version with `proposed` keeps every delta
+    /// encoded against the current version decodable
+    pub fn check_compatible(&self, proposed: &ImmutableSchema) -> Result<SchemaDiff, String> {
+        let current = self.current.load().snapshot();
+        let diff = SchemaDiff::between(&current, proposed);
+        if !diff.compatibility().is_backward() {
+            return Err(format!("Incompatible schema change: {}", diff));
+        }
+        Ok(diff)
+    }
+
     /// Get historical immutable schema by version number
     pub fn get_historical_schema(&self, version: u32) -> Option<Arc<ImmutableSchema>> {
         // Check current first
@@ -331,283 +666,213 @@ impl SchemaRegistry {
 // }
 
 impl SchemaRegistry {
-    // TODO: Update these methods for the new parameter system
-    /*
-    /// Encode a field reference with optional parameters to wire format using TLV
-    pub fn encode(&self, field_index: u32, params: &Params) -> Vec<u8> {
-        let mut output = Vec::with_capacity(16);
-        
-        // Schema version (2 bytes, big-endian)
-        let version = self.current_version();
-        output.push((version.version() >> 8) as u8);
-        output.push((version.version() & 0xFF) as u8);
-        
-        // Field index (1-3 bytes varint)
-        self.encode_varint(field_index, &mut output);
-        
-        // Parameter count (1 byte) - 0 for most fields
-        output.push(params.params.len() as u8);
-        
-        // Encode each parameter as TLV
-        for param in &params.params {
-            // Type byte
-            output.push(param.type_byte());
-            
-            // Length and value based on type
-            match param {
-                Param::Parent(idx) => {
-                    output.push(1);  // Length = 1 varint
-                    self.encode_varint(*idx, &mut output);
-                }
-                Param::ArrayIndex(idx) => {
-                    output.push(1);  // Length = 1 varint
-                    self.encode_varint(*idx, &mut output);
-                }
-                Param::ArrayRange(start, end) => {
-                    output.push(2);  // Length = 2 varints
-                    self.encode_varint(*start, &mut output);
-                    self.encode_varint(*end, &mut output);
-                }
-                Param::ArrayIndices(indices) => {
-                    self.encode_varint(indices.len() as u32, &mut output);  // Length
-                    self.encode_varint(indices.len() as u32, &mut output);  // Count
-                    for idx in indices {
-                        self.encode_varint(*idx, &mut output);
-                    }
-                }
-                Param::ArrayRanges(ranges) => {
-                    self.encode_varint((ranges.len() * 2) as u32, &mut output);  // Length
-                    self.encode_varint(ranges.len() as u32, &mut output);  // Count
-                    for (start, end) in ranges {
-                        self.encode_varint(*start, &mut output);
-                        self.encode_varint(*end, &mut output);
-                    }
-                }
-                Param::MapKey(key) => {
-                    self.encode_varint(key.len() as u32, &mut output);  // Length
-                    output.extend_from_slice(key.as_bytes());
-                }
-            }
-        }
-        
-        output
+    /// Encode a field address against the current version
+    ///
+    /// Layout matches the field address of a delta payload and what `ParamIterator` reads:
+    /// `[schema version varint][field index varint][group count][tag][body length][body]...`
+    pub fn encode(&self, field_index: u32, params: &FieldParams) -> Result<Vec<u8>, String> {
+        self.encode_at_version(self.current_version().version(), field_index, params)
     }
-    */
-    
-    // TODO: Update for new parameter system
-    /*
-    /// Decode wire format to field descriptor and parameters using TLV
-    pub fn decode(&self, bytes: &[u8]) -> Option<(FieldDescriptor, Params)> {
-        if bytes.len() < 4 {
-            return None;  // Minimum: 2 version + 1 index + 1 param count
-        }
-        
-        let mut offset = 0;
-        
-        // Schema version
-        let wire_version = ((bytes[0] as u32) << 8) | (bytes[1] as u32);
-        offset += 2;
-        
-        // Get appropriate schema version (current or historical)
-        let schema = if wire_version == self.current_version().version() {
-            self.current_version()
-        } else {
-            self.get_historical_version(wire_version)?
-        };
-        
-        // Field index
-        let (field_index, consumed) = self.decode_varint(&bytes[offset..])?;
-        offset += consumed;
-        
-        // Get field descriptor
-        let field = schema.get_field(field_index)?.clone();
-        
-        // Parameter count
-        let param_count = bytes[offset];
-        offset += 1;
-        
-        // Decode TLV parameters
-        let mut params = Params { params: Vec::new() };
-        
-        for _ in 0..param_count {
-            if offset >= bytes.len() {
-                return None;  // Incomplete parameter
-            }
-            
-            // Type byte
-            let param_type = bytes[offset];
-            offset += 1;
-            
-            // Length
-            let (length, consumed) = self.decode_varint(&bytes[offset..])?;
-            offset += consumed;
-            
-            // Value based on type
-            let param = match param_type {
-                0x01 => {  // Parent
-                    let (idx, consumed) = self.decode_varint(&bytes[offset..])?;
-                    offset += consumed;
-                    Param::Parent(idx)
-                }
-                0x02 => {  // ArrayIndex
-                    let (idx, consumed) = self.decode_varint(&bytes[offset..])?;
-                    offset += consumed;
-                    Param::ArrayIndex(idx)
-                }
-                0x03 => {  // ArrayRange
-                    let (start, consumed) = self.decode_varint(&bytes[offset..])?;
-                    offset += consumed;
-                    let (end, consumed) = self.decode_varint(&bytes[offset..])?;
-                    offset += consumed;
-                    Param::ArrayRange(start, end)
-                }
-                0x04 => {  // ArrayIndices
-                    let (count, consumed) = self.decode_varint(&bytes[offset..])?;
-                    offset += consumed;
-                    let mut indices = Vec::with_capacity(count as usize);
-                    for _ in 0..count {
-                        let (idx, consumed) = self.decode_varint(&bytes[offset..])?;
-                        offset += consumed;
-                        indices.push(idx);
-                    }
-                    Param::ArrayIndices(indices)
-                }
-                0x05 => {  // ArrayRanges
-                    let (count, consumed) = self.decode_varint(&bytes[offset..])?;
-                    offset += consumed;
-                    let mut ranges = Vec::with_capacity(count as usize);
-                    for _ in 0..count {
-                        let (start, consumed) = self.decode_varint(&bytes[offset..])?;
-                        offset += consumed;
-                        let (end, consumed) = self.decode_varint(&bytes[offset..])?;
-                        offset += consumed;
-                        ranges.push((start, end));
-                    }
-                    Param::ArrayRanges(ranges)
-                }
-                0x06 => {  // MapKey
-                    let key = String::from_utf8(
-                        bytes[offset..offset + length as usize].to_vec()
-                    ).ok()?;
-                    offset += length as usize;
-                    Param::MapKey(key)
-                }
-                _ => return None,  // Unknown parameter type
-            };
-            
-            params.params.push(param);
+
+    /// Encode a field address against a specific schema version
+    pub fn encode_at_version(&self, version: u32, field_index: u32, params: &FieldParams) -> Result<Vec<u8>, String> {
+        let schema_version = u16::try_from(version).map_err(|_| format!("Schema version {} does not fit a field address", version))?;
+        let descriptor = self
+            .get_field_at_version(field_index, version)
+            .ok_or_else(|| format!("Unknown field {} at schema version {}", field_index, version))?;
+        FieldAddress::encode(schema_version, field_index, &descriptor, params)
+    }
+
+    /// Decode a field address written by `encode`, returning its descriptor, typed
+    /// parameters and the number of bytes read
+    pub fn decode(&self, bytes: &[u8]) -> Result<(FieldDescriptor, FieldParams, usize), String> {
+        let (version, mut offset) = decode_varint(bytes).map_err(|e| e.to_string())?;
+        let schema_version = u16::try_from(version).map_err(|_| format!("Schema version {} does not fit a field address", version))?;
+        let (field_index, read) = decode_varint(&bytes[offset..]).map_err(|e| e.to_string())?;
+        offset += read;
+        let descriptor = self
+            .get_field_at_version(field_index, version)
+            .ok_or_else(|| format!("Unknown field {} at schema version {}", field_index, version))?;
+
+        let section = validate_params_section(&bytes[offset..]).map_err(|e| e.to_string())?;
+        let params = &bytes[offset + 1..offset + section];
+        let address = FieldAddress { schema_version, field_index, params_raw: (params.as_ptr(), params.len()) };
+        let params = address.decode_params(&descriptor.param_groups).map_err(|e| e.to_string())?;
+        Ok((descriptor, params, offset + section))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::varint::varint_len;
+    use crate::types::ValueType;
+
+    fn registry(fields: usize) -> Arc<SchemaRegistry> {
+        let registry = Arc::new(SchemaRegistry::new());
+        for i in 0..fields {
+            registry.add_field(FieldDescriptor::new(format!("f{}", i), ValueType::Int).unwrap());
         }
-        
-        Some((field, params))
+        registry
     }
-    */
-    
-    /// Variable-length integer encoding (1-3 bytes)
-    /// Encode a 32-bit value as variable-length integer
-    fn encode_varint(&self, value: u32, output: &mut Vec<u8>) {
-        if value < 128 {
-            // 1 byte: 0xxxxxxx
-            output.push(value as u8);
-        } else if value < 16384 {
-            // 2 bytes: 10xxxxxx xxxxxxxx
-            output.push(0x80 | ((value >> 8) as u8));
-            output.push((value & 0xFF) as u8);
-        } else {
-            // 3 bytes: 11xxxxxx xxxxxxxx xxxxxxxx
-            output.push(0xC0 | ((value >> 16) as u8));
-            output.push(((value >> 8) & 0xFF) as u8);
-            output.push((value & 0xFF) as u8);
+
+    #[test]
+    fn test_optimized_version_moves_hot_fields_to_low_indices() {
+        let registry = registry(200);
+        for index in 190..200 {
+            for _ in 0..(index - 180) {
+                registry.record_access(index, 0);
+            }
         }
+        let version = registry.create_optimized_version();
+        assert_eq!(version, 1);
+        assert_eq!(registry.current_version().version(), 1);
+
+        // Hottest first, and every hot field now fits a one-byte varint
+        assert_eq!(registry.get_field_index("f199"), Some(0));
+        assert_eq!(registry.get_field_index("f190"), Some(9));
+        assert_eq!(registry.get_field_index("f0"), Some(10));
+        let before: usize = (190..200).map(varint_len).sum();
+        let after: usize = (190..200).map(|i| varint_len(registry.translate_index(i, 0, 1).unwrap())).sum();
+        assert!(after < before);
+
+        // The old version still decodes and maps onto the new one
+        assert_eq!(registry.get_field_at_version(199, 0).unwrap().path, "f199");
+        assert_eq!(registry.translate_index(199, 0, 1), Some(0));
+        assert_eq!(registry.translate_index(0, 1, 0), Some(199));
+        assert_eq!(registry.canonical_index(0, 1), Some(199));
+        assert_eq!(registry.translate_index(200, 0, 1), None);
     }
-    
-    /// Variable-length integer decoding
-    /// Decode a variable-length integer from bytes
-    fn decode_varint(&self, bytes: &[u8]) -> Option<(u32, usize)> {
-        if bytes.is_empty() {
-            return None;
+
+    #[test]
+    fn test_fields_added_while_reordering_are_kept() {
+        let registry = registry(0);
+        let adders: Vec<_> = (0..4)
+            .map(|t| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    for i in 0..150 {
+                        registry.add_field(FieldDescriptor::new(format!("t{}.f{}", t, i), ValueType::Int).unwrap());
+                    }
+                })
+            })
+            .collect();
+        while !adders.iter().all(|adder| adder.is_finished()) {
+            registry.create_optimized_version();
         }
-        
-        let first = bytes[0];
-        if first < 128 {
-            // 1 byte
-            Some((first as u32, 1))
-        } else if first < 192 && bytes.len() >= 2 {
-            // 2 bytes
-            let value = ((first & 0x3F) as u32) << 8 | bytes[1] as u32;
-            Some((value, 2))
-        } else if bytes.len() >= 3 {
-            // 3 bytes
-            let value = ((first & 0x3F) as u32) << 16 
-                      | (bytes[1] as u32) << 8 
-                      | bytes[2] as u32;
-            Some((value, 3))
-        } else {
-            None
+        adders.into_iter().for_each(|adder| adder.join().unwrap());
+
+        for (t, i) in (0..4).flat_map(|t| (0..150).map(move |i| (t, i))) {
+            let path = format!("t{}.f{}", t, i);
+            let index = registry.get_field_index(&path).unwrap();
+            assert_eq!(registry.get_field(index).unwrap().path, path);
         }
     }
-    
-    /// Get a historical schema version by number
-    fn get_historical_version(&self, version: u32) -> Option<Arc<CachedSchemaVersion>> {
-        // Check current first
-        let current = self.current.load();
-        if version == current.version() {
-            return Some(current.clone());
+
+    #[test]
+    fn test_canonical_indices_invert_through_the_table() {
+        let field = |path: &str| Some(FieldDescriptor::new(path.to_string(), ValueType::Int).unwrap());
+        let schema = ImmutableSchema::with_canonical(1, vec![field("a"), field("b")], Arc::from([5, 0]));
+        assert_eq!(schema.index_of_canonical(5), Some(0));
+        assert_eq!(schema.index_of_canonical(0), Some(1));
+        assert_eq!(schema.index_of_canonical(1), None);
+        assert_eq!(schema.index_of_canonical(7), Some(7));
+    }
+
+    #[test]
+    fn test_fields_added_after_reordering_keep_unique_identity() {
+        let registry = registry(3);
+        registry.record_access(2, 0);
+        registry.create_optimized_version();
+        let index = registry.add_field(FieldDescriptor::new("late".to_string(), ValueType::Int).unwrap());
+        assert_eq!(index, 3);
+        assert_eq!(registry.canonical_index(index, 1), Some(3));
+        assert_eq!(registry.get_field_at_version(index, 1).unwrap().path, "late");
+
+        // Access counts follow the field across versions
+        registry.record_access(0, 1);
+        assert_eq!(registry.access_count(2), 2);
+    }
+
+    #[test]
+    fn test_field_address_round_trip() {
+        use crate::types::field::ArrayParam;
+
+        let registry = registry(0);
+        let scores = registry.add_field(FieldDescriptor::new("users{}.scores[]".to_string(), ValueType::Int).unwrap());
+        let mut params = FieldParams::new();
+        params.add_keys(vec!["alice".to_string(), "bob".to_string()]);
+        params.add_array(vec![ArrayParam::Ranges(vec![(0, 4), (300, 310)]), ArrayParam::Dimensions(vec![2, 3])]);
+
+        let bytes = registry.encode(scores, &params).unwrap();
+        let (descriptor, decoded, read) = registry.decode(&bytes).unwrap();
+        assert_eq!(descriptor.path, "users{}.scores[]");
+        assert_eq!(decoded, params);
+        assert_eq!(read, bytes.len());
+        for len in 0..bytes.len() {
+            assert!(registry.decode(&bytes[..len]).is_err());
         }
-        
-        // Check history for immutable schema and wrap it in a cached version
-        self.history.get(&version).map(|immutable_schema| {
-            Arc::new(CachedSchemaVersion::new(immutable_schema.clone()))
-        })
+
+        // Group order and count must match the descriptor
+        let mut swapped = FieldParams::new();
+        swapped.add_array(vec![ArrayParam::Index(1)]);
+        swapped.add_keys(vec!["alice".to_string()]);
+        assert!(registry.encode(scores, &swapped).is_err());
+        assert!(registry.encode(scores, &FieldParams::new()).is_err());
     }
-}
 
-// fn example_usage() {
-//     // Create registry
-//     let registry = SchemaRegistry::new();
-    
-//     // Add simple field
-//     let user_id = registry.add_field(
-//         FieldDescriptor::new("user.id".to_string(), ValueType::String)
-//     );
-    
-//     // Add array template field (detected from [] in path)
-//     let items_field = registry.add_field(
-//         FieldDescriptor::new("items[].name".to_string(), ValueType::String)
-//     );
-    
-//     // Example 1: Simple field with no parameters (most common case)
-//     let encoded = registry.encode(user_id, &Params { params: vec![] });
-//     // Wire: [version:2][index:1][count:0] = 4 bytes total
-    
-//     // Example 2: Multiple array indices
-//     let multi_indices = registry.encode(items_field, &Params {
-//         params: vec![
-//             Param::ArrayIndices(vec![5, 12, 23, 45])
-//         ]
-//     });
-//     // Wire: [version:2][index:1-3][count:1][type:0x04][length][count:4][5][12][23][45]
-    
-//     // Example 3: Multiple array ranges (complex selection)
-//     let multi_ranges = registry.encode(items_field, &Params {
-//         params: vec![
-//             Param::ArrayRanges(vec![(0, 5), (10, 15), (20, 25)])
-//         ]
-//     });
-//     // Wire: [version:2][index:1-3][count:1][type:0x05][length][count:3][0][5][10][15][20][25]
-    
-//     // Example 4: Nested with parent and array operations
-//     let complex = registry.encode(items_field, &Params {
-//         params: vec![
-//             Param::Parent(89),  // Applied first
-//             Param::ArrayIndex(0),  // Then array index
-//             Param::MapKey("primary".to_string()),  // Then map key
-//         ]
-//     });
-//     // Parameters are applied in order for proper nesting
-    
-//     // Decode
-//     if let Some((field, params)) = registry.decode(&encoded) {
-//         println!("Decoded field: {} (type: {:?})", field.path, field.value_type);
-//         println!("Parameters: {} params", params.params.len());
-//     }
-// }
\ No newline at end of file
+    #[test]
+    fn test_field_address_at_historical_version() {
+        let registry = registry(3);
+        registry.record_access(2, 0);
+        registry.create_optimized_version();
+
+        let bytes = registry.encode_at_version(0, 2, &FieldParams::new()).unwrap();
+        assert_eq!(registry.decode(&bytes).unwrap().0.path, "f2");
+        let bytes = registry.encode(0, &FieldParams::new()).unwrap();
+        assert_eq!(registry.decode(&bytes).unwrap().0.path, "f2");
+        assert!(registry.encode_at_version(5, 0, &FieldParams::new()).is_err());
+    }
+
+    #[test]
+    fn test_paths_resolve_at_historical_versions() {
+        let registry = registry(2);
+        let name = registry.add_field(FieldDescriptor::new("users.{}.profile.name".to_string(), ValueType::String).unwrap());
+        registry.record_access(name, 0);
+        registry.create_optimized_version();
+
+        assert_eq!(registry.get_field_index_at_version("users.{}.profile.name", 0), Some(2));
+        assert_eq!(registry.get_field_index_at_version("users.{}.profile.name", 1), Some(0));
+        assert_eq!(registry.get_field_index_at_version("f1", 0), Some(1));
+
+        let (index, descriptor, params) = registry.match_path_at_version("users.alice.profile.name", 0).unwrap();
+        assert_eq!((index, descriptor.path.as_str()), (2, "users.{}.profile.name"));
+        assert_eq!(params.groups, vec![crate::types::field::ParamGroupValues::KeySet(vec!["alice".to_string()])]);
+        assert_eq!(registry.match_path("users.bob.profile.name").unwrap().0, 0);
+
+        // Pending fields of the current version match too
+        let late = registry.add_field(FieldDescriptor::new("tags[]".to_string(), ValueType::String).unwrap());
+        assert_eq!(registry.match_path("tags.4").unwrap().0, late);
+        assert!(registry.match_path("users.bob").is_none());
+    }
+
+    #[test]
+    fn test_resolved_paths_encode_field_addresses() {
+        use crate::types::field::ArrayParam;
+
+        let registry = registry(0);
+        let tags = registry.add_field(FieldDescriptor::new("users[].tags{}".to_string(), ValueType::String).unwrap());
+        let resolved = registry.resolve_path("users[3].tags{blue}").unwrap();
+        assert_eq!(resolved.field_index, tags);
+
+        let (descriptor, params, _) = registry.decode(&resolved.encode().unwrap()).unwrap();
+        assert_eq!(descriptor.path, "users[].tags{}");
+        let mut expected = FieldParams::new();
+        expected.add_array(vec![ArrayParam::Index(3)]);
+        expected.add_keys(vec!["blue".to_string()]);
+        assert_eq!(params, expected);
+
+        assert!(registry.resolve_path("users[3].colours{blue}").unwrap_err().contains("colours"));
+        assert!(registry.resolve_path_at_version("users[3].tags{blue}", 4).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Schema version compatibility
+//!
+//! Deltas address fields by index at the schema version they were encoded against, and
+//! a field keeps its canonical index across versions (see `SchemaRegistry::canonical_index`),
+//! so two versions are compared field by field on canonical index:
+//! - a field only in the newer version is an addition: data written against the older
+//!   version still decodes (backward compatible), but older readers cannot decode it;
+//! - a field only in the older version is a removal: the reverse (forward compatible);
+//! - a field whose path, value type or parameter groups changed is breaking, since
+//!   existing payloads for it would be decoded as something else.
+//!
+//! Reordering alone (`create_optimized_version`) changes no field and is fully compatible.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::types::{FieldDescriptor, ImmutableSchema, ParamGroup, ValueType};
+
+/// Change to one field between two schema versions
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldChange {
+    /// Field only in the newer version
+    Added {
+        /// Canonical index
+        canonical: u32,
+        /// Field path
+        path: String,
+    },
+    /// Field only in the older version
+    Removed {
+        /// Canonical index
+        canonical: u32,
+        /// Field path
+        path: String,
+    },
+    /// Path of the field changed
+    PathChanged {
+        /// Canonical index
+        canonical: u32,
+        /// Older path
+        from: String,
+        /// Newer path
+        to: String,
+    },
+    /// Value type of the field changed
+    TypeChanged {
+        /// Canonical index
+        canonical: u32,
+        /// Field path in the newer version
+        path: String,
+        /// Older type
+        from: ValueType,
+        /// Newer type
+        to: ValueType,
+    },
+    /// Parameter groups of the field changed
+    ParamGroupsChanged {
+        /// Canonical index
+        canonical: u32,
+        /// Field path in the newer version
+        path: String,
+        /// Older groups
+        from: Vec<ParamGroup>,
+        /// Newer groups
+        to: Vec<ParamGroup>,
+    },
+}
+
+impl FieldChange {
+    /// Compatibility of this change on its own
+    pub fn compatibility(&self) -> Compatibility {
+        match self {
+            FieldChange::Added { .. } => Compatibility::Backward,
+            FieldChange::Removed { .. } => Compatibility::Forward,
+            _ => Compatibility::Breaking,
+        }
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldChange::Added { canonical, path } => write!(f, "added {} (#{})", path, canonical),
+            FieldChange::Removed { canonical, path } => write!(f, "removed {} (#{})", path, canonical),
+            FieldChange::PathChanged { canonical, from, to } => write!(f, "#{} moved from {} to {}", canonical, from, to),
+            FieldChange::TypeChanged { path, from, to, .. } => write!(f, "{} changed type from {:?} to {:?}", path, from, to),
+            FieldChange::ParamGroupsChanged { path, from, to, .. } => {
+                write!(f, "{} changed parameters from {:?} to {:?}", path, from, to)
+            }
+        }
+    }
+}
+
+/// Compatibility between two schema versions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compatibility {
+    /// No field changed: data decodes under either version
+    Full,
+    /// Only additions: the newer version decodes data written against the older one
+    Backward,
+    /// Only removals: the older version decodes data written against the newer one
+    Forward,
+    /// Neither version can be relied on to decode the other's data
+    Breaking,
+}
+
+impl Compatibility {
+    /// Whether data written against the older version decodes under the newer one
+    pub fn is_backward(self) -> bool {
+        matches!(self, Compatibility::Full | Compatibility::Backward)
+    }
+
+    /// Whether data written against the newer version decodes under the older one
+    pub fn is_forward(self) -> bool {
+        matches!(self, Compatibility::Full | Compatibility::Forward)
+    }
+
+    /// Compatibility of two sets of changes applied together
+    pub fn combine(self, other: Compatibility) -> Compatibility {
+        match (self, other) {
+            (Compatibility::Full, c) | (c, Compatibility::Full) => c,
+            (a, b) if a == b => a,
+            _ => Compatibility::Breaking,
+        }
+    }
+}
+
+/// Field changes between two schema versions, in canonical index order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SchemaDiff {
+    /// Changed fields
+    pub changes: Vec<FieldChange>,
+}
+
+impl SchemaDiff {
+    /// Diff an older schema version against a newer one
+    pub fn between(older: &ImmutableSchema, newer: &ImmutableSchema) -> Self {
+        let older = by_canonical(older);
+        let mut newer = by_canonical(newer);
+        let mut changes = Vec::new();
+
+        for (canonical, old) in older {
+            let Some(new) = newer.remove(&canonical) else {
+                changes.push(FieldChange::Removed { canonical, path: old.path.clone() });
+                continue;
+            };
+            if old.path != new.path {
+                changes.push(FieldChange::PathChanged { canonical, from: old.path.clone(), to: new.path.clone() });
+            }
+            if old.value_type != new.value_type {
+                changes.push(FieldChange::TypeChanged {
+                    canonical,
+                    path: new.path.clone(),
+                    from: old.value_type,
+                    to: new.value_type,
+                });
+            }
+            if old.param_groups != new.param_groups || old.requires_parent != new.requires_parent {
+                changes.push(FieldChange::ParamGroupsChanged {
+                    canonical,
+                    path: new.path.clone(),
+                    from: old.param_groups.clone(),
+                    to: new.param_groups.clone(),
+                });
+            }
+        }
+        for (canonical, new) in newer {
+            changes.push(FieldChange::Added { canonical, path: new.path.clone() });
+        }
+        changes.sort_by_key(|change| match change {
+            FieldChange::Added { canonical, .. }
+            | FieldChange::Removed { canonical, .. }
+            | FieldChange::PathChanged { canonical, .. }
+            | FieldChange::TypeChanged { canonical, .. }
+            | FieldChange::ParamGroupsChanged { canonical, .. } => *canonical,
+        });
+        Self { changes }
+    }
+
+    /// Whether no field changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Compatibility of all changes together
+    pub fn compatibility(&self) -> Compatibility {
+        self.changes.iter().fold(Compatibility::Full, |c, change| c.combine(change.compatibility()))
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return write!(f, "no changes");
+        }
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fields of a schema keyed by canonical index
+fn by_canonical(schema: &ImmutableSchema) -> BTreeMap<u32, &FieldDescriptor> {
+    schema
+        .fields
+        .iter()
+        .enumerate()
+        .filter_map(|(index, field)| field.as_ref().map(|field| (schema.canonical_index(index as u32), field)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::types::SchemaRegistry;
+
+    fn field(path: &str, value_type: ValueType) -> Option<FieldDescriptor> {
+        Some(FieldDescriptor::new(path.to_string(), value_type).unwrap())
+    }
+
+    #[test]
+    fn test_classification() {
+        let v0 = ImmutableSchema::new(0, vec![field("name", ValueType::String), field("age", ValueType::Int)]);
+        let added = ImmutableSchema::new(1, vec![field("name", ValueType::String), field("age", ValueType::Int), field("tags[]", ValueType::String)]);
+        let removed = ImmutableSchema::new(1, vec![field("name", ValueType::String), None]);
+        let retyped = ImmutableSchema::new(1, vec![field("name", ValueType::String), field("age", ValueType::Float)]);
+        let nested = ImmutableSchema::new(1, vec![field("name", ValueType::String), field("age{}", ValueType::Int)]);
+
+        assert_eq!(SchemaDiff::between(&v0, &added).compatibility(), Compatibility::Backward);
+        assert_eq!(SchemaDiff::between(&added, &v0).compatibility(), Compatibility::Forward);
+        assert_eq!(SchemaDiff::between(&v0, &removed).changes, vec![FieldChange::Removed { canonical: 1, path: "age".to_string() }]);
+        assert_eq!(SchemaDiff::between(&v0, &retyped).compatibility(), Compatibility::Breaking);
+        assert_eq!(SchemaDiff::between(&removed, &added).compatibility(), Compatibility::Backward);
+        assert_eq!(SchemaDiff::between(&retyped, &removed).compatibility(), Compatibility::Forward);
+
+        let diff = SchemaDiff::between(&v0, &nested);
+        assert!(matches!(diff.changes[..], [FieldChange::PathChanged { .. }, FieldChange::ParamGroupsChanged { .. }]));
+        assert!(!diff.compatibility().is_backward());
+        assert!(!diff.compatibility().is_forward());
+    }
+
+    #[test]
+    fn test_reordered_versions_are_compatible() {
+        let registry = Arc::new(SchemaRegistry::new());
+        registry.add_field(FieldDescriptor::new("a".to_string(), ValueType::Int).unwrap());
+        registry.add_field(FieldDescriptor::new("b".to_string(), ValueType::Int).unwrap());
+        registry.record_access(1, 0);
+        let v1 = registry.create_optimized_version();
+        assert!(registry.diff_versions(0, v1).unwrap().is_empty());
+
+        registry.add_field(FieldDescriptor::new("c".to_string(), ValueType::Int).unwrap());
+        let diff = registry.diff_versions(0, v1).unwrap();
+        assert_eq!(diff.compatibility(), Compatibility::Backward);
+        assert!(registry.diff_versions(0, 9).is_none());
+    }
+
+    #[test]
+    fn test_registry_refuses_breaking_changes() {
+        let registry = Arc::new(SchemaRegistry::new());
+        registry.add_field(FieldDescriptor::new("a".to_string(), ValueType::Int).unwrap());
+
+        let grown = ImmutableSchema::new(1, vec![field("a", ValueType::Int), field("b", ValueType::Bool)]);
+        assert_eq!(registry.check_compatible(&grown).unwrap().changes.len(), 1);
+        let error = registry.check_compatible(&ImmutableSchema::new(1, vec![field("a", ValueType::String)])).unwrap_err();
+        assert!(error.contains("a changed type"));
+        assert!(registry.check_compatible(&ImmutableSchema::new(1, vec![])).is_err());
+    }
+}
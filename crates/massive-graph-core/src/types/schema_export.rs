@@ -0,0 +1,348 @@
+//! Schema export and import
+//!
+//! A stable, human editable representation of a schema and its version history, so
+//! schemas can live in source control as JSON or TOML:
+//! ```text
+//! { "format": 1, "versions": [
+//!     { "version": 0, "fields": [ { "index": 0, "canonical": 0, "path": "*users{}.age", "type": "Int" } ] } ] }
+//! ```
+//! Paths use the `FieldDescriptor::new` syntax, so a leading `*` marks a field that
+//! requires a parent. Creation timestamps are not exported.
+//!
+//! `fields_from_json_schema` converts a standard JSON Schema document into descriptors
+//! on a best-effort basis:
+//! - `properties` become `.name` segments and `additionalProperties` a `{}` map key
+//! - `items` becomes `[]`; an array without `items` is a single `Array` field
+//! - `string` (`date-time` format: `Timestamp`, base64 content: `Binary`), `integer`,
+//!   `number`, `boolean` and `null` become leaf fields; an object without properties is
+//!   an `Object` field
+//! - local `$ref`s (`#/definitions/..`, `#/$defs/..`) are followed; recursive ones and
+//!   property names that are not valid path segments are reported as skipped
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+
+use crate::types::{FieldDescriptor, ImmutableSchema, SchemaRegistry, ValueType};
+
+/// Version of the export format
+pub const SCHEMA_EXPORT_FORMAT: u32 = 1;
+
+/// Exported schema with its version history
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SchemaExport {
+    /// Export format version
+    pub format: u32,
+    /// Schema versions, oldest first; the last is current
+    pub versions: Vec<VersionExport>,
+}
+
+/// Exported schema version
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VersionExport {
+    /// Schema version
+    pub version: u32,
+    /// Fields in index order
+    pub fields: Vec<FieldExport>,
+}
+
+/// Exported field
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldExport {
+    /// Field index in this version
+    pub index: u32,
+    /// Canonical index (see `SchemaRegistry::canonical_index`)
+    pub canonical: u32,
+    /// Field path, `*` prefixed when the field requires a parent
+    pub path: String,
+    /// Value type name, as in `ValueType`
+    #[serde(rename = "type")]
+    pub value_type: String,
+}
+
+impl SchemaExport {
+    /// Export every version of a registry
+    pub fn from_registry(registry: &SchemaRegistry) -> Self {
+        Self {
+            format: SCHEMA_EXPORT_FORMAT,
+            versions: registry.versions().iter().map(|schema| VersionExport::from_schema(schema)).collect(),
+        }
+    }
+
+    /// Build a registry holding the exported versions
+    pub fn to_registry(&self) -> Result<SchemaRegistry, String> {
+        if self.format != SCHEMA_EXPORT_FORMAT {
+            return Err(format!("Unsupported schema export format {}", self.format));
+        }
+        SchemaRegistry::from_versions(self.versions.iter().map(VersionExport::to_schema).collect::<Result<_, _>>()?)
+    }
+
+    /// Serialize as pretty printed JSON
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| format!("Schema JSON export failed: {}", e))
+    }
+
+    /// Parse from JSON
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid schema JSON: {}", e))
+    }
+
+    /// Serialize as TOML
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| format!("Schema TOML export failed: {}", e))
+    }
+
+    /// Parse from TOML
+    pub fn from_toml(source: &str) -> Result<Self, String> {
+        toml::from_str(source).map_err(|e| format!("Invalid schema TOML: {}", e))
+    }
+}
+
+impl VersionExport {
+    /// Export one schema version
+    pub fn from_schema(schema: &ImmutableSchema) -> Self {
+        let fields = schema
+            .fields
+            .iter()
+            .enumerate()
+            .filter_map(|(index, field)| {
+                let field = field.as_ref()?;
+                Some(FieldExport {
+                    index: index as u32,
+                    canonical: schema.canonical_index(index as u32),
+                    path: if field.requires_parent { format!("*{}", field.path) } else { field.path.clone() },
+                    value_type: format!("{:?}", field.value_type),
+                })
+            })
+            .collect();
+        Self { version: schema.version, fields }
+    }
+
+    /// Rebuild the schema version
+    ///
+    /// Indices may leave gaps (e.g. of fields whose journal write failed), but no more
+    /// empty slots than fields, so an export cannot allocate far beyond its own size.
+    pub fn to_schema(&self) -> Result<ImmutableSchema, String> {
+        let len = self.fields.iter().map(|f| f.index as usize + 1).max().unwrap_or(0);
+        if len > 2 * self.fields.len() {
+            return Err(format!("Version {} has field index {} but only {} fields", self.version, len - 1, self.fields.len()));
+        }
+        let mut fields: Vec<Option<FieldDescriptor>> = vec![None; len];
+        let mut canonical: Vec<u32> = (0..len as u32).collect();
+        for field in &self.fields {
+            let slot = &mut fields[field.index as usize];
+            if slot.is_some() {
+                return Err(format!("Version {} defines field index {} twice", self.version, field.index));
+            }
+            let value_type = value_type_named(&field.value_type)
+                .ok_or_else(|| format!("Unknown value type {} for {}", field.value_type, field.path))?;
+            *slot = Some(FieldDescriptor::new(field.path.clone(), value_type)?);
+            canonical[field.index as usize] = field.canonical;
+        }
+        let mut seen = canonical.clone();
+        seen.sort_unstable();
+        if seen.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(format!("Version {} reuses a canonical index", self.version));
+        }
+        Ok(ImmutableSchema::with_canonical(self.version, fields, canonical.into()))
+    }
+}
+
+/// Value type by its `Debug` name
+fn value_type_named(name: &str) -> Option<ValueType> {
+    (0..=u8::MAX).filter_map(|tag| ValueType::try_from(tag).ok()).find(|t| format!("{:?}", t) == name)
+}
+
+/// Result of converting a JSON Schema document
+#[derive(Clone, Debug, Default)]
+pub struct JsonSchemaImport {
+    /// Converted fields, in document order
+    pub fields: Vec<FieldDescriptor>,
+    /// JSON pointers of the parts that could not be converted, with the reason
+    pub skipped: Vec<String>,
+}
+
+/// Convert a JSON Schema document describing an object into field descriptors
+pub fn fields_from_json_schema(schema: &Json) -> Result<JsonSchemaImport, String> {
+    if schema_type(schema) != Some("object") {
+        return Err("A JSON Schema import needs an object at the root".to_string());
+    }
+    let mut import = JsonSchemaImport::default();
+    let mut refs = Vec::new();
+    convert(schema, schema, "", "#", &mut refs, &mut import);
+    Ok(import)
+}
+
+/// The `type` of a schema, taking the first non-null entry of a type list
+fn schema_type(schema: &Json) -> Option<&str> {
+    match schema.get("type")? {
+        Json::String(t) => Some(t),
+        Json::Array(types) => types.iter().filter_map(Json::as_str).find(|&t| t != "null").or(Some("null")),
+        _ => None,
+    }
+}
+
+/// Join a parent path and a segment
+fn join(path: &str, segment: &str) -> String {
+    match path {
+        "" => segment.to_string(),
+        _ => format!("{}.{}", path, segment),
+    }
+}
+
+/// Convert the schema at `pointer` for field `path`, following local refs
+fn convert(root: &Json, schema: &Json, path: &str, pointer: &str, refs: &mut Vec<String>, import: &mut JsonSchemaImport) {
+    if let Some(reference) = schema.get("$ref").and_then(Json::as_str) {
+        if refs.iter().any(|r| r == reference) {
+            import.skipped.push(format!("{}: recursive reference {}", pointer, reference));
+            return;
+        }
+        let target = reference.strip_prefix('#').and_then(|p| root.pointer(p));
+        let Some(target) = target else {
+            import.skipped.push(format!("{}: unresolved reference {}", pointer, reference));
+            return;
+        };
+        refs.push(reference.to_string());
+        convert(root, target, path, reference, refs, import);
+        refs.pop();
+        return;
+    }
+
+    let leaf = |value_type: ValueType, import: &mut JsonSchemaImport| match FieldDescriptor::new(path.to_string(), value_type) {
+        Ok(field) => import.fields.push(field),
+        Err(e) => import.skipped.push(format!("{}: {}", pointer, e)),
+    };
+    match schema_type(schema) {
+        Some("object") => {
+            let properties = schema.get("properties").and_then(Json::as_object);
+            let additional = schema.get("additionalProperties").filter(|a| a.is_object());
+            if properties.is_none() && additional.is_none() {
+                return leaf(ValueType::Object, import);
+            }
+            for (name, property) in properties.into_iter().flatten() {
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    import.skipped.push(format!("{}/properties/{}: not a valid path segment", pointer, name));
+                    continue;
+                }
+                convert(root, property, &join(path, name), &format!("{}/properties/{}", pointer, name), refs, import);
+            }
+            if let Some(additional) = additional {
+                convert(root, additional, &format!("{}{{}}", path), &format!("{}/additionalProperties", pointer), refs, import);
+            }
+        }
+        Some("array") => match schema.get("items").filter(|i| i.is_object()) {
+            Some(items) => convert(root, items, &format!("{}[]", path), &format!("{}/items", pointer), refs, import),
+            None => leaf(ValueType::Array, import),
+        },
+        Some("string") => {
+            let binary = schema.get("contentEncoding").and_then(Json::as_str) == Some("base64");
+            match schema.get("format").and_then(Json::as_str) {
+                Some("date-time") => leaf(ValueType::Timestamp, import),
+                _ if binary => leaf(ValueType::Binary, import),
+                _ => leaf(ValueType::String, import),
+            }
+        }
+        Some("integer") => leaf(ValueType::Int, import),
+        Some("number") => leaf(ValueType::Float, import),
+        Some("boolean") => leaf(ValueType::Bool, import),
+        Some("null") => leaf(ValueType::Null, import),
+        Some(other) => import.skipped.push(format!("{}: unsupported type {}", pointer, other)),
+        None => import.skipped.push(format!("{}: no type", pointer)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn registry() -> Arc<SchemaRegistry> {
+        let registry = Arc::new(SchemaRegistry::new());
+        registry.add_field(FieldDescriptor::new("name".to_string(), ValueType::String).unwrap());
+        registry.add_field(FieldDescriptor::new("*users{}.age".to_string(), ValueType::Int).unwrap());
+        registry.record_access(1, 0);
+        registry.create_optimized_version();
+        registry.add_field(FieldDescriptor::new("tags[]".to_string(), ValueType::String).unwrap());
+        registry
+    }
+
+    #[test]
+    fn test_registry_round_trips_through_json_and_toml() {
+        let export = SchemaExport::from_registry(&registry());
+        assert_eq!(export.versions.len(), 2);
+
+        let json = export.to_json().unwrap();
+        assert_eq!(SchemaExport::from_json(&json).unwrap(), export);
+        let toml = export.to_toml().unwrap();
+        assert_eq!(SchemaExport::from_toml(&toml).unwrap(), export);
+
+        let imported = SchemaExport::from_json(&json).unwrap().to_registry().unwrap();
+        assert_eq!(imported.current_version().version(), 1);
+        assert_eq!(imported.get_field_index("users{}.age"), Some(0));
+        assert!(imported.get_field(0).unwrap().requires_parent);
+        assert_eq!(imported.get_field_index_at_version("users{}.age", 0), Some(1));
+        assert_eq!(imported.translate_index(1, 0, 1), Some(0));
+        assert_eq!(SchemaExport::from_registry(&imported), export);
+
+        // New fields continue after the imported ones
+        let imported = Arc::new(imported);
+        assert_eq!(imported.add_field(FieldDescriptor::new("late".to_string(), ValueType::Int).unwrap()), 3);
+    }
+
+    #[test]
+    fn test_invalid_exports_rejected() {
+        let mut export = SchemaExport::from_registry(&registry());
+        export.versions[1].fields[0].value_type = "Integer".to_string();
+        assert!(export.to_registry().is_err());
+
+        let mut export = SchemaExport::from_registry(&registry());
+        export.versions[1].fields[1].canonical = export.versions[1].fields[0].canonical;
+        assert!(export.to_registry().is_err());
+
+        let mut export = SchemaExport::from_registry(&registry());
+        export.versions.swap(0, 1);
+        assert!(export.to_registry().is_err());
+
+        // Sparse indices are bounded by the field count
+        let mut export = SchemaExport::from_registry(&registry());
+        (export.versions[1].fields[2].index, export.versions[1].fields[2].canonical) = (5, 5);
+        assert!(export.to_registry().is_ok());
+        export.versions[1].fields[2].index = u32::MAX;
+        assert!(export.to_registry().unwrap_err().contains("only 3 fields"));
+        assert!(SchemaExport::from_json("{\"format\": 1}").is_err());
+    }
+
+    #[test]
+    fn test_json_schema_conversion() {
+        let schema: Json = serde_json::from_str(r##"{
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "born": { "type": ["string", "null"], "format": "date-time" },
+                "scores": { "type": "array", "items": { "type": "number" } },
+                "raw": { "type": "array" },
+                "labels": { "type": "object", "additionalProperties": { "type": "boolean" } },
+                "friends": { "type": "array", "items": { "$ref": "#/$defs/person" } },
+                "bad-name": { "type": "string" }
+            },
+            "$defs": {
+                "person": {
+                    "type": "object",
+                    "properties": { "id": { "type": "integer" }, "best": { "$ref": "#/$defs/person" } }
+                }
+            }
+        }"##).unwrap();
+
+        let import = fields_from_json_schema(&schema).unwrap();
+        let fields: Vec<(&str, ValueType)> = import.fields.iter().map(|f| (f.path.as_str(), f.value_type)).collect();
+        assert!(fields.contains(&("name", ValueType::String)));
+        assert!(fields.contains(&("born", ValueType::Timestamp)));
+        assert!(fields.contains(&("scores[]", ValueType::Float)));
+        assert!(fields.contains(&("raw", ValueType::Array)));
+        assert!(fields.contains(&("labels{}", ValueType::Bool)));
+        assert!(fields.contains(&("friends[].id", ValueType::Int)));
+        assert_eq!(import.fields.len(), 6);
+        assert_eq!(import.skipped.len(), 2);
+        assert!(fields_from_json_schema(&serde_json::json!({ "type": "array" })).is_err());
+    }
+}
@@ -1,9 +1,13 @@
-use crate::{types::storage::ChunkRef, VersionId};
+use crate::constants::{ID16_LENGTH, ID32_LENGTH, ID8_LENGTH};
+use crate::types::value_nested::{encode_elements, encode_entries};
+use crate::types::varint::{decode_varint, encode_varint};
+use crate::types::ParseError;
+use crate::{DeltaId, DocId, UserId};
 
 
 /// Wire format type identifiers - one-to-one with Value variants
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValueType {
     // Primitives
     /// Null value
@@ -117,16 +121,80 @@ pub enum ValueType {
 }
 
 impl ValueType {
-    /// Convert a u8 value to ValueType
-    pub fn from_u8(value: u8) -> Self {
-        // Safe because we control the wire format
-        unsafe { std::mem::transmute(value) }
+    /// Data width of fixed-size types, which carry no length prefix on the wire
+    pub fn fixed_size(&self) -> Option<usize> {
+        Self::tag_fixed_size(*self as u8)
+    }
+
+    /// Data width for a raw type tag, `None` for variable-length (or unknown) tags
+    pub fn tag_fixed_size(tag: u8) -> Option<usize> {
+        const NULL: u8 = ValueType::Null as u8;
+        const UNDEFINED: u8 = ValueType::Undefined as u8;
+        const BOOL: u8 = ValueType::Bool as u8;
+        const INT: u8 = ValueType::Int as u8;
+        const FLOAT: u8 = ValueType::Float as u8;
+        const TIMESTAMP: u8 = ValueType::Timestamp as u8;
+        match tag {
+            NULL | UNDEFINED => Some(0),
+            BOOL => Some(1),
+            INT | FLOAT | TIMESTAMP => Some(8),
+            _ => None,
+        }
     }
 }
 
+impl TryFrom<u8> for ValueType {
+    type Error = ParseError;
+
+    /// Checked conversion from a wire type tag - unknown tags are rejected
+    fn try_from(tag: u8) -> Result<Self, Self::Error> {
+        Ok(match tag {
+            0 => ValueType::Null,
+            1 => ValueType::Bool,
+            2 => ValueType::Int,
+            3 => ValueType::Float,
+            4 => ValueType::Timestamp,
+            8 => ValueType::Undefined,
+            16 => ValueType::String,
+            17 => ValueType::Binary,
+            32 => ValueType::Array,
+            33 => ValueType::Map,
+            34 => ValueType::Object,
+            35 => ValueType::Collection,
+            40 => ValueType::State,
+            41 => ValueType::Derived,
+            42 => ValueType::Goal,
+            43 => ValueType::Optimizable,
+            44 => ValueType::Effect,
+            50 => ValueType::Node,
+            51 => ValueType::Edge,
+            52 => ValueType::HyperEdge,
+            128 => ValueType::DocumentRef,
+            129 => ValueType::DeltaRef,
+            130 => ValueType::UserRef,
+            144 => ValueType::Tree,
+            145 => ValueType::Graph,
+            146 => ValueType::StateGraph,
+            160 => ValueType::TextStream,
+            161 => ValueType::BinaryStream,
+            162 => ValueType::DeltaStream,
+            163 => ValueType::DocumentStream,
+            164 => ValueType::EventStream,
+            176 => ValueType::Matrix,
+            177 => ValueType::Tensor,
+            192 => ValueType::TextFile,
+            193 => ValueType::BinaryFile,
+            194 => ValueType::Table,
+            208 => ValueType::System,
+            209 => ValueType::Event,
+            _ => return Err(ParseError::InvalidValueType(tag)),
+        })
+    }
+}
 
 /// Value reading from wire format - zero-copy view into chunk memory
 #[allow(dead_code)] // POC: Fields will be used in future implementation
+#[derive(Clone, Copy)]
 pub struct Value<'a> {
     // Raw wire bytes - the complete value including type byte
     raw_bytes: &'a [u8],
@@ -138,33 +206,37 @@ pub struct Value<'a> {
 }
 
 impl<'a> Value<'a> {
-    /// Parse from wire bytes
-    pub fn from_bytes(raw_bytes: &'a [u8]) -> Self {
-        let value_type = ValueType::from_u8(raw_bytes[0]);
+    /// Parse the value at the start of `bytes`, checking its type tag and length
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let raw_bytes = &bytes[..Self::encoded_len(bytes)?];
+        let value_type = ValueType::try_from(raw_bytes[0])?;
         
-        let (data_offset, data_len) = match value_type {
+        let (data_offset, data_len) = match value_type.fixed_size() {
             // Fixed size - no length prefix
-            ValueType::Null | ValueType::Undefined => (1, 0),
-            ValueType::Bool => (1, 1),
-            ValueType::Int | ValueType::Float | ValueType::Timestamp => (1, 8),
+            Some(size) => (1, size as u32),
 
             // References - fixed size
             // ValueType::TextStream | ValueType::BinaryStream | ValueType::DeltaStream | ValueType::DocumentStream | ValueType::EventStream => (1, 16),            
             // ValueType::TextFile | ValueType::BinaryFile | ValueType::Table => (1, 16),            
 
             // Variable size - has varint length prefix
-            _ => {
-                let (len, bytes_read) = decode_varint(&raw_bytes[1..]);
+            None => {
+                let (len, bytes_read) = decode_varint(&raw_bytes[1..]).unwrap_or((0, 0));
                 (1 + bytes_read as u8, len)
             }
         };
         
-        Value {
+        Ok(Value {
             raw_bytes,
             value_type,
             data_offset,
             data_len,
-        }
+        })
+    }
+
+    /// Type of the value
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
     }
     
     /// Get data bytes (excluding type and length prefix)
@@ -172,39 +244,277 @@ impl<'a> Value<'a> {
         &self.raw_bytes[self.data_offset as usize..]
     }
     
+    /// Complete encoded value, type byte included
+    pub fn encoded(&self) -> &'a [u8] {
+        self.raw_bytes
+    }
+
     /// Get total size of this value in wire format
     pub fn total_size(&self) -> usize {
         self.raw_bytes.len()
     }
+
+    /// Data bytes, if the value is of the `expected` type
+    fn data_of(&self, expected: ValueType) -> Result<&'a [u8], ParseError> {
+        if self.value_type != expected {
+            return Err(ParseError::TypeMismatch { expected: expected as u8, actual: self.value_type as u8 });
+        }
+        Ok(self.data())
+    }
+
+    /// Read a `Bool`
+    pub fn as_bool(&self) -> Result<bool, ParseError> {
+        match self.data_of(ValueType::Bool)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(ParseError::InvalidFormat),
+        }
+    }
+
+    /// Read an `Int`
+    pub fn as_i64(&self) -> Result<i64, ParseError> {
+        Ok(i64::from_le_bytes(self.data_of(ValueType::Int)?.try_into().unwrap()))
+    }
+
+    /// Read a `Float`
+    pub fn as_f64(&self) -> Result<f64, ParseError> {
+        Ok(f64::from_le_bytes(self.data_of(ValueType::Float)?.try_into().unwrap()))
+    }
+
+    /// Read a `Timestamp` (nanoseconds since the Unix epoch)
+    pub fn as_timestamp(&self) -> Result<u64, ParseError> {
+        Ok(u64::from_le_bytes(self.data_of(ValueType::Timestamp)?.try_into().unwrap()))
+    }
+
+    /// Read a `String`
+    pub fn as_str(&self) -> Result<&'a str, ParseError> {
+        std::str::from_utf8(self.data_of(ValueType::String)?).map_err(|_| ParseError::InvalidUtf8)
+    }
+
+    /// Read a `Binary`
+    pub fn as_bytes(&self) -> Result<&'a [u8], ParseError> {
+        self.data_of(ValueType::Binary)
+    }
+
+    /// Read a `DocumentRef`
+    pub fn as_document_ref(&self) -> Result<DocId, ParseError> {
+        Ok(DocId::from_bytes(id_bytes(self.data_of(ValueType::DocumentRef)?)?))
+    }
+
+    /// Read a `DeltaRef` as the document holding the delta and the delta's id
+    pub fn as_delta_ref(&self) -> Result<(DocId, DeltaId), ParseError> {
+        let data = self.data_of(ValueType::DeltaRef)?;
+        if data.len() != ID16_LENGTH + ID8_LENGTH {
+            return Err(ParseError::InvalidFormat);
+        }
+        let (doc_id, delta_id) = data.split_at(ID16_LENGTH);
+        Ok((DocId::from_bytes(id_bytes(doc_id)?), DeltaId::new(id_bytes(delta_id)?)))
+    }
+
+    /// Read a `UserRef`
+    pub fn as_user_ref(&self) -> Result<UserId, ParseError> {
+        Ok(UserId::new(id_bytes(self.data_of(ValueType::UserRef)?)?))
+    }
+    
+    /// Length of the encoded value at the start of `bytes`, type byte included
+    pub fn encoded_len(bytes: &[u8]) -> Result<usize, ParseError> {
+        let (&tag, rest) = bytes.split_first().ok_or(ParseError::InsufficientData { expected: 1, actual: 0 })?;
+        let len = match ValueType::tag_fixed_size(tag) {
+            Some(size) => 1 + size,
+            None => {
+                let (data_len, read) = decode_varint(rest)?;
+                1 + read + data_len as usize
+            }
+        };
+        if bytes.len() < len {
+            return Err(ParseError::InsufficientData { expected: len, actual: bytes.len() });
+        }
+        Ok(len)
+    }
+
+    /// Encode a value to wire format: [type][length varint, variable types only][data]
+    pub fn encode(value_type: ValueType, data: &[u8], output: &mut Vec<u8>) -> Result<(), String> {
+        match value_type.fixed_size() {
+            Some(size) if size != data.len() => {
+                return Err(format!("{:?} expects {} data bytes, got {}", value_type, size, data.len()));
+            }
+            Some(_) => output.push(value_type as u8),
+            None => {
+                output.push(value_type as u8);
+                encode_varint(data.len() as u32, output)?;
+            }
+        }
+        output.extend_from_slice(data);
+        Ok(())
+    }
 }
 
+/// Id of exactly `N` base62 characters
+fn id_bytes<const N: usize>(data: &[u8]) -> Result<[u8; N], ParseError> {
+    match <[u8; N]>::try_from(data) {
+        Ok(id) if id.iter().all(u8::is_ascii_alphanumeric) => Ok(id),
+        _ => Err(ParseError::InvalidFormat),
+    }
+}
+
+/// Encodes values, one after another, into the wire layout `Value::from_bytes` reads
+#[derive(Clone, Debug, Default)]
+pub struct ValueWriter {
+    buffer: Vec<u8>,
+}
+
+impl ValueWriter {
+    /// Create an empty writer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty writer with room for `capacity` bytes
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { buffer: Vec::with_capacity(capacity) }
+    }
+
+    /// Write a fixed-size value, whose width the caller guarantees
+    fn fixed(&mut self, value_type: ValueType, data: &[u8]) -> &mut Self {
+        self.buffer.push(value_type as u8);
+        self.buffer.extend_from_slice(data);
+        self
+    }
+
+    /// Write a `Null`
+    pub fn null(&mut self) -> &mut Self { self.fixed(ValueType::Null, &[]) }
+    /// Write an `Undefined`
+    pub fn undefined(&mut self) -> &mut Self { self.fixed(ValueType::Undefined, &[]) }
+    /// Write a `Bool`
+    pub fn bool(&mut self, value: bool) -> &mut Self { self.fixed(ValueType::Bool, &[value as u8]) }
+    /// Write an `Int`
+    pub fn int(&mut self, value: i64) -> &mut Self { self.fixed(ValueType::Int, &value.to_le_bytes()) }
+    /// Write a `Float`
+    pub fn float(&mut self, value: f64) -> &mut Self { self.fixed(ValueType::Float, &value.to_le_bytes()) }
+    /// Write a `Timestamp`
+    pub fn timestamp(&mut self, value: u64) -> &mut Self { self.fixed(ValueType::Timestamp, &value.to_le_bytes()) }
+
+    /// Write a `String`
+    pub fn string(&mut self, value: &str) -> Result<&mut Self, String> {
+        self.value(ValueType::String, value.as_bytes())
+    }
+
+    /// Write a `Binary`
+    pub fn binary(&mut self, value: &[u8]) -> Result<&mut Self, String> {
+        self.value(ValueType::Binary, value)
+    }
+
+    /// Write a `DocumentRef`
+    pub fn document_ref(&mut self, doc_id: DocId) -> &mut Self {
+        self.buffer.extend_from_slice(&[ValueType::DocumentRef as u8, ID16_LENGTH as u8]);
+        self.buffer.extend_from_slice(doc_id.as_bytes());
+        self
+    }
+
+    /// Write a `DeltaRef` to a delta of `doc_id`: [document id][delta id]
+    pub fn delta_ref(&mut self, doc_id: DocId, delta_id: DeltaId) -> &mut Self {
+        self.buffer.extend_from_slice(&[ValueType::DeltaRef as u8, (ID16_LENGTH + ID8_LENGTH) as u8]);
+        self.buffer.extend_from_slice(doc_id.as_bytes());
+        self.buffer.extend_from_slice(delta_id.as_bytes());
+        self
+    }
+
+    /// Write a `UserRef`
+    pub fn user_ref(&mut self, user_id: UserId) -> &mut Self {
+        self.buffer.extend_from_slice(&[ValueType::UserRef as u8, ID32_LENGTH as u8]);
+        self.buffer.extend_from_slice(user_id.as_bytes());
+        self
+    }
+
+    /// Write a value of any type from its data bytes
+    pub fn value(&mut self, value_type: ValueType, data: &[u8]) -> Result<&mut Self, String> {
+        Value::encode(value_type, data, &mut self.buffer)?;
+        Ok(self)
+    }
+
+    /// Write an Array or Collection of encoded values (see `value_nested`)
+    pub fn elements<E: AsRef<[u8]>>(&mut self, value_type: ValueType, elements: impl IntoIterator<Item = E>) -> Result<&mut Self, String> {
+        encode_elements(value_type, elements, &mut self.buffer)?;
+        Ok(self)
+    }
+
+    /// Write a Map or Object of encoded values, keys strictly ascending (see `value_nested`)
+    pub fn entries<K: AsRef<str>, V: AsRef<[u8]>>(
+        &mut self,
+        value_type: ValueType,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<&mut Self, String> {
+        encode_entries(value_type, entries, &mut self.buffer)?;
+        Ok(self)
+    }
+
+    /// Encoded bytes written so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Take the encoded bytes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
 
 // struct VersionedValue {
 //     version: VersionId,
 //     value_ref: ChunkRef<Value>,
 // }
 
-/// Decode a varint from bytes, returning (value, bytes_read)
-fn decode_varint(bytes: &[u8]) -> (u32, usize) {
-    let mut value = 0u32;
-    let mut shift = 0;
-    let mut bytes_read = 0;
-    
-    for byte in bytes {
-        bytes_read += 1;
-        value |= ((byte & 0x7F) as u32) << shift;
-        
-        if byte & 0x80 == 0 {
-            // MSB is 0, this is the last byte
-            break;
-        }
-        shift += 7;
-        
-        if bytes_read >= 5 {
-            // Max 5 bytes for u32
-            break;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_type_tags_round_trip() {
+        for tag in 0..=u8::MAX {
+            if let Ok(value_type) = ValueType::try_from(tag) {
+                assert_eq!(value_type as u8, tag);
+            } else {
+                assert_eq!(ValueType::try_from(tag), Err(ParseError::InvalidValueType(tag)));
+            }
         }
     }
-    
-    (value, bytes_read)
-}
\ No newline at end of file
+
+    #[test]
+    fn test_writer_output_reads_back() {
+        let mut writer = ValueWriter::new();
+        writer.null().bool(true).int(-7).float(2.5).timestamp(1_700_000_000);
+        writer.string("héllo").unwrap().binary(&[0, 255]).unwrap();
+        let bytes = writer.into_bytes();
+
+        let mut rest = bytes.as_slice();
+        let mut next = || {
+            let value = Value::from_bytes(rest).unwrap();
+            rest = &rest[value.total_size()..];
+            value
+        };
+        assert_eq!(next().value_type(), ValueType::Null);
+        assert!(next().as_bool().unwrap());
+        assert_eq!(next().as_i64().unwrap(), -7);
+        assert_eq!(next().as_f64().unwrap(), 2.5);
+        assert_eq!(next().as_timestamp().unwrap(), 1_700_000_000);
+        assert_eq!(next().as_str().unwrap(), "héllo");
+        assert_eq!(next().as_bytes().unwrap(), &[0, 255]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_accessors_check_type_and_contents() {
+        let mut writer = ValueWriter::new();
+        writer.int(1);
+        let int = Value::from_bytes(writer.as_bytes()).unwrap();
+        assert_eq!(int.as_f64(), Err(ParseError::TypeMismatch { expected: ValueType::Float as u8, actual: ValueType::Int as u8 }));
+        assert!(int.as_str().is_err());
+
+        assert_eq!(Value::from_bytes(&[ValueType::Bool as u8, 2]).unwrap().as_bool(), Err(ParseError::InvalidFormat));
+        assert_eq!(Value::from_bytes(&[ValueType::String as u8, 1, 0xff]).unwrap().as_str(), Err(ParseError::InvalidUtf8));
+        assert_eq!(Value::from_bytes(&[7, 0]).err(), Some(ParseError::InvalidValueType(7)));
+        assert!(Value::from_bytes(&[ValueType::Int as u8, 1, 2]).is_err());
+        assert!(Value::from_bytes(&[]).is_err());
+        assert!(ValueWriter::new().value(ValueType::Int, &[1]).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! CBOR codec
+//!
+//! Wire values map to CBOR (RFC 8949) one-to-one:
+//! ```text
+//! Null, Undefined, Bool        simple values null, undefined, false / true
+//! Int                          unsigned / negative integer
+//! Float                        float 64 (half and single precision read as Float too)
+//! String, Binary               text string, byte string
+//! Array, Object                array, map with text keys
+//! Timestamp                    tag 1 with integer seconds when whole, else
+//!                              tag 1001 {1: seconds, -9: nanoseconds} (RFC 9581)
+//! any other type               tag TAG_BASE + type tag holding a map (Map), an array
+//!                              (Collection) or a byte string of the value's data
+//! ```
+//! Only definite lengths are read. Integers outside i64, maps with non-text keys,
+//! duplicate keys or Collection elements, other tags and tag TAG_BASE + a type with its
+//! own form above are rejected.
+
+use std::collections::HashSet;
+
+use crate::types::value_format::{Reader, MAX_BODY_DEPTH};
+use crate::types::value_nested::{encode_elements, encode_entries};
+use crate::types::{Value, ValueType};
+
+/// Tag number of a value type without a native CBOR form is this plus its type tag
+pub const TAG_BASE: u64 = 0x4d47_0000;
+
+/// Types with a CBOR form of their own, never carried in a TAG_BASE tag
+const NATIVE: &[ValueType] = &[
+    ValueType::Null,
+    ValueType::Undefined,
+    ValueType::Bool,
+    ValueType::Int,
+    ValueType::Float,
+    ValueType::Timestamp,
+    ValueType::String,
+    ValueType::Binary,
+    ValueType::Array,
+    ValueType::Object,
+];
+
+/// Epoch-based date/time tag
+const TAG_EPOCH: u64 = 1;
+
+/// Extended time tag
+const TAG_EXTENDED_TIME: u64 = 1001;
+
+/// Nanoseconds per second
+const NANOS: u64 = 1_000_000_000;
+
+// Major types
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const SIMPLE: u8 = 7;
+
+/// Encode a wire value as CBOR
+pub fn encode_cbor(value: &Value<'_>, output: &mut Vec<u8>) -> Result<(), String> {
+    write_value(value, output, 0)
+}
+
+fn write_value(value: &Value<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    if depth > MAX_BODY_DEPTH {
+        return Err(format!("Values nest deeper than {}", MAX_BODY_DEPTH));
+    }
+    let value_type = value.value_type();
+    let error = |e: crate::types::ParseError| format!("{:?}: {}", value_type, e);
+    match value_type {
+        ValueType::Null => output.push(0xf6),
+        ValueType::Undefined => output.push(0xf7),
+        ValueType::Bool => output.push(if value.as_bool().map_err(error)? { 0xf5 } else { 0xf4 }),
+        ValueType::Int => write_int(value.as_i64().map_err(error)?, output),
+        ValueType::Float => {
+            output.push(0xfb);
+            output.extend_from_slice(&value.as_f64().map_err(error)?.to_be_bytes());
+        }
+        ValueType::String => write_bytes(TEXT, value.as_str().map_err(error)?.as_bytes(), output),
+        ValueType::Binary => write_bytes(BYTES, value.as_bytes().map_err(error)?, output),
+        ValueType::Array => write_array(value, output, depth)?,
+        ValueType::Object => write_map(value, output, depth)?,
+        ValueType::Timestamp => {
+            let nanos = value.as_timestamp().map_err(error)?;
+            let (secs, subsec) = (nanos / NANOS, nanos % NANOS);
+            if subsec == 0 {
+                write_head(TAG, TAG_EPOCH, output);
+                write_head(UNSIGNED, secs, output);
+            } else {
+                write_head(TAG, TAG_EXTENDED_TIME, output);
+                write_head(MAP, 2, output);
+                write_head(UNSIGNED, 1, output);
+                write_head(UNSIGNED, secs, output);
+                write_int(-9, output);
+                write_head(UNSIGNED, subsec, output);
+            }
+        }
+        _ => {
+            write_head(TAG, TAG_BASE + value_type as u64, output);
+            match value_type {
+                ValueType::Map => write_map(value, output, depth)?,
+                ValueType::Collection => write_array(value, output, depth)?,
+                _ => write_bytes(BYTES, value.data(), output),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decode a CBOR body holding one value to its wire encoding
+pub fn decode_cbor(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader::new(bytes);
+    let mut output = Vec::new();
+    read_value(&mut reader, &mut output, 0)?;
+    reader.finish()?;
+    Ok(output)
+}
+
+/// Initial byte and argument, in the shortest form
+fn write_head(major: u8, argument: u64, output: &mut Vec<u8>) {
+    let major = major << 5;
+    match argument {
+        0..=23 => output.push(major | argument as u8),
+        24..=0xff => output.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xffff => {
+            output.push(major | 25);
+            output.extend_from_slice(&(argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            output.push(major | 26);
+            output.extend_from_slice(&(argument as u32).to_be_bytes());
+        }
+        _ => {
+            output.push(major | 27);
+            output.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+fn write_int(value: i64, output: &mut Vec<u8>) {
+    match u64::try_from(value) {
+        Ok(value) => write_head(UNSIGNED, value, output),
+        // -1 - n is stored as n, which is the bitwise complement
+        Err(_) => write_head(NEGATIVE, !value as u64, output),
+    }
+}
+
+fn write_bytes(major: u8, bytes: &[u8], output: &mut Vec<u8>) {
+    write_head(major, bytes.len() as u64, output);
+    output.extend_from_slice(bytes);
+}
+
+fn write_array(value: &Value<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let elements = value.elements().map_err(|e| e.to_string())?;
+    write_head(ARRAY, elements.len() as u64, output);
+    for element in elements {
+        write_value(&element.map_err(|e| e.to_string())?, output, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn write_map(value: &Value<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let entries = value.entries().map_err(|e| e.to_string())?;
+    write_head(MAP, entries.len() as u64, output);
+    for entry in entries {
+        let (key, item) = entry.map_err(|e| e.to_string())?;
+        write_bytes(TEXT, key.as_bytes(), output);
+        write_value(&item, output, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Major type and argument of the next item
+fn read_head(reader: &mut Reader<'_>) -> Result<(u8, u8, u64), String> {
+    let initial = reader.byte()?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let argument = match info {
+        0..=23 => info as u64,
+        // For major type 7 this is the float's bits
+        24..=27 => reader.uint(1 << (info - 24))?,
+        31 if major != SIMPLE => return Err("Indefinite-length items are not supported".to_string()),
+        _ if major == SIMPLE => 0,
+        _ => return Err(format!("Invalid CBOR initial byte {:#04x}", initial)),
+    };
+    Ok((major, info, argument))
+}
+
+/// Length argument, bounded by what is left so corrupt lengths fail early
+fn length(argument: u64, reader: &Reader<'_>) -> Result<usize, String> {
+    usize::try_from(argument)
+        .ok()
+        .filter(|&len| len <= reader.remaining())
+        .ok_or_else(|| format!("Length {} runs past the body", argument))
+}
+
+/// Integer of major type 0 or 1 as i64
+fn integer(major: u8, argument: u64) -> Result<i64, String> {
+    let value = i64::try_from(argument).ok();
+    match major {
+        UNSIGNED => value.ok_or_else(|| format!("Integer {} is outside the Int range", argument)),
+        NEGATIVE => value.map(|n| -1 - n).ok_or_else(|| format!("Integer -1-{} is outside the Int range", argument)),
+        _ => Err(format!("Expected an integer, got major type {}", major)),
+    }
+}
+
+/// Integer item as i64
+fn read_int(reader: &mut Reader<'_>) -> Result<i64, String> {
+    let (major, _, argument) = read_head(reader)?;
+    integer(major, argument)
+}
+
+fn read_value(reader: &mut Reader<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    if depth > MAX_BODY_DEPTH {
+        return Err(format!("Values nest deeper than {}", MAX_BODY_DEPTH));
+    }
+    let (major, info, argument) = read_head(reader)?;
+    let float = |value: f64, output: &mut Vec<u8>| Value::encode(ValueType::Float, &value.to_le_bytes(), output);
+    match major {
+        UNSIGNED | NEGATIVE => Value::encode(ValueType::Int, &integer(major, argument)?.to_le_bytes(), output),
+        BYTES => {
+            let len = length(argument, reader)?;
+            Value::encode(ValueType::Binary, reader.take(len)?, output)
+        }
+        TEXT => {
+            let len = length(argument, reader)?;
+            let text = std::str::from_utf8(reader.take(len)?).map_err(|_| "Text string is not UTF-8")?;
+            Value::encode(ValueType::String, text.as_bytes(), output)
+        }
+        ARRAY => read_array(reader, length(argument, reader)?, ValueType::Array, output, depth),
+        MAP => read_map(reader, length(argument, reader)?, ValueType::Object, output, depth),
+        TAG => read_tagged(reader, argument, output, depth),
+        _ => match info {
+            20 | 21 => Value::encode(ValueType::Bool, &[info - 20], output),
+            22 => Value::encode(ValueType::Null, &[], output),
+            23 => Value::encode(ValueType::Undefined, &[], output),
+            25 => float(half_to_f64(argument as u16), output),
+            26 => float(f32::from_bits(argument as u32) as f64, output),
+            27 => float(f64::from_bits(argument), output),
+            _ => Err(format!("Unsupported CBOR simple value {}", info)),
+        },
+    }
+}
+
+fn read_array(reader: &mut Reader<'_>, count: usize, value_type: ValueType, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let mut elements: Vec<Vec<u8>> = Vec::new();
+    for _ in 0..count {
+        let mut element = Vec::new();
+        read_value(reader, &mut element, depth + 1)?;
+        elements.push(element);
+    }
+    let mut seen = HashSet::with_capacity(elements.len());
+    if value_type == ValueType::Collection && !elements.iter().all(|element| seen.insert(element.as_slice())) {
+        return Err("Duplicate element in Collection".to_string());
+    }
+    encode_elements(value_type, elements, output)
+}
+
+fn read_map(reader: &mut Reader<'_>, count: usize, value_type: ValueType, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let key = match read_head(reader)? {
+            (TEXT, _, len) => {
+                let len = length(len, reader)?;
+                std::str::from_utf8(reader.take(len)?).map_err(|_| "Map key is not UTF-8")?
+            }
+            (major, ..) => return Err(format!("Map key of major type {} is not a text string", major)),
+        };
+        let mut item = Vec::new();
+        read_value(reader, &mut item, depth + 1)?;
+        entries.push((key, item));
+    }
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    encode_entries(value_type, entries, output)
+}
+
+fn read_tagged(reader: &mut Reader<'_>, tag: u64, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let timestamp = |secs: i64, subsec: i64, output: &mut Vec<u8>| {
+        let nanos = u64::try_from(secs)
+            .ok()
+            .zip(u64::try_from(subsec).ok().filter(|&subsec| subsec < NANOS))
+            .and_then(|(secs, subsec)| secs.checked_mul(NANOS)?.checked_add(subsec))
+            .ok_or_else(|| format!("Time {}s {}ns is outside the Timestamp range", secs, subsec))?;
+        Value::encode(ValueType::Timestamp, &nanos.to_le_bytes(), output)
+    };
+    match tag {
+        TAG_EPOCH => timestamp(read_int(reader)?, 0, output),
+        TAG_EXTENDED_TIME => {
+            let (mut secs, mut subsec) = (None, 0);
+            let count = match read_head(reader)? {
+                (MAP, _, count) => count,
+                (major, ..) => return Err(format!("Extended time holds major type {}", major)),
+            };
+            for _ in 0..count {
+                match read_int(reader)? {
+                    1 => secs = Some(read_int(reader)?),
+                    -9 => subsec = read_int(reader)?,
+                    key => return Err(format!("Unsupported extended time key {}", key)),
+                }
+            }
+            timestamp(secs.ok_or("Extended time without seconds")?, subsec, output)
+        }
+        _ => {
+            let value_type = tag
+                .checked_sub(TAG_BASE)
+                .and_then(|tag| u8::try_from(tag).ok())
+                .and_then(|tag| ValueType::try_from(tag).ok())
+                .ok_or_else(|| format!("Unsupported CBOR tag {}", tag))?;
+            if NATIVE.contains(&value_type) {
+                return Err(format!("Tag for {:?}, which has its own form", value_type));
+            }
+            match (value_type, read_head(reader)?) {
+                (ValueType::Map, (MAP, _, count)) => read_map(reader, length(count, reader)?, value_type, output, depth),
+                (ValueType::Collection, (ARRAY, _, count)) => read_array(reader, length(count, reader)?, value_type, output, depth),
+                (ValueType::Map | ValueType::Collection, _) => Err(format!("Tag for {:?} holds the wrong item", value_type)),
+                (_, (BYTES, _, len)) => {
+                    let len = length(len, reader)?;
+                    let start = output.len();
+                    Value::encode(value_type, reader.take(len)?, output)?;
+                    // Reference ids must read back
+                    Value::from_bytes(&output[start..])
+                        .and_then(|value| value.reference())
+                        .map_err(|e| format!("Tag for {:?} holds invalid data: {}", value_type, e))?;
+                    Ok(())
+                }
+                _ => Err(format!("Tag for {:?} must hold a byte string", value_type)),
+            }
+        }
+    }
+}
+
+/// IEEE 754 half precision to f64
+fn half_to_f64(bits: u16) -> f64 {
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent - 25),
+    };
+    if bits & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValueWriter;
+
+    fn round_trip(wire: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        encode_cbor(&Value::from_bytes(wire).unwrap(), &mut body).unwrap();
+        decode_cbor(&body).unwrap()
+    }
+
+    #[test]
+    fn test_every_type_round_trips() {
+        let mut one = ValueWriter::new();
+        one.int(1);
+        let one = one.into_bytes();
+        let mut writer = ValueWriter::new();
+        writer.null().undefined().bool(false).float(f64::INFINITY);
+        for int in [0, 23, 24, 256, -1, -25, i64::MIN, i64::MAX] {
+            writer.int(int);
+        }
+        for nanos in [0, 7 * NANOS, 7 * NANOS + 5, u64::MAX] {
+            writer.timestamp(nanos);
+        }
+        writer.string("héllo").unwrap().binary(&[1, 2]).unwrap();
+        writer.elements(ValueType::Array, [one.clone()]).unwrap();
+        writer.elements(ValueType::Collection, [one.clone()]).unwrap();
+        writer.entries(ValueType::Object, [("k", one.clone())]).unwrap();
+        writer.entries(ValueType::Map, [("k", one.clone())]).unwrap();
+        writer.value(ValueType::UserRef, crate::UserId::random().as_bytes()).unwrap();
+        writer.value(ValueType::Edge, &[9]).unwrap();
+        let bytes = writer.into_bytes();
+
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let len = Value::encoded_len(rest).unwrap();
+            assert_eq!(round_trip(&rest[..len]), &rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+
+    #[test]
+    fn test_reads_foreign_encodings() {
+        // {"a": [half 1.5, single 2.5], "t": 1(1.0 as int)}
+        let body = [
+            0xa2, 0x61, b'a', 0x82, 0xf9, 0x3e, 0x00, 0xfa, 0x40, 0x20, 0x00, 0x00, 0x61, b't', 0xc1, 0x01,
+        ];
+        let value = decode_cbor(&body).unwrap();
+        let value = Value::from_bytes(&value).unwrap();
+        assert_eq!(value.get_path("a[0]").unwrap().unwrap().as_f64().unwrap(), 1.5);
+        assert_eq!(value.get_path("a[1]").unwrap().unwrap().as_f64().unwrap(), 2.5);
+        assert_eq!(value.get_path("t").unwrap().unwrap().as_timestamp().unwrap(), NANOS);
+
+        // Indefinite length, integer keys, unknown tags, negative time, trailing bytes
+        assert!(decode_cbor(&[0x9f, 0xff]).is_err());
+        assert!(decode_cbor(&[0xa1, 0x01, 0x01]).is_err());
+        assert!(decode_cbor(&[0xc2, 0x41, 0x01]).is_err());
+        assert!(decode_cbor(&[0xc1, 0x20]).is_err());
+        assert!(decode_cbor(&[0xf6, 0xf6]).is_err());
+        assert!(decode_cbor(&[0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode_cbor(&[[0x81; MAX_BODY_DEPTH + 2].as_slice(), &[0xf6]].concat()).unwrap_err().contains("nest deeper"));
+    }
+
+    #[test]
+    fn test_type_tags_limited_to_types_without_a_form() {
+        let tagged = |value_type: ValueType, data: &[u8]| {
+            let mut body = Vec::new();
+            write_head(TAG, TAG_BASE + value_type as u64, &mut body);
+            write_head(BYTES, data.len() as u64, &mut body);
+            body.extend_from_slice(data);
+            body
+        };
+        // Natively mapped types would skip their own checks
+        assert!(decode_cbor(&tagged(ValueType::Object, &[9, 9, 9])).unwrap_err().contains("own form"));
+        assert!(decode_cbor(&tagged(ValueType::String, &[0xff, 0xfe])).unwrap_err().contains("own form"));
+
+        assert_eq!(decode_cbor(&tagged(ValueType::Node, &[7])).unwrap(), [ValueType::Node as u8, 1, 7]);
+        assert!(decode_cbor(&tagged(ValueType::DocumentRef, &[9])).is_err());
+    }
+}
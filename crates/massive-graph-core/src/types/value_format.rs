@@ -0,0 +1,297 @@
+//! Document body formats
+//!
+//! Documents travel as JSON, MessagePack or CBOR. Each format converts to and from wire
+//! values (see `value_json`, `value_msgpack` and `value_cbor`), so a body can be re-encoded
+//! in any other format without loss. HTTP handlers take the request format from
+//! `Content-Type` (`from_content_type`) and negotiate the response format from `Accept`
+//! (`negotiate`); request and response structs pass through their JSON document form.
+
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value as Json;
+
+use crate::types::value_cbor::{decode_cbor, encode_cbor};
+use crate::types::value_json::{decode_json, encode_json};
+use crate::types::value_msgpack::{decode_msgpack, encode_msgpack};
+use crate::types::Value;
+
+/// Deepest nesting a body may have, checked when decoding and when encoding
+pub const MAX_BODY_DEPTH: usize = 128;
+
+/// Why a request body was refused
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BodyError {
+    /// `Content-Type` names no supported format
+    UnsupportedMediaType(String),
+    /// The body does not decode to the expected data
+    Invalid(String),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::UnsupportedMediaType(media_type) => write!(
+                f,
+                "Unsupported Content-Type '{}', expected 'application/json', 'application/msgpack' or 'application/cbor'",
+                media_type
+            ),
+            BodyError::Invalid(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Encoding of a document body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueFormat {
+    /// `application/json`
+    Json,
+    /// `application/msgpack`
+    MessagePack,
+    /// `application/cbor`
+    Cbor,
+}
+
+impl ValueFormat {
+    /// Every format, JSON first
+    pub const ALL: [ValueFormat; 3] = [ValueFormat::Json, ValueFormat::MessagePack, ValueFormat::Cbor];
+
+    /// Media type written in `Content-Type`
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ValueFormat::Json => "application/json",
+            ValueFormat::MessagePack => "application/msgpack",
+            ValueFormat::Cbor => "application/cbor",
+        }
+    }
+
+    /// Format named by a media type, ignoring parameters such as `charset`
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        let essence = media_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" => Some(ValueFormat::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(ValueFormat::MessagePack),
+            "application/cbor" => Some(ValueFormat::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Request format for a `Content-Type` header, JSON when it is absent
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Self, BodyError> {
+        match content_type {
+            None => Ok(ValueFormat::Json),
+            Some(media_type) => {
+                Self::from_media_type(media_type).ok_or_else(|| BodyError::UnsupportedMediaType(media_type.to_string()))
+            }
+        }
+    }
+
+    /// Response format for an `Accept` header
+    ///
+    /// Picks the supported type with the highest quality (the earliest on a tie). JSON
+    /// answers an absent header and wildcards; `None` means nothing supported is acceptable.
+    pub fn negotiate(accept: Option<&str>) -> Option<Self> {
+        let Some(accept) = accept.filter(|accept| !accept.trim().is_empty()) else {
+            return Some(ValueFormat::Json);
+        };
+        let mut best: Option<(f32, Self)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(q) => q.trim().parse::<f32>().unwrap_or(0.0),
+                None => 1.0,
+            };
+            let format = match media_type.as_str() {
+                "*/*" | "application/*" => Some(ValueFormat::Json),
+                media_type => Self::from_media_type(media_type),
+            };
+            if let Some(format) = format.filter(|_| quality > 0.0) {
+                if best.is_none_or(|(best, _)| quality > best) {
+                    best = Some((quality, format));
+                }
+            }
+        }
+        best.map(|(_, format)| format)
+    }
+
+    /// Encode a wire value as a body
+    pub fn encode(self, value: &Value<'_>) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        match self {
+            ValueFormat::Json => body = serde_json::to_vec(&value.to_json()?).map_err(|e| e.to_string())?,
+            ValueFormat::MessagePack => encode_msgpack(value, &mut body)?,
+            ValueFormat::Cbor => encode_cbor(value, &mut body)?,
+        }
+        Ok(body)
+    }
+
+    /// Decode a body holding one value to its wire encoding
+    pub fn decode(self, body: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            ValueFormat::Json => {
+                let json: Json = serde_json::from_slice(body).map_err(|e| format!("Malformed JSON: {}", e))?;
+                let mut value = Vec::new();
+                encode_json(&json, &mut value)?;
+                Ok(value)
+            }
+            ValueFormat::MessagePack => decode_msgpack(body),
+            ValueFormat::Cbor => decode_cbor(body),
+        }
+    }
+
+    /// Encode a JSON document (in the `value_json` mapping) as a body
+    pub fn encode_document(self, json: &Json) -> Result<Vec<u8>, String> {
+        if self == ValueFormat::Json {
+            return serde_json::to_vec(json).map_err(|e| e.to_string());
+        }
+        let mut value = Vec::new();
+        encode_json(json, &mut value)?;
+        self.encode(&Value::from_bytes(&value).map_err(|e| e.to_string())?)
+    }
+
+    /// Decode a body to a JSON document (in the `value_json` mapping)
+    pub fn decode_document(self, body: &[u8]) -> Result<Json, String> {
+        if self == ValueFormat::Json {
+            return serde_json::from_slice(body).map_err(|e| format!("Malformed JSON: {}", e));
+        }
+        decode_json(&self.decode(body)?)
+    }
+
+    /// Decode a request body to `T` through its JSON document form
+    pub fn decode_request<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, BodyError> {
+        let json = self
+            .decode_document(body)
+            .map_err(|e| BodyError::Invalid(format!("Invalid {} body: {}", self.content_type(), e)))?;
+        serde_json::from_value(json).map_err(|e| BodyError::Invalid(format!("Invalid document data: {}", e)))
+    }
+
+    /// Encode a response as a body through its JSON document form
+    pub fn encode_response<T: Serialize>(self, response: &T) -> Result<Vec<u8>, String> {
+        if self == ValueFormat::Json {
+            return serde_json::to_vec(response).map_err(|e| e.to_string());
+        }
+        self.encode_document(&serde_json::to_value(response).map_err(|e| e.to_string())?)
+    }
+}
+
+/// Cursor over a binary body
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    /// Fail unless every byte was read
+    pub(crate) fn finish(&self) -> Result<(), String> {
+        match self.remaining() {
+            0 => Ok(()),
+            stray => Err(format!("{} stray bytes after value", stray)),
+        }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| format!("Body truncated at byte {}", self.position))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Big-endian unsigned integer of `width` bytes (at most 8)
+    pub(crate) fn uint(&mut self, width: usize) -> Result<u64, String> {
+        Ok(self.take(width)?.iter().fold(0, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    /// Bytes not yet read
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    /// Length read as `width` bytes, bounded by what is left so corrupt lengths fail early
+    pub(crate) fn length(&mut self, width: usize) -> Result<usize, String> {
+        let len = self.uint(width)?;
+        match usize::try_from(len) {
+            Ok(len) if len <= self.remaining() => Ok(len),
+            _ => Err(format!("Length {} at byte {} runs past the body", len, self.position)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_negotiation() {
+        assert_eq!(ValueFormat::negotiate(None), Some(ValueFormat::Json));
+        assert_eq!(ValueFormat::negotiate(Some("*/*")), Some(ValueFormat::Json));
+        assert_eq!(ValueFormat::negotiate(Some("application/cbor")), Some(ValueFormat::Cbor));
+        assert_eq!(
+            ValueFormat::negotiate(Some("application/json;q=0.5, application/msgpack, */*;q=0.1")),
+            Some(ValueFormat::MessagePack)
+        );
+        assert_eq!(ValueFormat::negotiate(Some("application/cbor;q=0, application/json;q=0.2")), Some(ValueFormat::Json));
+        assert_eq!(ValueFormat::negotiate(Some("text/html")), None);
+        assert_eq!(ValueFormat::from_media_type("Application/JSON; charset=utf-8"), Some(ValueFormat::Json));
+        assert_eq!(ValueFormat::from_media_type("application/x-msgpack"), Some(ValueFormat::MessagePack));
+        assert_eq!(ValueFormat::from_media_type("text/plain"), None);
+    }
+
+    #[test]
+    fn test_documents_convert_between_formats() {
+        let document = json!({
+            "doc_type": "user",
+            "properties": {"name": "ada", "age": 36, "avatar": {"$binary": "AP8="}, "tags": {"$set": ["a"]}},
+            "parent_id": null
+        });
+        for format in ValueFormat::ALL {
+            let body = format.encode_document(&document).unwrap();
+            assert_eq!(format.decode_document(&body).unwrap(), document, "{:?}", format);
+            let value = format.decode(&body).unwrap();
+            let reencoded = format.encode(&Value::from_bytes(&value).unwrap()).unwrap();
+            assert_eq!(format.decode_document(&reencoded).unwrap(), document, "{:?}", format);
+        }
+        assert!(ValueFormat::Cbor.decode_document(&[0xff]).is_err());
+        assert!(ValueFormat::MessagePack.decode_document(&[0xc1]).is_err());
+    }
+
+    #[test]
+    fn test_requests_and_responses() {
+        #[derive(Debug, PartialEq, serde::Deserialize, Serialize)]
+        struct Request {
+            doc_type: String,
+            parent_id: Option<String>,
+        }
+        let request = Request { doc_type: "Tree".to_string(), parent_id: None };
+
+        assert_eq!(ValueFormat::from_content_type(None), Ok(ValueFormat::Json));
+        assert_eq!(ValueFormat::from_content_type(Some("application/cbor")), Ok(ValueFormat::Cbor));
+        assert_eq!(
+            ValueFormat::from_content_type(Some("text/plain")),
+            Err(BodyError::UnsupportedMediaType("text/plain".to_string()))
+        );
+        for format in ValueFormat::ALL {
+            let body = format.encode_response(&request).unwrap();
+            assert_eq!(format.decode_request::<Request>(&body).unwrap(), request, "{:?}", format);
+        }
+        assert_eq!(ValueFormat::Json.encode_response(&request).unwrap(), br#"{"doc_type":"Tree","parent_id":null}"#);
+
+        // Undecodable bodies and bodies of the wrong shape are both invalid
+        let error = ValueFormat::MessagePack.decode_request::<Request>(&[0xc1]).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid application/msgpack body"));
+        let wrong = ValueFormat::Cbor.encode_response(&serde_json::json!({"doc_type": 1})).unwrap();
+        assert!(matches!(ValueFormat::Cbor.decode_request::<Request>(&wrong), Err(BodyError::Invalid(e)) if e.starts_with("Invalid document data")));
+    }
+}
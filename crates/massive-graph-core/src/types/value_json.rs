@@ -0,0 +1,386 @@
+//! JSON conversion
+//!
+//! The REST layer speaks JSON while storage keeps binary wire values. Conversion is
+//! lossless in both directions for every type JSON can carry:
+//! ```text
+//! JSON                                          Value
+//! null / true / false / string                  Null / Bool / String
+//! integer within i64                            Int
+//! any other number                              Float
+//! array / object                                Array / Object
+//! {"$undefined": true}                          Undefined
+//! {"$float": "NaN" | "Infinity" | "-Infinity"}  non-finite Float
+//! {"$timestamp": "<RFC 3339>"}                  Timestamp, nanoseconds, written in UTC
+//! {"$binary": "<base64>"}                       Binary, standard padded alphabet
+//! {"$map": {...}}                               Map
+//! {"$set": [...]}                               Collection
+//! {"$doc": "<document id>"}                     DocumentRef
+//! {"$delta": "<document id>:<delta id>"}        DeltaRef
+//! {"$user": "<user id>"}                        UserRef
+//! {"$object": {...}}                            Object that would read as a tag
+//! ```
+//! An object reads as a tag only when its single key is one of the tags above; Objects
+//! of that shape are written wrapped in `$object`. Floats are written with a fraction
+//! or exponent (`2.0`), so they read back as Float. Integers past i64 but within u64 are
+//! rejected rather than rounded; larger ones already parse as floats and become Float.
+//! `$timestamp` also accepts integer nanoseconds since the Unix epoch. Types with no
+//! JSON form (streams, graphs, ...) and values nested deeper than `MAX_BODY_DEPTH` fail
+//! to convert.
+
+use std::collections::HashSet;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat};
+use serde_json::{Map, Number, Value as Json};
+
+use crate::types::value_format::MAX_BODY_DEPTH;
+use crate::types::value_nested::{encode_elements, encode_entries};
+use crate::types::delta::DeltaBuilder;
+use crate::types::{DeltaOp, SchemaRegistry, Value, ValueType};
+
+/// Keys that mark a single-key JSON object as a tagged value
+const TAGS: &[&str] = &["$undefined", "$float", "$timestamp", "$binary", "$map", "$set", "$doc", "$delta", "$user", "$object"];
+
+/// Nanoseconds per second
+const NANOS: u64 = 1_000_000_000;
+
+/// Tag and payload of a tagged JSON object
+fn tagged(map: &Map<String, Json>) -> Option<(&str, &Json)> {
+    match map.iter().next() {
+        Some((key, value)) if map.len() == 1 && TAGS.contains(&key.as_str()) => Some((key, value)),
+        _ => None,
+    }
+}
+
+/// Single-key JSON object `{tag: value}`
+fn tag(tag: &str, value: impl Into<Json>) -> Json {
+    Json::Object(Map::from_iter([(tag.to_string(), value.into())]))
+}
+
+/// Encode a JSON value as a wire value
+pub fn encode_json(json: &Json, output: &mut Vec<u8>) -> Result<(), String> {
+    match json {
+        Json::Null => Value::encode(ValueType::Null, &[], output),
+        Json::Bool(value) => Value::encode(ValueType::Bool, &[*value as u8], output),
+        Json::Number(number) => encode_number(number, output),
+        Json::String(value) => Value::encode(ValueType::String, value.as_bytes(), output),
+        Json::Array(items) => encode_list(ValueType::Array, items, output),
+        Json::Object(map) => match tagged(map) {
+            Some((tag, payload)) => encode_tagged(tag, payload, output),
+            None => encode_object(ValueType::Object, map, output),
+        },
+    }
+}
+
+/// Set deltas writing each property of a JSON object to the field its key resolves to
+/// in `schema`; keys are concrete paths such as `users{alice}.age` (see `path_index`)
+pub fn set_deltas(schema: &SchemaRegistry, properties: &Map<String, Json>) -> Result<Vec<Vec<u8>>, String> {
+    properties
+        .iter()
+        .map(|(path, json)| {
+            let property = |e: String| format!("Property '{}': {}", path, e);
+            let resolved = schema.resolve_path(path).map_err(property)?;
+            let mut value = Vec::new();
+            encode_json(json, &mut value).map_err(property)?;
+            DeltaBuilder::new(DeltaOp::Set)
+                .field(resolved.schema_version, resolved.field_index)
+                .params(resolved.params)
+                .raw_value(&value)
+                .build()
+        })
+        .collect()
+}
+
+/// Decode one complete wire value to JSON
+pub fn decode_json(bytes: &[u8]) -> Result<Json, String> {
+    let value = Value::from_bytes(bytes).map_err(|e| e.to_string())?;
+    if value.total_size() != bytes.len() {
+        return Err(format!("{} stray bytes after value", bytes.len() - value.total_size()));
+    }
+    value.to_json()
+}
+
+fn encode_number(number: &Number, output: &mut Vec<u8>) -> Result<(), String> {
+    if let Some(value) = number.as_i64() {
+        return Value::encode(ValueType::Int, &value.to_le_bytes(), output);
+    }
+    if number.is_u64() {
+        return Err(format!("Integer {} is outside the Int range", number));
+    }
+    let value = number.as_f64().ok_or_else(|| format!("Number {} is not representable", number))?;
+    Value::encode(ValueType::Float, &value.to_le_bytes(), output)
+}
+
+fn encode_list(value_type: ValueType, items: &[Json], output: &mut Vec<u8>) -> Result<(), String> {
+    let mut elements: Vec<Vec<u8>> = Vec::with_capacity(items.len());
+    for item in items {
+        let mut element = Vec::new();
+        encode_json(item, &mut element)?;
+        elements.push(element);
+    }
+    if value_type == ValueType::Collection {
+        let mut seen = HashSet::with_capacity(elements.len());
+        if let Some(duplicate) = elements.iter().position(|element| !seen.insert(element.as_slice())) {
+            return Err(format!("Duplicate element {} in $set", items[duplicate]));
+        }
+    }
+    encode_elements(value_type, elements, output)
+}
+
+fn encode_object(value_type: ValueType, map: &Map<String, Json>, output: &mut Vec<u8>) -> Result<(), String> {
+    let mut entries = Vec::with_capacity(map.len());
+    for (key, item) in map {
+        let mut value = Vec::new();
+        encode_json(item, &mut value)?;
+        entries.push((key.as_str(), value));
+    }
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    encode_entries(value_type, entries, output)
+}
+
+fn encode_tagged(tag: &str, payload: &Json, output: &mut Vec<u8>) -> Result<(), String> {
+    let expected = |what: &str| format!("{} expects {}, got {}", tag, what, payload);
+    match (tag, payload) {
+        ("$undefined", Json::Bool(true)) => Value::encode(ValueType::Undefined, &[], output),
+        ("$float", Json::String(value)) => {
+            let value = match value.as_str() {
+                "NaN" => f64::NAN,
+                "Infinity" => f64::INFINITY,
+                "-Infinity" => f64::NEG_INFINITY,
+                _ => return Err(expected("NaN, Infinity or -Infinity")),
+            };
+            Value::encode(ValueType::Float, &value.to_le_bytes(), output)
+        }
+        ("$timestamp", Json::String(value)) => {
+            let time = DateTime::parse_from_rfc3339(value).map_err(|e| format!("Invalid timestamp {:?}: {}", value, e))?;
+            let nanos = u64::try_from(time.timestamp())
+                .ok()
+                .and_then(|secs| secs.checked_mul(NANOS))
+                .and_then(|nanos| nanos.checked_add(time.timestamp_subsec_nanos() as u64))
+                .ok_or_else(|| format!("Timestamp {:?} is outside the Timestamp range", value))?;
+            Value::encode(ValueType::Timestamp, &nanos.to_le_bytes(), output)
+        }
+        ("$timestamp", Json::Number(number)) => {
+            let nanos = number.as_u64().ok_or_else(|| expected("non-negative integer nanoseconds"))?;
+            Value::encode(ValueType::Timestamp, &nanos.to_le_bytes(), output)
+        }
+        ("$binary", Json::String(value)) => {
+            let bytes = STANDARD.decode(value).map_err(|e| format!("Invalid base64 in $binary: {}", e))?;
+            Value::encode(ValueType::Binary, &bytes, output)
+        }
+        ("$map", Json::Object(map)) => encode_object(ValueType::Map, map, output),
+        ("$object", Json::Object(map)) => encode_object(ValueType::Object, map, output),
+        ("$set", Json::Array(items)) => encode_list(ValueType::Collection, items, output),
+        ("$doc", Json::String(id)) => encode_reference(ValueType::DocumentRef, id.as_bytes(), output),
+        ("$delta", Json::String(id)) => {
+            let (doc_id, delta_id) = id.split_once(':').ok_or_else(|| expected("\"<document id>:<delta id>\""))?;
+            encode_reference(ValueType::DeltaRef, [doc_id, delta_id].concat().as_bytes(), output)
+        }
+        ("$user", Json::String(id)) => encode_reference(ValueType::UserRef, id.as_bytes(), output),
+        ("$undefined", _) => Err(expected("true")),
+        ("$float" | "$binary" | "$doc" | "$delta" | "$user", _) => Err(expected("a string")),
+        ("$timestamp", _) => Err(expected("an RFC 3339 string or integer nanoseconds")),
+        ("$map" | "$object", _) => Err(expected("an object")),
+        _ => Err(expected("an array")),
+    }
+}
+
+/// Encode a reference value, checking its ids
+fn encode_reference(value_type: ValueType, data: &[u8], output: &mut Vec<u8>) -> Result<(), String> {
+    let mut value = Vec::new();
+    Value::encode(value_type, data, &mut value)?;
+    match Value::from_bytes(&value).and_then(|value| value.reference()) {
+        Ok(_) => {
+            output.extend_from_slice(&value);
+            Ok(())
+        }
+        Err(_) => Err(format!("Invalid {:?} id {:?}", value_type, String::from_utf8_lossy(data))),
+    }
+}
+
+impl Value<'_> {
+    /// Convert to JSON (see `value_json` for the mapping)
+    pub fn to_json(&self) -> Result<Json, String> {
+        self.json_at(0)
+    }
+
+    fn json_at(&self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_BODY_DEPTH {
+            return Err(format!("Values nest deeper than {}", MAX_BODY_DEPTH));
+        }
+        let value_type = self.value_type();
+        let error = |e: crate::types::ParseError| format!("{:?}: {}", value_type, e);
+        Ok(match value_type {
+            ValueType::Null => Json::Null,
+            ValueType::Undefined => tag("$undefined", true),
+            ValueType::Bool => Json::Bool(self.as_bool().map_err(error)?),
+            ValueType::Int => Json::from(self.as_i64().map_err(error)?),
+            ValueType::Float => {
+                let value = self.as_f64().map_err(error)?;
+                match Number::from_f64(value) {
+                    Some(number) => Json::Number(number),
+                    None if value.is_nan() => tag("$float", "NaN"),
+                    None if value > 0.0 => tag("$float", "Infinity"),
+                    None => tag("$float", "-Infinity"),
+                }
+            }
+            ValueType::Timestamp => {
+                let nanos = self.as_timestamp().map_err(error)?;
+                let time = DateTime::from_timestamp((nanos / NANOS) as i64, (nanos % NANOS) as u32)
+                    .ok_or_else(|| format!("Timestamp {} is outside the supported range", nanos))?;
+                tag("$timestamp", time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            ValueType::String => Json::from(self.as_str().map_err(error)?),
+            ValueType::Binary => tag("$binary", STANDARD.encode(self.as_bytes().map_err(error)?)),
+            ValueType::Array | ValueType::Collection => {
+                let items = self.elements().map_err(error)?.map(|e| e.map_err(error)?.json_at(depth + 1)).collect::<Result<_, _>>()?;
+                match value_type {
+                    ValueType::Array => Json::Array(items),
+                    _ => tag("$set", Json::Array(items)),
+                }
+            }
+            ValueType::Map | ValueType::Object => {
+                let mut map = Map::new();
+                for entry in self.entries().map_err(error)? {
+                    let (key, item) = entry.map_err(error)?;
+                    map.insert(key.to_string(), item.json_at(depth + 1)?);
+                }
+                match value_type {
+                    ValueType::Map => tag("$map", map),
+                    _ if tagged(&map).is_some() => tag("$object", map),
+                    _ => Json::Object(map),
+                }
+            }
+            ValueType::DocumentRef => tag("$doc", self.as_document_ref().map_err(error)?.to_string()),
+            ValueType::DeltaRef => {
+                let (doc_id, delta_id) = self.as_delta_ref().map_err(error)?;
+                tag("$delta", format!("{}:{}", doc_id, delta_id))
+            }
+            ValueType::UserRef => tag("$user", self.as_user_ref().map_err(error)?.to_string()),
+            _ => return Err(format!("{:?} values have no JSON form", value_type)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{DocId, UserId};
+
+    fn round_trip(json: &Json) -> Json {
+        let mut bytes = Vec::new();
+        encode_json(json, &mut bytes).unwrap();
+        decode_json(&bytes).unwrap()
+    }
+
+    fn encoded(json: Json) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        encode_json(&json, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let doc = DocId::random().to_string();
+        let user = UserId::random().to_string();
+        let json = json!({
+            "name": "ada",
+            "age": 36,
+            "score": 2.0,
+            "tags": ["a", null, true, [1, -2.5]],
+            "nothing": {"$undefined": true},
+            "inf": {"$float": "-Infinity"},
+            "born": {"$timestamp": "1815-12-10T00:00:00Z"},
+            "seen": {"$timestamp": "2024-03-01T12:00:00.000000001Z"},
+            "avatar": {"$binary": "AP8="},
+            "friends": {"$map": {"bob": {"$doc": doc}}},
+            "owner": {"$user": user},
+            "cause": {"$delta": format!("{}:delta001", doc)},
+            "flags": {"$set": [1, "x"]},
+            "escaped": {"$object": {"$map": {}}},
+            "empty": {}
+        });
+        // Before the Unix epoch: not a Timestamp
+        let mut bytes = Vec::new();
+        assert!(encode_json(&json, &mut bytes).unwrap_err().contains("outside the Timestamp range"));
+
+        let mut json = json;
+        json["born"] = json!({"$timestamp": "1970-01-01T00:00:01.5Z"});
+        let decoded = round_trip(&json);
+        assert_eq!(decoded["born"], json!({"$timestamp": "1970-01-01T00:00:01.500Z"}));
+        json["born"] = decoded["born"].clone();
+        assert_eq!(decoded, json);
+        assert_eq!(round_trip(&decoded), json);
+    }
+
+    #[test]
+    fn test_number_rules() {
+        let int = encoded(json!(3));
+        assert_eq!(Value::from_bytes(&int).unwrap().as_i64().unwrap(), 3);
+        let float = encoded(serde_json::from_str("3.0").unwrap());
+        assert_eq!(Value::from_bytes(&float).unwrap().as_f64().unwrap(), 3.0);
+        assert_eq!(serde_json::to_string(&decode_json(&float).unwrap()).unwrap(), "3.0");
+        assert!(encode_json(&json!(u64::MAX), &mut Vec::new()).is_err());
+        let past_u64 = encoded(serde_json::from_str("18446744073709551616").unwrap());
+        assert_eq!(Value::from_bytes(&past_u64).unwrap().as_f64().unwrap(), 18446744073709551616.0);
+
+        let nan = encoded(json!({"$float": "NaN"}));
+        assert!(Value::from_bytes(&nan).unwrap().as_f64().unwrap().is_nan());
+        assert_eq!(decode_json(&nan).unwrap(), json!({"$float": "NaN"}));
+    }
+
+    #[test]
+    fn test_tagged_values() {
+        let nanos = encoded(json!({"$timestamp": 1_500_000_000u64}));
+        assert_eq!(Value::from_bytes(&nanos).unwrap().as_timestamp().unwrap(), 1_500_000_000);
+        assert_eq!(decode_json(&nanos).unwrap(), json!({"$timestamp": "1970-01-01T00:00:01.500Z"}));
+
+        let binary = encoded(json!({"$binary": "AP8="}));
+        assert_eq!(Value::from_bytes(&binary).unwrap().as_bytes().unwrap(), &[0, 255]);
+
+        // A tag key among others is an ordinary field
+        let object = encoded(json!({"$binary": 1, "other": 2}));
+        assert_eq!(Value::from_bytes(&object).unwrap().value_type(), ValueType::Object);
+
+        for invalid in [
+            json!({"$binary": "not base64!"}),
+            json!({"$undefined": false}),
+            json!({"$float": 1.5}),
+            json!({"$timestamp": "yesterday"}),
+            json!({"$doc": "short"}),
+            json!({"$doc": "has spaces in it"}),
+            json!({"$delta": "no separator"}),
+            json!({"$set": [1, 1]}),
+            json!({"$map": []}),
+        ] {
+            assert!(encode_json(&invalid, &mut Vec::new()).is_err(), "{}", invalid);
+        }
+
+        let mut state = Vec::new();
+        Value::encode(ValueType::State, &[], &mut state).unwrap();
+        assert!(decode_json(&state).is_err());
+        assert!(decode_json(&[ValueType::DocumentRef as u8, 1, b'x']).is_err());
+    }
+
+    #[test]
+    fn test_deep_values_rejected() {
+        let nested = |depth: usize| {
+            let mut bytes = encoded(json!(1));
+            for _ in 0..depth {
+                let mut writer = crate::types::ValueWriter::new();
+                writer.elements(ValueType::Array, [bytes]).unwrap();
+                bytes = writer.into_bytes();
+            }
+            bytes
+        };
+        assert!(decode_json(&nested(MAX_BODY_DEPTH)).is_ok());
+        let deep = nested(5000);
+        assert!(decode_json(&deep).unwrap_err().contains("nest deeper"));
+        let value = Value::from_bytes(&deep).unwrap();
+        assert!(crate::types::value_msgpack::encode_msgpack(&value, &mut Vec::new()).unwrap_err().contains("nest deeper"));
+        assert!(crate::types::value_cbor::encode_cbor(&value, &mut Vec::new()).unwrap_err().contains("nest deeper"));
+    }
+}
@@ -0,0 +1,510 @@
+//! MessagePack codec
+//!
+//! Wire values map to MessagePack one-to-one:
+//! ```text
+//! Null, Bool, String, Binary   nil, bool, str, bin
+//! Int                          int family, smallest encoding
+//! Float                        float 64 (float 32 reads as Float too)
+//! Array, Object                array, map with str keys
+//! Timestamp                    timestamp extension (-1), smallest of its three forms
+//! Undefined                    ext 1, empty
+//! Map                          ext 2 holding a map
+//! Collection                   ext 3 holding an array
+//! DocumentRef, UserRef         ext 4, ext 5 holding the id bytes
+//! DeltaRef                     ext 6 holding the document and delta id bytes
+//! any other type               ext 16 holding the encoded wire value
+//! ```
+//! Integers outside i64, maps with non-str keys and duplicate keys or Collection
+//! elements are rejected, as is ext 16 holding a type listed above, so every value has
+//! a single encoding that is checked where it is read.
+
+use std::collections::HashSet;
+
+use crate::types::value_format::{Reader, MAX_BODY_DEPTH};
+use crate::types::value_nested::{encode_elements, encode_entries};
+use crate::types::{Value, ValueType};
+
+/// Extension type of the standard timestamp
+const EXT_TIMESTAMP: i8 = -1;
+/// Extension type of Undefined
+const EXT_UNDEFINED: i8 = 1;
+/// Extension type of Map
+const EXT_MAP: i8 = 2;
+/// Extension type of Collection
+const EXT_COLLECTION: i8 = 3;
+/// Extension type of DocumentRef
+const EXT_DOCUMENT_REF: i8 = 4;
+/// Extension type of UserRef
+const EXT_USER_REF: i8 = 5;
+/// Extension type of DeltaRef
+const EXT_DELTA_REF: i8 = 6;
+/// Extension type carrying any other wire value as-is
+const EXT_WIRE: i8 = 16;
+
+/// Types with a MessagePack form of their own, never carried in ext 16
+const NATIVE: &[ValueType] = &[
+    ValueType::Null,
+    ValueType::Bool,
+    ValueType::Int,
+    ValueType::Float,
+    ValueType::Timestamp,
+    ValueType::Undefined,
+    ValueType::String,
+    ValueType::Binary,
+    ValueType::Array,
+    ValueType::Map,
+    ValueType::Object,
+    ValueType::Collection,
+    ValueType::DocumentRef,
+    ValueType::DeltaRef,
+    ValueType::UserRef,
+];
+
+/// Nanoseconds per second
+const NANOS: u64 = 1_000_000_000;
+
+/// Encode a wire value as MessagePack
+pub fn encode_msgpack(value: &Value<'_>, output: &mut Vec<u8>) -> Result<(), String> {
+    write_value(value, output, 0)
+}
+
+fn write_value(value: &Value<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    if depth > MAX_BODY_DEPTH {
+        return Err(format!("Values nest deeper than {}", MAX_BODY_DEPTH));
+    }
+    let error = |e: crate::types::ParseError| format!("{:?}: {}", value.value_type(), e);
+    match value.value_type() {
+        ValueType::Null => output.push(0xc0),
+        ValueType::Bool => output.push(if value.as_bool().map_err(error)? { 0xc3 } else { 0xc2 }),
+        ValueType::Int => write_int(value.as_i64().map_err(error)?, output),
+        ValueType::Float => {
+            output.push(0xcb);
+            output.extend_from_slice(&value.as_f64().map_err(error)?.to_be_bytes());
+        }
+        ValueType::String => {
+            let text = value.as_str().map_err(error)?;
+            write_len(text.len(), Some(0xa0), 32, [Some(0xd9), Some(0xda), Some(0xdb)], output)?;
+            output.extend_from_slice(text.as_bytes());
+        }
+        ValueType::Binary => {
+            let bytes = value.as_bytes().map_err(error)?;
+            write_len(bytes.len(), None, 0, [Some(0xc4), Some(0xc5), Some(0xc6)], output)?;
+            output.extend_from_slice(bytes);
+        }
+        ValueType::Array => write_array(value, output, depth)?,
+        ValueType::Object => write_map(value, output, depth)?,
+        ValueType::Timestamp => {
+            let nanos = value.as_timestamp().map_err(error)?;
+            let (secs, subsec) = (nanos / NANOS, nanos % NANOS);
+            let data = if secs >> 34 != 0 {
+                [(subsec as u32).to_be_bytes().as_slice(), &secs.to_be_bytes()].concat()
+            } else if subsec == 0 && secs <= u32::MAX as u64 {
+                (secs as u32).to_be_bytes().to_vec()
+            } else {
+                ((subsec << 34) | secs).to_be_bytes().to_vec()
+            };
+            write_ext(EXT_TIMESTAMP, &data, output)?;
+        }
+        ValueType::Undefined => write_ext(EXT_UNDEFINED, &[], output)?,
+        ValueType::Map => {
+            let mut map = Vec::new();
+            write_map(value, &mut map, depth)?;
+            write_ext(EXT_MAP, &map, output)?;
+        }
+        ValueType::Collection => {
+            let mut array = Vec::new();
+            write_array(value, &mut array, depth)?;
+            write_ext(EXT_COLLECTION, &array, output)?;
+        }
+        ValueType::DocumentRef => write_ext(EXT_DOCUMENT_REF, value.data(), output)?,
+        ValueType::UserRef => write_ext(EXT_USER_REF, value.data(), output)?,
+        ValueType::DeltaRef => write_ext(EXT_DELTA_REF, value.data(), output)?,
+        _ => write_ext(EXT_WIRE, value.encoded(), output)?,
+    }
+    Ok(())
+}
+
+/// Decode a MessagePack body holding one value to its wire encoding
+pub fn decode_msgpack(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader::new(bytes);
+    let mut output = Vec::new();
+    read_value(&mut reader, &mut output, 0)?;
+    reader.finish()?;
+    Ok(output)
+}
+
+fn write_int(value: i64, output: &mut Vec<u8>) {
+    match value {
+        -32..=127 => output.push(value as u8),
+        128..=0xff => output.extend_from_slice(&[0xcc, value as u8]),
+        0x100..=0xffff => {
+            output.push(0xcd);
+            output.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            output.push(0xce);
+            output.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        0x1_0000_0000.. => {
+            output.push(0xcf);
+            output.extend_from_slice(&(value as u64).to_be_bytes());
+        }
+        -0x80..=-33 => output.extend_from_slice(&[0xd0, value as u8]),
+        -0x8000..=-0x81 => {
+            output.push(0xd1);
+            output.extend_from_slice(&(value as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            output.push(0xd2);
+            output.extend_from_slice(&(value as i32).to_be_bytes());
+        }
+        _ => {
+            output.push(0xd3);
+            output.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+/// Write a length with its marker: a `fix` marker below `fix_limit`, else the
+/// 8/16/32-bit marker that fits
+fn write_len(len: usize, fix: Option<u8>, fix_limit: usize, markers: [Option<u8>; 3], output: &mut Vec<u8>) -> Result<(), String> {
+    match (fix, markers) {
+        (Some(fix), _) if len < fix_limit => output.push(fix | len as u8),
+        (_, [Some(marker), _, _]) if len <= 0xff => output.extend_from_slice(&[marker, len as u8]),
+        (_, [_, Some(marker), _]) if len <= 0xffff => {
+            output.push(marker);
+            output.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        (_, [_, _, Some(marker)]) if len <= 0xffff_ffff => {
+            output.push(marker);
+            output.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        _ => return Err(format!("Length {} is too large for MessagePack", len)),
+    }
+    Ok(())
+}
+
+fn write_array(value: &Value<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let elements = value.elements().map_err(|e| e.to_string())?;
+    write_len(elements.len(), Some(0x90), 16, [None, Some(0xdc), Some(0xdd)], output)?;
+    for element in elements {
+        write_value(&element.map_err(|e| e.to_string())?, output, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn write_map(value: &Value<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let entries = value.entries().map_err(|e| e.to_string())?;
+    write_len(entries.len(), Some(0x80), 16, [None, Some(0xde), Some(0xdf)], output)?;
+    for entry in entries {
+        let (key, item) = entry.map_err(|e| e.to_string())?;
+        write_len(key.len(), Some(0xa0), 32, [Some(0xd9), Some(0xda), Some(0xdb)], output)?;
+        output.extend_from_slice(key.as_bytes());
+        write_value(&item, output, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn write_ext(ext_type: i8, data: &[u8], output: &mut Vec<u8>) -> Result<(), String> {
+    let fixed = match data.len() {
+        1 => Some(0xd4),
+        2 => Some(0xd5),
+        4 => Some(0xd6),
+        8 => Some(0xd7),
+        16 => Some(0xd8),
+        _ => None,
+    };
+    match fixed {
+        Some(marker) => output.push(marker),
+        None => write_len(data.len(), None, 0, [Some(0xc7), Some(0xc8), Some(0xc9)], output)?,
+    }
+    output.push(ext_type as u8);
+    output.extend_from_slice(data);
+    Ok(())
+}
+
+/// Length following a marker from one of the 8/16/32-bit `markers`, or `None`
+fn marker_len(marker: u8, markers: [u8; 3], reader: &mut Reader<'_>) -> Result<Option<usize>, String> {
+    match markers.iter().position(|&m| m == marker) {
+        Some(width) => reader.length(1 << width).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Entry count of a map marker
+fn map_len(marker: u8, reader: &mut Reader<'_>) -> Result<Option<usize>, String> {
+    match marker {
+        0x80..=0x8f => Ok(Some((marker & 0x0f) as usize)),
+        0xde => reader.length(2).map(Some),
+        0xdf => reader.length(4).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Element count of an array marker
+fn array_len(marker: u8, reader: &mut Reader<'_>) -> Result<Option<usize>, String> {
+    match marker {
+        0x90..=0x9f => Ok(Some((marker & 0x0f) as usize)),
+        0xdc => reader.length(2).map(Some),
+        0xdd => reader.length(4).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// String length of a str marker
+fn str_len(marker: u8, reader: &mut Reader<'_>) -> Result<Option<usize>, String> {
+    match marker {
+        0xa0..=0xbf => Ok(Some((marker & 0x1f) as usize)),
+        _ => marker_len(marker, [0xd9, 0xda, 0xdb], reader),
+    }
+}
+
+fn read_str<'a>(len: usize, reader: &mut Reader<'a>) -> Result<&'a str, String> {
+    std::str::from_utf8(reader.take(len)?).map_err(|_| "String is not UTF-8".to_string())
+}
+
+fn read_value(reader: &mut Reader<'_>, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    if depth > MAX_BODY_DEPTH {
+        return Err(format!("Values nest deeper than {}", MAX_BODY_DEPTH));
+    }
+    let marker = reader.byte()?;
+    let int = |value: i64, output: &mut Vec<u8>| Value::encode(ValueType::Int, &value.to_le_bytes(), output);
+    if let Some(len) = str_len(marker, reader)? {
+        return Value::encode(ValueType::String, read_str(len, reader)?.as_bytes(), output);
+    }
+    if let Some(count) = array_len(marker, reader)? {
+        return read_array(reader, count, ValueType::Array, output, depth);
+    }
+    if let Some(count) = map_len(marker, reader)? {
+        return read_map(reader, count, ValueType::Object, output, depth);
+    }
+    if let Some(len) = marker_len(marker, [0xc4, 0xc5, 0xc6], reader)? {
+        return Value::encode(ValueType::Binary, reader.take(len)?, output);
+    }
+    match marker {
+        0x00..=0x7f => int(marker as i64, output),
+        0xe0..=0xff => int(marker as i8 as i64, output),
+        0xc0 => Value::encode(ValueType::Null, &[], output),
+        0xc2 | 0xc3 => Value::encode(ValueType::Bool, &[marker - 0xc2], output),
+        0xcc..=0xcf => {
+            let value = reader.uint(1 << (marker - 0xcc))?;
+            let value = i64::try_from(value).map_err(|_| format!("Integer {} is outside the Int range", value))?;
+            int(value, output)
+        }
+        0xd0..=0xd3 => {
+            let width = 1 << (marker - 0xd0);
+            let value = reader.uint(width)?;
+            // Sign-extend from `width` bytes
+            let shift = 64 - 8 * width as u32;
+            int(((value << shift) as i64) >> shift, output)
+        }
+        0xca => Value::encode(ValueType::Float, &(f32::from_bits(reader.uint(4)? as u32) as f64).to_le_bytes(), output),
+        0xcb => Value::encode(ValueType::Float, &f64::from_bits(reader.uint(8)?).to_le_bytes(), output),
+        0xd4..=0xd8 => {
+            let len = 1 << (marker - 0xd4);
+            let ext_type = reader.byte()? as i8;
+            read_ext(ext_type, reader.take(len)?, output, depth)
+        }
+        0xc7..=0xc9 => {
+            let len = reader.length(1 << (marker - 0xc7))?;
+            let ext_type = reader.byte()? as i8;
+            read_ext(ext_type, reader.take(len)?, output, depth)
+        }
+        _ => Err(format!("Unsupported MessagePack marker {:#04x}", marker)),
+    }
+}
+
+fn read_array(reader: &mut Reader<'_>, count: usize, value_type: ValueType, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let mut elements: Vec<Vec<u8>> = Vec::new();
+    for _ in 0..count {
+        let mut element = Vec::new();
+        read_value(reader, &mut element, depth + 1)?;
+        elements.push(element);
+    }
+    let mut seen = HashSet::with_capacity(elements.len());
+    if value_type == ValueType::Collection && !elements.iter().all(|element| seen.insert(element.as_slice())) {
+        return Err("Duplicate element in Collection".to_string());
+    }
+    encode_elements(value_type, elements, output)
+}
+
+fn read_map(reader: &mut Reader<'_>, count: usize, value_type: ValueType, output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let marker = reader.byte()?;
+        let len = str_len(marker, reader)?.ok_or_else(|| format!("Map key marker {:#04x} is not a string", marker))?;
+        let key = read_str(len, reader)?;
+        let mut item = Vec::new();
+        read_value(reader, &mut item, depth + 1)?;
+        entries.push((key, item));
+    }
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    encode_entries(value_type, entries, output)
+}
+
+fn read_ext(ext_type: i8, data: &[u8], output: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+    let expect_len = |len: usize| match data.len() == len {
+        true => Ok(()),
+        false => Err(format!("Extension {} expects {} bytes, got {}", ext_type, len, data.len())),
+    };
+    match ext_type {
+        EXT_TIMESTAMP => {
+            let mut reader = Reader::new(data);
+            let (secs, subsec) = match data.len() {
+                4 => (reader.uint(4)?, 0),
+                8 => {
+                    let packed = reader.uint(8)?;
+                    (packed & ((1 << 34) - 1), packed >> 34)
+                }
+                12 => {
+                    let subsec = reader.uint(4)?;
+                    let secs = reader.uint(8)? as i64;
+                    (u64::try_from(secs).map_err(|_| "Timestamps before the Unix epoch are not supported")?, subsec)
+                }
+                len => return Err(format!("Timestamp extension of {} bytes", len)),
+            };
+            let nanos = secs
+                .checked_mul(NANOS)
+                .and_then(|nanos| nanos.checked_add(subsec))
+                .filter(|_| subsec < NANOS)
+                .ok_or("Timestamp is outside the Timestamp range")?;
+            Value::encode(ValueType::Timestamp, &nanos.to_le_bytes(), output)
+        }
+        EXT_UNDEFINED => {
+            expect_len(0)?;
+            Value::encode(ValueType::Undefined, &[], output)
+        }
+        EXT_MAP | EXT_COLLECTION => {
+            let mut reader = Reader::new(data);
+            let marker = reader.byte()?;
+            let (count, value_type) = match ext_type {
+                EXT_MAP => (map_len(marker, &mut reader)?, ValueType::Map),
+                _ => (array_len(marker, &mut reader)?, ValueType::Collection),
+            };
+            let count = count.ok_or_else(|| format!("Extension {} holds marker {:#04x}", ext_type, marker))?;
+            match value_type {
+                ValueType::Map => read_map(&mut reader, count, value_type, output, depth)?,
+                _ => read_array(&mut reader, count, value_type, output, depth)?,
+            }
+            reader.finish()
+        }
+        EXT_DOCUMENT_REF | EXT_DELTA_REF | EXT_USER_REF => {
+            let value_type = match ext_type {
+                EXT_DOCUMENT_REF => ValueType::DocumentRef,
+                EXT_DELTA_REF => ValueType::DeltaRef,
+                _ => ValueType::UserRef,
+            };
+            let start = output.len();
+            Value::encode(value_type, data, output)?;
+            Value::from_bytes(&output[start..])
+                .and_then(|value| value.reference())
+                .map_err(|e| format!("Extension {} holds an invalid id: {}", ext_type, e))?;
+            Ok(())
+        }
+        EXT_WIRE => {
+            let value = Value::from_bytes(data).map_err(|e| e.to_string())?;
+            if NATIVE.contains(&value.value_type()) {
+                return Err(format!("Extension {} holds a {:?}, which has its own form", ext_type, value.value_type()));
+            }
+            // The data of the remaining types is opaque; its framing must be canonical
+            let mut canonical = Vec::with_capacity(data.len());
+            Value::encode(value.value_type(), value.data(), &mut canonical)?;
+            if canonical != data {
+                return Err(format!("Extension {} holds a non-canonical {:?}", ext_type, value.value_type()));
+            }
+            output.extend_from_slice(data);
+            Ok(())
+        }
+        _ => Err(format!("Unsupported MessagePack extension {}", ext_type)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValueWriter;
+
+    fn round_trip(wire: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        encode_msgpack(&Value::from_bytes(wire).unwrap(), &mut body).unwrap();
+        decode_msgpack(&body).unwrap()
+    }
+
+    #[test]
+    fn test_every_type_round_trips() {
+        let mut inner = ValueWriter::new();
+        inner.int(1);
+        let one = inner.into_bytes();
+        let mut values = vec![];
+        for int in [0, 127, 128, -32, -33, 255, 65_536, i64::MIN, i64::MAX] {
+            let mut writer = ValueWriter::new();
+            writer.int(int);
+            values.push(writer.into_bytes());
+        }
+        for nanos in [0, 5 * NANOS, 5 * NANOS + 1, (1 << 34) * NANOS + 7, u64::MAX] {
+            let mut writer = ValueWriter::new();
+            writer.timestamp(nanos);
+            values.push(writer.into_bytes());
+        }
+        let mut writer = ValueWriter::new();
+        writer.null().undefined().bool(true).float(-1.5);
+        writer.string(&"x".repeat(40)).unwrap().binary(&[0; 300]).unwrap();
+        writer.elements(ValueType::Array, [one.clone(), one.clone()]).unwrap();
+        writer.elements(ValueType::Collection, [one.clone()]).unwrap();
+        writer.entries(ValueType::Object, [("a", one.clone())]).unwrap();
+        writer.entries(ValueType::Map, [("a", one.clone())]).unwrap();
+        writer.document_ref(crate::DocId::random()).delta_ref(crate::DocId::random(), crate::DeltaId::random());
+        writer.user_ref(crate::UserId::random());
+        writer.value(ValueType::Node, &[1, 2, 3]).unwrap();
+        let bytes = writer.into_bytes();
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let len = Value::encoded_len(rest).unwrap();
+            values.push(rest[..len].to_vec());
+            rest = &rest[len..];
+        }
+
+        for value in values {
+            assert_eq!(round_trip(&value), value, "{:?}", Value::from_bytes(&value).unwrap().value_type());
+        }
+    }
+
+    #[test]
+    fn test_reads_foreign_encodings() {
+        // {"a": [1, 2.5 as float32]} with an 8-bit length str key
+        let body = [0x81, 0xd9, 0x01, b'a', 0x92, 0x01, 0xca, 0x40, 0x20, 0x00, 0x00];
+        let value = decode_msgpack(&body).unwrap();
+        let value = Value::from_bytes(&value).unwrap();
+        assert_eq!(value.get_path("a[1]").unwrap().unwrap().as_f64().unwrap(), 2.5);
+
+        assert!(decode_msgpack(&[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode_msgpack(&[0x81, 0x01, 0x01]).is_err());
+        assert!(decode_msgpack(&[0xc7, 0x03, EXT_COLLECTION as u8, 0x92, 0x01, 0x01]).is_err());
+        assert!(decode_msgpack(&[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode_msgpack(&[0xc0, 0xc0]).is_err());
+        assert!(decode_msgpack(&[[0x91; MAX_BODY_DEPTH + 2].as_slice(), &[0xc0]].concat()).unwrap_err().contains("nest deeper"));
+    }
+
+    #[test]
+    fn test_wire_extension_limited_to_opaque_types() {
+        let wire = |value: &[u8]| {
+            let mut body = Vec::new();
+            write_ext(EXT_WIRE, value, &mut body).unwrap();
+            body
+        };
+        // Natively mapped types would skip their own checks
+        let mut deep = vec![ValueType::Null as u8];
+        for _ in 0..3000 {
+            let mut writer = ValueWriter::new();
+            writer.elements(ValueType::Array, [deep]).unwrap();
+            deep = writer.into_bytes();
+        }
+        assert!(decode_msgpack(&wire(&deep)).unwrap_err().contains("own form"));
+        assert!(decode_msgpack(&wire(&[ValueType::String as u8, 2, 0xff, 0xfe])).unwrap_err().contains("own form"));
+
+        // Opaque types need canonical framing
+        let node = [ValueType::Node as u8, 1, 7];
+        assert_eq!(decode_msgpack(&wire(&node)).unwrap(), node);
+        assert!(decode_msgpack(&wire(&[ValueType::Node as u8, 0x80, 0x01, 7])).unwrap_err().contains("non-canonical"));
+    }
+}
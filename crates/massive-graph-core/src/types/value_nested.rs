@@ -0,0 +1,400 @@
+//! Nested values
+//!
+//! Array, Map, Object and Collection values hold other encoded values. Their data starts
+//! with an offsets table, so any element is reached without decoding the ones before it:
+//! ```text
+//! Array / Collection:  [count varint][offsets: count × u32 LE][value]...
+//! Map / Object:        [count varint][offsets: count × u32 LE][entry]...
+//! entry:               [key length varint][UTF-8 key][value]
+//! ```
+//! Offsets are relative to the first element, which follows the table; the first is 0
+//! and each element runs to the next offset (the last to the end of the data). Map and
+//! Object entries are sorted bytewise by key with no duplicates, so the entries double
+//! as a key dictionary that lookups binary search. Collection elements are unique.
+//!
+//! Reading borrows from the encoded bytes: elements, keys and lookups are views into
+//! chunk memory and nothing is allocated. Each element is checked when it is touched,
+//! so a malformed value is reported instead of read out of bounds.
+
+use std::cmp::Ordering;
+
+use crate::types::varint::{decode_varint, encode_varint};
+use crate::types::{ParseError, Value, ValueType};
+
+/// Width of one offsets table entry
+const OFFSET_SIZE: usize = 4;
+
+/// Types whose elements are keyed entries
+const KEYED: &[ValueType] = &[ValueType::Map, ValueType::Object];
+
+/// Element count, offsets table and elements of a nested value's data
+#[derive(Clone, Copy)]
+struct Layout<'a> {
+    count: usize,
+    offsets: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> Layout<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, ParseError> {
+        let (count, read) = decode_varint(data)?;
+        let count = count as usize;
+        let table_end = count.checked_mul(OFFSET_SIZE).and_then(|len| len.checked_add(read)).ok_or(ParseError::InvalidFormat)?;
+        if data.len() < table_end {
+            return Err(ParseError::InsufficientData { expected: table_end, actual: data.len() });
+        }
+        let layout = Self { count, offsets: &data[read..table_end], body: &data[table_end..] };
+        let starts_at_zero = if count == 0 { layout.body.is_empty() } else { layout.offset(0) == 0 };
+        if !starts_at_zero {
+            return Err(ParseError::InvalidFormat);
+        }
+        Ok(layout)
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        let at = index * OFFSET_SIZE;
+        u32::from_le_bytes(self.offsets[at..at + OFFSET_SIZE].try_into().unwrap()) as usize
+    }
+
+    /// Bytes of element `index` (which must be below `count`)
+    fn slot(&self, index: usize) -> Result<&'a [u8], ParseError> {
+        let start = self.offset(index);
+        let end = if index + 1 < self.count { self.offset(index + 1) } else { self.body.len() };
+        self.body.get(start..end).ok_or(ParseError::InvalidFormat)
+    }
+}
+
+/// A slot holding exactly one value
+fn element(slot: &[u8]) -> Result<Value<'_>, ParseError> {
+    let value = Value::from_bytes(slot)?;
+    if value.total_size() != slot.len() {
+        return Err(ParseError::LengthMismatch { declared: slot.len(), actual: value.total_size() });
+    }
+    Ok(value)
+}
+
+/// Key of an entry slot and the bytes after it
+fn entry_key(slot: &[u8]) -> Result<(&str, &[u8]), ParseError> {
+    let (len, read) = decode_varint(slot)?;
+    let end = read.checked_add(len as usize).ok_or(ParseError::InvalidFormat)?;
+    let key = slot.get(read..end).ok_or(ParseError::InsufficientData { expected: end, actual: slot.len() })?;
+    Ok((std::str::from_utf8(key).map_err(|_| ParseError::InvalidUtf8)?, &slot[end..]))
+}
+
+/// An entry slot: key and value
+fn entry(slot: &[u8]) -> Result<(&str, Value<'_>), ParseError> {
+    let (key, rest) = entry_key(slot)?;
+    Ok((key, element(rest)?))
+}
+
+impl<'a> Value<'a> {
+    /// Layout of a nested value's data, checking the value is one of `accepted`
+    fn layout(&self, accepted: &[ValueType]) -> Result<Layout<'a>, ParseError> {
+        if !accepted.contains(&self.value_type()) {
+            return Err(ParseError::TypeMismatch { expected: accepted[0] as u8, actual: self.value_type() as u8 });
+        }
+        Layout::parse(self.data())
+    }
+
+    /// Number of elements (or entries) of an Array, Map, Object or Collection
+    pub fn element_count(&self) -> Result<usize, ParseError> {
+        Ok(self.layout(&[ValueType::Array, ValueType::Map, ValueType::Object, ValueType::Collection])?.count)
+    }
+
+    /// Element `index` of an Array, `None` past the end
+    pub fn get(&self, index: usize) -> Result<Option<Value<'a>>, ParseError> {
+        let layout = self.layout(&[ValueType::Array])?;
+        if index >= layout.count {
+            return Ok(None);
+        }
+        element(layout.slot(index)?).map(Some)
+    }
+
+    /// Value of a Map or Object entry, `None` if the key is absent
+    pub fn get_key(&self, key: &str) -> Result<Option<Value<'a>>, ParseError> {
+        let layout = self.layout(KEYED)?;
+        let (mut low, mut high) = (0, layout.count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (candidate, rest) = entry_key(layout.slot(middle)?)?;
+            match candidate.cmp(key) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return element(rest).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Elements of an Array or Collection, in order
+    pub fn elements(&self) -> Result<Elements<'a>, ParseError> {
+        Ok(Elements { layout: self.layout(&[ValueType::Array, ValueType::Collection])?, next: 0 })
+    }
+
+    /// Entries of a Map or Object, in key order
+    pub fn entries(&self) -> Result<Entries<'a>, ParseError> {
+        Ok(Entries { layout: self.layout(KEYED)?, next: 0 })
+    }
+
+    /// Value at a path of keys and Array indices, such as `users[3].name`
+    ///
+    /// Dotted segments are Map/Object keys and `[n]` selects an Array element; the path
+    /// may start with an index. Keys containing `.` or `[` cannot be addressed this way
+    /// (use `get_key`). Returns `None` when a key or index is absent.
+    pub fn get_path(&self, path: &str) -> Result<Option<Value<'a>>, ParseError> {
+        let mut current = *self;
+        let mut rest = path;
+        let mut first = true;
+        while !rest.is_empty() {
+            let next = if let Some(tail) = rest.strip_prefix('[') {
+                let close = tail.find(']').ok_or(ParseError::InvalidFormat)?;
+                let index = tail[..close].parse::<usize>().map_err(|_| ParseError::InvalidFormat)?;
+                rest = &tail[close + 1..];
+                current.get(index)?
+            } else {
+                let tail = match rest.strip_prefix('.') {
+                    Some(tail) if !first => tail,
+                    None if first => rest,
+                    _ => return Err(ParseError::InvalidFormat),
+                };
+                let end = tail.find(['.', '[']).unwrap_or(tail.len());
+                if end == 0 {
+                    return Err(ParseError::InvalidFormat);
+                }
+                rest = &tail[end..];
+                current.get_key(&tail[..end])?
+            };
+            first = false;
+            match next {
+                Some(value) => current = value,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current))
+    }
+}
+
+/// Elements of an Array or Collection, borrowed from its encoded bytes
+#[derive(Clone)]
+pub struct Elements<'a> {
+    layout: Layout<'a>,
+    next: usize,
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Result<Value<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.layout.count {
+            return None;
+        }
+        let index = self.next;
+        self.next += 1;
+        Some(self.layout.slot(index).and_then(element))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.layout.count - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Elements<'_> {}
+
+/// Entries of a Map or Object, borrowed from its encoded bytes
+#[derive(Clone)]
+pub struct Entries<'a> {
+    layout: Layout<'a>,
+    next: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<(&'a str, Value<'a>), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.layout.count {
+            return None;
+        }
+        let index = self.next;
+        self.next += 1;
+        Some(self.layout.slot(index).and_then(entry))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.layout.count - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Entries<'_> {}
+
+/// Write `[type][length][count][offsets][body]` from the parts of a nested value
+fn finish(value_type: ValueType, count: usize, offsets: &[u8], body: &[u8], output: &mut Vec<u8>) -> Result<(), String> {
+    let mut data = Vec::with_capacity(5 + offsets.len() + body.len());
+    encode_varint(count as u32, &mut data)?;
+    data.extend_from_slice(offsets);
+    data.extend_from_slice(body);
+    Value::encode(value_type, &data, output)
+}
+
+/// Offset of the next element, which must fit the table
+fn push_offset(offsets: &mut Vec<u8>, body: &[u8]) -> Result<(), String> {
+    let offset = u32::try_from(body.len()).map_err(|_| "Nested value is too large".to_string())?;
+    offsets.extend_from_slice(&offset.to_le_bytes());
+    Ok(())
+}
+
+/// Encode an Array or Collection from encoded element values
+///
+/// Collection uniqueness is the caller's to keep.
+pub fn encode_elements<E: AsRef<[u8]>>(
+    value_type: ValueType,
+    elements: impl IntoIterator<Item = E>,
+    output: &mut Vec<u8>,
+) -> Result<(), String> {
+    if !matches!(value_type, ValueType::Array | ValueType::Collection) {
+        return Err(format!("{:?} does not hold elements", value_type));
+    }
+    let (mut count, mut offsets, mut body) = (0, Vec::new(), Vec::new());
+    for element in elements {
+        let element = element.as_ref();
+        if Value::encoded_len(element).ok() != Some(element.len()) {
+            return Err(format!("Element {} is not a single encoded value", count));
+        }
+        push_offset(&mut offsets, &body)?;
+        body.extend_from_slice(element);
+        count += 1;
+    }
+    finish(value_type, count, &offsets, &body, output)
+}
+
+/// Encode a Map or Object from entries with strictly ascending keys
+pub fn encode_entries<K: AsRef<str>, V: AsRef<[u8]>>(
+    value_type: ValueType,
+    entries: impl IntoIterator<Item = (K, V)>,
+    output: &mut Vec<u8>,
+) -> Result<(), String> {
+    if !KEYED.contains(&value_type) {
+        return Err(format!("{:?} does not hold entries", value_type));
+    }
+    let (mut count, mut offsets, mut body) = (0, Vec::new(), Vec::new());
+    let mut previous = None::<K>;
+    for (key, value) in entries {
+        let value = value.as_ref();
+        if previous.as_ref().is_some_and(|previous| previous.as_ref() >= key.as_ref()) {
+            return Err(format!("Entry key {:?} is out of order or duplicated", key.as_ref()));
+        }
+        if Value::encoded_len(value).ok() != Some(value.len()) {
+            return Err(format!("Entry {:?} is not a single encoded value", key.as_ref()));
+        }
+        push_offset(&mut offsets, &body)?;
+        encode_varint(key.as_ref().len() as u32, &mut body)?;
+        body.extend_from_slice(key.as_ref().as_bytes());
+        body.extend_from_slice(value);
+        previous = Some(key);
+        count += 1;
+    }
+    finish(value_type, count, &offsets, &body, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValueWriter;
+
+    fn int(v: i64) -> Vec<u8> {
+        let mut writer = ValueWriter::new();
+        writer.int(v);
+        writer.into_bytes()
+    }
+
+    fn string(v: &str) -> Vec<u8> {
+        let mut writer = ValueWriter::new();
+        writer.string(v).unwrap();
+        writer.into_bytes()
+    }
+
+    fn object(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_entries(ValueType::Object, entries.iter().map(|(k, v)| (*k, v)), &mut out).unwrap();
+        out
+    }
+
+    fn array(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_elements(ValueType::Array, elements, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_path_lookup_into_nested_values() {
+        let users: Vec<_> = ["ada", "bob", "cy", "dee"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| object(&[("age", int(30 + i as i64)), ("name", string(name))]))
+            .collect();
+        let root = object(&[("count", int(4)), ("users", array(&users))]);
+        let value = Value::from_bytes(&root).unwrap();
+
+        assert_eq!(value.get_path("users[3].name").unwrap().unwrap().as_str().unwrap(), "dee");
+        assert_eq!(value.get_path("users[1].age").unwrap().unwrap().as_i64().unwrap(), 31);
+        assert_eq!(value.get_path("users").unwrap().unwrap().element_count().unwrap(), 4);
+        assert!(value.get_path("users[4].name").unwrap().is_none());
+        assert!(value.get_path("users[0].email").unwrap().is_none());
+        assert_eq!(value.get_path("").unwrap().unwrap().total_size(), root.len());
+
+        // Views borrow the original bytes
+        let name = value.get_path("users[0].name").unwrap().unwrap();
+        assert!(root.as_ptr_range().contains(&name.data().as_ptr()));
+
+        assert!(value.get_path("count[0]").is_err());
+        assert!(value.get_path("users.[0]").is_err());
+        assert!(value.get_path("users[x]").is_err());
+        assert!(value.get_path("users..name").is_err());
+    }
+
+    #[test]
+    fn test_iterators() {
+        let bytes = array(&[int(1), string("two"), array(&[])]);
+        let value = Value::from_bytes(&bytes).unwrap();
+        let elements = value.elements().unwrap();
+        assert_eq!(elements.len(), 3);
+        let types: Vec<_> = elements.map(|e| e.unwrap().value_type()).collect();
+        assert_eq!(types, vec![ValueType::Int, ValueType::String, ValueType::Array]);
+        assert!(value.entries().is_err());
+        assert!(value.get_key("a").is_err());
+
+        let bytes = object(&[("a", int(1)), ("b", int(2))]);
+        let value = Value::from_bytes(&bytes).unwrap();
+        let keys: Vec<_> = value.entries().unwrap().map(|e| e.unwrap().0).collect();
+        assert_eq!(keys, vec!["a", "b"]);
+        assert!(value.get(0).is_err());
+        assert!(value.get_key("c").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_malformed_values_are_rejected() {
+        assert!(encode_entries(ValueType::Map, [("b", int(1)), ("a", int(2))], &mut Vec::new()).is_err());
+        assert!(encode_entries(ValueType::Map, [("a", int(1)), ("a", int(2))], &mut Vec::new()).is_err());
+        assert!(encode_elements(ValueType::Array, [vec![ValueType::Int as u8]], &mut Vec::new()).is_err());
+        assert!(encode_elements(ValueType::Map, [int(1)], &mut Vec::new()).is_err());
+
+        // Offsets pointing past the data or between values
+        let mut bytes = array(&[int(1), int(2)]);
+        let second_offset = 3 + OFFSET_SIZE;
+        bytes[second_offset] = 200;
+        let value = Value::from_bytes(&bytes).unwrap();
+        assert!(value.get(1).is_err());
+        bytes[second_offset] = 4;
+        let value = Value::from_bytes(&bytes).unwrap();
+        assert!(value.get(0).is_err());
+        assert!(value.elements().unwrap().any(|e| e.is_err()));
+
+        // Count larger than the offsets table
+        let bytes = [ValueType::Array as u8, 1, 3];
+        assert!(Value::from_bytes(&bytes).unwrap().element_count().is_err());
+        // Elements without a table
+        let bytes = [ValueType::Array as u8, 2, 0, ValueType::Null as u8];
+        assert!(Value::from_bytes(&bytes).unwrap().element_count().is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Reference values
+//!
+//! `DocumentRef`, `DeltaRef` and `UserRef` values hold the id of their target as base62
+//! characters:
+//! ```text
+//! DocumentRef  [document id: 16]
+//! DeltaRef     [document id: 16][delta id: 8]
+//! UserRef      [user id: 32]
+//! ```
+//! A delta id is only unique within its document, so a `DeltaRef` names both. References
+//! are followed by `Store::resolve`; `Value::references` collects those nested anywhere in
+//! a value, which is how the store keeps its reverse-reference index.
+
+use crate::types::{ParseError, Value, ValueType};
+use crate::{DeltaId, DocId, UserId};
+
+/// Target of a reference value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reference {
+    /// A document
+    Document(DocId),
+    /// A delta of a document
+    Delta(DocId, DeltaId),
+    /// A user
+    User(UserId),
+}
+
+impl Reference {
+    /// Document the reference points into, if any
+    pub fn doc_id(&self) -> Option<DocId> {
+        match self {
+            Reference::Document(doc_id) | Reference::Delta(doc_id, _) => Some(*doc_id),
+            Reference::User(_) => None,
+        }
+    }
+}
+
+impl Value<'_> {
+    /// Target of a reference value, `None` for any other type
+    pub fn reference(&self) -> Result<Option<Reference>, ParseError> {
+        Ok(Some(match self.value_type() {
+            ValueType::DocumentRef => Reference::Document(self.as_document_ref()?),
+            ValueType::DeltaRef => {
+                let (doc_id, delta_id) = self.as_delta_ref()?;
+                Reference::Delta(doc_id, delta_id)
+            }
+            ValueType::UserRef => Reference::User(self.as_user_ref()?),
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Every reference in the value, nested ones included, in document order
+    pub fn references(&self) -> Result<Vec<Reference>, ParseError> {
+        let mut references = Vec::new();
+        let mut pending = vec![*self];
+        while let Some(value) = pending.pop() {
+            let start = pending.len();
+            match value.value_type() {
+                ValueType::Array | ValueType::Collection => {
+                    for element in value.elements()? {
+                        pending.push(element?);
+                    }
+                }
+                ValueType::Map | ValueType::Object => {
+                    for entry in value.entries()? {
+                        pending.push(entry?.1);
+                    }
+                }
+                _ => references.extend(value.reference()?),
+            }
+            // Children were pushed in order; visit the first one next
+            pending[start..].reverse();
+        }
+        Ok(references)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ValueWriter;
+
+    #[test]
+    fn test_reference_values_read_back() {
+        let (doc_id, delta_id, user_id) = (DocId::random(), DeltaId::random(), UserId::random());
+        let mut writer = ValueWriter::new();
+        writer.document_ref(doc_id).delta_ref(doc_id, delta_id).user_ref(user_id).int(1);
+        let bytes = writer.into_bytes();
+
+        let mut rest = bytes.as_slice();
+        let mut next = || {
+            let value = Value::from_bytes(rest).unwrap();
+            rest = &rest[value.total_size()..];
+            value.reference().unwrap()
+        };
+        assert_eq!(next(), Some(Reference::Document(doc_id)));
+        assert_eq!(next(), Some(Reference::Delta(doc_id, delta_id)));
+        assert_eq!(next(), Some(Reference::User(user_id)));
+        assert_eq!(next(), None);
+
+        // Wrong widths and non-base62 ids are rejected
+        let short = [ValueType::DocumentRef as u8, 1, b'a'];
+        assert_eq!(Value::from_bytes(&short).unwrap().as_document_ref(), Err(ParseError::InvalidFormat));
+        let mut invalid = vec![ValueType::UserRef as u8, 32];
+        invalid.extend([0xff; 32]);
+        assert_eq!(Value::from_bytes(&invalid).unwrap().as_user_ref(), Err(ParseError::InvalidFormat));
+    }
+
+    #[test]
+    fn test_nested_references_in_order() {
+        let (a, b, user_id) = (DocId::random(), DocId::random(), UserId::random());
+        let item = |f: &dyn Fn(&mut ValueWriter)| {
+            let mut writer = ValueWriter::new();
+            f(&mut writer);
+            writer.into_bytes()
+        };
+        let list = item(&|w| {
+            w.elements(ValueType::Array, [item(&|w| { w.document_ref(b); }), item(&|w| { w.string("x").unwrap(); })]).unwrap();
+        });
+        let root = item(&|w| {
+            w.entries(ValueType::Object, [
+                ("author", item(&|w| { w.user_ref(user_id); })),
+                ("links", list.clone()),
+                ("parent", item(&|w| { w.document_ref(a); })),
+            ]).unwrap();
+        });
+        assert_eq!(
+            Value::from_bytes(&root).unwrap().references().unwrap(),
+            vec![Reference::User(user_id), Reference::Document(b), Reference::Document(a)]
+        );
+        assert!(Value::from_bytes(&item(&|w| { w.int(3); })).unwrap().references().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+//! Variable-length integer encoding shared by the delta and field address wire formats.
+//!
+//! Values use a 1-3 byte prefix encoding where the top bits of the first byte give the width:
+//! - `0xxxxxxx` - 1 byte, values up to 127
+//! - `10xxxxxx xxxxxxxx` - 2 bytes, values up to 16,383
+//! - `11xxxxxx xxxxxxxx xxxxxxxx` - 3 bytes, values up to 4,194,303
+
+use crate::types::ParseError;
+
+/// Largest value representable by the prefix varint (22 bits)
+pub const VARINT_MAX: u32 = (1 << 22) - 1;
+
+/// Number of bytes needed to encode a value
+#[inline(always)]
+pub fn varint_len(value: u32) -> usize {
+    if value < 128 {
+        1
+    } else if value < 16_384 {
+        2
+    } else {
+        3
+    }
+}
+
+/// Encode a value, returning an error if it exceeds `VARINT_MAX`
+#[inline]
+pub fn encode_varint(value: u32, output: &mut Vec<u8>) -> Result<(), String> {
+    if value < 128 {
+        output.push(value as u8);
+    } else if value < 16_384 {
+        output.push(0x80 | (value >> 8) as u8);
+        output.push(value as u8);
+    } else if value <= VARINT_MAX {
+        output.push(0xC0 | (value >> 16) as u8);
+        output.push((value >> 8) as u8);
+        output.push(value as u8);
+    } else {
+        return Err(format!("Value {} exceeds varint maximum {}", value, VARINT_MAX));
+    }
+    Ok(())
+}
+
+/// Decode a value from the start of `bytes`, returning (value, bytes_read)
+#[inline(always)]
+pub fn decode_varint(bytes: &[u8]) -> Result<(u32, usize), ParseError> {
+    let Some(&first) = bytes.first() else {
+        return Err(ParseError::InsufficientData { expected: 1, actual: 0 });
+    };
+
+    let width = if first < 0x80 { 1 } else if first < 0xC0 { 2 } else { 3 };
+    if bytes.len() < width {
+        return Err(ParseError::InsufficientData { expected: width, actual: bytes.len() });
+    }
+
+    let value = match width {
+        1 => first as u32,
+        2 => ((first & 0x3F) as u32) << 8 | bytes[1] as u32,
+        _ => ((first & 0x3F) as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32,
+    };
+    Ok((value, width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_round_trip_at_boundaries() {
+        for value in [0, 1, 127, 128, 16_383, 16_384, VARINT_MAX] {
+            let mut buf = Vec::new();
+            encode_varint(value, &mut buf).unwrap();
+            assert_eq!(buf.len(), varint_len(value));
+            assert_eq!(decode_varint(&buf).unwrap(), (value, buf.len()));
+        }
+    }
+
+    #[test]
+    fn test_varint_errors() {
+        assert!(encode_varint(VARINT_MAX + 1, &mut Vec::new()).is_err());
+        assert_eq!(decode_varint(&[]), Err(ParseError::InsufficientData { expected: 1, actual: 0 }));
+        assert_eq!(decode_varint(&[0x80]), Err(ParseError::InsufficientData { expected: 2, actual: 1 }));
+        assert_eq!(decode_varint(&[0xC0, 0x01]), Err(ParseError::InsufficientData { expected: 3, actual: 2 }));
+    }
+}
@@ -3,9 +3,10 @@
 //! Document creation now integrated with SimpleDocumentStorage
 
 use axum::{
-    extract::{Path, State, rejection::JsonRejection},
-    http::StatusCode,
-    response::{IntoResponse, Json},
+    body::Bytes,
+    extract::{FromRequest, Path, State, rejection::JsonRejection},
+    http::{header::{ACCEPT, CONTENT_TYPE}, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
     Json as JsonExtractor,
 };
 use std::{str::FromStr, sync::Arc};
@@ -15,7 +16,8 @@ use serde_json::{json, Value};
 use massive_graph_core::{
     comms::{
         connection_manager::ConnectionStatus, network::{ConnectRequest, ConnectResponse}
-    }, core::AppState, log_debug, log_error, log_info, log_warn, storage::StorageImpl, types::{UserId, ID16}
+    }, core::AppState, log_debug, log_error, log_info, log_warn, storage::StorageImpl,
+    types::{delta::DELTA_SECURE_HEADER_SIZE, document::DocumentType, value_format::{BodyError, ValueFormat}, value_json::set_deltas, DeltaBuilder, DeltaOp, UserId, ID16}
 };
 
 // Response types
@@ -86,7 +88,7 @@ pub struct CreateDocumentRequest {
     pub doc_type: String,
     /// Parent document ID (optional for root documents)
     pub parent_id: Option<String>,
-    /// Document properties as JSON
+    /// Document properties, a JSON object keyed by field path (e.g. `users{alice}.age`)
     pub properties: Option<Value>,
 }
 
@@ -133,6 +135,8 @@ pub struct InfoResponse {
     pub capabilities: Vec<String>,
     /// List of supported protocols
     pub protocols: Vec<String>,
+    /// Media types document endpoints accept and return
+    pub formats: Vec<String>,
 }
 
 // POC helper to get user ID - in production this would come from auth middleware
@@ -181,6 +185,85 @@ where
     }
 }
 
+/// Error response with its status, in the format negotiated for the request
+pub type ApiError = (StatusCode, Negotiated<ErrorResponse>);
+
+/// Document request body in JSON, MessagePack or CBOR, chosen by `Content-Type`
+///
+/// Binary bodies are converted to JSON with the core value mapping, so tagged values
+/// (binary, timestamps, references) arrive in their `$`-tagged JSON form. Rejections are
+/// answered in the format negotiated from `Accept`, JSON when nothing supported is accepted.
+pub struct DocumentRequest<T>(pub T);
+
+impl<T, S> FromRequest<S> for DocumentRequest<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().clone();
+        let accept = headers.get(ACCEPT).and_then(|v| v.to_str().ok());
+        let reply_format = ValueFormat::negotiate(accept).unwrap_or(ValueFormat::Json);
+        let rejected = |error: BodyError| {
+            log_warn!("Document body error: {}", error);
+            let status = match error {
+                BodyError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                BodyError::Invalid(_) => StatusCode::BAD_REQUEST,
+            };
+            (status, Negotiated(reply_format, ErrorResponse::bad_request(error.to_string())))
+        };
+        let content_type = match headers.get(CONTENT_TYPE).map(|v| v.to_str()) {
+            None => None,
+            Some(Ok(content_type)) => Some(content_type),
+            Some(Err(_)) => return Err(rejected(BodyError::UnsupportedMediaType("<non-ASCII>".to_string()))),
+        };
+        let format = ValueFormat::from_content_type(content_type).map_err(rejected)?;
+        let body = Bytes::from_request(req, state).await
+            .map_err(|_| rejected(BodyError::Invalid("Failed to read request body".to_string())))?;
+        format.decode_request(&body).map(DocumentRequest).map_err(rejected)
+    }
+}
+
+/// Response body in the format negotiated from `Accept`
+pub struct Negotiated<T>(pub ValueFormat, pub T);
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let Negotiated(format, body) = self;
+        match format.encode_response(&body) {
+            Ok(bytes) => ([(CONTENT_TYPE, format.content_type())], bytes).into_response(),
+            Err(e) => {
+                log_error!("❌ Failed to encode {} response: {}", format.content_type(), e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// Response format for the request's `Accept` header, or 406 (in JSON) when none is supported
+fn response_format(headers: &HeaderMap) -> Result<ValueFormat, ApiError> {
+    let accept = headers.get(ACCEPT).and_then(|v| v.to_str().ok());
+    ValueFormat::negotiate(accept).ok_or_else(|| {
+        log_warn!("No supported format in Accept: {:?}", accept);
+        (
+            StatusCode::NOT_ACCEPTABLE,
+            Negotiated(ValueFormat::Json, ErrorResponse::bad_request(
+                "Supported formats are 'application/json', 'application/msgpack' and 'application/cbor'".to_string()
+            ))
+        )
+    })
+}
+
+/// Document type named in a creation request, e.g. "Tree" or "TextStream"
+fn parse_document_type(name: &str) -> Result<DocumentType, String> {
+    (0..=u8::MAX)
+        .filter_map(|tag| DocumentType::try_from(tag).ok())
+        .find(|doc_type| format!("{:?}", doc_type).eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("Unknown document type '{}'", name))
+}
+
 /// Generate or validate document ID
 fn handle_document_id(provided_id: Option<String>) -> Result<ID16, String> {
     match provided_id {
@@ -209,12 +292,16 @@ fn handle_document_id(provided_id: Option<String>) -> Result<ID16, String> {
 // Real handlers with storage integration
 
 /// Create a new document - now with real storage integration
+///
+/// Accepts JSON, MessagePack or CBOR bodies and answers in the format negotiated from `Accept`.
 pub async fn create_document<S: StorageImpl>(
     State(app_state): State<Arc<AppState<S>>>,
-    JsonRequest(request): JsonRequest<CreateDocumentRequest>,
-) -> Result<(StatusCode, Json<ApiResponse<DocumentInfo>>), (StatusCode, Json<ErrorResponse>)> {
+    headers: HeaderMap,
+    DocumentRequest(request): DocumentRequest<CreateDocumentRequest>,
+) -> Result<(StatusCode, Negotiated<ApiResponse<DocumentInfo>>), ApiError> {
     log_info!("🚀 Starting create_document handler");
     log_debug!("Request data: {:?}", request);
+    let format = response_format(&headers)?;
     
     // POC: User ID handling is now done internally by Store
     log_info!("📋 Step 1: User isolation handled by storage layer");
@@ -224,32 +311,50 @@ pub async fn create_document<S: StorageImpl>(
     let doc_id = handle_document_id(request.id.clone())
         .map_err(|e| {
             log_error!("❌ Failed to handle document ID: {}", e);
-            (StatusCode::BAD_REQUEST, Json(ErrorResponse::bad_request(e)))
+            (StatusCode::BAD_REQUEST, Negotiated(format, ErrorResponse::bad_request(e)))
         })?;
     log_info!("✅ Document ID handled: {}", doc_id);
 
-    // Create document data as JSON bytes
-    log_info!("📋 Step 3: Creating document data JSON");
-    let doc_data = serde_json::json!({
-        "id": doc_id.to_string(),
-        "doc_type": request.doc_type.clone(),
-        "parent_id": request.parent_id.clone(),
-        "properties": request.properties.clone().unwrap_or(json!({})),
-    });
-    log_debug!("Document data JSON: {}", doc_data);
-
-    log_info!("📋 Step 4: Serializing document data to bytes");
-    let doc_bytes = serde_json::to_vec(&doc_data)
-        .map_err(|e| {
-            log_error!("❌ Failed to serialize document data: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse::bad_request(format!("Failed to serialize document: {}", e))))
-        })?;
-    log_info!("✅ Document serialized, size: {} bytes", doc_bytes.len());
+    // Build the CreateDocument delta, grouped with SetParent when placed under a parent
+    // and with a Set delta per property, so the document appears with all of them or not at all
+    log_info!("📋 Step 3: Building CreateDocument delta");
+    let bad_request = |e: String| (StatusCode::BAD_REQUEST, Negotiated(format, ErrorResponse::bad_request(e)));
+    let doc_type = parse_document_type(&request.doc_type).map_err(bad_request)?;
+    let mut children = vec![DeltaBuilder::new(DeltaOp::CreateDocument).raw_value(&[doc_type as u8]).build().map_err(bad_request)?];
+    if let Some(parent) = request.parent_id.as_deref() {
+        let parent = ID16::from_str(parent).map_err(|e| bad_request(format!("Invalid parent ID format: {}", e)))?;
+        children.push(DeltaBuilder::new(DeltaOp::SetParent).raw_value(parent.as_bytes()).build().map_err(bad_request)?);
+    }
+    match &request.properties {
+        None => {}
+        Some(Value::Object(properties)) => children.extend(set_deltas(app_state.store.schema(), properties).map_err(bad_request)?),
+        Some(_) => return Err(bad_request("Document properties must be an object keyed by field path".to_string())),
+    }
+    let mut create = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+    let built = match children.as_slice() {
+        [single] => {
+            create.extend_from_slice(single);
+            Ok(())
+        }
+        _ => {
+            let children: Vec<&[u8]> = children.iter().map(|c| c.as_slice()).collect();
+            DeltaBuilder::group(&children).and_then(|group| group.build_into(&mut create))
+        }
+    };
+    built.map_err(bad_request)?;
+
+    // Stamping consumes a sequence number, so reject duplicates first
+    log_info!("📋 Step 4: Stamping delta");
+    let user_id = get_poc_user_id();
+    if app_state.store.document_exists(user_id, doc_id) {
+        return Err((StatusCode::CONFLICT, Negotiated(format, ErrorResponse::bad_request(format!("Document {} already exists", doc_id)))));
+    }
+    app_state.stamper.stamp(user_id, doc_id, &mut create).map_err(|e| bad_request(e.to_string()))?;
+    log_info!("✅ Delta stamped, size: {} bytes", create.len());
 
     // Store document using the storage layer
     log_info!("📋 Step 5: Calling storage.create_document");
-    let user_id = get_poc_user_id();
-    let result = app_state.store.create_document(user_id, doc_id, doc_bytes);
+    let result = app_state.store.create_document(user_id, doc_id, create);
 
     // Handle storage result
     log_info!("📋 Step 6: Processing storage result");
@@ -270,23 +375,30 @@ pub async fn create_document<S: StorageImpl>(
 
             Ok((
                 StatusCode::CREATED,
-                Json(ApiResponse::success(doc_info)),
+                Negotiated(format, ApiResponse::success(doc_info)),
             ))
         }
         Err(error_msg) => {
             log_error!("❌ Storage error: {}", error_msg);
             // Storage error
-            Err((StatusCode::BAD_REQUEST, Json(ErrorResponse::bad_request(error_msg))))
+            Err(bad_request(error_msg))
         }
     }
 }
 
 /// Get a document by ID - fetches from storage
+///
+/// Answers in JSON, MessagePack or CBOR as negotiated from `Accept`.
 pub async fn get_document<S: StorageImpl>(
     State(app_state): State<Arc<AppState<S>>>,
     Path(id): Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     log_info!("🔍 Starting get_document handler for ID: {}", id);
+    let format = match response_format(&headers) {
+        Ok(format) => format,
+        Err(rejection) => return rejection.into_response(),
+    };
     
     // POC: User ID handling is now done internally by Store
     log_info!("📋 Step 1: User isolation handled by storage layer");
@@ -297,7 +409,7 @@ pub async fn get_document<S: StorageImpl>(
         Ok(did) => did,
         Err(e) => {
             log_error!("❌ Invalid document ID format '{}': {}", id, e);
-            return (StatusCode::BAD_REQUEST, Json(ErrorResponse::bad_request(format!("Invalid document ID format: {}", e)))).into_response();
+            return (StatusCode::BAD_REQUEST, Negotiated(format, ErrorResponse::bad_request(format!("Invalid document ID format: {}", e)))).into_response();
         }
     };
     log_info!("✅ Document ID parsed: {}", doc_id);
@@ -305,62 +417,37 @@ pub async fn get_document<S: StorageImpl>(
     // Get document from storage
     log_info!("📋 Step 3: Fetching document from storage");
     let user_id = get_poc_user_id();
-    match app_state.store.get_document(user_id, doc_id) {
-        Some(doc_data) => {
-            log_info!("✅ Document found, data size: {} bytes", doc_data.len());
-            
-            // Parse the document data as JSON
-            log_debug!("📄 Parsing document data as JSON");
-            let doc_json: Value = match serde_json::from_slice(&doc_data) {
-                Ok(json) => json,
-                Err(e) => {
-                    log_error!("❌ Failed to parse document JSON: {}", e);
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                }
-            };
-            log_debug!("✅ Document JSON parsed successfully");
+    let document = app_state.store.get_document(user_id, doc_id).filter(|document| !document.is_deleted());
+    let Some(document) = document else {
+        log_warn!("📭 Document not found: doc={}", doc_id);
+        return StatusCode::NOT_FOUND.into_response();
+    };
 
-            // Extract document information
-            let doc_info = DocumentInfo {
-                id: doc_json.get("id")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or(&id)
-                    .to_string(),
-                doc_type: doc_json.get("doc_type")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("generic")
-                    .to_string(),
-                parent_id: doc_json.get("parent_id")
-                    .and_then(|v| v.as_str())
-                    .map(|s| s.to_string()),
-                properties: doc_json.get("properties")
-                    .cloned()
-                    .unwrap_or(json!({})),
-                created_at: doc_json.get("created_at")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("")
-                    .to_string(),
-                updated_at: doc_json.get("updated_at")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("")
-                    .to_string(),
-                version: doc_json.get("version")
-                    .and_then(|v| v.as_u64())
-                    .unwrap_or(1),
-            };
-            
-            log_info!("🎉 Document retrieved successfully: {}", doc_id);
-            Json(ApiResponse::success(doc_info)).into_response()
-        }
-        None => {
-            log_warn!("📭 Document not found: doc={}", doc_id);
-            StatusCode::NOT_FOUND.into_response()
+    // Render the stored state by field path; the negotiated format encodes the values
+    log_info!("📋 Step 4: Rendering document state");
+    let properties = match document.properties() {
+        Ok(properties) => properties,
+        Err(e) => {
+            log_error!("❌ Failed to render document {}: {}", doc_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
-    }
+    };
+    let doc_info = DocumentInfo {
+        id: doc_id.to_string(),
+        doc_type: format!("{:?}", document.doc_type()),
+        parent_id: app_state.store.parent(user_id, doc_id).map(|parent| parent.to_string()),
+        properties: Value::Object(properties),
+        created_at: String::new(),
+        updated_at: String::new(),
+        version: document.sequence(),
+    };
+
+    log_info!("🎉 Document retrieved successfully: {}", doc_id);
+    Negotiated(format, ApiResponse::success(doc_info)).into_response()
 }
 
 
-/// Delete a document - removes from storage
+/// Delete a document by applying a stamped DeleteDocument delta
 pub async fn delete_document<S: StorageImpl>(
     State(app_state): State<Arc<AppState<S>>>,
     Path(id): Path<String>,
@@ -379,27 +466,30 @@ pub async fn delete_document<S: StorageImpl>(
         })?;
     log_info!("✅ Document ID parsed: {}", doc_id);
 
-    // Check if document exists before attempting deletion
+    // Deleted documents keep their log, so check the lifecycle rather than presence
     log_info!("📋 Step 3: Checking if document exists");
     let user_id = get_poc_user_id();
-    if !app_state.store.document_exists(user_id, doc_id) {
+    if !app_state.store.get_document(user_id, doc_id).is_some_and(|document| !document.is_deleted()) {
         log_warn!("📭 Document not found for deletion: doc={}", doc_id);
         return Err((StatusCode::NOT_FOUND, Json(ErrorResponse::bad_request(format!("Document {} not found", id)))));
     }
     log_info!("✅ Document exists, proceeding with deletion");
 
-    // Remove document from storage
-    log_info!("📋 Step 4: Removing document from storage");
-    match app_state.store.remove_document(user_id, doc_id) {
-        Ok(()) => {
-            log_info!("🎉 Document deleted successfully: {}", doc_id);
-            Ok(StatusCode::NO_CONTENT)
-        }
-        Err(error_msg) => {
-            log_error!("❌ Storage error during deletion: {}", error_msg);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse::bad_request(format!("Failed to delete document: {}", error_msg)))))
-        }
-    }
+    // Build and stamp the DeleteDocument delta
+    log_info!("📋 Step 4: Building DeleteDocument delta");
+    let failed = |e: String| {
+        log_error!("❌ Storage error during deletion: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorResponse::bad_request(format!("Failed to delete document: {}", e))))
+    };
+    let mut delete = vec![0u8; DELTA_SECURE_HEADER_SIZE];
+    DeltaBuilder::new(DeltaOp::DeleteDocument).build_into(&mut delete).map_err(failed)?;
+    app_state.stamper.stamp(user_id, doc_id, &mut delete).map_err(|e| failed(e.to_string()))?;
+
+    // Apply it like any other delta, which also detaches the document from the hierarchy
+    log_info!("📋 Step 5: Applying DeleteDocument delta");
+    app_state.store.apply_delta(user_id, doc_id, delete).map_err(failed)?;
+    log_info!("🎉 Document deleted successfully: {}", doc_id);
+    Ok(StatusCode::NO_CONTENT)
 }
 
 /// Apply delta operations to a document - returns mock response
@@ -468,6 +558,7 @@ pub async fn system_info() -> Result<Json<InfoResponse>, StatusCode> {
             "documents".to_string(),
             "deltas".to_string(),
         ],
+        formats: ValueFormat::ALL.iter().map(|format| format.content_type().to_string()).collect(),
         protocols: vec![
             "http".to_string(),
         ],